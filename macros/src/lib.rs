@@ -0,0 +1,347 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+    parse_macro_input, spanned::Spanned, Error, FnArg, GenericArgument, Ident, ItemTrait, LitStr,
+    Pat, PathArguments, ReturnType, TraitItem, TraitItemFn, Type,
+};
+
+/// A method of an `#[rpc]` trait.
+struct Method {
+    ident: Ident,
+    /// The name of the method on the wire.
+    name: LitStr,
+    args: Vec<(Ident, Type)>,
+    /// The type of the result, the `T` of `Result<T, E>`.
+    result: Type,
+}
+
+/// Derive a JSON-RPC server and client from a trait.
+///
+/// Each method of the trait must be an `async fn` taking `&self` and returning `Result<T, E>`,
+/// where `E` implements `IntoRpcError`. The trait gains an `into_router` method serving an
+/// implementation, and a `<Trait>Client` is generated to call the methods on a server, so that
+/// both sides agree on method names and params.
+///
+/// Methods are named as in the trait, `#[rpc(name = "...")]` on a method renames it. Params are
+/// sent by position, and accepted by position or by name.
+///
+/// ```no_run
+/// use async_json_rpc::{clients::http::Client, objects::RpcError, rpc, server::http::Server};
+///
+/// #[rpc]
+/// pub trait Calculator {
+///     async fn add(&self, a: i64, b: i64) -> Result<i64, RpcError>;
+///
+///     #[rpc(name = "calc_version")]
+///     async fn version(&self) -> Result<String, RpcError>;
+/// }
+///
+/// struct MyCalculator;
+///
+/// impl Calculator for MyCalculator {
+///     async fn add(&self, a: i64, b: i64) -> Result<i64, RpcError> {
+///         Ok(a + b)
+///     }
+///
+///     async fn version(&self) -> Result<String, RpcError> {
+///         Ok("1.0".to_owned())
+///     }
+/// }
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let server = Server::bind("127.0.0.1:3000", MyCalculator.into_router()).await?;
+/// tokio::spawn(server.serve());
+///
+/// let client = Client::new("http://127.0.0.1:3000".to_owned(), None, None);
+/// let sum = CalculatorClient::new(client).add(1, 2).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[proc_macro_attribute]
+pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let err = Error::new(Span::call_site(), "#[rpc] takes no arguments on a trait");
+        return err.to_compile_error().into();
+    }
+    let item = parse_macro_input!(item as ItemTrait);
+    expand(item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(mut item: ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(Error::new(
+            item.generics.span(),
+            "#[rpc] traits can't be generic",
+        ));
+    }
+
+    let mut methods = Vec::new();
+    for trait_item in &mut item.items {
+        if let TraitItem::Fn(function) = trait_item {
+            methods.push(method(function)?);
+            desugar(function);
+        }
+    }
+
+    let router = router(&methods);
+    item.items.push(syn::parse2(router)?);
+    let client = client(&item, &methods);
+    Ok(quote! {
+        #item
+        #client
+    })
+}
+
+/// Parse a method, removing its `#[rpc]` attribute.
+fn method(function: &mut TraitItemFn) -> syn::Result<Method> {
+    let signature = &function.sig;
+    if signature.asyncness.is_none() {
+        return Err(Error::new(signature.span(), "#[rpc] methods must be async"));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(Error::new(
+            signature.generics.span(),
+            "#[rpc] methods can't be generic",
+        ));
+    }
+
+    let mut name = LitStr::new(&signature.ident.to_string(), signature.ident.span());
+    let mut attrs = Vec::new();
+    for attr in function.attrs.drain(..) {
+        if !attr.path().is_ident("rpc") {
+            attrs.push(attr);
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    function.attrs = attrs;
+
+    let mut inputs = signature.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        _ => {
+            return Err(Error::new(
+                signature.span(),
+                "#[rpc] methods must take `&self`",
+            ))
+        }
+    }
+    let mut args = Vec::new();
+    for input in inputs {
+        let arg = match input {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new(receiver.span(), "unexpected self"))
+            }
+        };
+        match &*arg.pat {
+            Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                args.push((pat.ident.clone(), (*arg.ty).clone()))
+            }
+            pat => {
+                return Err(Error::new(
+                    pat.span(),
+                    "#[rpc] method arguments must be named",
+                ))
+            }
+        }
+    }
+
+    let result = match &signature.output {
+        ReturnType::Type(_, ty) => result_type(ty),
+        ReturnType::Default => None,
+    };
+    let result = result.ok_or_else(|| {
+        Error::new(
+            signature.output.span(),
+            "#[rpc] methods must return `Result<T, E>`",
+        )
+    })?;
+
+    Ok(Method {
+        ident: signature.ident.clone(),
+        name,
+        args,
+        result,
+    })
+}
+
+/// The `T` of a `Result<T, E>`.
+fn result_type(ty: &Type) -> Option<Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(ty) => Some(ty.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Rewrite an `async fn` into a `fn` returning a `Send` future, so that it can be served.
+fn desugar(function: &mut TraitItemFn) {
+    let signature = &mut function.sig;
+    signature.asyncness = None;
+    let output = match &signature.output {
+        ReturnType::Type(_, ty) => quote!(#ty),
+        ReturnType::Default => quote!(()),
+    };
+    let span = signature.output.span();
+    signature.output = syn::parse2(quote_spanned! {span=>
+        -> impl ::core::future::Future<Output = #output> + ::core::marker::Send
+    })
+    .unwrap(); // This is safe
+    if let Some(block) = &mut function.default {
+        *block = syn::parse2(quote!({ async move #block })).unwrap(); // This is safe
+    }
+}
+
+/// The name of the struct which params deserialize into.
+fn params_ident(method: &Method) -> Ident {
+    format_ident!("__{}Params", method.ident)
+}
+
+/// The `into_router` method of the trait.
+fn router(methods: &[Method]) -> TokenStream2 {
+    let registrations = methods.iter().map(|method| {
+        let ident = &method.ident;
+        let name = &method.name;
+        let params = params_ident(method);
+        let (fields, types): (Vec<_>, Vec<_>) = method.args.iter().cloned().unzip();
+        // Params without fields are ignored, clients may send nothing or an empty array
+        let (definition, ty, call) = match fields.is_empty() {
+            true => (
+                quote!(),
+                quote!(::async_json_rpc::__private::serde_json::Value),
+                quote!(this.#ident()),
+            ),
+            false => (
+                quote! {
+                    #[derive(::async_json_rpc::__private::serde::Deserialize)]
+                    #[serde(crate = "::async_json_rpc::__private::serde")]
+                    #[allow(non_camel_case_types)]
+                    struct #params {
+                        #(#fields: #types,)*
+                    }
+                },
+                quote!(#params),
+                quote!(this.#ident(#(params.#fields),*)),
+            ),
+        };
+        quote! {
+            #definition
+            let this = self_.clone();
+            let router = router.register(#name, move |params: #ty| {
+                let _ = &params;
+                let this = this.clone();
+                async move { #call.await }
+            });
+        }
+    });
+
+    quote! {
+        /// Serve this implementation using a router.
+        fn into_router(self) -> ::async_json_rpc::server::Router
+        where
+            Self: ::core::marker::Sized + ::core::marker::Send + ::core::marker::Sync + 'static,
+        {
+            let self_ = ::std::sync::Arc::new(self);
+            let router = ::async_json_rpc::server::Router::new();
+            #(#registrations)*
+            router
+        }
+    }
+}
+
+/// The client calling the methods of the trait.
+fn client(item: &ItemTrait, methods: &[Method]) -> TokenStream2 {
+    let vis = &item.vis;
+    let trait_ident = &item.ident;
+    let ident = format_ident!("{}Client", trait_ident);
+    let doc = format!(
+        "A client calling the methods of [`{}`] on a server.",
+        trait_ident
+    );
+
+    let calls = methods.iter().map(|method| {
+        let method_ident = &method.ident;
+        let name = &method.name;
+        let result = &method.result;
+        let (args, types): (Vec<_>, Vec<_>) = method.args.iter().cloned().unzip();
+        let doc = format!("Call `{}`.", name.value());
+        quote! {
+            #[doc = #doc]
+            pub async fn #method_ident(&self, #(#args: #types),*) -> ::core::result::Result<#result, ::async_json_rpc::clients::Error<E>> {
+                use ::async_json_rpc::clients::RequestFactory;
+                use ::async_json_rpc::__private::ServiceExt;
+
+                let params = ::async_json_rpc::__private::serde_json::to_value((#(&#args,)*))
+                    .map_err(::async_json_rpc::clients::Error::Json)?;
+                let request = self
+                    .client
+                    .build_request()
+                    .method(#name)
+                    .params(params)
+                    .finish()
+                    .unwrap(); // This is safe
+                let response = self.client.clone().oneshot(request).await?;
+                if let ::core::option::Option::Some(err) = response.error {
+                    return ::core::result::Result::Err(::async_json_rpc::clients::Error::Rpc(err));
+                }
+                let result = response.result.unwrap_or_default();
+                ::async_json_rpc::__private::serde_json::from_value(result)
+                    .map_err(::async_json_rpc::clients::Error::Json)
+            }
+        }
+    });
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Clone, Debug)]
+        #vis struct #ident<C> {
+            client: C,
+        }
+
+        impl<C> #ident<C> {
+            /// Creates a client calling the methods using `client`.
+            pub fn new(client: C) -> Self {
+                #ident { client }
+            }
+
+            /// Returns the underlying client.
+            pub fn into_inner(self) -> C {
+                self.client
+            }
+        }
+
+        impl<C, E> #ident<C>
+        where
+            C: ::async_json_rpc::prelude::Service<
+                    ::async_json_rpc::objects::Request,
+                    Response = ::async_json_rpc::objects::Response,
+                    Error = ::async_json_rpc::clients::Error<E>,
+                > + ::async_json_rpc::clients::RequestFactory
+                + ::core::clone::Clone,
+        {
+            #(#calls)*
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_core::{future::BoxFuture, Future};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+use super::{BoxError, TokenSource};
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: Option<u64>,
+}
+
+/// Read the `exp` claim of a JWT, without verifying the signature.
+///
+/// Returns `None` if the claim is missing, or too far in the future for a [`SystemTime`].
+pub fn expiry(token: &str) -> Option<SystemTime> {
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(claims.exp?))
+}
+
+struct CachedToken {
+    token: Zeroizing<String>,
+    expires_at: Option<SystemTime>,
+}
+
+/// Caches JWTs minted by an async callback, invoking it again when the token is near expiry.
+///
+/// Expiry is read from the `exp` claim, tokens without one are reused until [`invalidate`] is
+/// called. Register it on a client using [`Client::with_token_source`].
+///
+/// [`invalidate`]: JwtSource::invalidate
+/// [`Client::with_token_source`]: crate::clients::http::Client::with_token_source
+pub struct JwtSource<F> {
+    mint: F,
+    refresh_margin: Duration,
+    cache: Arc<Mutex<Option<CachedToken>>>,
+}
+
+impl<F> fmt::Debug for JwtSource<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtSource")
+            .field("refresh_margin", &self.refresh_margin)
+            .finish()
+    }
+}
+
+impl<F, Fut> JwtSource<F>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+{
+    /// Creates a token source calling `mint` for new tokens.
+    ///
+    /// Tokens are refreshed 30 seconds before expiry by default.
+    pub fn new(mint: F) -> Self {
+        JwtSource {
+            mint,
+            refresh_margin: Duration::from_secs(30),
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets how long before expiry a token is refreshed.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Returns the cached token, minting a new one if it is close to expiry.
+    pub async fn jwt(&self) -> String {
+        let mut cache = self.cache.lock().await;
+        if let Some(cached) = &*cache {
+            let fresh = cached
+                .expires_at
+                .is_none_or(|at| at > SystemTime::now() + self.refresh_margin);
+            if fresh {
+                return cached.token.to_string();
+            }
+        }
+
+        let token = (self.mint)().await;
+        *cache = Some(CachedToken {
+            expires_at: expiry(&token),
+            token: Zeroizing::new(token.clone()),
+        });
+        token
+    }
+
+    /// Discards the cached token so the next call mints a new one.
+    pub async fn invalidate(&self) {
+        *self.cache.lock().await = None;
+    }
+}
+
+impl<F, Fut> TokenSource for JwtSource<F>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = String> + Send,
+{
+    fn token(&self) -> BoxFuture<'_, Result<String, BoxError>> {
+        Box::pin(async move { Ok(self.jwt().await) })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move { *self.cache.lock().await = None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    /// An unsigned token expiring at `exp`, if any.
+    fn token(exp: Option<u64>) -> String {
+        let claims = match exp {
+            Some(exp) => format!(r#"{{"exp":{}}}"#, exp),
+            None => "{}".to_owned(),
+        };
+        let claims = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+        format!("eyJhbGciOiJub25lIn0.{}.", claims)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn reads_the_expiry() {
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(expiry(&token(Some(1_700_000_000))), Some(at));
+        assert_eq!(expiry(&token(None)), None);
+        assert_eq!(expiry(&token(Some(u64::MAX))), None);
+        assert_eq!(expiry("not a jwt"), None);
+    }
+
+    #[tokio::test]
+    async fn mints_again_near_expiry() {
+        let minted = Arc::new(AtomicU64::new(0));
+        let counter = minted.clone();
+        let source = JwtSource::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            // Expires within the refresh margin
+            async { token(Some(now() + 10)) }
+        });
+        source.jwt().await;
+        source.jwt().await;
+        assert_eq!(minted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reuses_fresh_tokens_until_invalidated() {
+        let minted = Arc::new(AtomicU64::new(0));
+        let counter = minted.clone();
+        let source = JwtSource::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { token(None) }
+        });
+        assert_eq!(source.jwt().await, source.jwt().await);
+        assert_eq!(minted.load(Ordering::SeqCst), 1);
+        source.invalidate().await;
+        source.jwt().await;
+        assert_eq!(minted.load(Ordering::SeqCst), 2);
+    }
+}
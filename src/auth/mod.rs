@@ -0,0 +1,80 @@
+#[cfg(feature = "hmac")]
+pub mod hmac;
+pub mod jwt;
+#[cfg(all(
+    feature = "oauth2",
+    any(feature = "tls-native", feature = "tls-rustls")
+))]
+pub mod oauth2;
+#[cfg(feature = "sigv4")]
+pub mod sigv4;
+
+use std::error;
+
+use futures_core::future::BoxFuture;
+use hyper::{
+    header::{HeaderValue, InvalidHeaderValue},
+    http::request::Parts,
+};
+use zeroize::Zeroizing;
+
+/// A boxed error returned by a [`TokenSource`].
+pub type BoxError = Box<dyn error::Error + Send + Sync>;
+
+/// A source of bearer tokens, queried by the client before each call.
+///
+/// Implementations are expected to cache tokens and only fetch new ones when needed.
+pub trait TokenSource: Send + Sync + 'static {
+    /// Returns a valid bearer token.
+    fn token(&self) -> BoxFuture<'_, Result<String, BoxError>>;
+
+    /// Discards the cached token after the server rejected it, so the next one is fetched anew.
+    ///
+    /// This does nothing by default.
+    fn invalidate(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Signs outgoing HTTP requests once the body has been serialized.
+pub trait Signer: Send + Sync + 'static {
+    /// Sign the request, typically by adding headers, given its serialized body.
+    fn sign<'a>(
+        &'a self,
+        request: &'a mut Parts,
+        body: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), BoxError>>;
+}
+
+/// Build a sensitive `Authorization` value for HTTP basic authentication.
+///
+/// The intermediate buffers holding the credentials are zeroized.
+pub(crate) fn basic_auth(user: &str, password: &str) -> HeaderValue {
+    let credentials = Zeroizing::new(format!("{}:{}", user, password));
+    let encoded = Zeroizing::new(base64::encode(credentials.as_bytes()));
+    let value = Zeroizing::new(format!("Basic {}", encoded.as_str()));
+    let mut value = HeaderValue::from_str(&value).unwrap(); // This is safe
+    value.set_sensitive(true);
+    value
+}
+
+/// Build a sensitive `Authorization` value for a bearer token.
+pub(crate) fn bearer_auth(token: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+    let value = Zeroizing::new(format!("Bearer {}", token));
+    let mut value = HeaderValue::from_str(&value)?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+/// Hex encode `bytes` in lowercase.
+#[cfg(any(feature = "hmac", feature = "sigv4"))]
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes
+        .iter()
+        .fold(String::with_capacity(2 * bytes.len()), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
@@ -0,0 +1,337 @@
+use std::{error, fmt, sync::Arc, time::Duration};
+
+use futures_core::future::BoxFuture;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::{
+    body::Bytes,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Request as HttpRequest, StatusCode,
+};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time::Instant};
+use zeroize::Zeroizing;
+
+use super::{basic_auth, BoxError, TokenSource};
+use crate::clients::{
+    http::{hyper_client, Body, HyperClient},
+    tls::{Connector, TlsConfig},
+};
+
+/// The lifetime assumed for tokens issued without `expires_in`.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// The time allowed for a token request by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest token response accepted by default, in bytes.
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Error fetching an OAuth2 access token.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The token request failed.
+    Http(BoxError),
+    /// The token endpoint responded with a non-success status.
+    Status(StatusCode, String),
+    /// The token response could not be deserialized.
+    Json(serde_json::Error),
+    /// The token endpoint didn't respond in time.
+    Timeout,
+    /// The token response exceeded the maximum size.
+    TooLarge {
+        /// The maximum size in bytes.
+        limit: usize,
+    },
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(_) => write!(f, "token request failed"),
+            Self::Status(status, _) => write!(f, "token endpoint error, {}", status),
+            Self::Json(_) => write!(f, "invalid token response"),
+            Self::Timeout => write!(f, "token request timed out"),
+            Self::TooLarge { limit } => write!(f, "token response exceeds {} bytes", limit),
+        }
+    }
+}
+
+// The wrapped errors are reported as sources rather than displayed
+impl error::Error for TokenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Http(err) => Some(&**err),
+            Self::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Zeroizing<String>,
+    expires_in: Option<u64>,
+}
+
+struct CachedToken {
+    access_token: Zeroizing<String>,
+    expires_at: Instant,
+}
+
+/// Fetches and caches access tokens using the OAuth2 client credentials grant.
+///
+/// Tokens are refreshed when they are within the refresh margin of expiring, 30 seconds by
+/// default. Register it on a client using [`Client::with_token_source`]. The `oauth2` feature
+/// requires a TLS feature, `tls-native` or `tls-rustls`, to fetch the tokens.
+///
+/// [`Client::with_token_source`]: crate::clients::http::Client::with_token_source
+#[derive(Clone)]
+pub struct ClientCredentials {
+    http: HyperClient<Connector>,
+    token_url: String,
+    client_id: String,
+    client_secret: Zeroizing<String>,
+    scope: Option<String>,
+    refresh_margin: Duration,
+    timeout: Duration,
+    max_response_size: usize,
+    cache: Arc<Mutex<Option<CachedToken>>>,
+}
+
+impl fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .field("refresh_margin", &self.refresh_margin)
+            .field("timeout", &self.timeout)
+            .field("max_response_size", &self.max_response_size)
+            .finish()
+    }
+}
+
+impl ClientCredentials {
+    /// Creates a token source requesting tokens from `token_url`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the TLS backend could not be initialized.
+    pub fn new<U, I, S>(token_url: U, client_id: I, client_secret: S) -> Self
+    where
+        U: Into<String>,
+        I: Into<String>,
+        S: Into<String>,
+    {
+        let https = TlsConfig::new()
+            .connector()
+            .unwrap_or_else(|err| panic!("failed to initialize tls, {}", err));
+        ClientCredentials {
+            http: hyper_client(https),
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: Zeroizing::new(client_secret.into()),
+            scope: None,
+            refresh_margin: Duration::from_secs(30),
+            timeout: DEFAULT_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sets the space-delimited scopes requested.
+    pub fn scope<S: Into<String>>(mut self, scope: S) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Sets how long before expiry a token is refreshed.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Sets the time allowed for a token request, response body included, 30 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the largest token response accepted, 64 KiB by default.
+    pub fn max_response_size(mut self, limit: usize) -> Self {
+        self.max_response_size = limit;
+        self
+    }
+
+    /// Returns a cached access token, fetching a new one if it is close to expiry.
+    pub async fn access_token(&self) -> Result<String, TokenError> {
+        let mut cache = self.cache.lock().await;
+        if let Some(cached) = &*cache {
+            if cached.expires_at > Instant::now() + self.refresh_margin {
+                return Ok(cached.access_token.to_string());
+            }
+        }
+
+        let token = self.fetch().await?;
+        let now = Instant::now();
+        // The lifetime is chosen by the endpoint, it mustn't overflow the clock
+        let expires_at = token
+            .expires_in
+            .and_then(|secs| now.checked_add(Duration::from_secs(secs)))
+            .unwrap_or(now + DEFAULT_LIFETIME);
+        *cache = Some(CachedToken {
+            access_token: token.access_token.clone(),
+            expires_at,
+        });
+        Ok(token.access_token.to_string())
+    }
+
+    /// Discards the cached token so the next call fetches a new one.
+    pub async fn invalidate(&self) {
+        *self.cache.lock().await = None;
+    }
+
+    /// Request a new token from the token endpoint.
+    async fn fetch(&self) -> Result<TokenResponse, TokenError> {
+        let body = {
+            let mut form = form_urlencoded::Serializer::new(String::new());
+            form.append_pair("grant_type", "client_credentials");
+            if let Some(scope) = &self.scope {
+                form.append_pair("scope", scope);
+            }
+            form.finish()
+        };
+
+        // Client authentication as described in RFC 6749, section 2.3.1
+        let client_id =
+            form_urlencoded::byte_serialize(self.client_id.as_bytes()).collect::<String>();
+        let client_secret = Zeroizing::new(
+            form_urlencoded::byte_serialize(self.client_secret.as_bytes()).collect::<String>(),
+        );
+        let request = HttpRequest::post(&self.token_url)
+            .header(AUTHORIZATION, basic_auth(&client_id, &client_secret))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(Bytes::from(body)))
+            .unwrap(); // This is safe
+
+        let limit = self.max_response_size;
+        let exchange = async {
+            let response = self
+                .http
+                .request(request)
+                .await
+                .map_err(|err| TokenError::Http(err.into()))?;
+            let status = response.status();
+            let body = Limited::new(response.into_body(), limit)
+                .collect()
+                .await
+                .map_err(|err| match err.is::<LengthLimitError>() {
+                    true => TokenError::TooLarge { limit },
+                    false => TokenError::Http(err),
+                })?
+                .to_bytes();
+            Ok((status, body))
+        };
+        let (status, body) = tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| TokenError::Timeout)??;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(TokenError::Status(status, body));
+        }
+        serde_json::from_slice(&body).map_err(TokenError::Json)
+    }
+}
+
+impl TokenSource for ClientCredentials {
+    fn token(&self) -> BoxFuture<'_, Result<String, BoxError>> {
+        Box::pin(async move { Ok(self.access_token().await?) })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, ()> {
+        Box::pin(ClientCredentials::invalidate(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Serves a single token request with `response`, or never responds if `None`.
+    async fn endpoint(response: Option<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await.unwrap();
+            match response {
+                Some(response) => stream.write_all(response.as_bytes()).await.unwrap(),
+                None => std::future::pending().await,
+            }
+        });
+        url
+    }
+
+    fn ok(body: &str) -> Option<String> {
+        Some(format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+    }
+
+    #[tokio::test]
+    async fn fetches_and_caches_tokens() {
+        let url = endpoint(ok(r#"{"access_token":"secret","expires_in":600}"#)).await;
+        let source = ClientCredentials::new(url, "id", "password");
+        assert_eq!(source.access_token().await.unwrap(), "secret");
+        // The endpoint only answers once
+        assert_eq!(source.access_token().await.unwrap(), "secret");
+    }
+
+    #[tokio::test]
+    async fn accepts_huge_lifetimes() {
+        let body = format!(r#"{{"access_token":"secret","expires_in":{}}}"#, u64::MAX);
+        let url = endpoint(ok(&body)).await;
+        let source = ClientCredentials::new(url, "id", "password");
+        assert_eq!(source.access_token().await.unwrap(), "secret");
+        assert_eq!(source.access_token().await.unwrap(), "secret");
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let url = endpoint(None).await;
+        let source =
+            ClientCredentials::new(url, "id", "password").timeout(Duration::from_millis(50));
+        assert!(matches!(
+            source.access_token().await,
+            Err(TokenError::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn limits_the_response_size() {
+        let token = "x".repeat(1024);
+        let url = endpoint(ok(&format!(r#"{{"access_token":"{}"}}"#, token))).await;
+        let source = ClientCredentials::new(url, "id", "password").max_response_size(512);
+        let err = source.access_token().await.unwrap_err();
+        assert!(matches!(err, TokenError::TooLarge { limit: 512 }));
+    }
+
+    #[tokio::test]
+    async fn reports_the_cause() {
+        let url = endpoint(ok("{")).await;
+        let source = ClientCredentials::new(url, "id", "password");
+        let err = source.access_token().await.unwrap_err();
+        assert_eq!(err.to_string(), "invalid token response");
+        assert!(err.source().unwrap().is::<serde_json::Error>());
+    }
+}
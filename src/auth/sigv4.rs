@@ -0,0 +1,486 @@
+use std::{
+    env, fmt, fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_core::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use hyper::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, HOST},
+    http::request::Parts,
+};
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use zeroize::Zeroizing;
+
+use super::{hex, BoxError, Signer};
+use crate::clock::{Clock, SharedClock};
+
+/// How long the [`DefaultCredentialsChain`] reuses credentials by default.
+const CREDENTIALS_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// AWS access credentials.
+///
+/// The secret access key and session token are zeroed once dropped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: Zeroizing<String>,
+    pub session_token: Option<Zeroizing<String>>,
+}
+
+impl fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish()
+    }
+}
+
+/// A source of [`AwsCredentials`].
+pub trait ProvideCredentials: Send + Sync + 'static {
+    /// Returns the credentials to sign the next request with.
+    fn credentials(&self) -> BoxFuture<'_, Result<AwsCredentials, BoxError>>;
+}
+
+impl ProvideCredentials for AwsCredentials {
+    fn credentials(&self) -> BoxFuture<'_, Result<AwsCredentials, BoxError>> {
+        Box::pin(async move { Ok(self.clone()) })
+    }
+}
+
+/// No credentials were found by the [`DefaultCredentialsChain`].
+#[derive(Debug)]
+pub struct CredentialsNotFound;
+
+impl fmt::Display for CredentialsNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no aws credentials found")
+    }
+}
+
+impl std::error::Error for CredentialsNotFound {}
+
+/// Resolves credentials from the standard locations, again once they are older than its TTL.
+///
+/// The `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables
+/// are tried first, followed by the shared credentials file, `~/.aws/credentials` or
+/// `AWS_SHARED_CREDENTIALS_FILE`, using the `AWS_PROFILE` profile or `default`.
+///
+/// Sources requiring network access, such as instance metadata, are not supported; implement
+/// [`ProvideCredentials`] to use them. Credentials are resolved again every 5 minutes by default,
+/// picking up rotated keys and session tokens.
+#[derive(Debug)]
+pub struct DefaultCredentialsChain {
+    // The credentials last resolved, and when
+    resolved: Mutex<Option<(AwsCredentials, Instant)>>,
+    ttl: Duration,
+    clock: SharedClock,
+}
+
+impl Default for DefaultCredentialsChain {
+    fn default() -> Self {
+        DefaultCredentialsChain {
+            resolved: Mutex::new(None),
+            ttl: CREDENTIALS_TTL,
+            clock: SharedClock::default(),
+        }
+    }
+}
+
+impl DefaultCredentialsChain {
+    /// Creates a chain reusing credentials for 5 minutes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long resolved credentials are reused before resolving them again.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the clock timing the TTL, [`TokioClock`](crate::clock::TokioClock) by default.
+    pub fn clock<K: Clock>(mut self, clock: K) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    fn from_env() -> Option<AwsCredentials> {
+        Some(AwsCredentials {
+            access_key_id: env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: Zeroizing::new(env::var("AWS_SECRET_ACCESS_KEY").ok()?),
+            session_token: env::var("AWS_SESSION_TOKEN").ok().map(Zeroizing::new),
+        })
+    }
+
+    fn from_profile() -> Option<AwsCredentials> {
+        let path = match env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var_os("HOME")?).join(".aws/credentials"),
+        };
+        let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+        let contents = Zeroizing::new(fs::read_to_string(path).ok()?);
+
+        let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+        let mut in_profile = false;
+        for line in contents.lines().map(str::trim) {
+            if line.starts_with('[') && line.ends_with(']') {
+                in_profile = line[1..line.len() - 1].trim() == profile;
+                continue;
+            }
+            if !in_profile {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = Some(Zeroizing::new(value.trim().to_string()));
+                match key.trim() {
+                    "aws_access_key_id" => access_key_id = value,
+                    "aws_secret_access_key" => secret_access_key = value,
+                    "aws_session_token" => session_token = value,
+                    _ => (),
+                }
+            }
+        }
+        Some(AwsCredentials {
+            access_key_id: access_key_id?.to_string(),
+            secret_access_key: secret_access_key?,
+            session_token,
+        })
+    }
+}
+
+impl ProvideCredentials for DefaultCredentialsChain {
+    fn credentials(&self) -> BoxFuture<'_, Result<AwsCredentials, BoxError>> {
+        Box::pin(async move {
+            let now = self.clock.now();
+            let mut resolved = self.resolved.lock().unwrap();
+            if let Some((credentials, at)) = &*resolved {
+                if now < *at + self.ttl {
+                    return Ok(credentials.clone());
+                }
+            }
+            let credentials = Self::from_env()
+                .or_else(Self::from_profile)
+                .ok_or(CredentialsNotFound)?;
+            *resolved = Some((credentials.clone(), now));
+            Ok(credentials)
+        })
+    }
+}
+
+/// Signs requests using AWS Signature Version 4.
+///
+/// Credentials come from the [`DefaultCredentialsChain`] unless another provider is given.
+/// Register it on a client using [`Client::with_signer`].
+///
+/// [`Client::with_signer`]: crate::clients::http::Client::with_signer
+pub struct SigV4Signer {
+    region: String,
+    service: String,
+    provider: Box<dyn ProvideCredentials>,
+}
+
+impl fmt::Debug for SigV4Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigV4Signer")
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+impl SigV4Signer {
+    /// Creates a signer for `service`, e.g. `execute-api`, in `region`.
+    pub fn new<R: Into<String>, S: Into<String>>(region: R, service: S) -> Self {
+        SigV4Signer {
+            region: region.into(),
+            service: service.into(),
+            provider: Box::new(DefaultCredentialsChain::default()),
+        }
+    }
+
+    /// Sets the credentials provider.
+    pub fn credentials<P: ProvideCredentials>(mut self, provider: P) -> Self {
+        self.provider = Box::new(provider);
+        self
+    }
+
+    /// Sign the request at `time`.
+    pub fn sign_at(
+        &self,
+        request: &mut Parts,
+        body: &[u8],
+        credentials: &AwsCredentials,
+        time: SystemTime,
+    ) {
+        let (date, timestamp) = format_time(time);
+        let payload_hash = hex(&Sha256::digest(body));
+
+        let headers = &mut request.headers;
+        if let Some(authority) = request.uri.authority() {
+            let host = HeaderValue::from_str(authority.as_str()).unwrap(); // This is safe
+            headers.insert(HOST, host);
+        }
+        headers.insert(
+            HeaderName::from_static("x-amz-date"),
+            HeaderValue::from_str(&timestamp).unwrap(), // This is safe
+        );
+        headers.insert(
+            HeaderName::from_static("x-amz-content-sha256"),
+            HeaderValue::from_str(&payload_hash).unwrap(), // This is safe
+        );
+        if let Some(session_token) = &credentials.session_token {
+            if let Ok(mut value) = HeaderValue::from_str(session_token) {
+                value.set_sensitive(true);
+                headers.insert(HeaderName::from_static("x-amz-security-token"), value);
+            }
+        }
+
+        // Canonical request
+        let mut signed: Vec<(&str, String)> = headers
+            .iter()
+            .filter(|(name, _)| {
+                *name == HOST || *name == "content-type" || name.as_str().starts_with("x-amz-")
+            })
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                (
+                    name.as_str(),
+                    value.split_whitespace().collect::<Vec<_>>().join(" "),
+                )
+            })
+            .collect();
+        signed.sort();
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = signed
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let mut query: Vec<&str> = request
+            .uri
+            .query()
+            .map(|query| query.split('&').filter(|pair| !pair.is_empty()).collect())
+            .unwrap_or_default();
+        query.sort_unstable();
+        let path = match request.uri.path() {
+            "" => "/",
+            path => path,
+        };
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            request.method,
+            uri_encode(path),
+            query.join("&"),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+
+        // String to sign
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        // Signature
+        let key = signing_key(
+            &credentials.secret_access_key,
+            &date,
+            &self.region,
+            &self.service,
+        );
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        );
+        if let Ok(mut value) = HeaderValue::from_str(&authorization) {
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+    }
+}
+
+impl Signer for SigV4Signer {
+    fn sign<'a>(
+        &'a self,
+        request: &'a mut Parts,
+        body: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), BoxError>> {
+        Box::pin(async move {
+            let credentials = self.provider.credentials().await?;
+            self.sign_at(request, body, &credentials, SystemTime::now());
+            Ok(())
+        })
+    }
+}
+
+/// Derive the key signing the requests of `date`, `region` and `service`.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Zeroizing<Vec<u8>> {
+    let secret = Zeroizing::new(format!("AWS4{}", secret));
+    let key = hmac_sha256(secret.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// The MAC of `data`, zeroed once dropped as it keys the next step of the signing key.
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    Zeroizing::new(mac.finalize().into_bytes().to_vec())
+}
+
+/// Encode the path a second time, as required for services other than S3.
+fn uri_encode(path: &str) -> String {
+    path.bytes().fold(String::new(), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+        encoded
+    })
+}
+
+/// Format `time` as the `YYYYMMDD` date and `YYYYMMDDTHHMMSSZ` timestamp, in UTC.
+fn format_time(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    );
+    (date, timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request as HttpRequest;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: Zeroizing::new(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            ),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn derives_the_signing_key() {
+        // The example of the AWS documentation
+        let key = signing_key(
+            &credentials().secret_access_key,
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn signs_requests() {
+        let (mut parts, _) = HttpRequest::post("https://example.amazonaws.com/")
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body = br#"{"jsonrpc":"2.0","method":"ping","id":0}"#;
+        let time = UNIX_EPOCH + Duration::from_secs(1_440_938_160);
+        SigV4Signer::new("us-east-1", "execute-api").sign_at(
+            &mut parts,
+            body,
+            &credentials(),
+            time,
+        );
+
+        assert_eq!(parts.headers["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            parts.headers[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/execute-api/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date, \
+             Signature=a039b89f6ebdabaf3b6186be07c1080a3f077fbc2fb4eaa9d240b53cca8962bc"
+        );
+        assert!(parts.headers[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn sends_the_session_token() {
+        let (mut parts, _) = HttpRequest::post("https://example.amazonaws.com/")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let credentials = AwsCredentials {
+            session_token: Some(Zeroizing::new("session".to_owned())),
+            ..credentials()
+        };
+        let signer = SigV4Signer::new("us-east-1", "execute-api");
+        signer.sign_at(&mut parts, b"", &credentials, SystemTime::now());
+        assert_eq!(parts.headers["x-amz-security-token"], "session");
+        let authorization = parts.headers[AUTHORIZATION].to_str().unwrap();
+        assert!(authorization.contains("x-amz-security-token"));
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        let debug = format!("{:?}", credentials());
+        assert!(!debug.contains("EXAMPLEKEY"));
+    }
+
+    #[tokio::test]
+    async fn resolves_credentials_again_after_the_ttl() {
+        // The only test reading the environment
+        env::set_var("AWS_ACCESS_KEY_ID", "first");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        let clock = ManualClock::new();
+        let chain = DefaultCredentialsChain::new()
+            .ttl(Duration::from_secs(60))
+            .clock(clock.clone());
+        assert_eq!(chain.credentials().await.unwrap().access_key_id, "first");
+
+        env::set_var("AWS_ACCESS_KEY_ID", "rotated");
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(chain.credentials().await.unwrap().access_key_id, "first");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(chain.credentials().await.unwrap().access_key_id, "rotated");
+    }
+}
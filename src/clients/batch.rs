@@ -0,0 +1,242 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use serde::de::Error as _;
+
+use super::Error;
+use crate::objects::Response;
+
+/// Where the scanner is within the batch array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Before the opening bracket.
+    Start,
+    /// After the opening bracket, before the first response.
+    First,
+    /// After a comma, before the next response.
+    Next,
+    /// Within a response, `depth` brackets deep.
+    Element {
+        depth: usize,
+        string: bool,
+        escaped: bool,
+    },
+    /// After a response, before a comma or the closing bracket.
+    After,
+    /// After the closing bracket.
+    Done,
+}
+
+/// Parses a JSON-RPC batch response incrementally, yielding each [`Response`] once it is complete.
+///
+/// Only the response being parsed is buffered, rather than the entire body. Any stream of
+/// [`Bytes`] can be parsed, such as a [`Body`] read using [`BodyDataStream`], see
+/// [`Client::send_raw_batch_stream`]. A single error object in place of the array, as servers
+/// send when the batch couldn't be parsed, is yielded as the only response.
+///
+/// [`Body`]: super::http::Body
+/// [`BodyDataStream`]: http_body_util::BodyDataStream
+/// [`Client::send_raw_batch_stream`]: super::http::Client::send_raw_batch_stream
+#[derive(Debug)]
+pub struct BatchStream<B> {
+    body: B,
+    buffer: BytesMut,
+    // The number of buffered bytes scanned
+    scanned: usize,
+    state: State,
+    // Whether the body is a single response rather than an array
+    single: bool,
+    failed: bool,
+}
+
+impl<B> BatchStream<B> {
+    /// Parses the batch response `body`.
+    pub fn new(body: B) -> Self {
+        BatchStream {
+            body,
+            buffer: BytesMut::new(),
+            scanned: 0,
+            state: State::Start,
+            single: false,
+            failed: false,
+        }
+    }
+
+    /// Scan the buffered bytes, returning the next complete response, if any.
+    fn next_element(&mut self) -> Result<Option<Bytes>, serde_json::Error> {
+        while self.scanned < self.buffer.len() {
+            let byte = self.buffer[self.scanned];
+            if let State::Element {
+                depth,
+                string,
+                escaped,
+            } = &mut self.state
+            {
+                self.scanned += 1;
+                if *string {
+                    *string = *escaped || byte != b'"';
+                    *escaped = !*escaped && byte == b'\\';
+                    continue;
+                }
+                match byte {
+                    b'"' => *string = true,
+                    b'{' | b'[' => *depth += 1,
+                    b'}' | b']' => *depth -= 1,
+                    _ => {}
+                }
+                if *depth == 0 {
+                    let element = self.buffer.split_to(self.scanned).freeze();
+                    self.scanned = 0;
+                    self.state = match self.single {
+                        true => State::Done,
+                        false => State::After,
+                    };
+                    return Ok(Some(element));
+                }
+                continue;
+            }
+
+            self.state = match self.state {
+                _ if byte.is_ascii_whitespace() => self.state,
+                State::Start if byte == b'[' => State::First,
+                State::First if byte == b']' => State::Done,
+                State::After if byte == b',' => State::Next,
+                State::After if byte == b']' => State::Done,
+                State::Start | State::First | State::Next if byte == b'{' => {
+                    self.single = self.state == State::Start;
+                    // Start the response at the front of the buffer, with its brace unscanned
+                    self.buffer.advance(self.scanned);
+                    self.scanned = 0;
+                    self.state = State::Element {
+                        depth: 0,
+                        string: false,
+                        escaped: false,
+                    };
+                    continue;
+                }
+                _ => {
+                    return Err(serde_json::Error::custom(format!(
+                        "unexpected byte {:?} in batch response",
+                        byte as char
+                    )))
+                }
+            };
+            self.scanned += 1;
+        }
+
+        // Discard the whitespace and separators scanned
+        if !matches!(self.state, State::Element { .. }) {
+            self.buffer.advance(self.scanned);
+            self.scanned = 0;
+        }
+        Ok(None)
+    }
+}
+
+impl<B, E> Stream for BatchStream<B>
+where
+    B: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Response, Error<E>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.failed {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            let result = match this.next_element() {
+                Ok(Some(element)) => serde_json::from_slice(&element).map_err(Error::Json),
+                Ok(None) => match Pin::new(&mut this.body).poll_next(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        this.buffer.extend_from_slice(&chunk);
+                        continue;
+                    }
+                    Poll::Ready(Some(Err(err))) => Err(Error::Connection(err)),
+                    Poll::Ready(None) if this.state == State::Done => return Poll::Ready(None),
+                    Poll::Ready(None) => Err(Error::Json(serde_json::Error::custom(
+                        "batch response ended unexpectedly",
+                    ))),
+                    Poll::Pending => return Poll::Pending,
+                },
+                Err(err) => Err(Error::Json(err)),
+            };
+            this.failed = result.is_err();
+            return Poll::Ready(Some(result));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures_util::{stream, StreamExt};
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Parse the batch response sent in `chunks`.
+    async fn parse(chunks: &[&'static str]) -> Vec<Result<Response, Error<Infallible>>> {
+        let chunks = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())));
+        BatchStream::new(stream::iter(chunks)).collect().await
+    }
+
+    #[tokio::test]
+    async fn parses_responses_split_across_chunks() {
+        let responses = parse(&[
+            " [{\"jsonrpc\":\"2.0\",\"result\":\"a]\\\"}",
+            "\",\"id\":1} , {\"jsonrpc\":\"2.0\",\"res",
+            "ult\":[{}],\"id\":2}]\n",
+        ])
+        .await;
+        let responses: Vec<_> = responses.into_iter().map(Result::unwrap).collect();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].result, Some(Value::from("a]\"}")));
+        assert_eq!(responses[0].id, 1);
+        assert_eq!(responses[1].result, Some(serde_json::json!([{}])));
+        assert_eq!(responses[1].id, 2);
+    }
+
+    #[tokio::test]
+    async fn parses_empty_batches() {
+        assert!(parse(&["[", " ]"]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn yields_a_single_error_object() {
+        let error =
+            r#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"Invalid Request"},"id":null}"#;
+        let responses = parse(&[error, "\n"]).await;
+        assert_eq!(responses.len(), 1);
+        let response = responses.into_iter().next().unwrap().unwrap();
+        assert_eq!(response.error.unwrap().code, -32600);
+    }
+
+    #[tokio::test]
+    async fn fails_on_malformed_batches() {
+        let responses = parse(&["[{\"jsonrpc\":\"2.0\",\"result\":1,\"id\":1} 2]"]).await;
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].is_ok());
+        assert!(matches!(responses[1], Err(Error::Json(_))));
+
+        // Nothing follows a single response
+        let responses = parse(&["{\"jsonrpc\":\"2.0\",\"result\":1,\"id\":1} {"]).await;
+        assert!(matches!(responses[1], Err(Error::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_batches() {
+        let responses = parse(&["[{\"jsonrpc\":\"2.0\",\"result\":1,\"id\":1},", "{\"id\""]).await;
+        assert_eq!(responses.len(), 2);
+        let err = responses[1].as_ref().unwrap_err();
+        assert!(err.to_string().contains("ended unexpectedly"), "{}", err);
+    }
+}
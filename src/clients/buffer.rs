@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+
+use bytes::{Bytes, BytesMut};
+
+/// The number of idle buffers kept.
+const MAX_IDLE: usize = 16;
+
+/// The capacity reserved before each write, sufficient for most requests.
+const RESERVE: usize = 1024;
+
+/// Serialization buffers reused across calls.
+///
+/// Each write splits the bytes written off a pooled buffer. Once hyper drops them, the next write
+/// to that buffer reclaims the allocation rather than making a new one.
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool(Mutex<Vec<BytesMut>>);
+
+impl BufferPool {
+    /// Write into a pooled buffer, returning the bytes written.
+    ///
+    /// If writing fails, the bytes written are discarded.
+    pub(crate) fn write<F, E>(&self, write: F) -> Result<Bytes, E>
+    where
+        F: FnOnce(&mut BytesMut) -> Result<(), E>,
+    {
+        let mut buffer = self.0.lock().unwrap().pop().unwrap_or_default();
+        buffer.reserve(RESERVE);
+        let result = write(&mut buffer);
+
+        let bytes = buffer.split().freeze();
+        let mut idle = self.0.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(buffer);
+        }
+        result.map(|()| bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use bytes::BufMut;
+
+    use super::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let pool = BufferPool::default();
+        let write = |bytes: &'static [u8]| {
+            let written = pool.write(|buffer| {
+                buffer.put_slice(bytes);
+                Ok::<_, ()>(())
+            });
+            written.unwrap()
+        };
+        let first = write(b"first");
+        assert_eq!(first, "first");
+        let allocation = first.as_ptr();
+
+        // Once released, the allocation is reclaimed
+        drop(first);
+        let second = write(b"second");
+        assert_eq!(second, "second");
+        assert_eq!(second.as_ptr(), allocation);
+
+        // But not while it's still in use
+        let third = write(b"third");
+        assert_eq!(third, "third");
+        assert_ne!(third.as_ptr(), allocation);
+    }
+
+    #[test]
+    fn discards_failed_writes() {
+        let pool = BufferPool::default();
+        let failed = pool.write(|buffer| {
+            buffer.put_slice(b"partial");
+            Err(io::Error::other("failed"))
+        });
+        assert!(failed.is_err());
+        let written = pool.write(|buffer| {
+            buffer.put_slice(b"whole");
+            Ok::<_, ()>(())
+        });
+        assert_eq!(written.unwrap(), "whole");
+    }
+}
@@ -0,0 +1,172 @@
+use std::io;
+
+use hyper::{
+    body::Bytes,
+    header::{HeaderMap, HeaderValue, CONTENT_ENCODING},
+};
+
+/// The `Accept-Encoding` header listing the enabled encodings, if any.
+pub(crate) fn accept_encoding() -> Option<HeaderValue> {
+    let encodings: &[&str] = &[
+        #[cfg(feature = "gzip")]
+        "gzip",
+        #[cfg(feature = "gzip")]
+        "deflate",
+        #[cfg(feature = "brotli")]
+        "br",
+    ];
+    if encodings.is_empty() {
+        None
+    } else {
+        Some(HeaderValue::from_str(&encodings.join(", ")).unwrap()) // This is safe
+    }
+}
+
+/// Compress a request body using gzip.
+#[cfg(feature = "gzip")]
+pub(crate) fn gzip(body: &[u8]) -> Bytes {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a `Vec` can't fail
+    encoder.write_all(body).unwrap();
+    Bytes::from(encoder.finish().unwrap())
+}
+
+/// Decompress a response body according to its `Content-Encoding` headers.
+///
+/// Decompression stops just after `limit` bytes, leaving the caller to reject the body.
+pub(crate) fn decompress(
+    headers: &HeaderMap,
+    mut body: Bytes,
+    limit: Option<usize>,
+) -> io::Result<Bytes> {
+    let mut encodings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value
+            .to_str()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        encodings.extend(
+            value
+                .split(',')
+                .map(|encoding| encoding.trim().to_ascii_lowercase())
+                .filter(|encoding| !encoding.is_empty() && encoding != "identity"),
+        );
+    }
+
+    // Encodings are listed in the order they were applied
+    for encoding in encodings.into_iter().rev() {
+        body = decode(&encoding, &body, limit)?;
+    }
+    Ok(body)
+}
+
+fn decode(encoding: &str, body: &[u8], limit: Option<usize>) -> io::Result<Bytes> {
+    match encoding {
+        #[cfg(feature = "gzip")]
+        "gzip" | "x-gzip" => read_all(flate2::read::GzDecoder::new(body), limit),
+        #[cfg(feature = "gzip")]
+        "deflate" => read_all(flate2::read::ZlibDecoder::new(body), limit),
+        #[cfg(feature = "brotli")]
+        "br" => read_all(brotli_decompressor::Decompressor::new(body, 4096), limit),
+        _ => {
+            let _ = (body, limit);
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported content encoding {}", encoding),
+            ))
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+fn read_all<R: io::Read>(reader: R, limit: Option<usize>) -> io::Result<Bytes> {
+    use std::io::Read;
+
+    let limit = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+    let mut decoded = Vec::new();
+    reader.take(limit).read_to_end(&mut decoded)?;
+    Ok(Bytes::from(decoded))
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn headers(encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        headers
+    }
+
+    fn deflate(body: &[u8]) -> Bytes {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    #[test]
+    fn passes_through_identity() {
+        let body = Bytes::from_static(b"{}");
+        let decoded = decompress(&HeaderMap::new(), body.clone(), Some(1)).unwrap();
+        assert_eq!(decoded, body);
+        let decoded = decompress(&headers("identity"), body.clone(), None).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn round_trips_gzip() {
+        let body = br#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+        let decoded = decompress(&headers("gzip"), gzip(body), None).unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+        let decoded = decompress(&headers("X-Gzip"), gzip(body), None).unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+    }
+
+    #[test]
+    fn round_trips_deflate() {
+        let body = br#"{"jsonrpc":"2.0","result":true,"id":1}"#;
+        let decoded = decompress(&headers("deflate"), deflate(body), None).unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+    }
+
+    #[test]
+    fn undoes_encodings_in_reverse_order() {
+        let body = b"layered";
+        let encoded = gzip(&deflate(body));
+        let decoded = decompress(&headers("deflate, gzip"), encoded.clone(), None).unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+
+        let mut split = HeaderMap::new();
+        split.append(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+        split.append(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let decoded = decompress(&split, encoded, None).unwrap();
+        assert_eq!(&decoded[..], &body[..]);
+    }
+
+    #[test]
+    fn stops_just_after_the_limit() {
+        let body = vec![b'a'; 1024];
+        let decoded = decompress(&headers("gzip"), gzip(&body), Some(100)).unwrap();
+        assert_eq!(decoded.len(), 101);
+        let decoded = decompress(&headers("gzip"), gzip(&body), Some(1024)).unwrap();
+        assert_eq!(decoded.len(), 1024);
+    }
+
+    #[test]
+    fn rejects_unsupported_and_corrupt_bodies() {
+        let err = decompress(&headers("compress"), Bytes::from_static(b"{}"), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decompress(&headers("gzip"), Bytes::from_static(b"{}"), None).is_err());
+    }
+
+    #[test]
+    fn lists_accepted_encodings() {
+        let accepted = accept_encoding().unwrap();
+        assert!(accepted.to_str().unwrap().starts_with("gzip, deflate"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use hyper::header::{HeaderMap, HeaderValue, SET_COOKIE};
+
+#[derive(Clone, Debug)]
+struct Cookie {
+    value: String,
+    expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Cookies set by a server, replayed on subsequent requests.
+///
+/// Cookies are scoped to the endpoint of the client they are registered on, the `Domain` and
+/// `Path` attributes are ignored. Clones share the same cookies.
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<Mutex<HashMap<String, Cookie>>>,
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cookies = self.cookies.lock().unwrap();
+        f.debug_set().entries(cookies.keys()).finish()
+    }
+}
+
+impl CookieJar {
+    /// Creates an empty cookie jar.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of the cookie `name`, if it is set and has not expired.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let cookies = self.cookies.lock().unwrap();
+        cookies
+            .get(name)
+            .filter(|cookie| !cookie.is_expired(SystemTime::now()))
+            .map(|cookie| cookie.value.clone())
+    }
+
+    /// Sets the cookie `name`, without expiry.
+    pub fn insert<N: Into<String>, V: Into<String>>(&self, name: N, value: V) {
+        let cookie = Cookie {
+            value: value.into(),
+            expires: None,
+        };
+        self.cookies.lock().unwrap().insert(name.into(), cookie);
+    }
+
+    /// Removes the cookie `name`.
+    pub fn remove(&self, name: &str) {
+        self.cookies.lock().unwrap().remove(name);
+    }
+
+    /// Removes all cookies.
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    /// Stores the cookies from the `Set-Cookie` headers of a response.
+    pub(crate) fn store(&self, headers: &HeaderMap) {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        for set_cookie in headers.get_all(SET_COOKIE) {
+            let (name, cookie) = match set_cookie.to_str().ok().and_then(|s| parse(s, now)) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            if cookie.is_expired(now) {
+                cookies.remove(&name);
+            } else {
+                cookies.insert(name, cookie);
+            }
+        }
+    }
+
+    /// The `Cookie` header carrying the stored cookies, if any.
+    pub(crate) fn header(&self) -> Option<HeaderValue> {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|_, cookie| !cookie.is_expired(now));
+        if cookies.is_empty() {
+            return None;
+        }
+
+        let header = cookies
+            .iter()
+            .map(|(name, cookie)| format!("{}={}", name, cookie.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let mut header = HeaderValue::from_str(&header).ok()?;
+        header.set_sensitive(true);
+        Some(header)
+    }
+}
+
+/// Parse a `Set-Cookie` header value into the cookie name and cookie.
+fn parse(set_cookie: &str, now: SystemTime) -> Option<(String, Cookie)> {
+    let mut attributes = set_cookie.split(';');
+    let (name, value) = attributes.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut max_age = None;
+    let mut expires = None;
+    for attribute in attributes {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let key = key.trim();
+        if key.eq_ignore_ascii_case("max-age") {
+            max_age = value.trim().parse::<i64>().ok();
+        } else if key.eq_ignore_ascii_case("expires") {
+            expires = httpdate::parse_http_date(value.trim()).ok();
+        }
+    }
+
+    // Max-Age takes precedence over Expires, a lifetime past the end of time never expires
+    let expires = match max_age {
+        Some(secs) if secs <= 0 => Some(SystemTime::UNIX_EPOCH),
+        Some(secs) => now.checked_add(Duration::from_secs(secs as u64)),
+        None => expires,
+    };
+    let cookie = Cookie {
+        value: value.trim().trim_matches('"').to_owned(),
+        expires,
+    };
+    Some((name.to_owned(), cookie))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(set_cookies: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for set_cookie in set_cookies {
+            headers.append(SET_COOKIE, HeaderValue::from_str(set_cookie).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn stores_the_cookies_set() {
+        let jar = CookieJar::new();
+        jar.store(&headers(&[
+            "session=abc; Path=/; HttpOnly",
+            "theme=\"dark\"",
+            "malformed",
+            "=nameless",
+        ]));
+        assert_eq!(jar.get("session").as_deref(), Some("abc"));
+        assert_eq!(jar.get("theme").as_deref(), Some("dark"));
+
+        let header = jar.header().unwrap();
+        assert!(header.is_sensitive());
+        let mut cookies: Vec<_> = header.to_str().unwrap().split("; ").collect();
+        cookies.sort_unstable();
+        assert_eq!(cookies, ["session=abc", "theme=dark"]);
+    }
+
+    #[test]
+    fn removes_the_cookies_expired() {
+        let jar = CookieJar::new();
+        jar.insert("session", "abc");
+        jar.insert("theme", "dark");
+        jar.store(&headers(&[
+            "session=; Max-Age=0",
+            "theme=; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+        ]));
+        assert_eq!(jar.get("session"), None);
+        assert_eq!(jar.get("theme"), None);
+        assert_eq!(jar.header(), None);
+    }
+
+    #[test]
+    fn expires_cookies() {
+        let now = SystemTime::now();
+        let (_, cookie) = parse("session=abc; Max-Age=60", now).unwrap();
+        assert!(!cookie.is_expired(now + Duration::from_secs(59)));
+        assert!(cookie.is_expired(now + Duration::from_secs(60)));
+
+        let (_, cookie) = parse("session=abc; Expires=Wed, 21 Oct 2015 07:28:00 GMT", now).unwrap();
+        assert_eq!(
+            cookie.expires,
+            httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").ok()
+        );
+
+        // Max-Age takes precedence over Expires
+        let set_cookie = "session=abc; Expires=Wed, 21 Oct 2015 07:28:00 GMT; max-age=60";
+        let (_, cookie) = parse(set_cookie, now).unwrap();
+        assert_eq!(cookie.expires, Some(now + Duration::from_secs(60)));
+
+        let (_, cookie) = parse("session=abc", now).unwrap();
+        assert_eq!(cookie.expires, None);
+
+        let (_, cookie) = parse("session=abc; Max-Age=9223372036854775807", now).unwrap();
+        assert_eq!(cookie.expires, None);
+        let jar = CookieJar::new();
+        jar.store(&headers(&["session=abc; Max-Age=9223372036854775807"]));
+        assert_eq!(jar.get("session").as_deref(), Some("abc"));
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::future::BoxFuture;
+use hyper_util::client::legacy::connect::dns::Name;
+use tokio::time::Instant;
+use tower_service::Service;
+
+use crate::{
+    auth::BoxError,
+    clock::{Clock, SharedClock},
+};
+
+/// Looks up the addresses of a host, for example using hickory-resolver.
+pub trait Resolve: Send + Sync + 'static {
+    /// Returns the IP addresses of `host`.
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>, BoxError>>;
+}
+
+/// A [`Resolve`] registered on a resolver.
+#[derive(Clone)]
+struct SharedResolve(Arc<dyn Resolve>);
+
+impl fmt::Debug for SharedResolve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolve")
+    }
+}
+
+/// Lookups cached until they expire.
+#[derive(Debug)]
+struct Cache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>,
+    clock: SharedClock,
+}
+
+impl Cache {
+    fn get(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(host) {
+            Some((expires_at, addrs)) if *expires_at > self.clock.now() => Some(addrs.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, host: String, addrs: Vec<IpAddr>) {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&host) {
+            entries.retain(|_, (expires_at, _)| *expires_at > now);
+            // Evict the entry closest to expiring
+            if entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (expires_at, _))| *expires_at)
+                    .map(|(host, _)| host.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(host, (now + self.ttl, addrs));
+    }
+}
+
+/// Resolves host names for a [`Connector`], using the system resolver by default.
+///
+/// Hosts can be pinned to fixed addresses, and lookups cached. Register it using
+/// [`TlsConfig::resolver`].
+///
+/// [`Connector`]: super::tls::Connector
+/// [`TlsConfig::resolver`]: super::tls::TlsConfig::resolver
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    resolve: Option<SharedResolve>,
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    cache: Option<Arc<Cache>>,
+    clock: SharedClock,
+}
+
+impl Resolver {
+    /// Creates a resolver using the system resolver, without caching.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look up hosts using `resolve` instead of the system resolver.
+    pub fn resolve<R: Resolve>(mut self, resolve: R) -> Self {
+        self.resolve = Some(SharedResolve(Arc::new(resolve)));
+        self
+    }
+
+    /// Resolve `host` to `addrs` without looking it up.
+    pub fn override_host<H, A>(mut self, host: H, addrs: A) -> Self
+    where
+        H: Into<String>,
+        A: IntoIterator<Item = IpAddr>,
+    {
+        Arc::make_mut(&mut self.overrides).insert(
+            host.into().to_ascii_lowercase(),
+            addrs.into_iter().collect(),
+        );
+        self
+    }
+
+    /// Cache up to `max_entries` lookups for `ttl`.
+    pub fn cache(mut self, ttl: Duration, max_entries: usize) -> Self {
+        self.cache = Some(Arc::new(Cache {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            clock: self.clock.clone(),
+        }));
+        self
+    }
+
+    /// Sets the clock expiring cached lookups, [`TokioClock`] by default.
+    ///
+    /// This replaces the cache, set it afterwards.
+    ///
+    /// [`TokioClock`]: crate::clock::TokioClock
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self.cache = None;
+        self
+    }
+
+    /// Look up the addresses of `host`.
+    async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, BoxError> {
+        let host = host.to_ascii_lowercase();
+        if let Some(addrs) = self.overrides.get(&host) {
+            return Ok(addrs.clone());
+        }
+        if let Some(addrs) = self.cache.as_ref().and_then(|cache| cache.get(&host)) {
+            return Ok(addrs);
+        }
+
+        let addrs = match &self.resolve {
+            Some(SharedResolve(resolve)) => resolve.resolve(&host).await?,
+            None => tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .map(|addr| addr.ip())
+                .collect(),
+        };
+        if addrs.is_empty() {
+            let message = format!("no addresses found for {}", host);
+            return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+        }
+        if let Some(cache) = &self.cache {
+            cache.insert(host, addrs.clone());
+        }
+        Ok(addrs)
+    }
+}
+
+impl Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.lookup(name.as_str()).await?;
+            // The connector fills in the port
+            let addrs: Vec<_> = addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use tower_util::ServiceExt;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    /// Resolves every host to `ADDR` but `empty.test`, counting the lookups.
+    #[derive(Clone, Default)]
+    struct Counting(Arc<AtomicUsize>);
+
+    impl Resolve for Counting {
+        fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<IpAddr>, BoxError>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let addrs = match host {
+                "empty.test" => vec![],
+                _ => vec![ADDR],
+            };
+            Box::pin(async move { Ok(addrs) })
+        }
+    }
+
+    #[tokio::test]
+    async fn overrides_hosts() {
+        let counting = Counting::default();
+        let pinned = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let resolver = Resolver::new()
+            .resolve(counting.clone())
+            .override_host("Pinned.Test", vec![pinned]);
+        assert_eq!(resolver.lookup("pinned.test").await.unwrap(), [pinned]);
+        assert_eq!(resolver.lookup("PINNED.test").await.unwrap(), [pinned]);
+        assert_eq!(resolver.lookup("other.test").await.unwrap(), [ADDR]);
+        assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn resolves_to_socket_addresses() {
+        let resolver = Resolver::new().resolve(Counting::default());
+        let name = "host.test".parse::<Name>().unwrap();
+        let addrs: Vec<_> = resolver.oneshot(name).await.unwrap().collect();
+        assert_eq!(addrs, [SocketAddr::new(ADDR, 0)]);
+    }
+
+    #[tokio::test]
+    async fn fails_without_addresses() {
+        let resolver = Resolver::new().resolve(Counting::default());
+        let err = resolver.lookup("empty.test").await.unwrap_err();
+        let err = err.downcast::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn caches_lookups_until_they_expire() {
+        let counting = Counting::default();
+        let clock = ManualClock::new();
+        let resolver = Resolver::new()
+            .resolve(counting.clone())
+            .clock(clock.clone())
+            .cache(Duration::from_secs(60), 16);
+        resolver.lookup("host.test").await.unwrap();
+        resolver.lookup("host.test").await.unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+
+        clock.advance(Duration::from_secs(60));
+        resolver.lookup("host.test").await.unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn evicts_the_lookup_closest_to_expiring() {
+        let counting = Counting::default();
+        let clock = ManualClock::new();
+        let resolver = Resolver::new()
+            .resolve(counting.clone())
+            .clock(clock.clone())
+            .cache(Duration::from_secs(60), 2);
+        resolver.lookup("first.test").await.unwrap();
+        clock.advance(Duration::from_secs(1));
+        resolver.lookup("second.test").await.unwrap();
+        resolver.lookup("third.test").await.unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 3);
+
+        resolver.lookup("second.test").await.unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 3);
+        resolver.lookup("first.test").await.unwrap();
+        assert_eq!(counting.0.load(Ordering::Relaxed), 4);
+    }
+}
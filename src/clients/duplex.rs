@@ -0,0 +1,1239 @@
+#[cfg(feature = "server")]
+use std::sync::Weak;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures_core::{future::BoxFuture, Future};
+use futures_util::future::{select, Either};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Serialize,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+use tower_service::Service;
+
+use super::{
+    events::{Event, Events},
+    http::{Counters, InFlight, Stats},
+    subscription::{self, Notifications, Queue, Subscription, SubscriptionBuffer},
+    CallContext, Error, RequestFactory,
+};
+use crate::objects::{Notification, Request, RequestBuilder, Response, RpcError};
+#[cfg(feature = "server")]
+use crate::server::{
+    self,
+    connection::{Connection, Outgoing, Queue as OutgoingQueue},
+    metrics::Connected,
+    Router, Shutdown,
+};
+
+pub type DuplexError = Error<io::Error>;
+
+/// The delay before the first attempt to reconnect, doubled after each failed attempt.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest delay between attempts to reconnect.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// The number of messages read back to back before the reader yields to other tasks.
+const READ_BATCH: usize = 64;
+
+/// The largest message read by default, in bytes.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of messages queued for writing before calls wait to queue theirs.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The notifications of a subscription, as they are delivered to its [`Subscription`].
+type Items = subscription::Sender;
+
+/// Handles the notifications of a method, see [`Client::on_notification`].
+type Handler = Arc<dyn Fn(Value) + Send + Sync>;
+
+/// A connection carrying newline-delimited JSON.
+trait Io: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Io for T {}
+
+type BoxIo = Pin<Box<dyn Io>>;
+
+/// Establishes the connections of a reconnecting client, see [`Client::connect_with`].
+#[derive(Clone)]
+struct SharedConnect(Arc<dyn Fn() -> BoxFuture<'static, io::Result<BoxIo>> + Send + Sync>);
+
+/// A message received from the server, a response or a notification.
+#[derive(Deserialize)]
+struct Incoming {
+    #[serde(default)]
+    id: Option<Value>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+    result: Option<Value>,
+    error: Option<RpcError>,
+    jsonrpc: Option<String>,
+}
+
+/// Serves the calls made by the remote peer, see [`Peer`].
+///
+/// [`Peer`]: super::peer::Peer
+#[cfg(feature = "server")]
+struct Serving {
+    router: Arc<Router>,
+    connection: Connection,
+}
+
+/// Calls of the remote peer can't be served without the `server` feature.
+#[cfg(not(feature = "server"))]
+type Serving = std::convert::Infallible;
+
+/// An active subscription.
+struct Subscribed {
+    /// The items of each fork of the subscription, see [`Subscription::fork`].
+    items: Vec<Items>,
+    /// The id assigned by the server on the current connection, if any.
+    id: Option<Value>,
+    /// The subscribe call, replayed after reconnecting.
+    request: Request,
+}
+
+impl Subscribed {
+    /// Deliver the item made by `item` to each fork, returning `false` once they are all dropped.
+    fn send<F>(&mut self, mut item: F) -> bool
+    where
+        F: FnMut() -> Result<Value, DuplexError>,
+    {
+        self.items.retain(|items| items.send(item()).is_ok());
+        !self.items.is_empty()
+    }
+}
+
+/// A call awaiting its response.
+enum Pending {
+    Call(oneshot::Sender<Response>),
+    /// A subscribe call, registering the subscription once it succeeds.
+    Subscribe(oneshot::Sender<Response>, u64, Subscribed),
+    /// A subscribe call replayed after reconnecting.
+    Resubscribe(u64),
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<String, Pending>,
+    /// The active subscriptions, by their key on the client.
+    subscriptions: HashMap<u64, Subscribed>,
+    /// The keys of the active subscriptions, by their id on the current connection.
+    ids: HashMap<String, u64>,
+    next_subscription: u64,
+    handlers: HashMap<String, Handler>,
+    unhandled: Option<subscription::Sender<Notification>>,
+    // Why the client is disconnected, if it is
+    error: Option<(io::ErrorKind, String)>,
+    closed: bool,
+    // The events of the client, and the endpoint they name
+    events: Option<(Events, Arc<str>)>,
+}
+
+impl State {
+    /// The error failing calls made while disconnected.
+    fn error(&self) -> io::Error {
+        match &self.error {
+            Some((kind, message)) => io::Error::new(*kind, message.clone()),
+            None => io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"),
+        }
+    }
+
+    /// Deliver a notification to the subscription it belongs to, returning its parameters if
+    /// there is none.
+    fn notify(&mut self, mut params: Value) -> Option<Value> {
+        let id = match params.get("subscription") {
+            Some(id) => key(id),
+            None => return Some(params),
+        };
+        let subscription = match self.ids.get(&id) {
+            Some(subscription) => *subscription,
+            None => return Some(params),
+        };
+        if let Some(subscribed) = self.subscriptions.get_mut(&subscription) {
+            let mut item = params.get_mut("result").map(Value::take);
+            // The last fork takes the item, the others a copy
+            let mut forks = subscribed.items.len();
+            let delivered = subscribed.send(|| {
+                forks -= 1;
+                match forks {
+                    0 => Ok(item.take().unwrap_or_default()),
+                    _ => Ok(item.clone().unwrap_or_default()),
+                }
+            });
+            if !delivered {
+                self.subscriptions.remove(&subscription);
+                self.ids.remove(&id);
+            }
+        }
+        None
+    }
+
+    /// Returns the handler of `notification`, queueing it as unhandled if there is none.
+    fn handler(&mut self, notification: Notification) -> Option<(Handler, Value)> {
+        if let Some(handler) = self.handlers.get(&notification.method) {
+            return Some((handler.clone(), notification.params));
+        }
+        if let Some(unhandled) = &self.unhandled {
+            if unhandled.send(Ok(notification)).is_err() {
+                self.unhandled = None;
+            }
+        }
+        None
+    }
+
+    /// Register the subscription `subscription` under the id returned by a replayed subscribe call.
+    fn resubscribed(&mut self, subscription: u64, response: Response) {
+        let subscribed = match self.subscriptions.get_mut(&subscription) {
+            Some(subscribed) => subscribed,
+            None => return,
+        };
+        match (response.result, response.error) {
+            (Some(id), None) => {
+                self.ids.insert(key(&id), subscription);
+                subscribed.id = Some(id);
+                subscribed.send(|| Err(Error::Resubscribed));
+            }
+            (_, error) => {
+                subscribed.send(|| match &error {
+                    Some(err) => Err(Error::Rpc(err.clone())),
+                    None => Err(Error::Json(serde_json::Error::missing_field("result"))),
+                });
+                self.subscriptions.remove(&subscription);
+            }
+        }
+    }
+}
+
+/// The state of a connection, shared by the client handles and the task driving it.
+struct Shared {
+    state: Mutex<State>,
+    nonce: AtomicUsize,
+    // Weak, so that the connection closes once the clients are dropped
+    outgoing: mpsc::WeakSender<Bytes>,
+    // Serves the calls of the remote peer, which are refused without a router
+    serving: Option<Serving>,
+    counters: Arc<Counters>,
+    max_message_size: AtomicUsize,
+}
+
+impl Shared {
+    /// Emit an event, made from the endpoint of the client, to the subscribers of its events.
+    fn emit(&self, event: impl FnOnce(&str) -> Event) {
+        let events = self.state.lock().unwrap().events.clone();
+        if let Some((events, endpoint)) = events {
+            events.emit(|| event(&endpoint));
+        }
+    }
+
+    /// Fail the pending calls after the connection failed with `err`.
+    ///
+    /// Once `closed` the subscriptions are ended too, otherwise they are kept to be replayed.
+    fn fail(&self, err: io::Error, closed: bool) {
+        let (pending, subscriptions) = {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.error = Some((err.kind(), err.to_string()));
+            state.closed = closed;
+            state.ids.clear();
+            for subscribed in state.subscriptions.values_mut() {
+                subscribed.id = None;
+            }
+            let subscriptions = match closed {
+                true => std::mem::take(&mut state.subscriptions),
+                false => HashMap::new(),
+            };
+            if closed {
+                state.unhandled = None;
+                // Handlers keeping the connection see it as closed
+                #[cfg(feature = "server")]
+                if let Some(serving) = &self.serving {
+                    let _ = serving.connection.send(Outgoing::Close);
+                }
+            }
+            (std::mem::take(&mut state.pending), subscriptions)
+        };
+
+        // Dropping the senders of pending calls fails them
+        drop(pending);
+        for mut subscribed in subscriptions.into_values() {
+            subscribed.send(|| {
+                let err = io::Error::new(err.kind(), err.to_string());
+                Err(Error::Connection(err))
+            });
+        }
+    }
+
+    /// Mark the client as connected, returning the subscribe calls to replay.
+    fn resubscribe(&self) -> Vec<Bytes> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.error = None;
+        let mut messages = Vec::new();
+        for (subscription, subscribed) in &mut state.subscriptions {
+            subscribed.request.id = self.next_id();
+            let message = match encode(&subscribed.request) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            state.pending.insert(
+                key(&subscribed.request.id),
+                Pending::Resubscribe(*subscription),
+            );
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Route a line received from the server.
+    fn dispatch(self: &Arc<Self>, line: &[u8]) {
+        let batch = line.first() == Some(&b'[');
+        let messages = match batch {
+            true => serde_json::from_slice::<Vec<Value>>(line),
+            false => serde_json::from_slice::<Value>(line).map(|message| vec![message]),
+        };
+        // Malformed messages can't be routed, and are skipped
+        let messages = match messages {
+            Ok(messages) => messages,
+            Err(_) => return,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let mut unmatched = Vec::new();
+        let mut calls = Vec::new();
+        for message in messages {
+            if message.get("method").is_some() && message.get("id").is_some() {
+                calls.push(message);
+                continue;
+            }
+            let message = match serde_json::from_value::<Incoming>(message) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            if let Some(method) = message.method {
+                if let Some(params) = state.notify(message.params) {
+                    unmatched.push(Notification { method, params });
+                }
+                continue;
+            }
+
+            let id = message.id.unwrap_or_default();
+            let response = Response {
+                result: message.result,
+                error: message.error,
+                id,
+                jsonrpc: message.jsonrpc,
+            };
+            match state.pending.remove(&key(&response.id)) {
+                Some(Pending::Call(sender)) => {
+                    let _ = sender.send(response);
+                }
+                Some(Pending::Subscribe(sender, subscription, mut subscribed)) => {
+                    // Register before any notification for the subscription is read
+                    if let Some(id) = &response.result {
+                        state.ids.insert(key(id), subscription);
+                        subscribed.id = Some(id.clone());
+                        state.subscriptions.insert(subscription, subscribed);
+                    }
+                    let _ = sender.send(response);
+                }
+                Some(Pending::Resubscribe(subscription)) => {
+                    state.resubscribed(subscription, response);
+                }
+                None => {}
+            }
+        }
+        drop(state);
+
+        // Handlers are run without holding the lock, they may call the client
+        for notification in unmatched {
+            let handler = self.state.lock().unwrap().handler(notification);
+            if let Some((handler, params)) = handler {
+                handler(params);
+            }
+        }
+        if !calls.is_empty() {
+            self.serve(calls, batch);
+        }
+    }
+
+    /// Serve the calls made by the remote peer, as a server does those of a TCP connection.
+    ///
+    /// Without a router every call fails with a method not found error.
+    fn serve(self: &Arc<Self>, calls: Vec<Value>, batch: bool) {
+        match &self.serving {
+            #[cfg(feature = "server")]
+            Some(serving) => {
+                let message = match batch {
+                    true => Value::Array(calls),
+                    false => calls.into_iter().next().unwrap_or_default(),
+                };
+                let message = serde_json::to_vec(&message).unwrap(); // This is safe
+                server::dispatch(message, &serving.connection, &serving.router);
+            }
+            _ => self.refuse(calls, batch),
+        }
+    }
+
+    /// Answer `calls` with method not found errors.
+    fn refuse(self: &Arc<Self>, calls: Vec<Value>, batch: bool) {
+        let mut responses: Vec<Response> = calls
+            .into_iter()
+            .map(|mut call: Value| Response {
+                result: None,
+                error: Some(RpcError::method_not_found()),
+                id: call.get_mut("id").map(Value::take).unwrap_or_default(),
+                jsonrpc: Some("2.0".to_owned()),
+            })
+            .collect();
+        let message = match batch {
+            true => encode(&responses),
+            false => encode(&responses.pop()),
+        };
+        // Queued from another task, reading mustn't wait for the queue
+        if let Ok(message) = message {
+            let shared = self.clone();
+            tokio::spawn(async move { shared.respond(message).await });
+        }
+    }
+
+    /// Send a response to the remote peer, discarding it if the connection it was called over
+    /// failed.
+    async fn respond(&self, message: Bytes) {
+        let outgoing = match self.outgoing.upgrade() {
+            Some(outgoing) => outgoing,
+            None => return,
+        };
+        let permit = match outgoing.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => return,
+        };
+        let state = self.state.lock().unwrap();
+        if state.error.is_none() {
+            permit.send(message);
+        }
+    }
+
+    /// Take the next request id from the nonce.
+    fn next_id(&self) -> Value {
+        Value::Number(self.nonce.fetch_add(1, Ordering::AcqRel).into())
+    }
+}
+
+/// The key identifying a request or subscription id.
+fn key(id: &Value) -> String {
+    id.to_string()
+}
+
+/// Encode `message` as a line of JSON.
+fn encode<T: Serialize>(message: &T) -> Result<Bytes, serde_json::Error> {
+    let mut message = serde_json::to_vec(message)?;
+    message.push(b'\n');
+    Ok(Bytes::from(message))
+}
+
+/// Removes a call from the pending calls if it is abandoned before its response arrives.
+struct PendingGuard<'a> {
+    shared: &'a Shared,
+    key: Option<String>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.shared.state.lock().unwrap().pending.remove(&key);
+        }
+    }
+}
+
+/// A handle to a JSON-RPC server over a duplex connection, such as a TCP or Unix socket.
+///
+/// Messages are newline-delimited JSON. Unlike HTTP, the server can send notifications at any
+/// time, see [`Client::subscribe`]. The connection is shared by the client and its clones, and
+/// is closed once they and their subscriptions are dropped.
+///
+/// Up to 1024 messages are queued for writing, calls then wait for the connection to catch up
+/// before sending theirs.
+#[derive(Clone)]
+pub struct Client {
+    shared: Arc<Shared>,
+    outgoing: mpsc::Sender<Bytes>,
+    endpoint: Arc<str>,
+    buffer: SubscriptionBuffer,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("endpoint", &self.endpoint)
+            .field("nonce", &self.shared.nonce)
+            .field("buffer", &self.buffer)
+            .finish()
+    }
+}
+
+impl Client {
+    /// Creates a new client over `io`, driving the connection on a background task.
+    ///
+    /// This must be called within a Tokio runtime.
+    pub fn new<T>(io: T) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::spawn(Box::pin(io), None, None)
+    }
+
+    /// Connects to a server over TCP, with `TCP_NODELAY` set.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let endpoint = format!("tcp://{}", stream.peer_addr()?);
+        Ok(Self::new(stream).with_endpoint(endpoint))
+    }
+
+    /// Connects to a server using `connect`, and reconnects using it whenever the connection
+    /// fails.
+    ///
+    /// Attempts to reconnect back off exponentially, from 100 milliseconds up to 10 seconds. Calls
+    /// pending when the connection fails, or made while reconnecting, fail with
+    /// [`Error::Connection`]. The active subscriptions are replayed once reconnected, each
+    /// yielding [`Error::Resubscribed`] to mark the notifications which may have been missed.
+    /// Connections, disconnections and attempts to reconnect are emitted as events, see
+    /// [`Client::with_events`].
+    pub async fn connect_with<F, Fut, T>(connect: F) -> io::Result<Self>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<T>> + Send + 'static,
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let connect = SharedConnect(Arc::new(move || {
+            let connecting = connect();
+            Box::pin(async move { Ok(Box::pin(connecting.await?) as BoxIo) })
+        }));
+        let io = (connect.0)().await?;
+        Ok(Self::spawn(io, Some(connect), None))
+    }
+
+    /// Creates a new client over `io` serving `router` to the remote peer, see [`Peer`].
+    ///
+    /// [`Peer`]: super::peer::Peer
+    #[cfg(feature = "server")]
+    pub(crate) fn new_serving<T>(io: T, router: Router) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let router = router.into_served();
+        let connected = router.connected();
+        let (connection, queue) = Connection::new(None, Shutdown::new(), router.connection_slots());
+        let client = Self::spawn(Box::pin(io), None, Some(Serving { router, connection }));
+        tokio::spawn(respond(queue, Arc::downgrade(&client.shared), connected));
+        client
+    }
+
+    fn spawn(io: BoxIo, connect: Option<SharedConnect>, serving: Option<Serving>) -> Self {
+        let (outgoing, messages) = mpsc::channel(QUEUE_CAPACITY);
+        let shared = Arc::new(Shared {
+            state: Mutex::default(),
+            nonce: AtomicUsize::new(0),
+            outgoing: outgoing.downgrade(),
+            serving,
+            counters: Arc::default(),
+            max_message_size: AtomicUsize::new(MAX_MESSAGE_SIZE),
+        });
+        tokio::spawn(drive(io, messages, shared.clone(), connect));
+        Client {
+            shared,
+            outgoing,
+            endpoint: Arc::from("duplex"),
+            buffer: SubscriptionBuffer::default(),
+        }
+    }
+
+    /// Sets the endpoint named in errors, `duplex` by default.
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.endpoint = Arc::from(endpoint.into());
+        self
+    }
+
+    /// Sets the size of the largest message read from the server, in bytes, 16 MiB by default.
+    ///
+    /// The connection fails once a larger message is received, like any connection error. This
+    /// applies to the client and its clones.
+    pub fn with_max_message_size(self, size: usize) -> Self {
+        self.shared.max_message_size.store(size, Ordering::Relaxed);
+        self
+    }
+
+    /// Sets the buffering of the notifications of subscriptions made from now on.
+    pub fn with_subscription_buffer(mut self, buffer: SubscriptionBuffer) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Emits the connections, disconnections and attempts to reconnect of the client and its
+    /// clones to `events`, naming the endpoint of this client as the host connected to.
+    ///
+    /// The first connection is established before events can be registered, so
+    /// [`Event::Connected`] is emitted at once if the client is connected.
+    pub fn with_events(self, events: Events) -> Self {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.error.is_none() && !state.closed {
+            events.emit(|| Event::Connected {
+                host: self.endpoint.to_string(),
+            });
+        }
+        state.events = Some((events, self.endpoint.clone()));
+        drop(state);
+        self
+    }
+
+    /// Returns a snapshot of the calls made by this client and its clones, and of the times it
+    /// reconnected.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            connections: self.is_connected() as usize,
+            ..self.shared.counters.stats()
+        }
+    }
+
+    /// Returns `true` while connected to the server.
+    pub fn is_connected(&self) -> bool {
+        self.shared.state.lock().unwrap().error.is_none()
+    }
+
+    /// Returns `true` once the connection has closed for good.
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().closed
+    }
+
+    /// Send a request.
+    pub async fn send(&self, request: Request) -> Result<Response, DuplexError> {
+        let context = self.call_context(&request);
+        self.call(request, None)
+            .await
+            .map_err(|err| err.with_context(|| context))
+    }
+
+    /// Send a request, aborting it with [`Error::Cancelled`] once `token` is cancelled.
+    ///
+    /// The call is forgotten, a response arriving later is discarded.
+    pub async fn send_with_cancellation(
+        &self,
+        request: Request,
+        token: &CancellationToken,
+    ) -> Result<Response, DuplexError> {
+        let context = self.call_context(&request);
+        token
+            .run_until_cancelled(self.send(request))
+            .await
+            .unwrap_or_else(|| Err(Error::Cancelled.with_context(|| context)))
+    }
+
+    /// Subscribe to notifications from the server, deserializing each item into `T`.
+    ///
+    /// Calls `subscribe_method` with `params`, and streams the notifications carrying the
+    /// subscription id it returns, in their `params` as `{"subscription": id, "result": item}`.
+    /// The subscription is cancelled on the server by calling `unsubscribe_method` with the id.
+    ///
+    /// Subscriptions and calls share the connection. Each subscription buffers its notifications
+    /// independently, so a subscription consumed slowly overflows its own buffer rather than
+    /// delaying responses or other subscriptions. To buffer a subscription differently, subscribe
+    /// using a clone of the client with [`Client::with_subscription_buffer`].
+    pub async fn subscribe<T: DeserializeOwned>(
+        &self,
+        subscribe_method: &str,
+        params: Value,
+        unsubscribe_method: &str,
+    ) -> Result<Subscription<T>, DuplexError> {
+        let request = self
+            .build_request()
+            .method(subscribe_method)
+            .params(params)
+            .finish()
+            .unwrap(); // This is safe
+        let context = self.call_context(&request);
+
+        let (items, queue) = subscription::channel(self.buffer);
+        let subscription = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.next_subscription += 1;
+            state.next_subscription
+        };
+        let subscribed = Subscribed {
+            items: vec![items],
+            id: None,
+            request: request.clone(),
+        };
+        self.call(request, Some((subscription, subscribed)))
+            .await
+            .and_then(|response| match (response.result, response.error) {
+                (_, Some(err)) => Err(Error::Rpc(err)),
+                (Some(_), None) => Ok(()),
+                (None, None) => Err(Error::Json(serde_json::Error::missing_field("result"))),
+            })
+            .map_err(|err| err.with_context(|| context))?;
+        Ok(Subscription::new(
+            self.clone(),
+            subscription,
+            unsubscribe_method.to_owned(),
+            queue,
+        ))
+    }
+
+    /// Handle the notifications of `method` which aren't for a subscription using `handler`,
+    /// called with their parameters.
+    ///
+    /// This replaces any handler of `method`. Handlers run on the task reading the connection, so
+    /// they should return quickly.
+    pub fn on_notification<M, F>(&self, method: M, handler: F)
+    where
+        M: Into<String>,
+        F: Fn(Value) + Send + Sync + 'static,
+    {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .handlers
+            .insert(method.into(), Arc::new(handler));
+    }
+
+    /// Returns the stream of notifications which aren't for a subscription and have no handler.
+    ///
+    /// Without a stream these are dropped. Only the latest stream receives notifications, the
+    /// previous one ends.
+    pub fn notifications(&self) -> Notifications {
+        let (unhandled, queue) = subscription::channel(self.buffer);
+        let mut state = self.shared.state.lock().unwrap();
+        // A closed connection ends the stream at once
+        if !state.closed {
+            state.unhandled = Some(unhandled);
+        }
+        Notifications::new(queue)
+    }
+
+    /// The id of the subscription `subscription` on the current connection, if any.
+    pub(crate) fn subscription_id(&self, subscription: u64) -> Option<Value> {
+        let state = self.shared.state.lock().unwrap();
+        state.subscriptions.get(&subscription)?.id.clone()
+    }
+
+    /// Deliver the notifications of the subscription `subscription` to a new fork, buffered by
+    /// `buffer`.
+    ///
+    /// The fork's stream ends at once if the subscription has ended.
+    pub(crate) fn fork(&self, subscription: u64, buffer: SubscriptionBuffer) -> Arc<Mutex<Queue>> {
+        let (items, queue) = subscription::channel(buffer);
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(subscribed) = state.subscriptions.get_mut(&subscription) {
+            subscribed.items.push(items);
+        }
+        queue
+    }
+
+    /// Forget the dropped forks of the subscription `subscription`, returning `true` if none
+    /// remain.
+    pub(crate) fn release(&self, subscription: u64) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.subscriptions.get_mut(&subscription) {
+            Some(subscribed) => {
+                subscribed.items.retain(|items| !items.is_closed());
+                subscribed.items.is_empty()
+            }
+            None => true,
+        }
+    }
+
+    /// Stop delivering the notifications of the subscription `subscription`, and cancel it on
+    /// the server.
+    pub(crate) async fn unsubscribe(
+        &self,
+        subscription: u64,
+        method: &str,
+    ) -> Result<(), DuplexError> {
+        let id = {
+            let mut state = self.shared.state.lock().unwrap();
+            let id = state
+                .subscriptions
+                .remove(&subscription)
+                .and_then(|subscribed| subscribed.id);
+            if let Some(id) = &id {
+                state.ids.remove(&key(id));
+            }
+            id
+        };
+        // Subscriptions which ended, or are yet to be replayed, don't exist on the server
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let request = self
+            .build_request()
+            .method(method)
+            .params(vec![id])
+            .finish()
+            .unwrap(); // This is safe
+        let context = self.call_context(&request);
+        match self.send(request).await?.error {
+            Some(err) => Err(Error::Rpc(err).with_context(|| context)),
+            None => Ok(()),
+        }
+    }
+
+    /// Send `request` and await its response, registering `subscription` for a subscribe call.
+    async fn call(
+        &self,
+        request: Request,
+        subscription: Option<(u64, Subscribed)>,
+    ) -> Result<Response, DuplexError> {
+        let in_flight = InFlight::new(self.shared.counters.clone());
+        let result = self.exchange(request, subscription).await;
+        if result.is_err() {
+            in_flight.failed();
+        }
+        result
+    }
+
+    /// Send `request` and wait for its response.
+    async fn exchange(
+        &self,
+        request: Request,
+        subscription: Option<(u64, Subscribed)>,
+    ) -> Result<Response, DuplexError> {
+        let message = encode(&request).map_err(Error::Json)?;
+        let key = key(&request.id);
+        let (sender, receiver) = oneshot::channel();
+        // Wait for room in the queue, the connection task holds the receiver until it fails
+        let permit = match self.outgoing.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => {
+                let state = self.shared.state.lock().unwrap();
+                return Err(Error::Connection(state.error()));
+            }
+        };
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.error.is_some() {
+                return Err(Error::Connection(state.error()));
+            }
+            let pending = match subscription {
+                Some((subscription, subscribed)) => {
+                    Pending::Subscribe(sender, subscription, subscribed)
+                }
+                None => Pending::Call(sender),
+            };
+            state.pending.insert(key.clone(), pending);
+            // Queued under the lock, so a failing connection discards both the call and message
+            permit.send(message);
+        }
+        let mut guard = PendingGuard {
+            shared: &self.shared,
+            key: Some(key),
+        };
+
+        let response = receiver
+            .await
+            .map_err(|_| Error::Connection(self.shared.state.lock().unwrap().error()));
+        guard.key = None;
+        response
+    }
+
+    /// Describe the call made with `request`, for annotating errors.
+    fn call_context(&self, request: &Request) -> CallContext {
+        CallContext {
+            id: request.id.clone(),
+            method: request.method.clone(),
+            endpoint: self.endpoint.to_string(),
+        }
+    }
+}
+
+impl Service<Request> for Client {
+    type Response = Response;
+    type Error = DuplexError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.send(request).await })
+    }
+}
+
+impl RequestFactory for Client {
+    /// Build the request.
+    fn build_request(&self) -> RequestBuilder {
+        Request::build().id(self.shared.next_id())
+    }
+}
+
+/// Drive the connection until the clients are dropped, reconnecting using `connect` if set.
+async fn drive(
+    mut io: BoxIo,
+    mut messages: mpsc::Receiver<Bytes>,
+    shared: Arc<Shared>,
+    connect: Option<SharedConnect>,
+) {
+    let dropped = || io::Error::new(io::ErrorKind::BrokenPipe, "client dropped");
+    let mut replay = Vec::new();
+    loop {
+        let err = match run(io, replay, &mut messages, &shared).await {
+            Ok(()) => return shared.fail(dropped(), true),
+            Err(err) => err,
+        };
+        shared.emit(|_| Event::Disconnected {
+            reason: err.to_string(),
+        });
+        let connect = match &connect {
+            Some(connect) => connect,
+            None => return shared.fail(err, true),
+        };
+
+        shared.fail(err, false);
+        // Discard the messages of the failed calls
+        while messages.try_recv().is_ok() {}
+        io = match reconnect(connect, &mut messages, &shared).await {
+            Some(io) => io,
+            None => return shared.fail(dropped(), true),
+        };
+        shared.counters.reconnected();
+        replay = shared.resubscribe();
+        shared.emit(|endpoint| Event::Connected {
+            host: endpoint.to_owned(),
+        });
+    }
+}
+
+/// Send the responses and notifications queued by the router to the remote peer, until the
+/// connection closes.
+#[cfg(feature = "server")]
+async fn respond(mut queue: OutgoingQueue, shared: Weak<Shared>, _connected: Connected) {
+    while let Some(Outgoing::Message(message)) = queue.recv().await {
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let mut message = Vec::from(message);
+        message.push(b'\n');
+        shared.respond(Bytes::from(message)).await;
+    }
+}
+
+/// Reconnect using `connect`, giving up once the clients are dropped.
+async fn reconnect(
+    connect: &SharedConnect,
+    messages: &mut mpsc::Receiver<Bytes>,
+    shared: &Shared,
+) -> Option<BoxIo> {
+    let mut delay = RECONNECT_DELAY;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        shared.emit(|_| Event::Reconnecting { attempt, delay });
+        let mut sleep = Box::pin(tokio::time::sleep(delay));
+        loop {
+            // No messages are queued while disconnected, until the clients are dropped
+            match select(sleep, Box::pin(messages.recv())).await {
+                Either::Left(_) => break,
+                Either::Right((None, _)) => return None,
+                Either::Right((Some(_), unslept)) => sleep = unslept,
+            }
+        }
+        if let Ok(io) = (connect.0)().await {
+            return Some(io);
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Exchange messages over `io`, writing `replay` first, until the connection fails or the
+/// clients are dropped.
+async fn run(
+    io: BoxIo,
+    replay: Vec<Bytes>,
+    messages: &mut mpsc::Receiver<Bytes>,
+    shared: &Arc<Shared>,
+) -> io::Result<()> {
+    let (read, mut write) = tokio::io::split(io);
+    let stop = CancellationToken::new();
+    let reader = tokio::spawn(read_messages(read, shared.clone(), stop.clone()));
+
+    let written = stop
+        .run_until_cancelled(async {
+            for message in replay {
+                write.write_all(&message).await?;
+            }
+            write.flush().await?;
+            while let Some(message) = messages.recv().await {
+                write.write_all(&message).await?;
+                write.flush().await?;
+            }
+            write.shutdown().await
+        })
+        .await;
+    stop.cancel();
+    let read = reader.await.unwrap_or_else(io::Error::other);
+    written.unwrap_or(Err(read))
+}
+
+/// Read and route messages until the connection fails or is stopped.
+async fn read_messages<R: AsyncRead + Unpin>(
+    read: R,
+    shared: Arc<Shared>,
+    stop: CancellationToken,
+) -> io::Error {
+    let reading = async {
+        let mut read = BufReader::new(read);
+        let mut line = Vec::new();
+        let mut batch = 0;
+        loop {
+            // Yield now and then, so a busy connection doesn't monopolize the worker
+            batch += 1;
+            if batch == READ_BATCH {
+                batch = 0;
+                tokio::task::yield_now().await;
+            }
+            line.clear();
+            let size = shared.max_message_size.load(Ordering::Relaxed);
+            let limit = size as u64 + 1;
+            match (&mut read).take(limit).read_until(b'\n', &mut line).await {
+                Ok(0) => {
+                    return io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed by server",
+                    )
+                }
+                Ok(n) if line.last() != Some(&b'\n') && n as u64 == limit => {
+                    let err = format!("message exceeds {} bytes", size);
+                    return io::Error::new(io::ErrorKind::InvalidData, err);
+                }
+                Ok(_) => {}
+                Err(err) => return err,
+            }
+            let message = line.trim_ascii();
+            if !message.is_empty() {
+                shared.dispatch(message);
+            }
+        }
+    };
+    let err = stop.run_until_cancelled(reading).await;
+    stop.cancel();
+    err.unwrap_or_else(|| io::Error::new(io::ErrorKind::ConnectionAborted, "connection stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    /// Answer the calls read from `server` with `true`, until it closes.
+    async fn answer(server: DuplexStream) {
+        let (read, mut write) = tokio::io::split(server);
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let response = json!({ "jsonrpc": "2.0", "result": true, "id": request["id"] });
+            let response = format!("{}\n", response);
+            write.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn call(client: &Client) -> Request {
+        client.build_request().method("ping").finish().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_reconnects() {
+        let (servers, mut accepted) = mpsc::unbounded_channel();
+        let client = Client::connect_with(move || {
+            let (client, server) = tokio::io::duplex(1024);
+            let _ = servers.send(server);
+            async move { Ok(client) }
+        })
+        .await
+        .unwrap();
+        let events = Events::new(16);
+        let mut received = events.subscribe();
+        let client = client.with_endpoint("test").with_events(events);
+        let connected = Event::Connected {
+            host: "test".to_owned(),
+        };
+        assert_eq!(received.recv().await.unwrap(), connected);
+
+        // The first connection fails with a call pending
+        let server = accepted.recv().await.unwrap();
+        let pending = tokio::spawn({
+            let client = client.clone();
+            async move { client.send(call(&client)).await }
+        });
+        tokio::task::yield_now().await;
+        drop(server);
+        let err = pending.await.unwrap().unwrap_err();
+        assert!(matches!(err.into_inner(), Error::Connection(_)));
+
+        let disconnected = Event::Disconnected {
+            reason: "connection closed by server".to_owned(),
+        };
+        assert_eq!(received.recv().await.unwrap(), disconnected);
+        let reconnecting = Event::Reconnecting {
+            attempt: 1,
+            delay: RECONNECT_DELAY,
+        };
+        assert_eq!(received.recv().await.unwrap(), reconnecting);
+
+        tokio::spawn(answer(accepted.recv().await.unwrap()));
+        assert_eq!(received.recv().await.unwrap(), connected);
+        assert!(client.is_connected());
+        let response = client.send(call(&client)).await.unwrap();
+        assert_eq!(response.result, Some(Value::Bool(true)));
+
+        let stats = client.stats();
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.reconnects, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_reconnecting() {
+        let (servers, mut accepted) = mpsc::unbounded_channel();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let connecting = attempts.clone();
+        let client = Client::connect_with(move || {
+            // The first two attempts to reconnect are refused
+            let attempt = connecting.fetch_add(1, Ordering::Relaxed);
+            let (client, server) = tokio::io::duplex(1024);
+            let _ = servers.send(server);
+            async move {
+                match attempt {
+                    1 | 2 => Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")),
+                    _ => Ok(client),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let events = Events::new(16);
+        let mut received = events.subscribe();
+        let client = client.with_events(events);
+
+        drop(accepted.recv().await.unwrap());
+        assert!(matches!(
+            received.recv().await.unwrap(),
+            Event::Connected { .. }
+        ));
+        assert!(matches!(
+            received.recv().await.unwrap(),
+            Event::Disconnected { .. }
+        ));
+        for (attempt, delay) in [(1, 100), (2, 200), (3, 400)] {
+            let reconnecting = Event::Reconnecting {
+                attempt,
+                delay: Duration::from_millis(delay),
+            };
+            assert_eq!(received.recv().await.unwrap(), reconnecting);
+        }
+        assert!(matches!(
+            received.recv().await.unwrap(),
+            Event::Connected { .. }
+        ));
+        assert_eq!(attempts.load(Ordering::Relaxed), 4);
+        assert_eq!(client.stats().reconnects, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_room_in_the_queue() {
+        // Nothing is read from the server until the queue is full
+        let (client, server) = tokio::io::duplex(64);
+        let client = Client::new(client);
+        let calls: Vec<_> = (0..QUEUE_CAPACITY + 8)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.send(call(&client)).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(client.outgoing.capacity(), 0);
+        let pending = client.shared.state.lock().unwrap().pending.len();
+        assert!(pending < calls.len());
+
+        tokio::spawn(answer(server));
+        for call in calls {
+            let response = call.await.unwrap().unwrap();
+            assert_eq!(response.result, Some(Value::Bool(true)));
+        }
+    }
+
+    #[tokio::test]
+    async fn forgets_cancelled_calls() {
+        let (client, server) = tokio::io::duplex(1024);
+        let client = Client::new(client);
+        let mut lines = BufReader::new(server).lines();
+        let token = CancellationToken::new();
+        let call = client.send_with_cancellation(call(&client), &token);
+        let cancel = async {
+            // Cancelled once the call is sent, it's never answered
+            lines.next_line().await.unwrap().unwrap();
+            assert_eq!(client.shared.state.lock().unwrap().pending.len(), 1);
+            token.cancel();
+        };
+        let (result, ()) = tokio::join!(call, cancel);
+
+        assert!(matches!(result.unwrap_err().into_inner(), Error::Cancelled));
+        assert!(client.shared.state.lock().unwrap().pending.is_empty());
+        assert!(client.is_connected());
+    }
+
+    #[tokio::test]
+    async fn fails_on_messages_too_large() {
+        let (client, server) = tokio::io::duplex(1024);
+        let client = Client::new(client).with_max_message_size(64);
+        let (read, mut write) = tokio::io::split(server);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let result = "a".repeat(64);
+                let response = json!({ "jsonrpc": "2.0", "result": result, "id": request["id"] });
+                let response = format!("{}\n", response);
+                write.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let err = client.send(call(&client)).await.unwrap_err();
+        match err.into_inner() {
+            Error::Connection(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            err => panic!("unexpected error {}", err),
+        }
+        assert!(!client.is_connected());
+    }
+}
@@ -0,0 +1,156 @@
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    auth::BoxError,
+    objects::{Request, Response},
+};
+
+/// Converts requests into bodies, and bodies into responses.
+///
+/// Codecs can wrap others, for example to compress or encrypt the bodies they produce.
+pub trait Codec: Send + Sync {
+    /// The `Content-Type` of bodies, also sent as `Accept`.
+    fn content_type(&self) -> &'static str;
+
+    /// Encode `request`, appending it to `buffer`.
+    fn encode(&self, request: &Request, buffer: &mut BytesMut) -> Result<(), BoxError>;
+
+    /// Decode a response body.
+    fn decode(&self, body: &[u8]) -> Result<Response, BoxError>;
+}
+
+/// The wire encoding of request and response bodies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// JSON, the default.
+    #[default]
+    Json,
+    /// MessagePack, with objects encoded as maps.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    /// CBOR.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Encoding {
+    /// The `Content-Type` of bodies in this encoding.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => "application/msgpack",
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+}
+
+impl Codec for Encoding {
+    fn content_type(&self) -> &'static str {
+        Encoding::content_type(*self)
+    }
+
+    fn encode(&self, request: &Request, buffer: &mut BytesMut) -> Result<(), BoxError> {
+        let mut writer = buffer.writer();
+        match self {
+            Encoding::Json => serde_json::to_writer(&mut writer, request)?,
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => rmp_serde::encode::write_named(&mut writer, request)?,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => ciborium::into_writer(request, &mut writer)?,
+        }
+        Ok(())
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Response, BoxError> {
+        Ok(match self {
+            Encoding::Json => serde_json::from_slice(body)?,
+            #[cfg(feature = "msgpack")]
+            Encoding::MessagePack => rmp_serde::from_slice(body)?,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => ciborium::from_reader(body)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::objects::RpcError;
+
+    fn request() -> Request {
+        Request::build()
+            .method("sum")
+            .params(json!({ "values": [1, 2.5, "three", null] }))
+            .id(json!(7))
+            .finish()
+            .unwrap()
+    }
+
+    fn responses() -> Vec<Response> {
+        let error = RpcError::method_not_found();
+        let body = json!({ "jsonrpc": "2.0", "error": error, "id": "abc" });
+        vec![
+            serde_json::from_value(json!({ "jsonrpc": "2.0", "result": [1, "two"], "id": 7 }))
+                .unwrap(),
+            serde_json::from_value(body).unwrap(),
+        ]
+    }
+
+    fn round_trip(
+        encoding: Encoding,
+        from_request: impl Fn(&[u8]) -> serde_json::Value,
+        to_response: impl Fn(&Response) -> Vec<u8>,
+    ) {
+        let mut buffer = BytesMut::new();
+        encoding.encode(&request(), &mut buffer).unwrap();
+        let expected = serde_json::to_value(request()).unwrap();
+        assert_eq!(from_request(&buffer), expected);
+
+        for response in responses() {
+            let decoded = encoding.decode(&to_response(&response)).unwrap();
+            assert_eq!(decoded, response);
+        }
+        assert!(encoding.decode(b"\xff\x00garbage").is_err());
+    }
+
+    #[test]
+    fn round_trips_json() {
+        assert_eq!(Encoding::default().content_type(), "application/json");
+        round_trip(
+            Encoding::Json,
+            |body| serde_json::from_slice(body).unwrap(),
+            |response| serde_json::to_vec(response).unwrap(),
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn round_trips_msgpack() {
+        assert_eq!(Encoding::MessagePack.content_type(), "application/msgpack");
+        round_trip(
+            Encoding::MessagePack,
+            |body| rmp_serde::from_slice(body).unwrap(),
+            |response| rmp_serde::to_vec_named(response).unwrap(),
+        );
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn round_trips_cbor() {
+        assert_eq!(Encoding::Cbor.content_type(), "application/cbor");
+        round_trip(
+            Encoding::Cbor,
+            |body| ciborium::from_reader(body).unwrap(),
+            |response| {
+                let mut body = Vec::new();
+                ciborium::into_writer(response, &mut body).unwrap();
+                body
+            },
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::{convert::TryFrom, fmt};
+
+use serde::{
+    de::{self, Deserializer, Visitor},
+    Deserialize, Serialize,
+};
+use serde_json::{Map, Value};
+
+use super::{
+    duplex::{Client, DuplexError},
+    subscription::Subscription,
+};
+
+/// A block header, as notified by `newHeads` subscriptions.
+///
+/// Quantities are decoded from hex, hashes, addresses and other data are kept as hex strings.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Header {
+    pub hash: Option<String>,
+    pub parent_hash: String,
+    #[serde(deserialize_with = "quantity")]
+    pub number: u64,
+    #[serde(deserialize_with = "quantity")]
+    pub timestamp: u64,
+    #[serde(deserialize_with = "quantity")]
+    pub gas_limit: u64,
+    #[serde(deserialize_with = "quantity")]
+    pub gas_used: u64,
+    #[serde(default)]
+    pub miner: Option<String>,
+    /// Absent before the London fork.
+    #[serde(default, deserialize_with = "optional_quantity")]
+    pub base_fee_per_gas: Option<u128>,
+    /// The fields not decoded above, such as `stateRoot` and `logsBloom`.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A log emitted by a transaction, as notified by `logs` subscriptions.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    // The location of the log is null while its transaction is pending
+    #[serde(default, deserialize_with = "optional_quantity")]
+    pub block_number: Option<u64>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    #[serde(default)]
+    pub transaction_hash: Option<String>,
+    #[serde(default, deserialize_with = "optional_quantity")]
+    pub transaction_index: Option<u64>,
+    #[serde(default, deserialize_with = "optional_quantity")]
+    pub log_index: Option<u64>,
+    /// Whether the log was removed by a chain reorganization.
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// Selects the logs notified by [`Client::subscribe_logs`].
+///
+/// An empty filter selects every log.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LogFilter {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    address: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    topics: Vec<Option<Vec<String>>>,
+}
+
+impl LogFilter {
+    /// Creates a filter selecting every log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Select the logs emitted by `address`, in addition to any other addresses selected.
+    pub fn address<A: Into<String>>(mut self, address: A) -> Self {
+        self.address.push(address.into());
+        self
+    }
+
+    /// Select the logs with `topic` at `position`, in addition to any other topics selected at
+    /// that position.
+    pub fn topic<T: Into<String>>(mut self, position: usize, topic: T) -> Self {
+        if self.topics.len() <= position {
+            self.topics.resize(position + 1, None);
+        }
+        self.topics[position]
+            .get_or_insert_with(Vec::new)
+            .push(topic.into());
+        self
+    }
+}
+
+impl Client {
+    /// Subscribe to the headers of new blocks, using `eth_subscribe`.
+    pub async fn subscribe_new_heads(&self) -> Result<Subscription<Header>, DuplexError> {
+        self.subscribe(
+            "eth_subscribe",
+            Value::from(vec!["newHeads"]),
+            "eth_unsubscribe",
+        )
+        .await
+    }
+
+    /// Subscribe to the logs selected by `filter` in new blocks, using `eth_subscribe`.
+    pub async fn subscribe_logs(
+        &self,
+        filter: LogFilter,
+    ) -> Result<Subscription<Log>, DuplexError> {
+        let filter = serde_json::to_value(filter).unwrap(); // This is safe
+        self.subscribe(
+            "eth_subscribe",
+            Value::Array(vec![Value::from("logs"), filter]),
+            "eth_unsubscribe",
+        )
+        .await
+    }
+}
+
+/// Parse a quantity, hex with a `0x` prefix.
+///
+/// Nodes differ in the quantities they send, leading zeros, uppercase digits, a bare `0x` for zero
+/// and plain JSON numbers are all accepted.
+struct QuantityVisitor;
+
+impl<'de> Visitor<'de> for QuantityVisitor {
+    type Value = u128;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a hex quantity")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u128, E> {
+        Ok(value.into())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u128, E> {
+        let digits = value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))?;
+        if digits.is_empty() {
+            return Ok(0);
+        }
+        // Signs are accepted by `from_str_radix`, but not in quantities
+        if !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(E::invalid_value(de::Unexpected::Str(value), &self));
+        }
+        u128::from_str_radix(digits, 16)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+fn quantity<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u128>,
+{
+    let value = deserializer.deserialize_any(QuantityVisitor)?;
+    T::try_from(value).map_err(|_| de::Error::custom(format!("quantity {} out of range", value)))
+}
+
+fn optional_quantity<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u128>,
+{
+    #[derive(Deserialize)]
+    struct Quantity(#[serde(deserialize_with = "quantity")] u128);
+
+    match Option::<Quantity>::deserialize(deserializer)? {
+        Some(Quantity(value)) => T::try_from(value)
+            .map(Some)
+            .map_err(|_| de::Error::custom(format!("quantity {} out of range", value))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Quantities {
+        #[serde(deserialize_with = "quantity")]
+        small: u8,
+        #[serde(default, deserialize_with = "optional_quantity")]
+        large: Option<u128>,
+    }
+
+    fn small(value: Value) -> Result<u8, serde_json::Error> {
+        serde_json::from_value::<Quantities>(json!({ "small": value })).map(|q| q.small)
+    }
+
+    #[test]
+    fn parses_quantities() {
+        assert_eq!(small(json!("0x1f")).unwrap(), 31);
+        assert_eq!(small(json!("0X1F")).unwrap(), 31);
+        assert_eq!(small(json!("0x001")).unwrap(), 1);
+        assert_eq!(small(json!("0x")).unwrap(), 0);
+        assert_eq!(small(json!(42)).unwrap(), 42);
+    }
+
+    #[test]
+    fn rejects_invalid_quantities() {
+        for value in [
+            json!("1f"),
+            json!("0x+1"),
+            json!("0xg"),
+            json!(-1),
+            json!(1.5),
+        ] {
+            assert!(small(value.clone()).is_err(), "{}", value);
+        }
+        let err = small(json!("0x100")).unwrap_err();
+        assert!(
+            err.to_string().contains("quantity 256 out of range"),
+            "{}",
+            err
+        );
+        let too_long = format!("0x1{}", "0".repeat(32));
+        assert!(small(json!(too_long)).is_err());
+    }
+
+    #[test]
+    fn parses_optional_quantities() {
+        let parse = |value: Value| serde_json::from_value::<Quantities>(value).map(|q| q.large);
+        assert_eq!(parse(json!({ "small": "0x0" })).unwrap(), None);
+        assert_eq!(
+            parse(json!({ "small": "0x0", "large": null })).unwrap(),
+            None
+        );
+        let max = format!("0x{}", "f".repeat(32));
+        assert_eq!(
+            parse(json!({ "small": "0x0", "large": max })).unwrap(),
+            Some(u128::MAX)
+        );
+    }
+
+    #[test]
+    fn deserializes_headers() {
+        let header: Header = serde_json::from_value(json!({
+            "hash": "0xabc",
+            "parentHash": "0xdef",
+            "number": "0x10",
+            "timestamp": "0x6553f100",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "miner": "0x0000000000000000000000000000000000000000",
+            "baseFeePerGas": "0x7",
+            "stateRoot": "0x123",
+        }))
+        .unwrap();
+        assert_eq!(header.number, 16);
+        assert_eq!(header.timestamp, 1_700_000_000);
+        assert_eq!(header.gas_limit, 30_000_000);
+        assert_eq!(header.base_fee_per_gas, Some(7));
+        assert_eq!(header.other["stateRoot"], "0x123");
+
+        // Before the London fork
+        let header: Header = serde_json::from_value(json!({
+            "hash": null,
+            "parentHash": "0xdef",
+            "number": 1,
+            "timestamp": "0x1",
+            "gasLimit": "0x1",
+            "gasUsed": "0x",
+        }))
+        .unwrap();
+        assert_eq!(header.base_fee_per_gas, None);
+        assert!(header.other.is_empty());
+    }
+
+    #[test]
+    fn deserializes_logs() {
+        let log: Log = serde_json::from_value(json!({
+            "address": "0xabc",
+            "topics": ["0x01", "0x02"],
+            "data": "0x",
+            "blockNumber": "0x10",
+            "blockHash": "0xdef",
+            "transactionHash": "0x123",
+            "transactionIndex": "0x0",
+            "logIndex": "0x3",
+            "removed": true,
+        }))
+        .unwrap();
+        assert_eq!(log.block_number, Some(16));
+        assert_eq!(log.transaction_index, Some(0));
+        assert_eq!(log.log_index, Some(3));
+        assert!(log.removed);
+
+        // Pending logs have no location yet
+        let log: Log = serde_json::from_value(json!({
+            "address": "0xabc",
+            "topics": [],
+            "data": "0x",
+            "blockNumber": null,
+            "logIndex": null,
+        }))
+        .unwrap();
+        assert_eq!(log.block_number, None);
+        assert_eq!(log.log_index, None);
+        assert!(!log.removed);
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use tokio::{sync::broadcast, time::Instant};
+
+/// Something that happened during the lifetime of a client.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A connection to `host` was established.
+    Connected {
+        /// The host connected to, or the endpoint of a duplex client.
+        host: String,
+    },
+    /// A request was sent to the server.
+    RequestSent {
+        /// The ID of the request.
+        id: serde_json::Value,
+        /// The method called.
+        method: String,
+    },
+    /// A response was received, possibly carrying a JSON-RPC error object.
+    ResponseReceived {
+        /// The ID of the request.
+        id: serde_json::Value,
+        /// The method called.
+        method: String,
+        /// The time taken by the call.
+        latency: Duration,
+    },
+    /// A call failed.
+    Failed {
+        /// The ID of the request.
+        id: serde_json::Value,
+        /// The method called.
+        method: String,
+        /// The kind of error, e.g. `timeout`.
+        error: &'static str,
+    },
+    /// The connection of a duplex client failed, failing the calls awaiting a response.
+    Disconnected {
+        /// Why the connection failed.
+        reason: String,
+    },
+    /// A duplex client attempts to reconnect after `delay`.
+    Reconnecting {
+        /// The number of the attempt, starting at 1.
+        attempt: usize,
+        /// The delay before the attempt.
+        delay: Duration,
+    },
+    /// The server rate limited the client, calls are held back until `until`.
+    RateLimited {
+        /// The end of the cool-down.
+        until: Instant,
+    },
+    /// A failed call is retried after `delay`.
+    RetryScheduled {
+        /// The ID of the request.
+        id: serde_json::Value,
+        /// The method called.
+        method: String,
+        /// The number of the retry, starting at 1.
+        attempt: usize,
+        /// The delay before the retry.
+        delay: Duration,
+    },
+}
+
+/// Broadcasts [`Event`]s to subscribers.
+///
+/// Register it on clients, duplex clients included, [`Retry`] layers and [`TlsConfig`]
+/// connectors. Clones share the same subscribers, and subscribers falling behind by more than the
+/// capacity miss the oldest events.
+///
+/// [`Retry`]: crate::layers::Retry
+/// [`TlsConfig`]: super::tls::TlsConfig
+#[derive(Clone, Debug)]
+pub struct Events(broadcast::Sender<Event>);
+
+impl Events {
+    /// Creates a channel buffering up to `capacity` events per subscriber.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        Events(broadcast::channel(capacity).0)
+    }
+
+    /// Subscribes to the events emitted from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+
+    /// Emit an event, only constructing it if there are subscribers.
+    pub(crate) fn emit(&self, event: impl FnOnce() -> Event) {
+        if self.0.receiver_count() > 0 {
+            let _ = self.0.send(event());
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use hyper::Request as HttpRequest;
+use tokio::sync::Mutex as AsyncMutex;
+use zeroize::Zeroizing;
+
+use super::{Body, Client, Config, HttpError};
+use crate::{
+    auth::{Signer, TokenSource},
+    clients::Error,
+};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub(super) url: String,
+    pub(super) user: Option<String>,
+    pub(super) password: Option<Zeroizing<String>>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("url", &self.url)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+/// The bearer token of a client, redacted from `Debug` output.
+#[derive(Default)]
+pub(super) struct Token {
+    value: RwLock<Option<Zeroizing<String>>>,
+    // Bumped each time the credentials are refreshed
+    pub(super) generation: AtomicU64,
+    // Held while refreshing, so calls rejected together refresh once
+    refreshing: AsyncMutex<()>,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token")
+    }
+}
+
+impl Token {
+    pub(super) fn new(token: String) -> Self {
+        Token {
+            value: RwLock::new(Some(Zeroizing::new(token))),
+            ..Token::default()
+        }
+    }
+
+    pub(super) fn get(&self) -> Option<Zeroizing<String>> {
+        self.value.read().unwrap().clone()
+    }
+}
+
+type ReauthFuture = Pin<Box<dyn Future<Output = Option<String>> + Send>>;
+
+/// An async callback minting a new bearer token after the server rejects the credentials.
+#[derive(Clone)]
+pub(super) struct ReauthHook(Arc<dyn Fn() -> ReauthFuture + Send + Sync>);
+
+impl fmt::Debug for ReauthHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReauthHook")
+    }
+}
+
+/// A [`TokenSource`] registered on a client.
+#[derive(Clone)]
+pub(super) struct SharedTokenSource(pub(super) Arc<dyn TokenSource>);
+
+impl fmt::Debug for SharedTokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TokenSource")
+    }
+}
+
+/// A [`Signer`] registered on a client.
+#[derive(Clone)]
+pub(super) struct SharedSigner(Arc<dyn Signer>);
+
+impl fmt::Debug for SharedSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Signer")
+    }
+}
+
+impl<S> Client<S> {
+    /// Authorize calls using a bearer token in place of the user and password.
+    ///
+    /// This doesn't change the token of the clients this was cloned from.
+    pub fn with_bearer_token<T: Into<String>>(mut self, token: T) -> Self {
+        Arc::make_mut(&mut self.config).token = Arc::new(Token::new(token.into()));
+        self
+    }
+
+    /// Authorize calls using bearer tokens obtained from `source` before each call.
+    ///
+    /// This takes precedence over a token set using [`with_bearer_token`]. When the server
+    /// responds with HTTP 401 or 403 the token is invalidated, and the call is replayed once
+    /// with a new token from `source`.
+    ///
+    /// [`with_bearer_token`]: Client::with_bearer_token
+    pub fn with_token_source<T: TokenSource>(mut self, source: T) -> Self {
+        Arc::make_mut(&mut self.config).token_source = Some(SharedTokenSource(Arc::new(source)));
+        self
+    }
+
+    /// Sign each HTTP request using `signer`, after the body is serialized.
+    pub fn with_signer<T: Signer>(mut self, signer: T) -> Self {
+        Arc::make_mut(&mut self.config).signer = Some(SharedSigner(Arc::new(signer)));
+        self
+    }
+
+    /// Sets a callback invoked when the server responds with HTTP 401 or 403.
+    ///
+    /// If the callback returns a new bearer token it replaces the current one and the rejected
+    /// call is replayed once. Calls rejected together invoke the callback once, and are replayed
+    /// with the same token. The callback isn't used when a token source is set, see
+    /// [`with_token_source`].
+    ///
+    /// [`with_token_source`]: Client::with_token_source
+    pub fn with_reauth<F, Fut>(mut self, reauth: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<String>> + Send + 'static,
+    {
+        Arc::make_mut(&mut self.config).reauth =
+            Some(ReauthHook(Arc::new(move || Box::pin(reauth()))));
+        self
+    }
+}
+
+/// Refresh the credentials rejected by the server, returning the token to replay the call with.
+///
+/// The call was sent with the credentials of `generation`. If they were refreshed since, by a
+/// call rejected concurrently, the refreshed token is returned as is.
+pub(super) async fn refresh_token<E>(
+    config: &Config,
+    generation: u64,
+) -> Result<Option<Zeroizing<String>>, HttpError<E>> {
+    let token = &config.token;
+    let _refreshing = token.refreshing.lock().await;
+    let refreshed = token.generation.load(Ordering::Acquire) != generation;
+    let new_token = match (&config.token_source, &config.reauth) {
+        (Some(SharedTokenSource(source)), _) => {
+            if !refreshed {
+                source.invalidate().await;
+            }
+            let new_token = source.token().await.map_err(Error::Auth)?;
+            Some(Zeroizing::new(new_token))
+        }
+        (None, Some(_)) if refreshed => token.get(),
+        (None, Some(ReauthHook(reauth))) => {
+            let new_token = reauth().await.map(Zeroizing::new);
+            if new_token.is_some() {
+                *token.value.write().unwrap() = new_token.clone();
+            }
+            new_token
+        }
+        (None, None) => return Ok(None),
+    };
+    if !refreshed && new_token.is_some() {
+        token.generation.fetch_add(1, Ordering::AcqRel);
+    }
+    Ok(new_token)
+}
+
+/// Sign the request if a signer is registered.
+pub(super) async fn sign_request<E>(
+    config: &Config,
+    request: HttpRequest<Body>,
+    body: &[u8],
+) -> Result<HttpRequest<Body>, HttpError<E>> {
+    match &config.signer {
+        Some(SharedSigner(signer)) => {
+            let (mut parts, body_stream) = request.into_parts();
+            signer.sign(&mut parts, body).await.map_err(Error::Auth)?;
+            Ok(HttpRequest::from_parts(parts, body_stream))
+        }
+        None => Ok(request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{atomic::AtomicUsize, Mutex},
+    };
+
+    use hyper::{body::Bytes, header::AUTHORIZATION, http::request::Parts, StatusCode};
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use serde_json::json;
+    use tokio::sync::Barrier;
+
+    use super::{
+        super::{
+            tests::{answered_by, result, status},
+            HyperClient,
+        },
+        *,
+    };
+    use crate::{
+        auth::jwt::JwtSource,
+        clients::{pool::Tracked, RequestFactory},
+        testing::MockServer,
+    };
+
+    fn authorization(parts: &Parts) -> String {
+        parts.headers[AUTHORIZATION].to_str().unwrap().to_owned()
+    }
+
+    fn token(client: &Client<HyperClient<Tracked<HttpConnector>>>) -> Option<String> {
+        client.config.token.get().as_deref().cloned()
+    }
+
+    #[tokio::test]
+    async fn replays_rejected_calls_once() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = sent.clone();
+        let reauths = Arc::new(AtomicUsize::new(0));
+        let counter = reauths.clone();
+        let client = answered_by(move |parts, body| {
+            let token = authorization(&parts);
+            received.lock().unwrap().push(token.clone());
+            async move {
+                match token.as_str() {
+                    "Bearer new" => result(&body, json!(true)),
+                    _ => status(StatusCode::UNAUTHORIZED),
+                }
+            }
+        })
+        .with_bearer_token("old")
+        .with_reauth(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Some("new".to_owned()) }
+        });
+
+        for _ in 0..2 {
+            let request = client.build_request().method("ping").finish().unwrap();
+            let response = client.send(request).await.unwrap();
+            assert_eq!(response.result, Some(json!(true)));
+        }
+        assert_eq!(reauths.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            ["Bearer old", "Bearer new", "Bearer new"]
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_calls_are_not_replayed() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let received = sent.clone();
+        let reauths = Arc::new(AtomicUsize::new(0));
+        let counter = reauths.clone();
+        let client = answered_by(move |_, _| {
+            received.fetch_add(1, Ordering::SeqCst);
+            async { status(StatusCode::UNAUTHORIZED) }
+        })
+        .with_reauth(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Some("new".to_owned()) }
+        });
+
+        let request = client.build_request().method("upload").finish().unwrap();
+        let params = futures_util::stream::iter(vec![Ok::<_, io::Error>(Bytes::from("[]"))]);
+        let err = client
+            .send_with_streamed_params(request, params)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            Error::Http {
+                status: StatusCode::UNAUTHORIZED,
+                ..
+            }
+        ));
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(reauths.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn calls_rejected_together_reauthorize_once() {
+        const CALLS: usize = 4;
+        // Each call is rejected once all were sent with the old token
+        let rejected = Arc::new(Barrier::new(CALLS));
+        let reauths = Arc::new(AtomicUsize::new(0));
+        let counter = reauths.clone();
+        let client = answered_by(move |parts, body| {
+            let rejected = rejected.clone();
+            async move {
+                if authorization(&parts) == "Bearer new" {
+                    return result(&body, json!(true));
+                }
+                rejected.wait().await;
+                status(StatusCode::UNAUTHORIZED)
+            }
+        })
+        .with_bearer_token("old")
+        .with_reauth(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Some("new".to_owned()) }
+        });
+
+        let calls = (0..CALLS).map(|_| {
+            let request = client.build_request().method("ping").finish().unwrap();
+            client.send(request)
+        });
+        for response in futures_util::future::join_all(calls).await {
+            assert_eq!(response.unwrap().result, Some(json!(true)));
+        }
+        assert_eq!(reauths.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refreshes_through_the_token_source() {
+        let minted = Arc::new(AtomicUsize::new(0));
+        let counter = minted.clone();
+        let source = JwtSource::new(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            async move { format!("token-{}", n) }
+        });
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = sent.clone();
+        let client = answered_by(move |parts, body| {
+            let token = authorization(&parts);
+            received.lock().unwrap().push(token.clone());
+            async move {
+                match token.as_str() {
+                    "Bearer token-0" => status(StatusCode::UNAUTHORIZED),
+                    _ => result(&body, json!(true)),
+                }
+            }
+        })
+        .with_token_source(source);
+
+        for _ in 0..2 {
+            let request = client.build_request().method("ping").finish().unwrap();
+            client.send(request).await.unwrap();
+        }
+        assert_eq!(
+            *sent.lock().unwrap(),
+            ["Bearer token-0", "Bearer token-1", "Bearer token-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn bearer_token_is_per_client() {
+        let client =
+            Client::new("http://127.0.0.1:1".to_owned(), None, None).with_bearer_token("original");
+        let other = client.clone().with_bearer_token("other");
+        assert_eq!(token(&client).as_deref(), Some("original"));
+        assert_eq!(token(&other).as_deref(), Some("other"));
+    }
+
+    #[tokio::test]
+    async fn invalid_bearer_token_fails_the_call() {
+        let server = MockServer::start().await.unwrap();
+        let client = Client::new(server.url(), None, None).with_bearer_token("line\nbreak");
+        let request = client.build_request().method("ping").params(json!([]));
+        let err = client.send(request.finish().unwrap()).await.unwrap_err();
+        assert!(matches!(err.into_inner(), Error::Auth(_)));
+        assert!(server.received().is_empty());
+    }
+}
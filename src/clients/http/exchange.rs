@@ -0,0 +1,555 @@
+use std::{
+    io,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime},
+};
+
+use bytes::{BufMut, BytesMut};
+use futures_util::TryStreamExt;
+use http_body_util::{BodyDataStream, BodyExt, StreamBody};
+use hyper::{
+    body::{Body as HttpBody, Bytes, Frame},
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION,
+        CONTENT_ENCODING, CONTENT_TYPE, COOKIE, PROXY_AUTHORIZATION, RETRY_AFTER,
+    },
+    Request as HttpRequest, Response as HttpResponse, StatusCode,
+};
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+use tower_service::Service;
+use tower_util::ServiceExt;
+use zeroize::Zeroizing;
+
+use super::{
+    auth::{refresh_token, sign_request, Credentials, SharedTokenSource},
+    Body, Config, ConnectionError, HttpError, SharedCodec,
+};
+use crate::{
+    auth::{basic_auth, bearer_auth, BoxError},
+    clients::{
+        batch::BatchStream, compression, cookie::CookieJar, encoding::Encoding, events::Event,
+        raw::Expected, stream::ResultStream, trace::TraceHeaders, Error,
+    },
+    layers::rate_limit::{Cooldown, DEFAULT_COOLDOWN},
+    objects::{RawResponse, Request, Response, ResponseRef, RpcError},
+};
+
+/// The state needed to perform a single call.
+pub(super) struct Exchange<S> {
+    pub(super) inner_service: S,
+    pub(super) credentials: Arc<Credentials>,
+    pub(super) config: Arc<Config>,
+    pub(super) cooldown: Cooldown,
+    pub(super) deadline: Option<Instant>,
+    pub(super) trace: Option<TraceHeaders>,
+    pub(super) accept_compressed: bool,
+    // Streamed in place of the encoded params
+    pub(super) params: Option<Body>,
+    // Sent in place of the encoded request
+    pub(super) payload: Option<Bytes>,
+}
+
+impl<S, B> Exchange<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    pub(super) async fn run(
+        mut self,
+        mut request: Request,
+    ) -> Result<Response, HttpError<S::Error>> {
+        let (status, body) = self.exchange(&mut request).await?;
+        let mut response = self.decode(status, &body)?;
+
+        for interceptor in self.config.interceptors.iter() {
+            interceptor.after(&mut response).await;
+        }
+        for on_response in &self.config.hooks.on_response {
+            on_response(&response);
+        }
+        Ok(response)
+    }
+
+    /// Like [`run`], streaming the params from `params`.
+    ///
+    /// [`run`]: Exchange::run
+    pub(super) async fn run_streamed(
+        mut self,
+        request: Request,
+        params: Body,
+    ) -> Result<Response, HttpError<S::Error>> {
+        if self.config.codec.is_some() {
+            return Err(Error::Unsupported("only JSON params can be streamed"));
+        }
+        if self.config.signer.is_some() {
+            return Err(Error::Unsupported("streamed params can't be signed"));
+        }
+
+        self.params = Some(params);
+        self.run(request).await
+    }
+
+    /// Like [`run`], leaving the result unparsed and skipping the response interceptors and hooks.
+    ///
+    /// [`run`]: Exchange::run
+    pub(super) async fn run_raw(
+        mut self,
+        mut request: Request,
+    ) -> Result<RawResponse, HttpError<S::Error>> {
+        let (status, body) = self.exchange(&mut request).await?;
+        if self.config.codec.is_none() {
+            return decode_response(status, &body, from_json);
+        }
+
+        // Only JSON results can be kept unparsed, others are transcoded
+        let response = self.decode(status, &body)?;
+        Ok(RawResponse {
+            result: response
+                .result
+                .map(|result| serde_json::value::to_raw_value(&result).unwrap()), // This is safe
+            error: response.error,
+            id: response.id,
+            jsonrpc: response.jsonrpc,
+        })
+    }
+
+    /// Like [`run`], returning the response body without decoding it.
+    ///
+    /// [`run`]: Exchange::run
+    pub(super) async fn run_body(
+        mut self,
+        mut request: Request,
+    ) -> Result<Bytes, HttpError<S::Error>> {
+        let (status, body) = self.exchange(&mut request).await?;
+        if status.is_success() {
+            return Ok(body);
+        }
+
+        // Some servers send JSON-RPC errors with a non-success status
+        match ResponseRef::from_slice(&body) {
+            Ok(response) if response.is_error() => Ok(body),
+            _ => Err(Error::Http {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            }),
+        }
+    }
+
+    /// Like [`run_body`], sending a pre-encoded request in place of `request`.
+    ///
+    /// [`run_body`]: Exchange::run_body
+    pub(super) async fn run_payload(
+        mut self,
+        request: Request,
+        payload: Bytes,
+        expected: Option<Expected>,
+    ) -> Result<Bytes, HttpError<S::Error>> {
+        if self.config.codec.is_some() {
+            return Err(Error::Unsupported("only JSON requests can be pre-encoded"));
+        }
+
+        self.payload = Some(payload);
+        let body = self.run_body(request).await?;
+        if let Some(expected) = expected {
+            expected.check(&body)?;
+        }
+        Ok(body)
+    }
+
+    /// Like [`run`], streaming the result as it is received.
+    ///
+    /// [`run`]: Exchange::run
+    pub(super) async fn run_stream(
+        mut self,
+        mut request: Request,
+    ) -> Result<ResultStream<S::Error>, HttpError<S::Error>> {
+        if self.config.codec.is_some() {
+            return Err(Error::Unsupported("only JSON results can be streamed"));
+        }
+
+        self.accept_compressed = false;
+        let response = self.send(&mut request).await?;
+        if response.headers().contains_key(CONTENT_ENCODING) {
+            let err = io::Error::other("compressed results can't be streamed");
+            return Err(Error::Decode(Box::new(err)));
+        }
+        let status = response.status();
+        if status.is_success() {
+            return Ok(ResultStream::new(response.into_body(), request.id.clone()));
+        }
+
+        let body = read_body(response.into_body(), self.config.max_response_size).await?;
+        // Only error objects are accepted with a non-success status
+        let response: Response = decode_response(status, &body, from_json)?;
+        Err(Error::Rpc(response.error().unwrap())) // This is safe
+    }
+
+    /// Like [`run_payload`], parsing the responses to the batch as they are received.
+    ///
+    /// [`run_payload`]: Exchange::run_payload
+    pub(super) async fn run_batch_stream(
+        mut self,
+        mut request: Request,
+        payload: Bytes,
+    ) -> Result<BatchStream<BodyDataStream<Body>>, HttpError<S::Error>> {
+        if self.config.codec.is_some() {
+            return Err(Error::Unsupported("only JSON batches can be streamed"));
+        }
+
+        self.accept_compressed = false;
+        self.payload = Some(payload);
+        let response = self.send(&mut request).await?;
+        if response.headers().contains_key(CONTENT_ENCODING) {
+            let err = io::Error::other("compressed batches can't be streamed");
+            return Err(Error::Decode(Box::new(err)));
+        }
+        let status = response.status();
+        if status.is_success() {
+            return Ok(BatchStream::new(BodyDataStream::new(response.into_body())));
+        }
+
+        let body = read_body(response.into_body(), self.config.max_response_size).await?;
+        // Only error objects are accepted with a non-success status
+        let response: Response = decode_response(status, &body, from_json)?;
+        Err(Error::Rpc(response.error().unwrap())) // This is safe
+    }
+
+    /// Send the request, returning the status and decompressed body of the response.
+    async fn exchange(
+        &mut self,
+        request: &mut Request,
+    ) -> Result<(StatusCode, Bytes), HttpError<S::Error>> {
+        let response = self.send(request).await?;
+        let status = response.status();
+        let (parts, body) = response.into_parts();
+        let limit = self.config.max_response_size;
+        let body = read_body(body, limit).await?;
+        let body = compression::decompress(&parts.headers, body, limit)
+            .map_err(|err| Error::Decode(Box::new(err)))?;
+        if let Some(limit) = limit.filter(|limit| body.len() > *limit) {
+            return Err(Error::ResponseTooLarge { limit });
+        }
+        if let (None, Some(limits)) = (&self.config.codec, &self.config.parse_limits) {
+            limits.check(&body)?;
+        }
+        Ok((status, body))
+    }
+
+    /// Decode a response body using the client's codec.
+    fn decode(&self, status: StatusCode, body: &[u8]) -> Result<Response, HttpError<S::Error>> {
+        match &self.config.codec {
+            Some(SharedCodec(codec)) => decode_response(status, body, |body| {
+                codec.decode(body).map_err(Error::Decode)
+            }),
+            None => decode_response(status, body, from_json),
+        }
+    }
+
+    /// Send the request, handling re-authorization and rate limiting.
+    async fn send(
+        &mut self,
+        request: &mut Request,
+    ) -> Result<HttpResponse<Body>, HttpError<S::Error>> {
+        // Pre-encoded requests are only described by `request`
+        let payload = self.payload.take();
+        if payload.is_none() {
+            for interceptor in self.config.interceptors.iter() {
+                interceptor.before(request).await;
+            }
+            for on_request in &self.config.hooks.on_request {
+                on_request(request);
+            }
+        }
+
+        let content_type = HeaderValue::from_static(match &self.config.codec {
+            Some(SharedCodec(codec)) => codec.content_type(),
+            None => Encoding::Json.content_type(),
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.clone());
+        headers.insert(ACCEPT, content_type);
+        if let Some(accept_encoding) =
+            compression::accept_encoding().filter(|_| self.accept_compressed)
+        {
+            headers.insert(ACCEPT_ENCODING, accept_encoding);
+        }
+        if let Some((name, key)) = &self.config.api_key {
+            headers.insert(name.clone(), key.clone());
+        }
+        if let Some(auth) = &self.config.proxy_auth {
+            headers.insert(PROXY_AUTHORIZATION, auth.clone());
+        }
+
+        // Propagate the deadline to the server
+        if let (Some(deadline), Some(name)) = (self.deadline, &self.config.deadline_header) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            headers.insert(name.clone(), (remaining.as_millis() as u64).into());
+        }
+        if let Some(trace) = &self.trace {
+            if let Ok(traceparent) = HeaderValue::from_str(&trace.traceparent) {
+                headers.insert(TRACEPARENT, traceparent);
+            }
+            match HeaderValue::from_str(&trace.tracestate) {
+                Ok(tracestate) if !tracestate.is_empty() => {
+                    headers.insert(TRACESTATE, tracestate);
+                }
+                _ => {}
+            }
+        }
+        if let Some(cookie) = self.config.cookies.as_ref().and_then(CookieJar::header) {
+            headers.insert(COOKIE, cookie);
+        }
+
+        // Streamed params can't be sent twice, so the call can't be replayed
+        let params = self.params.take();
+        let replayable = params.is_none();
+        let body = match (&params, payload) {
+            (Some(_), _) => Bytes::new(),
+            (None, Some(payload)) => payload,
+            (None, None) => {
+                let buffers = &self.config.buffers;
+                match &self.config.codec {
+                    Some(SharedCodec(codec)) => buffers
+                        .write(|buffer| codec.encode(request, buffer))
+                        .map_err(Error::Encode)?,
+                    None => buffers
+                        .write(|buffer| serde_json::to_writer(buffer.writer(), request))
+                        .unwrap(), // This is safe
+                }
+            }
+        };
+        #[cfg(feature = "gzip")]
+        let body = match self.config.compress_above {
+            Some(threshold) if body.len() > threshold => {
+                headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+                compression::gzip(&body)
+            }
+            _ => body,
+        };
+        // Read before the token, a refresh completing in between is then made again
+        let generation = self.config.token.generation.load(Ordering::Acquire);
+        let token = match &self.config.token_source {
+            Some(SharedTokenSource(source)) => {
+                Some(Zeroizing::new(source.token().await.map_err(Error::Auth)?))
+            }
+            None => self.config.token.get(),
+        };
+        let http_body = match params {
+            Some(params) => envelope(request, params),
+            None => Body::from(body.clone()),
+        };
+        let http_request = build_http_request(
+            &self.credentials,
+            token.as_deref().map(String::as_str),
+            &headers,
+            http_body,
+        )?;
+        let http_request = sign_request(&self.config, http_request, &body).await?;
+
+        if let Some(events) = &self.config.events {
+            events.emit(|| Event::RequestSent {
+                id: request.id.clone(),
+                method: request.method.clone(),
+            });
+        }
+
+        // Send request, the service is ready on the first attempt
+        let mut response = self
+            .inner_service
+            .call(http_request)
+            .await
+            .map_err(ConnectionError::Service)
+            .map_err(Error::Connection)?
+            .map(Body::new);
+        if let Some(cookies) = &self.config.cookies {
+            cookies.store(response.headers());
+        }
+
+        // Refresh the credentials and replay once
+        let status = response.status();
+        if replayable && (status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN) {
+            if let Some(new_token) = refresh_token(&self.config, generation).await? {
+                if let Some(cookies) = &self.config.cookies {
+                    match cookies.header() {
+                        Some(cookie) => headers.insert(COOKIE, cookie),
+                        None => headers.remove(COOKIE),
+                    };
+                }
+                let http_request = build_http_request(
+                    &self.credentials,
+                    Some(new_token.as_str()),
+                    &headers,
+                    Body::from(body.clone()),
+                )?;
+                let http_request = sign_request(&self.config, http_request, &body).await?;
+                response = self
+                    .inner_service
+                    .ready_and()
+                    .await
+                    .map_err(ConnectionError::Poll)
+                    .map_err(Error::Connection)?
+                    .call(http_request)
+                    .await
+                    .map_err(ConnectionError::Service)
+                    .map_err(Error::Connection)?
+                    .map(Body::new);
+                if let Some(cookies) = &self.config.cookies {
+                    cookies.store(response.headers());
+                }
+            }
+        }
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = parse_retry_after(response.headers());
+            self.cooldown
+                .extend(retry_after.unwrap_or(DEFAULT_COOLDOWN));
+            if let (Some(events), Some(until)) = (&self.config.events, self.cooldown.until()) {
+                events.emit(|| Event::RateLimited { until });
+            }
+            let error = self.refusal_error(response).await;
+            return Err(Error::RateLimited { retry_after, error });
+        }
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            let retry_after = parse_retry_after(response.headers());
+            let error = self.refusal_error(response).await;
+            return Err(Error::Unavailable { retry_after, error });
+        }
+        Ok(response)
+    }
+
+    /// Read the JSON-RPC error object sent along with a refused call, if any.
+    async fn refusal_error(&mut self, response: HttpResponse<Body>) -> Option<RpcError> {
+        let status = response.status();
+        let (parts, body) = response.into_parts();
+        let limit = self.config.max_response_size;
+        let body = read_body::<S::Error>(body, limit).await.ok()?;
+        let body = compression::decompress(&parts.headers, body, limit).ok()?;
+        self.decode(status, &body).ok()?.error()
+    }
+}
+
+/// Read a response body, failing once it exceeds `limit` bytes.
+async fn read_body<E>(mut body: Body, limit: Option<usize>) -> Result<Bytes, HttpError<E>> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            return Ok(body
+                .collect()
+                .await
+                .map_err(ConnectionError::Body)
+                .map_err(Error::Connection)?
+                .to_bytes())
+        }
+    };
+
+    // Reject bodies declared too large before reading them
+    if body.size_hint().lower() > limit as u64 {
+        return Err(Error::ResponseTooLarge { limit });
+    }
+    let mut buffer = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        let frame = frame
+            .map_err(ConnectionError::Body)
+            .map_err(Error::Connection)?;
+        let chunk = match frame.data_ref() {
+            Some(chunk) => chunk,
+            None => continue,
+        };
+        if buffer.len() + chunk.len() > limit {
+            return Err(Error::ResponseTooLarge { limit });
+        }
+        buffer.extend_from_slice(chunk);
+    }
+    Ok(buffer.freeze())
+}
+
+/// Decode a response body, accepting error objects sent with a non-success status.
+fn decode_response<R, E, F>(
+    status: StatusCode,
+    body: &[u8],
+    decode: F,
+) -> Result<Response<R>, HttpError<E>>
+where
+    F: FnOnce(&[u8]) -> Result<Response<R>, HttpError<E>>,
+{
+    if status.is_success() {
+        return decode(body);
+    }
+
+    // Some servers send JSON-RPC errors with a non-success status
+    match decode(body) {
+        Ok(response) if response.is_error() => Ok(response),
+        _ => Err(Error::Http {
+            status,
+            body: String::from_utf8_lossy(body).into_owned(),
+        }),
+    }
+}
+
+fn from_json<R: DeserializeOwned, E>(body: &[u8]) -> Result<R, HttpError<E>> {
+    serde_json::from_slice(body).map_err(Error::Json)
+}
+
+pub(super) const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+pub(super) const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// Wrap streamed params in the envelope of `request`, in place of its params.
+fn envelope(request: &Request, params: Body) -> Body {
+    use futures_util::stream::{self, StreamExt};
+
+    let head = format!(
+        r#"{{"jsonrpc":{},"id":{},"method":{},"params":"#,
+        serde_json::Value::from(request.jsonrpc.as_str()),
+        request.id,
+        serde_json::Value::from(request.method.as_str()),
+    );
+    let head = stream::iter(Some(Ok(Bytes::from(head))));
+    let tail = stream::iter(Some(Ok(Bytes::from_static(b"}"))));
+    let data = head.chain(BodyDataStream::new(params)).chain(tail);
+    Body::new(StreamBody::new(data.map_ok(Frame::data)))
+}
+
+/// Build the HTTP request carrying the serialized JSON-RPC request.
+///
+/// Fails with [`Error::Auth`] if `token` can't be sent in a header, such as a token containing a
+/// newline.
+fn build_http_request<E>(
+    credentials: &Credentials,
+    token: Option<&str>,
+    headers: &HeaderMap,
+    body: Body,
+) -> Result<HttpRequest<Body>, Error<E>> {
+    let mut builder = hyper::Request::post(&credentials.url);
+
+    // Add authorization
+    if let Some(token) = token {
+        let value = bearer_auth(token)
+            .map_err(|_| Error::Auth("bearer token is not a valid header value".into()))?;
+        builder = builder.header(AUTHORIZATION, value);
+    } else if let Some(ref user) = credentials.user {
+        let password = credentials.password.as_deref().map_or("", String::as_str);
+        builder = builder.header(AUTHORIZATION, basic_auth(user, password));
+    };
+
+    // Add headers and body
+    if let Some(map) = builder.headers_mut() {
+        map.extend(headers.clone());
+    }
+    Ok(builder.body(body).unwrap()) // This is safe
+}
+
+/// Parse the `Retry-After` header, either as delay in seconds or as an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::from_secs(0)),
+    )
+}
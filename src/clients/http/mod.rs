@@ -0,0 +1,1640 @@
+mod auth;
+mod exchange;
+mod send;
+
+pub use self::auth::Credentials;
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    error, fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_core::{
+    task::{Context, Poll},
+    Future,
+};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
+use hyper::{
+    body::{Body as HttpBody, Bytes, Frame, SizeHint},
+    header::{HeaderName, HeaderValue},
+    Request as HttpRequest, Response as HttpResponse,
+};
+#[cfg(feature = "tls-native")]
+use hyper_tls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{
+        connect::{Connect, HttpConnector},
+        Client as LegacyClient,
+    },
+    rt::{TokioExecutor, TokioTimer},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{timeout_at, Instant},
+};
+use tokio_util::sync::{CancellationToken, PollSemaphore};
+use tower_service::Service;
+use zeroize::Zeroizing;
+
+use self::{
+    auth::{ReauthHook, SharedSigner, SharedTokenSource, Token},
+    exchange::Exchange,
+};
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+use super::tls::{Connector, TlsConfig, TlsError};
+use super::{
+    batch::BatchStream,
+    buffer::BufferPool,
+    cookie::CookieJar,
+    encoding::{Codec, Encoding},
+    events::{Event, Events},
+    latency::{Latency, LatencyWindow},
+    limits::ParseLimits,
+    pool::{Pool, Tracked},
+    raw::RawIds,
+    stream::ResultStream,
+    tcp::TcpOptions,
+    trace::TraceContext,
+    CallContext, Error, Interceptor, Interceptors, RequestFactory,
+};
+use crate::{
+    auth::BoxError,
+    layers::rate_limit::Cooldown,
+    objects::{Request, RequestBuilder, Response},
+};
+
+pub type HttpError<E> = Error<ConnectionError<E>>;
+
+/// The body of the HTTP requests sent by a [`Client`], and of the responses it reads.
+#[derive(Debug)]
+pub struct Body(UnsyncBoxBody<Bytes, BoxError>);
+
+impl Body {
+    /// Wraps any body of [`Bytes`].
+    pub fn new<B>(body: B) -> Self
+    where
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        Body(body.map_err(Into::into).boxed_unsync())
+    }
+
+    /// An empty body.
+    pub fn empty() -> Self {
+        Body::from(Bytes::new())
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::new(Full::new(bytes))
+    }
+}
+
+impl HttpBody for Body {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+        Pin::new(&mut self.0).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.0.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.0.size_hint()
+    }
+}
+
+/// The hyper client used by the [`Client`] constructors.
+pub type HyperClient<C> = LegacyClient<C, Body>;
+
+/// Error specific to HTTP connections.
+#[derive(Debug)]
+pub enum ConnectionError<E> {
+    Poll(E),
+    Service(E),
+    Body(BoxError),
+}
+
+impl<E: fmt::Display> fmt::Display for ConnectionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Poll(err) => write!(f, "polling error, {}", err),
+            Self::Service(err) => write!(f, "service error, {}", err),
+            Self::Body(err) => write!(f, "body error, {}", err),
+        }
+    }
+}
+
+impl<E: fmt::Display + fmt::Debug> error::Error for ConnectionError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // The inner errors are displayed along with the error, skip to their causes
+        match self {
+            Self::Poll(_) | Self::Service(_) => None,
+            Self::Body(err) => err.source(),
+        }
+    }
+}
+
+/// A [`Codec`] registered on a client.
+#[derive(Clone)]
+struct SharedCodec(Arc<dyn Codec>);
+
+impl fmt::Debug for SharedCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Codec")
+    }
+}
+
+type RequestHook = Arc<dyn Fn(&Request) + Send + Sync>;
+type ResponseHook = Arc<dyn Fn(&Response) + Send + Sync>;
+type ErrorHook<E> = Arc<dyn Fn(&HttpError<E>) + Send + Sync>;
+
+/// Callbacks observing the requests, responses and errors of a client.
+#[derive(Clone, Default)]
+struct Hooks {
+    on_request: Vec<RequestHook>,
+    on_response: Vec<ResponseHook>,
+    // Each holds an `ErrorHook<E>`, where `E` is the error of the inner service
+    on_error: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_request", &self.on_request.len())
+            .field("on_response", &self.on_response.len())
+            .field("on_error", &self.on_error.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    fn error<E: 'static>(&self, error: &HttpError<E>) {
+        for on_error in &self.on_error {
+            if let Some(on_error) = on_error.downcast_ref::<ErrorHook<E>>() {
+                on_error(error);
+            }
+        }
+    }
+}
+
+/// A [`TraceContext`] registered on a client.
+#[derive(Clone)]
+struct SharedTraceContext(Arc<dyn TraceContext>);
+
+impl fmt::Debug for SharedTraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceContext")
+    }
+}
+
+/// Settings shared between clones of a [`Client`].
+#[derive(Clone, Debug, Default)]
+struct Config {
+    token: Arc<Token>,
+    token_source: Option<SharedTokenSource>,
+    signer: Option<SharedSigner>,
+    reauth: Option<ReauthHook>,
+    cancellation: Option<CancellationToken>,
+    timeout: Option<Duration>,
+    deadline_header: Option<HeaderName>,
+    api_key: Option<(HeaderName, HeaderValue)>,
+    // Sent with plain HTTP requests forwarded by a proxy
+    proxy_auth: Option<HeaderValue>,
+    cookies: Option<CookieJar>,
+    trace_context: Option<SharedTraceContext>,
+    interceptors: Interceptors,
+    hooks: Hooks,
+    latency: Option<Arc<LatencyWindow>>,
+    #[cfg(feature = "tracing")]
+    slow_threshold: Option<Duration>,
+    events: Option<Events>,
+    // JSON bodies are handled directly when `None`
+    codec: Option<SharedCodec>,
+    buffers: Arc<BufferPool>,
+    max_response_size: Option<usize>,
+    parse_limits: Option<ParseLimits>,
+    raw_ids: RawIds,
+    #[cfg(feature = "gzip")]
+    compress_above: Option<usize>,
+    // The connections made by the connector of the client, if it tracks them
+    pool: Option<Arc<Pool>>,
+}
+
+/// A snapshot of the activity of a client and its clones, see [`Client::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of open connections, pooled by the client whether idle or busy.
+    ///
+    /// Clients built with [`Client::from_service`] can't see the connections of their service,
+    /// and report none. Duplex clients report their connection while connected.
+    pub connections: usize,
+    /// The number of calls awaiting a response.
+    pub in_flight: usize,
+    /// The number of calls made.
+    pub calls: u64,
+    /// The number of calls which failed, not counting JSON-RPC error objects.
+    pub failures: u64,
+    /// The number of times the connection was re-established, by reconnecting duplex clients.
+    pub reconnects: u64,
+}
+
+/// The counters of the activity of a client and its clones, see [`Stats`].
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    in_flight: AtomicUsize,
+    calls: AtomicU64,
+    failures: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl Counters {
+    /// Count the connection as re-established.
+    pub(crate) fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        Stats {
+            connections: 0,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            calls: self.calls.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a call as in-flight until dropped.
+pub(crate) struct InFlight(Arc<Counters>);
+
+impl InFlight {
+    pub(crate) fn new(counters: Arc<Counters>) -> Self {
+        counters.calls.fetch_add(1, Ordering::Relaxed);
+        counters.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(counters)
+    }
+}
+
+impl InFlight {
+    /// Count the call as failed.
+    pub(crate) fn failed(&self) {
+        self.0.failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A handle to a remote HTTP JSON-RPC server.
+#[derive(Debug)]
+pub struct Client<S> {
+    credentials: Arc<Credentials>,
+    config: Arc<Config>,
+    nonce: Arc<AtomicUsize>,
+    counters: Arc<Counters>,
+    in_flight: Option<PollSemaphore>,
+    permit: Option<OwnedSemaphorePermit>,
+    cooldown: Cooldown,
+    inner_service: S,
+}
+
+impl<S: Clone> Clone for Client<S> {
+    fn clone(&self) -> Self {
+        // The permit is reserved by this handle only, clones must acquire their own.
+        Client {
+            credentials: self.credentials.clone(),
+            config: self.config.clone(),
+            nonce: self.nonce.clone(),
+            counters: self.counters.clone(),
+            in_flight: self.in_flight.clone(),
+            permit: None,
+            cooldown: self.cooldown.clone(),
+            inner_service: self.inner_service.clone(),
+        }
+    }
+}
+
+impl<S> Client<S> {
+    /// Creates a new HTTP client from a [`Service`].
+    ///
+    /// This is how transports the crate doesn't provide are plugged in, such as a [`HyperClient`]
+    /// built on a connector dialing `.onion` endpoints through Tor. No Tor transport is built in.
+    ///
+    /// [`Service`]: tower::Service
+    pub fn from_service(
+        service: S,
+        url: String,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        let credentials = Arc::new(Credentials {
+            url,
+            user,
+            password: password.map(Zeroizing::new),
+        });
+        Client {
+            credentials,
+            config: Arc::new(Config::default()),
+            inner_service: service,
+            nonce: Arc::new(AtomicUsize::new(0)),
+            counters: Arc::new(Counters::default()),
+            in_flight: None,
+            permit: None,
+            cooldown: Cooldown::new(),
+        }
+    }
+
+    /// Aborts all in-flight and future calls with [`Error::Cancelled`] once `token` is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        Arc::make_mut(&mut self.config).cancellation = Some(token);
+        self
+    }
+
+    /// Fails calls with [`Error::Timeout`] if they take longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).timeout = Some(timeout);
+        self
+    }
+
+    /// Sends the time remaining until the call deadline, in milliseconds, in the `name` header.
+    ///
+    /// The deadline is given by [`send_with_deadline`] or the timeout, whichever is earlier.
+    ///
+    /// [`send_with_deadline`]: Client::send_with_deadline
+    pub fn with_deadline_header(mut self, name: HeaderName) -> Self {
+        Arc::make_mut(&mut self.config).deadline_header = Some(name);
+        self
+    }
+
+    /// Authorize calls by sending `key` in the `name` header, e.g. `X-API-Key`.
+    ///
+    /// The key is marked as sensitive and is redacted from `Debug` output.
+    pub fn with_api_key(mut self, name: HeaderName, mut key: HeaderValue) -> Self {
+        key.set_sensitive(true);
+        Arc::make_mut(&mut self.config).api_key = Some((name, key));
+        self
+    }
+
+    /// Stores cookies set by the server in `jar` and sends them with each call.
+    pub fn with_cookie_jar(mut self, jar: CookieJar) -> Self {
+        Arc::make_mut(&mut self.config).cookies = Some(jar);
+        self
+    }
+
+    /// Sends the `traceparent` and `tracestate` headers of the trace active when a call is made.
+    ///
+    /// Enable the `opentelemetry` feature and use [`OpenTelemetry`] to propagate the current
+    /// OpenTelemetry context.
+    ///
+    /// [`OpenTelemetry`]: super::trace::OpenTelemetry
+    pub fn with_trace_context<T: TraceContext>(mut self, context: T) -> Self {
+        Arc::make_mut(&mut self.config).trace_context = Some(SharedTraceContext(Arc::new(context)));
+        self
+    }
+
+    /// Registers an [`Interceptor`], run after those already registered.
+    pub fn with_interceptor<I: Interceptor>(mut self, interceptor: I) -> Self {
+        Arc::make_mut(&mut self.config)
+            .interceptors
+            .push(interceptor);
+        self
+    }
+
+    /// Registers a callback invoked with each request as it is sent, after the interceptors ran.
+    pub fn with_on_request<F>(mut self, on_request: F) -> Self
+    where
+        F: Fn(&Request) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.config)
+            .hooks
+            .on_request
+            .push(Arc::new(on_request));
+        self
+    }
+
+    /// Registers a callback invoked with each response, after the interceptors ran.
+    ///
+    /// This includes responses carrying a JSON-RPC error object.
+    pub fn with_on_response<F>(mut self, on_response: F) -> Self
+    where
+        F: Fn(&Response) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.config)
+            .hooks
+            .on_response
+            .push(Arc::new(on_response));
+        self
+    }
+
+    /// Tracks the latency of the last `samples` successful calls to each method.
+    ///
+    /// The percentiles are available from [`latency`] and [`latencies`]. Calls returning a
+    /// JSON-RPC error object count as successful.
+    ///
+    /// [`latency`]: Client::latency
+    /// [`latencies`]: Client::latencies
+    pub fn with_latency_window(mut self, samples: usize) -> Self {
+        Arc::make_mut(&mut self.config).latency = Some(Arc::new(LatencyWindow::new(samples)));
+        self
+    }
+
+    /// Emits a warning event for calls taking longer than `threshold`.
+    #[cfg(feature = "tracing")]
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        Arc::make_mut(&mut self.config).slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Encode request and response bodies using `encoding`, JSON by default.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        Arc::make_mut(&mut self.config).codec = if encoding == Encoding::Json {
+            None
+        } else {
+            Some(SharedCodec(Arc::new(encoding)))
+        };
+        self
+    }
+
+    /// Encode request and response bodies using `codec`.
+    ///
+    /// Results are only streamed or returned unparsed with the default JSON encoding, raw results
+    /// are transcoded from the responses decoded.
+    pub fn with_codec<C: Codec + 'static>(mut self, codec: C) -> Self {
+        Arc::make_mut(&mut self.config).codec = Some(SharedCodec(Arc::new(codec)));
+        self
+    }
+
+    /// Compress request bodies larger than `threshold` bytes using gzip.
+    ///
+    /// The server must accept `Content-Encoding: gzip` requests.
+    #[cfg(feature = "gzip")]
+    pub fn with_request_compression(mut self, threshold: usize) -> Self {
+        Arc::make_mut(&mut self.config).compress_above = Some(threshold);
+        self
+    }
+
+    /// Fails calls with [`Error::ResponseTooLarge`] once the response body exceeds `limit` bytes.
+    ///
+    /// The limit applies to both the received and the decompressed body.
+    pub fn with_max_response_size(mut self, limit: usize) -> Self {
+        Arc::make_mut(&mut self.config).max_response_size = Some(limit);
+        self
+    }
+
+    /// Checks JSON response bodies against `limits` before parsing them.
+    pub fn with_parse_limits(mut self, limits: ParseLimits) -> Self {
+        Arc::make_mut(&mut self.config).parse_limits = Some(limits);
+        self
+    }
+
+    /// Sets how the ids of requests sent with [`send_raw`] and [`send_raw_batch`] are treated.
+    ///
+    /// [`send_raw`]: Client::send_raw
+    /// [`send_raw_batch`]: Client::send_raw_batch
+    pub fn with_raw_ids(mut self, ids: RawIds) -> Self {
+        Arc::make_mut(&mut self.config).raw_ids = ids;
+        self
+    }
+
+    /// Emits the requests sent, responses received and calls failed to `events`.
+    ///
+    /// Clients built by the crate, rather than from a service, also emit the connections they
+    /// establish. Their clones share their connections, so these are emitted to the events set
+    /// last.
+    pub fn with_events(mut self, events: Events) -> Self {
+        if let Some(pool) = &self.config.pool {
+            pool.set_events(events.clone());
+        }
+        Arc::make_mut(&mut self.config).events = Some(events);
+        self
+    }
+
+    /// Limits the number of outstanding calls to `limit`.
+    ///
+    /// Once the limit is reached [`poll_ready`] returns [`Poll::Pending`] until a call completes.
+    /// The limit is shared with clones made after this is set.
+    ///
+    /// [`poll_ready`]: Service::poll_ready
+    pub fn with_max_in_flight(mut self, limit: usize) -> Self {
+        self.in_flight = Some(PollSemaphore::new(Arc::new(Semaphore::new(limit))));
+        self.permit = None;
+        self
+    }
+
+    /// Sets the longest cool-down applied after being rate limited, a minute by default.
+    ///
+    /// The server requests the delay in the `Retry-After` header, this bounds how long calls
+    /// are held back whatever it asks for. The cool-down is shared with clones made after this
+    /// is set.
+    pub fn with_max_cooldown(mut self, max: Duration) -> Self {
+        self.cooldown.set_max(max);
+        self
+    }
+
+    /// Returns the instant until which calls are held back after being rate limited.
+    pub fn cooldown(&self) -> Option<Instant> {
+        self.cooldown.until()
+    }
+
+    /// Returns a snapshot of the connections of this client and of the calls made by it and its
+    /// clones.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            connections: self.config.pool.as_ref().map_or(0, |pool| pool.open()),
+            ..self.counters.stats()
+        }
+    }
+
+    /// Returns the latency percentiles of recent calls to `method`.
+    ///
+    /// This is `None` unless enabled using [`with_latency_window`].
+    ///
+    /// [`with_latency_window`]: Client::with_latency_window
+    pub fn latency(&self, method: &str) -> Option<Latency> {
+        self.config.latency.as_ref()?.get(method)
+    }
+
+    /// Returns the latency percentiles of recent calls, by method.
+    pub fn latencies(&self) -> HashMap<String, Latency> {
+        match &self.config.latency {
+            Some(latency) => latency.all(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Increment nonce and return the last value.
+    pub fn next_nonce(&self) -> usize {
+        self.nonce.load(Ordering::Acquire)
+    }
+
+    /// Take the next request id from the nonce.
+    fn next_id(&self) -> serde_json::Value {
+        serde_json::Value::Number(self.nonce.fetch_add(1, Ordering::AcqRel).into())
+    }
+
+    /// Describe the call made with `request`, for annotating errors.
+    fn call_context(&self, request: &Request) -> CallContext {
+        CallContext {
+            id: request.id.clone(),
+            method: request.method.clone(),
+            endpoint: self.credentials.url.clone(),
+        }
+    }
+}
+
+impl<C> Client<HyperClient<Tracked<C>>>
+where
+    Tracked<C>: Connect + Clone,
+{
+    /// Creates a client making its connections using `connector`, counting them.
+    fn from_connector(
+        connector: C,
+        url: String,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        let connector = Tracked::new(connector);
+        let pool = connector.pool();
+        let mut client = Self::from_service(hyper_client(connector), url, user, password);
+        Arc::make_mut(&mut client.config).pool = Some(pool);
+        client
+    }
+}
+
+impl Client<HyperClient<Tracked<HttpConnector>>> {
+    /// Creates a new HTTP client.
+    pub fn new(url: String, user: Option<String>, password: Option<String>) -> Self {
+        Self::new_with_tcp(url, user, password, TcpOptions::default())
+    }
+
+    /// Creates a new HTTP client using the socket options `tcp`.
+    pub fn new_with_tcp(
+        url: String,
+        user: Option<String>,
+        password: Option<String>,
+        tcp: TcpOptions,
+    ) -> Self {
+        Self::from_connector(tcp.connector(), url, user, password)
+    }
+}
+
+#[cfg(feature = "tls-native")]
+impl Client<HyperClient<Tracked<HttpsConnector<HttpConnector>>>> {
+    /// Creates a new HTTPS client.
+    pub fn new_tls(url: String, user: Option<String>, password: Option<String>) -> Self {
+        let mut http = TcpOptions::default().connector();
+        http.enforce_http(false);
+        let connector = HttpsConnector::new_with_connector(http);
+        Self::from_connector(connector, url, user, password)
+    }
+}
+
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+impl Client<HyperClient<Tracked<Connector>>> {
+    /// Creates a new HTTPS client using the TLS configuration `tls`.
+    pub fn new_tls_with_config(
+        url: String,
+        user: Option<String>,
+        password: Option<String>,
+        tls: &TlsConfig,
+    ) -> Result<Self, TlsError> {
+        let proxy_auth = tls.proxy_authorization(&url);
+        let mut client = Self::from_connector(tls.connector()?, url, user, password);
+        Arc::make_mut(&mut client.config).proxy_auth = proxy_auth;
+        Ok(client)
+    }
+}
+
+type FutResponse<R, E> = Pin<Box<dyn Future<Output = Result<R, E>> + 'static + Send>>;
+
+impl<S, B> Service<Request> for Client<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>> + Clone + Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Error<ConnectionError<S::Error>>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Wait out any cool-down requested by the server
+        if self.cooldown.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        if let Some(in_flight) = &mut self.in_flight {
+            if self.permit.is_none() {
+                match in_flight.poll_acquire(cx) {
+                    // The semaphore is never closed
+                    Poll::Ready(permit) => self.permit = permit,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        self.inner_service
+            .poll_ready(cx)
+            .map_err(ConnectionError::Poll)
+            .map_err(Error::Connection)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.call_with_deadline(request, None)
+    }
+}
+
+impl<S> Client<S>
+where
+    S: Service<HttpRequest<Body>>,
+    S::Error: 'static,
+{
+    /// Registers a callback invoked with the error of each failed call.
+    pub fn with_on_error<F>(mut self, on_error: F) -> Self
+    where
+        F: Fn(&HttpError<S::Error>) + Send + Sync + 'static,
+    {
+        let on_error: ErrorHook<S::Error> = Arc::new(on_error);
+        Arc::make_mut(&mut self.config)
+            .hooks
+            .on_error
+            .push(Arc::new(on_error));
+        self
+    }
+}
+
+impl<S, B> Client<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>> + Clone + Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    /// Call the inner service, failing with [`Error::Timeout`] after `deadline`.
+    fn call_with_deadline(
+        &mut self,
+        request: Request,
+        deadline: Option<Instant>,
+    ) -> FutResponse<Response, HttpError<S::Error>> {
+        self.dispatch(request, deadline, Exchange::run)
+    }
+
+    /// Perform the call using `run`, which decodes the response.
+    fn dispatch<T, F, Fut>(
+        &mut self,
+        request: Request,
+        deadline: Option<Instant>,
+        run: F,
+    ) -> FutResponse<T, HttpError<S::Error>>
+    where
+        F: FnOnce(Exchange<S>, Request) -> Fut,
+        Fut: Future<Output = Result<T, HttpError<S::Error>>> + Send + 'static,
+        T: Outcome + Send + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "json_rpc_call",
+            method = %request.method,
+            id = %request.id,
+            endpoint = %self.credentials.url,
+        );
+
+        let context = self.call_context(&request);
+        let in_flight = InFlight::new(self.counters.clone());
+
+        // Held until the response is read
+        let permit = self.permit.take();
+        if self.in_flight.is_some() && permit.is_none() {
+            panic!("max requests in-flight; poll_ready must be called first");
+        }
+
+        let timeout = self.config.timeout.map(|timeout| Instant::now() + timeout);
+        let deadline = match (deadline, timeout) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+            (deadline, timeout) => deadline.or(timeout),
+        };
+
+        // Take the service which was driven to readiness
+        let clone = self.inner_service.clone();
+        let inner_service = std::mem::replace(&mut self.inner_service, clone);
+        let exchange = Exchange {
+            inner_service,
+            credentials: self.credentials.clone(),
+            config: self.config.clone(),
+            cooldown: self.cooldown.clone(),
+            deadline,
+            // The context is only available while the caller is running
+            trace: self
+                .config
+                .trace_context
+                .as_ref()
+                .and_then(|SharedTraceContext(context)| context.current()),
+            accept_compressed: true,
+            params: None,
+            payload: None,
+        };
+        let fut = run(exchange, request);
+        let fut = async move {
+            let _permit = permit;
+            match deadline {
+                Some(deadline) => timeout_at(deadline, fut)
+                    .await
+                    .unwrap_or(Err(Error::Timeout)),
+                None => fut.await,
+            }
+        };
+
+        let config = self.config.clone();
+        let start = Instant::now();
+        let fut = async move {
+            let result = match &config.cancellation {
+                Some(token) => token
+                    .run_until_cancelled(fut)
+                    .await
+                    .unwrap_or(Err(Error::Cancelled)),
+                None => fut.await,
+            };
+            let elapsed = start.elapsed();
+            if let (Ok(_), Some(latency)) = (&result, &config.latency) {
+                latency.record(&context.method, elapsed);
+            }
+            #[cfg(feature = "tracing")]
+            if config
+                .slow_threshold
+                .is_some_and(|threshold| elapsed > threshold)
+            {
+                tracing::warn!(
+                    method = %context.method,
+                    id = %context.id,
+                    ?elapsed,
+                    "slow json-rpc call",
+                );
+            }
+            if let Some(events) = &config.events {
+                events.emit(|| match &result {
+                    Ok(_) => Event::ResponseReceived {
+                        id: context.id.clone(),
+                        method: context.method.clone(),
+                        latency: elapsed,
+                    },
+                    Err(err) => Event::Failed {
+                        id: context.id.clone(),
+                        method: context.method.clone(),
+                        error: err.kind(),
+                    },
+                });
+            }
+            let result = result.map_err(|err| err.with_context(|| context));
+            if let Err(err) = &result {
+                in_flight.failed();
+                config.hooks.error(err);
+            }
+            result
+        };
+
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(trace_outcome(fut), span);
+        Box::pin(fut)
+    }
+}
+
+/// The output of a call, as traced.
+trait Outcome {
+    /// The code of the JSON-RPC error object received, if it has been decoded.
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    fn rpc_error(&self) -> Option<i32>;
+}
+
+impl<R> Outcome for Response<R> {
+    fn rpc_error(&self) -> Option<i32> {
+        self.error.as_ref().map(|error| error.code)
+    }
+}
+
+impl Outcome for Bytes {
+    fn rpc_error(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl<E> Outcome for ResultStream<E> {
+    fn rpc_error(&self) -> Option<i32> {
+        None
+    }
+}
+
+impl<B> Outcome for BatchStream<B> {
+    fn rpc_error(&self) -> Option<i32> {
+        None
+    }
+}
+
+/// Emit an event recording the latency and outcome of a call.
+#[cfg(feature = "tracing")]
+async fn trace_outcome<F, T, E>(fut: F) -> Result<T, HttpError<E>>
+where
+    F: Future<Output = Result<T, HttpError<E>>>,
+    T: Outcome,
+{
+    let start = Instant::now();
+    let result = fut.await;
+    let latency = start.elapsed();
+    match result.as_ref().map(Outcome::rpc_error) {
+        Ok(Some(code)) => tracing::debug!(?latency, outcome = "rpc_error", code),
+        Ok(None) => tracing::debug!(?latency, outcome = "ok"),
+        Err(err) => tracing::debug!(?latency, outcome = "error", error = err.kind()),
+    }
+    result
+}
+
+/// Build a hyper client connecting using `connector`.
+pub(crate) fn hyper_client<C>(connector: C) -> HyperClient<C>
+where
+    C: Connect + Clone,
+{
+    LegacyClient::builder(TokioExecutor::new())
+        .pool_timer(TokioTimer::new())
+        .build(connector)
+}
+
+impl<C> RequestFactory for Client<C> {
+    /// Build the request.
+    fn build_request(&self) -> RequestBuilder {
+        Request::build().id(self.next_id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{atomic::AtomicBool, Mutex},
+    };
+
+    use bytes::BytesMut;
+    use futures_core::future::BoxFuture;
+    use futures_util::TryStreamExt;
+    use hyper::{
+        header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER},
+        http::request::Parts,
+        StatusCode,
+    };
+    use serde_json::{json, Value};
+    use tokio::sync::Notify;
+    use tower_util::{service_fn, ServiceExt};
+
+    use super::{
+        exchange::{TRACEPARENT, TRACESTATE},
+        *,
+    };
+    use crate::{
+        clients::trace::TraceHeaders,
+        testing::{Expectation, MockServer},
+    };
+
+    pub(super) type Reply = HttpResponse<Full<Bytes>>;
+
+    /// Answers each HTTP request in place of a server, given its head and body.
+    #[derive(Clone)]
+    pub(super) struct Answer(Arc<dyn Fn(Parts, Bytes) -> BoxFuture<'static, Reply> + Send + Sync>);
+
+    impl fmt::Debug for Answer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Answer")
+        }
+    }
+
+    impl Service<HttpRequest<Body>> for Answer {
+        type Response = Reply;
+        type Error = io::Error;
+        type Future = BoxFuture<'static, Result<Reply, io::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: HttpRequest<Body>) -> Self::Future {
+            let answer = self.0.clone();
+            Box::pin(async move {
+                let (parts, body) = request.into_parts();
+                let body = body.collect().await.map_err(io::Error::other)?.to_bytes();
+                Ok(answer(parts, body).await)
+            })
+        }
+    }
+
+    /// A client whose calls are answered by `answer`.
+    pub(super) fn answered_by<F, Fut>(answer: F) -> Client<Answer>
+    where
+        F: Fn(Parts, Bytes) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Reply> + Send + 'static,
+    {
+        let answer = Answer(Arc::new(move |parts, body| Box::pin(answer(parts, body))));
+        Client::from_service(answer, "http://rpc.test".to_owned(), None, None)
+    }
+
+    /// A response to the call in `body` with `result`.
+    pub(super) fn result(body: &[u8], result: Value) -> Reply {
+        let call: Value = serde_json::from_slice(body).unwrap();
+        let response = json!({ "jsonrpc": "2.0", "result": result, "id": call["id"] });
+        HttpResponse::new(Full::new(Bytes::from(response.to_string())))
+    }
+
+    pub(super) fn status(status: StatusCode) -> Reply {
+        let mut response = HttpResponse::new(Full::new(Bytes::new()));
+        *response.status_mut() = status;
+        response
+    }
+
+    #[tokio::test]
+    async fn counts_pooled_connections() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("ping").result(true).times(3));
+        let client = Client::new(server.url(), None, None);
+        assert_eq!(client.stats().connections, 0);
+
+        for _ in 0..2 {
+            let request = client.build_request().method("ping").finish().unwrap();
+            client.send(request).await.unwrap();
+        }
+        // The connection is kept idle in the pool, and reused
+        let stats = client.stats();
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.calls, 2);
+
+        let from_service =
+            Client::from_service(hyper_client(HttpConnector::new()), server.url(), None, None);
+        let request = from_service
+            .build_request()
+            .method("ping")
+            .finish()
+            .unwrap();
+        from_service.send(request).await.unwrap();
+        assert_eq!(from_service.stats().connections, 0);
+    }
+
+    #[tokio::test]
+    async fn emits_connections() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("ping").result(true));
+        let events = Events::new(16);
+        let mut received = events.subscribe();
+        let client = Client::new(server.url(), None, None).with_events(events);
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        client.send(request).await.unwrap();
+        assert!(matches!(
+            received.recv().await.unwrap(),
+            Event::RequestSent { .. }
+        ));
+        let connected = Event::Connected {
+            host: "127.0.0.1".to_owned(),
+        };
+        assert_eq!(received.recv().await.unwrap(), connected);
+    }
+
+    #[tokio::test]
+    async fn connection_errors_are_reported_once() {
+        let client = Client::new("http://127.0.0.1:1".to_owned(), None, None);
+        let request = client.build_request().method("ping").finish().unwrap();
+        let err = client.send(request).await.unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            Error::Connection(ConnectionError::Service(_))
+        ));
+
+        let mut messages = vec![err.to_string()];
+        let mut source = error::Error::source(&err);
+        while let Some(err) = source {
+            messages.push(err.to_string());
+            source = err.source();
+        }
+        for (i, message) in messages.iter().enumerate() {
+            for cause in &messages[i + 1..] {
+                assert!(!message.contains(cause.as_str()), "{:?}", messages);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn maps_statuses_to_errors() {
+        let server = MockServer::start().await.unwrap();
+        let error = r#"{"jsonrpc":"2.0","error":{"code":-32005,"message":"Limited"},"id":1}"#;
+        server.expect(Expectation::call("missing").body(StatusCode::NOT_FOUND, "Not Found"));
+        server.expect(Expectation::call("failing").body(StatusCode::INTERNAL_SERVER_ERROR, error));
+        server.expect(Expectation::call("limited").body(StatusCode::TOO_MANY_REQUESTS, error));
+        server.expect(Expectation::call("down").body(StatusCode::SERVICE_UNAVAILABLE, ""));
+        let client = Client::new(server.url(), None, None);
+        let call = |method: &str| {
+            let request = client.build_request().method(method).finish().unwrap();
+            client.send(request)
+        };
+
+        let err = call("missing").await.unwrap_err();
+        match err.inner() {
+            Error::Http { status, body } => {
+                assert_eq!(*status, StatusCode::NOT_FOUND);
+                assert_eq!(body, "Not Found");
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        let response = call("failing").await.unwrap();
+        assert_eq!(response.error.unwrap().code, -32005);
+
+        let err = call("down").await.unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            Error::Unavailable { error: None, .. }
+        ));
+
+        // The client holds back calls afterwards
+        let err = call("limited").await.unwrap_err();
+        match err.inner() {
+            Error::RateLimited { error, .. } => {
+                assert_eq!(error.as_ref().unwrap().code, -32005);
+            }
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn holds_back_calls_over_the_in_flight_limit() {
+        use futures_util::FutureExt;
+
+        let released = Arc::new(Notify::new());
+        let release = released.clone();
+        let client = answered_by(move |_, body| {
+            let released = released.clone();
+            async move {
+                released.notified().await;
+                result(&body, json!(true))
+            }
+        })
+        .with_max_in_flight(1);
+        let mut first = client.clone();
+        let mut second = client.clone();
+
+        // The permit is held until the response is read
+        first.ready_and().await.unwrap();
+        let call = first.call(client.build_request().method("ping").finish().unwrap());
+        assert!(second.ready_and().now_or_never().is_none());
+        release.notify_one();
+        call.await.unwrap();
+        second.ready_and().await.unwrap();
+
+        // Or until the call is dropped
+        let call = second.call(client.build_request().method("ping").finish().unwrap());
+        assert!(first.ready_and().now_or_never().is_none());
+        drop(call);
+        assert!(first.ready_and().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn caps_the_cooldown() {
+        let limited = service_fn(|_: HttpRequest<Body>| async {
+            let response = HttpResponse::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, u64::MAX.to_string())
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            Ok::<_, io::Error>(response)
+        });
+        let client = Client::from_service(limited, "http://rpc.test".to_owned(), None, None)
+            .with_max_cooldown(Duration::from_secs(5));
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        let err = client.send(request).await.unwrap_err();
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(u64::MAX)));
+        let cooldown = client.cooldown().unwrap();
+        assert!(cooldown <= Instant::now() + Duration::from_secs(5));
+    }
+
+    #[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+    #[tokio::test]
+    async fn forwards_plain_requests_through_the_proxy() {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        use crate::clients::proxy::Proxy;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let proxy = Proxy::new(url.parse().unwrap()).basic_auth("user", "pass");
+        let config = TlsConfig::new().proxy(proxy);
+        let client =
+            Client::new_tls_with_config("http://rpc.test:8545/v1".to_owned(), None, None, &config)
+                .unwrap();
+        let proxying = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0; 1];
+                stream.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0]);
+            }
+            let body = r#"{"jsonrpc":"2.0","result":true,"id":0}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(head).unwrap().to_ascii_lowercase()
+        });
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.result, Some(json!(true)));
+        let head = proxying.await.unwrap();
+        assert!(
+            head.starts_with("post http://rpc.test:8545/v1 http/1.1\r\n"),
+            "{}",
+            head
+        );
+        assert!(
+            head.contains("\r\nproxy-authorization: basic dxnlcjpwyxnz\r\n"),
+            "{}",
+            head
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_batches() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("ping").result(true));
+        server.expect(Expectation::call("echo").result("]"));
+        let client = Client::new(server.url(), None, None);
+
+        let batch = r#"[{"jsonrpc":"2.0","method":"ping","id":1},
+            {"jsonrpc":"2.0","method":"echo","id":2}]"#;
+        let stream = client.send_raw_batch_stream(batch.into()).await.unwrap();
+        let responses: Vec<Response> = stream.try_collect().await.unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].result, Some(json!(true)));
+        assert_eq!(responses[1].result, Some(json!("]")));
+    }
+
+    #[tokio::test]
+    async fn streams_batches_answered_with_an_error() {
+        let server = MockServer::start().await.unwrap();
+        let error =
+            r#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"Invalid Request"},"id":null}"#;
+        server.expect(Expectation::call("ping").body(StatusCode::OK, error));
+        let client = Client::new(server.url(), None, None);
+
+        let batch = r#"[{"jsonrpc":"2.0","method":"ping","id":1}]"#;
+        let stream = client.send_raw_batch_stream(batch.into()).await.unwrap();
+        let responses: Vec<Response> = stream.try_collect().await.unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].error.as_ref().unwrap().code, -32600);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn compresses_large_requests() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("echo").result(true).times(2));
+        let client = Client::new(server.url(), None, None).with_request_compression(64);
+
+        for params in [json!(["short"]), json!(["long".repeat(64)])] {
+            let request = client.build_request().method("echo").params(params);
+            client.send(request.finish().unwrap()).await.unwrap();
+        }
+        let received = server.received();
+        assert_eq!(received[0]["params"], json!(["short"]));
+        assert_eq!(received[1]["params"], json!(["long".repeat(64)]));
+    }
+
+    /// Sets its flag once dropped, along with the future holding it.
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn aborts_cancelled_calls() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let started = Arc::new(Notify::new());
+        let (flag, notify) = (dropped.clone(), started.clone());
+        let client = answered_by(move |_, _| {
+            let flag = DropFlag(flag.clone());
+            notify.notify_one();
+            async move {
+                let _flag = flag;
+                std::future::pending().await
+            }
+        });
+
+        // Cancelling the client's token aborts the calls in flight
+        let token = CancellationToken::new();
+        let cancelled = client.clone().with_cancellation(token.clone());
+        let request = cancelled.build_request().method("ping").finish().unwrap();
+        let call = tokio::spawn(async move { cancelled.send(request).await });
+        started.notified().await;
+        token.cancel();
+        let err = call.await.unwrap().unwrap_err();
+        assert!(matches!(err.into_inner(), Error::Cancelled));
+        assert!(dropped.swap(false, Ordering::SeqCst));
+
+        // As does cancelling the token of a single call
+        let token = CancellationToken::new();
+        let request = client.build_request().method("ping").finish().unwrap();
+        let call = client.send_with_cancellation(request, &token);
+        let cancel = async {
+            started.notified().await;
+            token.cancel();
+        };
+        let (result, ()) = tokio::join!(call, cancel);
+        assert!(matches!(result.unwrap_err().into_inner(), Error::Cancelled));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn propagates_the_earlier_deadline() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = sent.clone();
+        let client = answered_by(move |parts, body| {
+            let remaining = parts.headers["x-deadline"].to_str().unwrap().to_owned();
+            received.lock().unwrap().push(remaining);
+            async move {
+                let call: Value = serde_json::from_slice(&body).unwrap();
+                if call["method"] == "hang" {
+                    std::future::pending::<()>().await;
+                }
+                result(&body, json!(true))
+            }
+        })
+        .with_timeout(Duration::from_secs(10))
+        .with_deadline_header(HeaderName::from_static("x-deadline"));
+
+        for (deadline, remaining) in [(2, "2000"), (60, "10000")] {
+            let deadline = Instant::now() + Duration::from_secs(deadline);
+            let request = client.build_request().method("ping").finish().unwrap();
+            client.send_with_deadline(request, deadline).await.unwrap();
+            assert_eq!(sent.lock().unwrap().pop().unwrap(), remaining);
+        }
+
+        // The call times out locally at the same time
+        for (deadline, elapsed) in [(2, 2), (60, 10)] {
+            let start = Instant::now();
+            let deadline = start + Duration::from_secs(deadline);
+            let request = client.build_request().method("hang").finish().unwrap();
+            let err = client
+                .send_with_deadline(request, deadline)
+                .await
+                .unwrap_err();
+            assert!(matches!(err.into_inner(), Error::Timeout));
+            assert_eq!(start.elapsed(), Duration::from_secs(elapsed));
+        }
+    }
+
+    /// Signs the params of each request and marks each response.
+    struct Signing;
+
+    impl Interceptor for Signing {
+        fn before<'a>(&'a self, request: &'a mut Request) -> BoxFuture<'a, ()> {
+            request.params = json!({ "signed": true });
+            Box::pin(async {})
+        }
+
+        fn after<'a>(&'a self, response: &'a mut Response) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                response.result = Some(json!(["intercepted", response.result.take()]));
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn intercepts_calls() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("ping").result("pong"));
+        let client = Client::new(server.url(), None, None).with_interceptor(Signing);
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(server.received()[0]["params"], json!({ "signed": true }));
+        assert_eq!(response.result, Some(json!(["intercepted", "pong"])));
+    }
+
+    #[tokio::test]
+    async fn sends_the_api_key() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = sent.clone();
+        let client = answered_by(move |parts, body| {
+            received
+                .lock()
+                .unwrap()
+                .push(parts.headers["x-api-key"].clone());
+            async move { result(&body, json!(true)) }
+        })
+        .with_api_key(
+            HeaderName::from_static("x-api-key"),
+            HeaderValue::from_static("hunter2"),
+        );
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        client.send(request).await.unwrap();
+        assert_eq!(sent.lock().unwrap()[0], "hunter2");
+        let debug = format!("{:?}", client);
+        assert!(debug.contains("x-api-key"), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+    }
+
+    #[tokio::test]
+    async fn runs_hooks() {
+        // The requests, responses and errors seen by the hooks
+        let counts: Arc<[AtomicUsize; 3]> = Arc::default();
+        let (requests, responses, errors) = (counts.clone(), counts.clone(), counts.clone());
+        let client = answered_by(|_, body| async move {
+            let call: Value = serde_json::from_slice(&body).unwrap();
+            match call["method"].as_str() {
+                Some("ping") => result(&body, json!(true)),
+                _ => status(StatusCode::INTERNAL_SERVER_ERROR),
+            }
+        })
+        .with_on_request(move |_| {
+            requests[0].fetch_add(1, Ordering::SeqCst);
+        })
+        .with_on_response(move |_| {
+            responses[1].fetch_add(1, Ordering::SeqCst);
+        })
+        .with_on_error(move |err| {
+            assert!(matches!(err.inner(), Error::Http { .. }), "{}", err);
+            errors[2].fetch_add(1, Ordering::SeqCst);
+        });
+        let counted = || counts.each_ref().map(|count| count.load(Ordering::SeqCst));
+
+        let request = client.build_request().method("ping").finish().unwrap();
+        client.send(request).await.unwrap();
+        assert_eq!(counted(), [1, 1, 0]);
+
+        let request = client.build_request().method("fail").finish().unwrap();
+        client.send(request).await.unwrap_err();
+        assert_eq!(counted(), [2, 1, 1]);
+    }
+
+    /// A trace context set by the test.
+    #[derive(Clone, Default)]
+    struct ActiveTrace(Arc<Mutex<Option<TraceHeaders>>>);
+
+    impl TraceContext for ActiveTrace {
+        fn current(&self) -> Option<TraceHeaders> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_active_trace_context() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let received = sent.clone();
+        let active = ActiveTrace::default();
+        let client = answered_by(move |parts, body| {
+            let header = |name| parts.headers.get(name).cloned();
+            received
+                .lock()
+                .unwrap()
+                .push((header(TRACEPARENT), header(TRACESTATE)));
+            async move { result(&body, json!(true)) }
+        })
+        .with_trace_context(active.clone());
+
+        // No headers are sent outside of a trace
+        let request = client.build_request().method("ping").finish().unwrap();
+        client.send(request).await.unwrap();
+        assert_eq!(sent.lock().unwrap().pop().unwrap(), (None, None));
+
+        let trace = TraceHeaders::new(0xabc, 0x12, true).tracestate("vendor=value");
+        *active.0.lock().unwrap() = Some(trace);
+        let request = client.build_request().method("ping").finish().unwrap();
+        client.send(request).await.unwrap();
+        let (traceparent, tracestate) = sent.lock().unwrap().pop().unwrap();
+        assert_eq!(
+            traceparent.unwrap(),
+            "00-00000000000000000000000000000abc-0000000000000012-01"
+        );
+        assert_eq!(tracestate.unwrap(), "vendor=value");
+    }
+
+    /// A client answering each call with the result in the call's params.
+    fn echo() -> Client<Answer> {
+        answered_by(|_, body| async move {
+            let call: Value = serde_json::from_slice(&body).unwrap();
+            result(&body, call["params"][0].clone())
+        })
+    }
+
+    #[tokio::test]
+    async fn limits_the_response_size() {
+        let client = echo().with_max_response_size(128);
+
+        let request = client.build_request().method("echo").params(vec!["short"]);
+        let response = client.send(request.finish().unwrap()).await.unwrap();
+        assert_eq!(response.result, Some(json!("short")));
+
+        let long = "long".repeat(32);
+        let request = client.build_request().method("echo").params(vec![long]);
+        let err = client.send(request.finish().unwrap()).await.unwrap_err();
+        match err.into_inner() {
+            Error::ResponseTooLarge { limit } => assert_eq!(limit, 128),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_the_parsed_response() {
+        let limits = ParseLimits::new().max_depth(4).max_elements(16);
+        let client = echo().with_parse_limits(limits);
+
+        let request = client
+            .build_request()
+            .method("echo")
+            .params(vec![json!([[1]])]);
+        let response = client.send(request.finish().unwrap()).await.unwrap();
+        assert_eq!(response.result, Some(json!([[1]])));
+
+        // The response object is the outermost level
+        let deep = json!([[[[1]]]]);
+        let request = client.build_request().method("echo").params(vec![deep]);
+        let err = client.send(request.finish().unwrap()).await.unwrap_err();
+        match err.into_inner() {
+            Error::TooDeep { limit } => assert_eq!(limit, 4),
+            err => panic!("unexpected error {}", err),
+        }
+
+        let long = json!(vec![1; 16]);
+        let request = client.build_request().method("echo").params(vec![long]);
+        let err = client.send(request.finish().unwrap()).await.unwrap_err();
+        match err.into_inner() {
+            Error::TooManyElements { limit } => assert_eq!(limit, 16),
+            err => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_raw_requests() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let reply = Arc::new(Mutex::new(""));
+        let (received, replying) = (sent.clone(), reply.clone());
+        let client = answered_by(move |_, body| {
+            received.lock().unwrap().push(body);
+            let reply = *replying.lock().unwrap();
+            async move { HttpResponse::new(Full::new(Bytes::from(reply))) }
+        });
+
+        // The request and response bodies are forwarded byte for byte
+        let request =
+            Bytes::from_static(b"{ \"id\": 7, \"method\": \"ping\", \"jsonrpc\": \"2.0\" }");
+        *reply.lock().unwrap() = r#"{"jsonrpc":"2.0","result":  1,"id":8}"#;
+        let response = client.send_raw(request.clone()).await.unwrap();
+        assert_eq!(sent.lock().unwrap().pop().unwrap(), request);
+        assert_eq!(response, *reply.lock().unwrap());
+
+        // Unless asked to, in which case the response ids must match
+        let client = client.with_raw_ids(RawIds::Check);
+        let err = client.send_raw(request.clone()).await.unwrap_err();
+        assert!(matches!(err.into_inner(), Error::NonceMismatch));
+        *reply.lock().unwrap() = r#"{"jsonrpc":"2.0","result":1,"id":7}"#;
+        client.send_raw(request.clone()).await.unwrap();
+
+        let batch = Bytes::from_static(
+            br#"[{"jsonrpc":"2.0","method":"a","id":1},{"jsonrpc":"2.0","method":"b","id":2}]"#,
+        );
+        *reply.lock().unwrap() =
+            r#"[{"jsonrpc":"2.0","result":1,"id":2},{"jsonrpc":"2.0","result":1,"id":3}]"#;
+        let err = client.send_raw_batch(batch.clone()).await.unwrap_err();
+        match err.into_inner() {
+            Error::WrongBatchResponseId(id) => assert_eq!(id, json!(3)),
+            err => panic!("unexpected error {}", err),
+        }
+        assert_eq!(sent.lock().unwrap().pop().unwrap(), batch);
+
+        // Or replaced with the client's own
+        let client = client.with_raw_ids(RawIds::Stamp);
+        *reply.lock().unwrap() = r#"{"jsonrpc":"2.0","result":1,"id":7}"#;
+        let err = client.send_raw(request).await.unwrap_err();
+        assert!(matches!(err.into_inner(), Error::NonceMismatch));
+        let stamped: Value = serde_json::from_slice(&sent.lock().unwrap().pop().unwrap()).unwrap();
+        assert_ne!(stamped["id"], 7);
+        assert_eq!(stamped["method"], "ping");
+    }
+
+    /// JSON with its bytes reversed.
+    struct Reversed;
+
+    impl Codec for Reversed {
+        fn content_type(&self) -> &'static str {
+            "application/x-reversed-json"
+        }
+
+        fn encode(&self, request: &Request, buffer: &mut BytesMut) -> Result<(), BoxError> {
+            let mut json = serde_json::to_vec(request)?;
+            json.reverse();
+            buffer.extend_from_slice(&json);
+            Ok(())
+        }
+
+        fn decode(&self, body: &[u8]) -> Result<Response, BoxError> {
+            let json: Vec<u8> = body.iter().rev().copied().collect();
+            Ok(serde_json::from_slice(&json)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn encodes_with_the_codec() {
+        let client = answered_by(|parts, body| async move {
+            assert_eq!(parts.headers[CONTENT_TYPE], "application/x-reversed-json");
+            assert_eq!(parts.headers[ACCEPT], "application/x-reversed-json");
+            let call: Vec<u8> = body.iter().rev().copied().collect();
+            let call: Value = serde_json::from_slice(&call).unwrap();
+            let response = json!({ "jsonrpc": "2.0", "result": call["params"], "id": call["id"] });
+            let mut response = serde_json::to_vec(&response).unwrap();
+            response.reverse();
+            HttpResponse::new(Full::new(Bytes::from(response)))
+        })
+        .with_codec(Reversed);
+
+        let request = client
+            .build_request()
+            .method("echo")
+            .params(json!(["back"]));
+        let response = client.send(request.finish().unwrap()).await.unwrap();
+        assert_eq!(response.result, Some(json!(["back"])));
+
+        // Bodies the codec can't decode fail the call
+        let client = answered_by(|_, _| async { status(StatusCode::OK) });
+        let client = client.with_codec(Reversed);
+        let request = client.build_request().method("echo").finish().unwrap();
+        let err = client.send(request).await.unwrap_err();
+        assert!(
+            matches!(err.into_inner(), Error::Decode(_)),
+            "unexpected error"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use futures_util::TryStreamExt;
+use http_body_util::{BodyDataStream, StreamBody};
+use hyper::{
+    body::{Body as HttpBody, Bytes, Frame},
+    Request as HttpRequest, Response as HttpResponse,
+};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+use super::{exchange::Exchange, Body, Client, ConnectionError};
+use crate::{
+    auth::BoxError,
+    clients::{batch::BatchStream, raw::Payload, stream::ResultStream, Error},
+    objects::{RawResponse, Request, Response},
+};
+
+impl<S, B> Client<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>> + Clone + Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    pub async fn send(
+        &self,
+        request: Request,
+    ) -> Result<Response, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        self.clone()
+            .oneshot(request)
+            .await
+            .map_err(|err| err.with_context(|| context))
+    }
+
+    /// Send a request, failing with [`Error::Timeout`] if no response is received by `deadline`.
+    pub async fn send_with_deadline(
+        &self,
+        request: Request,
+        deadline: Instant,
+    ) -> Result<Response, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client.call_with_deadline(request, Some(deadline)).await
+    }
+
+    /// Send a request, keeping the result unparsed until it is extracted.
+    ///
+    /// This avoids building a [`Value`] for results which are deserialized into a concrete type.
+    /// The response interceptors and hooks are not run, since they expect a parsed result.
+    ///
+    /// [`Value`]: serde_json::Value
+    pub async fn send_with_raw_result(
+        &self,
+        request: Request,
+    ) -> Result<RawResponse, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client.dispatch(request, None, Exchange::run_raw).await
+    }
+
+    /// Send a request, returning the response body for parsing as a [`ResponseRef`].
+    ///
+    /// The body is only decoded to check for error objects sent with a non-success status. The
+    /// response interceptors and hooks are not run, and only JSON bodies are supported.
+    ///
+    /// [`ResponseRef`]: crate::objects::ResponseRef
+    pub async fn send_for_body(
+        &self,
+        request: Request,
+    ) -> Result<Bytes, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client.dispatch(request, None, Exchange::run_body).await
+    }
+
+    /// Send a request, streaming the raw JSON of its result as it is received.
+    ///
+    /// The result is not held in memory, so it isn't subject to [`with_max_response_size`]. The
+    /// timeout and cancellation only apply until the response headers are received, and the
+    /// response interceptors and hooks are not run. Only JSON bodies are supported, so responses
+    /// are requested uncompressed.
+    ///
+    /// [`with_max_response_size`]: Client::with_max_response_size
+    pub async fn send_for_result_stream(
+        &self,
+        request: Request,
+    ) -> Result<ResultStream<S::Error>, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client.dispatch(request, None, Exchange::run_stream).await
+    }
+
+    /// Send a request whose params are streamed from `params`, such as a large upload.
+    ///
+    /// The params of `request` are replaced by the JSON streamed, which isn't validated. An
+    /// [`AsyncRead`] can be streamed using [`ReaderStream`]. Streamed calls can't be replayed after
+    /// reauthorization or signed, and only JSON bodies are supported.
+    ///
+    /// [`AsyncRead`]: tokio::io::AsyncRead
+    /// [`ReaderStream`]: tokio_util::io::ReaderStream
+    pub async fn send_with_streamed_params<P, E2>(
+        &self,
+        request: Request,
+        params: P,
+    ) -> Result<Response, Error<ConnectionError<S::Error>>>
+    where
+        P: futures_core::Stream<Item = Result<Bytes, E2>> + Send + 'static,
+        E2: Into<BoxError> + 'static,
+    {
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        let params = Body::new(StreamBody::new(params.map_ok(Frame::data)));
+        client
+            .dispatch(request, None, move |exchange, request| {
+                exchange.run_streamed(request, params)
+            })
+            .await
+    }
+
+    /// Send an already serialized request, returning the response body.
+    ///
+    /// The request is forwarded untouched unless the client stamps its id, see
+    /// [`with_raw_ids`]. Its method and id are read to describe the call, and the request
+    /// interceptors and hooks are not run.
+    ///
+    /// [`with_raw_ids`]: Client::with_raw_ids
+    pub async fn send_raw(
+        &self,
+        request: Bytes,
+    ) -> Result<Bytes, Error<ConnectionError<S::Error>>> {
+        let payload = Payload::single(request, self.config.raw_ids, || self.next_id())?;
+        self.send_payload(payload).await
+    }
+
+    /// Send an already serialized batch, returning the response body.
+    ///
+    /// Like [`send_raw`], errors and events describe the call as the method `batch` with the
+    /// array of request ids. Use [`send_raw_batch_stream`] to parse the responses as they are
+    /// received instead.
+    ///
+    /// [`send_raw`]: Client::send_raw
+    /// [`send_raw_batch_stream`]: Client::send_raw_batch_stream
+    pub async fn send_raw_batch(
+        &self,
+        batch: Bytes,
+    ) -> Result<Bytes, Error<ConnectionError<S::Error>>> {
+        let payload = Payload::batch(batch, self.config.raw_ids, || self.next_id())?;
+        self.send_payload(payload).await
+    }
+
+    /// Send an already serialized batch, yielding each response as it is received.
+    ///
+    /// Like [`send_for_result_stream`], the body is not held in memory, so it isn't subject to
+    /// [`with_max_response_size`], and the timeout and cancellation only apply until the response
+    /// headers are received. The ids of the responses are not checked against the batch.
+    ///
+    /// [`send_for_result_stream`]: Client::send_for_result_stream
+    /// [`with_max_response_size`]: Client::with_max_response_size
+    pub async fn send_raw_batch_stream(
+        &self,
+        batch: Bytes,
+    ) -> Result<BatchStream<BodyDataStream<Body>>, Error<ConnectionError<S::Error>>> {
+        let Payload { body, request, .. } =
+            Payload::batch(batch, self.config.raw_ids, || self.next_id())?;
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client
+            .dispatch(request, None, move |exchange, request| {
+                exchange.run_batch_stream(request, body)
+            })
+            .await
+    }
+
+    async fn send_payload(
+        &self,
+        payload: Payload,
+    ) -> Result<Bytes, Error<ConnectionError<S::Error>>> {
+        let Payload {
+            body,
+            request,
+            expected,
+        } = payload;
+        let context = self.call_context(&request);
+        let mut client = self.clone();
+        if let Err(err) = client.ready_and().await {
+            return Err(err.with_context(|| context));
+        }
+        client
+            .dispatch(request, None, move |exchange, request| {
+                exchange.run_payload(request, body, expected)
+            })
+            .await
+    }
+
+    /// Send a request, aborting it with [`Error::Cancelled`] once `token` is cancelled.
+    pub async fn send_with_cancellation(
+        &self,
+        request: Request,
+        token: &CancellationToken,
+    ) -> Result<Response, Error<ConnectionError<S::Error>>> {
+        let context = self.call_context(&request);
+        token
+            .run_until_cancelled(self.send(request))
+            .await
+            .unwrap_or_else(|| Err(Error::Cancelled.with_context(|| context)))
+    }
+}
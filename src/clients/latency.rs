@@ -0,0 +1,127 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Latency percentiles of the recent calls to a method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Latency {
+    /// The number of samples the percentiles are computed from.
+    pub samples: usize,
+    /// The median latency.
+    pub p50: Duration,
+    /// The 90th percentile latency.
+    pub p90: Duration,
+    /// The 99th percentile latency.
+    pub p99: Duration,
+    /// The highest latency.
+    pub max: Duration,
+}
+
+impl Latency {
+    fn from_samples(samples: &VecDeque<Duration>) -> Option<Self> {
+        let mut sorted: Vec<_> = samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let max = *sorted.last()?;
+        // The nearest-rank percentile, the smallest sample at or above `p`% of the samples
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        Some(Latency {
+            samples: sorted.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max,
+        })
+    }
+}
+
+/// Records the latency of the most recent calls to each method.
+#[derive(Debug)]
+pub(crate) struct LatencyWindow {
+    size: usize,
+    samples: Mutex<HashMap<String, VecDeque<Duration>>>,
+}
+
+impl LatencyWindow {
+    /// Creates a window keeping `size` samples per method.
+    pub(crate) fn new(size: usize) -> Self {
+        LatencyWindow {
+            size,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, method: &str, latency: Duration) {
+        if self.size == 0 {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        let samples = match samples.get_mut(method) {
+            Some(samples) => samples,
+            None => samples.entry(method.to_owned()).or_default(),
+        };
+        if samples.len() == self.size {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    pub(crate) fn get(&self, method: &str) -> Option<Latency> {
+        let samples = self.samples.lock().unwrap();
+        Latency::from_samples(samples.get(method)?)
+    }
+
+    pub(crate) fn all(&self) -> HashMap<String, Latency> {
+        let samples = self.samples.lock().unwrap();
+        samples
+            .iter()
+            .filter_map(|(method, samples)| Some((method.clone(), Latency::from_samples(samples)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(samples: impl IntoIterator<Item = u64>) -> VecDeque<Duration> {
+        samples.into_iter().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn uses_the_nearest_rank() {
+        let latency = Latency::from_samples(&millis(1..=100)).unwrap();
+        assert_eq!(latency.samples, 100);
+        assert_eq!(latency.p50, Duration::from_millis(50));
+        assert_eq!(latency.p90, Duration::from_millis(90));
+        assert_eq!(latency.p99, Duration::from_millis(99));
+        assert_eq!(latency.max, Duration::from_millis(100));
+
+        let latency = Latency::from_samples(&millis([40, 10, 30, 20])).unwrap();
+        assert_eq!(latency.p50, Duration::from_millis(20));
+        assert_eq!(latency.p90, Duration::from_millis(40));
+
+        let latency = Latency::from_samples(&millis([7])).unwrap();
+        assert_eq!(latency.p50, Duration::from_millis(7));
+        assert_eq!(latency.p99, Duration::from_millis(7));
+        assert!(Latency::from_samples(&VecDeque::new()).is_none());
+    }
+
+    #[test]
+    fn keeps_the_most_recent_samples() {
+        let window = LatencyWindow::new(2);
+        for latency in [300, 10, 20] {
+            window.record("ping", Duration::from_millis(latency));
+        }
+        let latency = window.get("ping").unwrap();
+        assert_eq!(latency.samples, 2);
+        assert_eq!(latency.max, Duration::from_millis(20));
+        assert!(window.get("pong").is_none());
+
+        let window = LatencyWindow::new(0);
+        window.record("ping", Duration::from_millis(10));
+        assert!(window.all().is_empty());
+    }
+}
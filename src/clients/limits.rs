@@ -0,0 +1,168 @@
+use super::Error;
+
+/// Limits on the structure of JSON response bodies, checked before they are parsed.
+///
+/// These protect against documents crafted to exhaust the stack or memory while parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseLimits {
+    max_depth: usize,
+    max_elements: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_depth: 128,
+            max_elements: usize::MAX,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Creates limits allowing 128 levels of nesting and any number of elements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum nesting depth of arrays and objects.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum total number of array elements and object members.
+    pub fn max_elements(mut self, max_elements: usize) -> Self {
+        self.max_elements = max_elements;
+        self
+    }
+
+    /// Check a JSON document against the limits.
+    ///
+    /// Malformed documents are left for the parser to reject.
+    pub(crate) fn check<E>(&self, json: &[u8]) -> Result<(), Error<E>> {
+        let mut depth = 0;
+        let mut elements = 0;
+        // Whether the next value is the first in its array or object
+        let mut first = false;
+        let mut string = false;
+        let mut escaped = false;
+        for &byte in json {
+            if string {
+                string = escaped || byte != b'"';
+                escaped = !escaped && byte == b'\\';
+                continue;
+            }
+
+            let value = match byte {
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > self.max_depth {
+                        return Err(Error::TooDeep {
+                            limit: self.max_depth,
+                        });
+                    }
+                    true
+                }
+                b'}' | b']' => {
+                    depth = depth.saturating_sub(1);
+                    first = false;
+                    false
+                }
+                b',' => {
+                    elements += 1;
+                    false
+                }
+                b'"' => {
+                    string = true;
+                    true
+                }
+                b':' => false,
+                _ if byte.is_ascii_whitespace() => false,
+                _ => true,
+            };
+            if value && first {
+                elements += 1;
+            }
+            if value {
+                first = matches!(byte, b'{' | b'[');
+            }
+            if elements > self.max_elements {
+                return Err(Error::TooManyElements {
+                    limit: self.max_elements,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    /// The number of elements counted in `json`, found as the lowest limit it passes.
+    fn elements(json: &str) -> usize {
+        (0..)
+            .find(|&limit| {
+                ParseLimits::new()
+                    .max_elements(limit)
+                    .check::<io::Error>(json.as_bytes())
+                    .is_ok()
+            })
+            .unwrap()
+    }
+
+    /// The nesting depth of `json`, found as the lowest limit it passes.
+    fn depth(json: &str) -> usize {
+        (0..)
+            .find(|&limit| {
+                ParseLimits::new()
+                    .max_depth(limit)
+                    .check::<io::Error>(json.as_bytes())
+                    .is_ok()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_elements() {
+        assert_eq!(elements("[]"), 0);
+        assert_eq!(elements("{}"), 0);
+        assert_eq!(elements("12345"), 0);
+        assert_eq!(elements("[12345, true, null]"), 3);
+        assert_eq!(elements(r#"{"a": 1, "b": [1, 2]}"#), 4);
+        assert_eq!(elements("[[1], [], [[2, 3]]]"), 7);
+        assert_eq!(elements(r#"[{"a": {}}, {}]"#), 3);
+    }
+
+    #[test]
+    fn skips_strings() {
+        assert_eq!(elements(r#"["a,b", "[{,}]"]"#), 2);
+        assert_eq!(elements(r#"["a\",b", "c\\", "d"]"#), 3);
+        assert_eq!(depth(r#"["[[\"[["]"#), 1);
+    }
+
+    #[test]
+    fn measures_depth() {
+        assert_eq!(depth("1"), 0);
+        assert_eq!(depth("[]"), 1);
+        assert_eq!(depth(r#"{"a": [{"b": []}], "c": {}}"#), 4);
+        assert_eq!(depth("[[], [], [[]]]"), 3);
+    }
+
+    #[test]
+    fn reports_the_limit_exceeded() {
+        let limits = ParseLimits::new().max_depth(2).max_elements(2);
+        assert!(matches!(
+            limits.check::<io::Error>(b"[[[]]]"),
+            Err(Error::TooDeep { limit: 2 })
+        ));
+        assert!(matches!(
+            limits.check::<io::Error>(b"[1, 2, 3]"),
+            Err(Error::TooManyElements { limit: 2 })
+        ));
+        assert!(limits.check::<io::Error>(b"[[1]]").is_ok());
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use futures_core::future::BoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use tower_service::Service;
+
+use super::{CallContext, Error, RequestFactory};
+use crate::objects::{Request, RequestBuilder, Response, RpcError};
+
+pub type MockError = Error<io::Error>;
+
+/// Produces the reply to a call from its parameters.
+type Handler = Arc<dyn Fn(Value) -> Result<Response, MockError> + Send + Sync>;
+
+/// A client answering calls from replies scripted per method, for testing code generic over
+/// clients without a server.
+///
+/// Calls of a method are answered by its queued replies in order, see
+/// [`MockClient::respond_sequence`], then by its standing reply. Calls of methods without a reply
+/// fail with the "Method not found" error object. Every request is recorded, see
+/// [`MockClient::calls`], and the `assert_*` methods panic with the calls made when their
+/// expectation isn't met.
+///
+/// Clones share their script and recorded calls.
+#[derive(Clone, Default)]
+pub struct MockClient {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    nonce: AtomicU64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    scripts: HashMap<String, Script>,
+    calls: Vec<Request>,
+}
+
+/// The replies to the calls of a method.
+#[derive(Default)]
+struct Script {
+    queued: VecDeque<Handler>,
+    standing: Option<Handler>,
+}
+
+impl fmt::Debug for MockClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.state.lock().unwrap();
+        f.debug_struct("MockClient")
+            .field("methods", &state.scripts.keys())
+            .field("calls", &state.calls.len())
+            .finish()
+    }
+}
+
+impl MockClient {
+    /// Creates a client without replies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every call of `method` with `result`, replacing its standing reply.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `result` fails to serialize.
+    pub fn respond<M, T>(&self, method: M, result: T) -> &Self
+    where
+        M: Into<String>,
+        T: Serialize,
+    {
+        let result = serde_json::to_value(result).expect("mock result must serialize");
+        self.stand(
+            method.into(),
+            Arc::new(move |_| Ok(reply(Ok(result.clone())))),
+        )
+    }
+
+    /// Answer every call of `method` with the error object `err`, replacing its standing reply.
+    pub fn respond_error<M: Into<String>>(&self, method: M, err: RpcError) -> &Self {
+        self.stand(
+            method.into(),
+            Arc::new(move |_| Ok(reply(Err(err.clone())))),
+        )
+    }
+
+    /// Answer every call of `method` using `handler`, called with the parameters of each call,
+    /// replacing its standing reply.
+    pub fn respond_with<M, F, T>(&self, method: M, handler: F) -> &Self
+    where
+        M: Into<String>,
+        F: Fn(Value) -> Result<T, RpcError> + Send + Sync + 'static,
+        T: Serialize,
+    {
+        let handler = move |params| {
+            let result = handler(params).and_then(|result| {
+                serde_json::to_value(result)
+                    .map_err(|err| RpcError::internal_error().with_data(err.to_string()))
+            });
+            Ok(reply(result))
+        };
+        self.stand(method.into(), Arc::new(handler))
+    }
+
+    /// Answer the next calls of `method` with `replies`, one each in order, before its standing
+    /// reply.
+    pub fn respond_sequence<M, I>(&self, method: M, replies: I) -> &Self
+    where
+        M: Into<String>,
+        I: IntoIterator<Item = Result<Value, RpcError>>,
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        let script = state.scripts.entry(method.into()).or_default();
+        for result in replies {
+            let handler: Handler = Arc::new(move |_| Ok(reply(result.clone())));
+            script.queued.push_back(handler);
+        }
+        drop(state);
+        self
+    }
+
+    /// Fail every call of `method` with the error made by `err`, such as [`Error::Timeout`],
+    /// replacing its standing reply.
+    pub fn fail<M, F>(&self, method: M, err: F) -> &Self
+    where
+        M: Into<String>,
+        F: Fn() -> MockError + Send + Sync + 'static,
+    {
+        self.stand(method.into(), Arc::new(move |_| Err(err())))
+    }
+
+    /// Remove the replies of every method and the recorded calls.
+    pub fn reset(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.scripts.clear();
+        state.calls.clear();
+    }
+
+    fn stand(&self, method: String, handler: Handler) -> &Self {
+        let mut state = self.shared.state.lock().unwrap();
+        state.scripts.entry(method).or_default().standing = Some(handler);
+        drop(state);
+        self
+    }
+
+    /// Send a request, recording it, and answer it from the script of its method.
+    pub async fn send(&self, request: Request) -> Result<Response, MockError> {
+        let context = CallContext {
+            id: request.id.clone(),
+            method: request.method.clone(),
+            endpoint: "mock".to_owned(),
+        };
+        let handler = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.calls.push(request.clone());
+            state.scripts.get_mut(&request.method).and_then(|script| {
+                script
+                    .queued
+                    .pop_front()
+                    .or_else(|| script.standing.clone())
+            })
+        };
+        let Request { params, id, .. } = request;
+        let response = match handler {
+            // Called without the lock, so that handlers may use the client
+            Some(handler) => handler(params),
+            None => Ok(reply(Err(RpcError::method_not_found()))),
+        };
+        response
+            .map(|response| Response { id, ..response })
+            .map_err(|err| err.with_context(|| context))
+    }
+
+    /// Returns the requests sent, in order.
+    pub fn calls(&self) -> Vec<Request> {
+        self.shared.state.lock().unwrap().calls.clone()
+    }
+
+    /// Returns the parameters of the calls of `method`, in order.
+    pub fn calls_to(&self, method: &str) -> Vec<Value> {
+        let state = self.shared.state.lock().unwrap();
+        let calls = state.calls.iter().filter(|call| call.method == method);
+        calls.map(|call| call.params.clone()).collect()
+    }
+
+    /// Returns the number of calls of `method`.
+    pub fn call_count(&self, method: &str) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .calls
+            .iter()
+            .filter(|call| call.method == method)
+            .count()
+    }
+
+    /// Panics unless `method` was called.
+    #[track_caller]
+    pub fn assert_called(&self, method: &str) {
+        if self.call_count(method) == 0 {
+            panic!("expected a call of {}, {}", method, self.described());
+        }
+    }
+
+    /// Panics if `method` was called.
+    #[track_caller]
+    pub fn assert_not_called(&self, method: &str) {
+        if self.call_count(method) != 0 {
+            panic!("expected no call of {}, {}", method, self.described());
+        }
+    }
+
+    /// Panics unless `method` was called exactly `times`.
+    #[track_caller]
+    pub fn assert_called_times(&self, method: &str, times: usize) {
+        let count = self.call_count(method);
+        if count != times {
+            panic!(
+                "expected {} calls of {}, found {}, {}",
+                times,
+                method,
+                count,
+                self.described()
+            );
+        }
+    }
+
+    /// Panics unless `method` was called with `params`.
+    #[track_caller]
+    pub fn assert_called_with<V: Into<Value>>(&self, method: &str, params: V) {
+        let params = params.into();
+        if !self.calls_to(method).contains(&params) {
+            panic!(
+                "expected a call of {} with {}, {}",
+                method,
+                params,
+                self.described()
+            );
+        }
+    }
+
+    /// The calls made, for the message of a failed assertion.
+    fn described(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        if state.calls.is_empty() {
+            return "no calls were made".to_owned();
+        }
+        let calls: Vec<_> = state
+            .calls
+            .iter()
+            .map(|call| format!("{}({})", call.method, call.params))
+            .collect();
+        format!("calls made: {}", calls.join(", "))
+    }
+}
+
+impl Service<Request> for MockClient {
+    type Response = Response;
+    type Error = MockError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.send(request).await })
+    }
+}
+
+impl RequestFactory for MockClient {
+    /// Build the request.
+    fn build_request(&self) -> RequestBuilder {
+        let nonce = self.shared.nonce.fetch_add(1, Ordering::AcqRel);
+        Request::build().id(nonce)
+    }
+}
+
+/// The response carrying `result`, its id set once sent.
+fn reply(result: Result<Value, RpcError>) -> Response {
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(err) => (None, Some(err)),
+    };
+    Response {
+        result,
+        error,
+        id: Value::Null,
+        jsonrpc: Some("2.0".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower_util::ServiceExt;
+
+    use super::*;
+
+    fn call(client: &MockClient, method: &str, params: Value) -> Request {
+        let request = client.build_request().method(method).params(params);
+        request.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_from_the_script() {
+        let client = MockClient::new();
+        client
+            .respond("version", "1.0")
+            .respond_error("fail", RpcError::invalid_params())
+            .respond_with("add", |params: Value| {
+                let a = params[0].as_i64().ok_or_else(RpcError::invalid_params)?;
+                Ok(a + params[1].as_i64().unwrap_or(0))
+            });
+
+        let response = client
+            .send(call(&client, "version", json!([])))
+            .await
+            .unwrap();
+        assert_eq!(response.result, Some(json!("1.0")));
+        assert_eq!(response.id, json!(0));
+
+        let response = client
+            .send(call(&client, "add", json!([1, 2])))
+            .await
+            .unwrap();
+        assert_eq!(response.result, Some(json!(3)));
+        let response = client
+            .send(call(&client, "add", json!(["one"])))
+            .await
+            .unwrap();
+        assert_eq!(response.error, Some(RpcError::invalid_params()));
+
+        let response = client.send(call(&client, "fail", json!([]))).await.unwrap();
+        assert_eq!(response.error, Some(RpcError::invalid_params()));
+        let response = client
+            .send(call(&client, "other", json!([])))
+            .await
+            .unwrap();
+        assert_eq!(response.error, Some(RpcError::method_not_found()));
+    }
+
+    #[tokio::test]
+    async fn answers_queued_replies_before_the_standing_one() {
+        let client = MockClient::new();
+        client.respond("poll", "done").respond_sequence(
+            "poll",
+            vec![Ok(json!("pending")), Err(RpcError::internal_error())],
+        );
+
+        let mut replies = Vec::new();
+        for _ in 0..4 {
+            let response = client.send(call(&client, "poll", json!([]))).await;
+            replies.push(response.unwrap());
+        }
+        assert_eq!(replies[0].result, Some(json!("pending")));
+        assert_eq!(replies[1].error, Some(RpcError::internal_error()));
+        assert_eq!(replies[2].result, Some(json!("done")));
+        assert_eq!(replies[3].result, Some(json!("done")));
+    }
+
+    #[tokio::test]
+    async fn fails_calls_with_context() {
+        let client = MockClient::new();
+        client.fail("slow", || Error::Timeout);
+        let request = call(&client, "slow", json!([]));
+        let err = client.clone().oneshot(request).await.unwrap_err();
+        match err {
+            Error::Context { context, error } => {
+                assert_eq!(context.method, "slow");
+                assert_eq!(context.endpoint, "mock");
+                assert!(matches!(*error, Error::Timeout));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_calls_shared_by_clones() {
+        let client = MockClient::new();
+        let clone = client.clone();
+        clone.send(call(&clone, "a", json!([1]))).await.unwrap();
+        client.send(call(&client, "b", json!([]))).await.unwrap();
+        clone.send(call(&clone, "a", json!([2]))).await.unwrap();
+
+        assert_eq!(client.calls().len(), 3);
+        assert_eq!(client.calls_to("a"), vec![json!([1]), json!([2])]);
+        assert_eq!(client.call_count("b"), 1);
+        client.assert_called("a");
+        client.assert_called_times("a", 2);
+        client.assert_called_with("a", json!([2]));
+        client.assert_not_called("c");
+
+        client.reset();
+        assert!(clone.calls().is_empty());
+        client.assert_not_called("a");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected a call of b with [2], calls made: a([1]), b([1])")]
+    async fn assertions_describe_the_calls_made() {
+        let client = MockClient::new();
+        client.send(call(&client, "a", json!([1]))).await.unwrap();
+        client.send(call(&client, "b", json!([1]))).await.unwrap();
+        client.assert_called_with("b", json!([2]));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a call of a, no calls were made")]
+    fn assertions_describe_the_absence_of_calls() {
+        MockClient::new().assert_called("a");
+    }
+}
@@ -1,24 +1,186 @@
+pub mod batch;
+pub(crate) mod buffer;
+pub(crate) mod compression;
+pub mod cookie;
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+pub mod dns;
+pub mod duplex;
+pub mod encoding;
+#[cfg(feature = "ethereum")]
+pub mod ethereum;
+pub mod events;
 pub mod http;
+pub mod latency;
+pub mod limits;
+pub mod mock;
+#[cfg(feature = "server")]
+pub mod peer;
+pub mod pool;
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+pub mod proxy;
+pub mod raw;
+pub mod stream;
+pub mod subscription;
+pub mod tcp;
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+pub mod tls;
+pub mod trace;
 
-use std::{error, fmt};
+use std::{error, fmt, ops::RangeInclusive, sync::Arc, time::Duration};
+
+use futures_core::future::BoxFuture;
+use hyper::StatusCode;
+
+use crate::{
+    auth::BoxError,
+    objects::{Request, Response, RpcError},
+};
 
 pub trait RequestFactory {
     fn build_request(&self) -> crate::objects::RequestBuilder;
 }
 
+/// Hooks run by a client around each call.
+///
+/// This is a lightweight alternative to wrapping the client in a tower layer.
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called with each request before it is sent.
+    fn before<'a>(&'a self, request: &'a mut Request) -> BoxFuture<'a, ()> {
+        let _ = request;
+        Box::pin(async {})
+    }
+
+    /// Called with each response before it is returned.
+    fn after<'a>(&'a self, response: &'a mut Response) -> BoxFuture<'a, ()> {
+        let _ = response;
+        Box::pin(async {})
+    }
+}
+
+/// The interceptors registered on a client, in order.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Vec<Arc<dyn Interceptor>>);
+
+impl Interceptors {
+    pub(crate) fn push<I: Interceptor>(&mut self, interceptor: I) {
+        self.0.push(Arc::new(interceptor));
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &dyn Interceptor> {
+        self.0.iter().map(|interceptor| interceptor.as_ref())
+    }
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+/// The call an error occurred in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallContext {
+    /// The ID of the request.
+    pub id: serde_json::Value,
+    /// The method called.
+    pub method: String,
+    /// The URL of the server.
+    pub endpoint: String,
+}
+
+impl fmt::Display for CallContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "method {}, id {}, endpoint {}",
+            self.method, self.id, self.endpoint
+        )
+    }
+}
+
 /// The error type for RPCs.
 #[derive(Debug)]
 pub enum Error<E> {
+    /// The credentials for the call could not be obtained.
+    Auth(BoxError),
     /// The batch response contained a duplicate ID.
     BatchDuplicateResponseId(serde_json::Value),
+    /// The call was cancelled before it completed.
+    Cancelled,
     /// A connection error occured.
     Connection(E),
+    /// An error annotated with the call it occurred in.
+    ///
+    /// Clients return all errors wrapped in this, use [`Error::inner`] to match on the cause.
+    Context {
+        /// The failed call.
+        context: CallContext,
+        /// The cause of the failure.
+        error: Box<Error<E>>,
+    },
+    /// The response body could not be decompressed, or decoded by the client's codec.
+    Decode(BoxError),
     /// Batches can't be empty.
     EmptyBatch,
+    /// The request could not be encoded by the client's codec.
+    Encode(BoxError),
+    /// The server responded with a non-success HTTP status and no JSON-RPC error object.
+    Http {
+        /// The HTTP status code.
+        status: StatusCode,
+        /// The response body.
+        body: String,
+    },
     /// An error occured during respnse JSON deserialization.
     Json(serde_json::Error),
     /// The response did not have the expected nonce.
     NonceMismatch,
+    /// The call wasn't recorded in the fixture being replayed, see [`FixtureLayer`].
+    ///
+    /// [`FixtureLayer`]: crate::layers::FixtureLayer
+    NotRecorded(Box<Request>),
+    /// The buffer of a subscription overflowed, ending its stream.
+    Overflow {
+        /// The number of notifications buffered.
+        capacity: usize,
+    },
+    /// The server rejected the call with HTTP 429.
+    RateLimited {
+        /// The delay requested by the server before trying again.
+        retry_after: Option<Duration>,
+        /// The JSON-RPC error object sent along with the status, if any.
+        error: Option<RpcError>,
+    },
+    /// The response body exceeded the configured maximum size.
+    ResponseTooLarge {
+        /// The maximum size in bytes.
+        limit: usize,
+    },
+    /// A subscription was replayed after reconnecting, notifications may have been missed.
+    Resubscribed,
+    /// The server responded with an error object in place of a streamed result.
+    Rpc(RpcError),
+    /// The call did not complete before its deadline.
+    Timeout,
+    /// The response body nested arrays and objects deeper than the configured limit.
+    TooDeep {
+        /// The maximum depth.
+        limit: usize,
+    },
+    /// The response body contained more elements than the configured limit.
+    TooManyElements {
+        /// The maximum number of elements.
+        limit: usize,
+    },
+    /// The server is temporarily unavailable.
+    Unavailable {
+        /// The delay requested by the server before trying again.
+        retry_after: Option<Duration>,
+        /// The JSON-RPC error object sent along with the status, if any.
+        error: Option<RpcError>,
+    },
+    /// The call can't be made with the client's configuration.
+    Unsupported(&'static str),
     /// The response had a jsonrpc field other than "2.0".
     VersionMismatch,
     /// The batch response contained an ID that didn't correspond to any request ID.
@@ -30,13 +192,50 @@ pub enum Error<E> {
 impl<E: fmt::Display> fmt::Display for Error<E> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let printable = match self {
+            Error::Auth(err) => return write!(f, "authorization error, {}", err),
             Error::BatchDuplicateResponseId(err) => {
                 return write!(f, "duplicate batch response id, {}", err)
             }
+            Error::Cancelled => "cancelled",
             Error::Connection(err) => return err.fmt(f),
+            Error::Context { context, error } => return write!(f, "{} ({})", error, context),
+            Error::Decode(err) => return write!(f, "decoding error, {}", err),
             Error::EmptyBatch => "empty batch",
+            Error::Encode(err) => return write!(f, "encoding error, {}", err),
+            Error::Http { status, .. } => return write!(f, "http error, {}", status),
             Error::Json(err) => return err.fmt(f),
             Error::NonceMismatch => "nonce mismatch",
+            Error::NotRecorded(request) => {
+                return write!(
+                    f,
+                    "call of {} with {} not recorded in fixture",
+                    request.method, request.params
+                )
+            }
+            Error::Overflow { capacity } => {
+                return write!(f, "subscription buffer of {} overflowed", capacity)
+            }
+            Error::RateLimited {
+                error: Some(err), ..
+            } => return write!(f, "rate limited, {}", err),
+            Error::RateLimited { .. } => "rate limited",
+            Error::ResponseTooLarge { limit } => {
+                return write!(f, "response body exceeds {} bytes", limit)
+            }
+            Error::Resubscribed => "resubscribed after reconnecting",
+            Error::Rpc(err) => return write!(f, "rpc error, {}", err),
+            Error::Timeout => "timed out",
+            Error::TooDeep { limit } => {
+                return write!(f, "response nesting exceeds depth {}", limit)
+            }
+            Error::TooManyElements { limit } => {
+                return write!(f, "response exceeds {} elements", limit)
+            }
+            Error::Unavailable {
+                error: Some(err), ..
+            } => return write!(f, "server unavailable, {}", err),
+            Error::Unavailable { .. } => "server unavailable",
+            Error::Unsupported(reason) => return write!(f, "unsupported, {}", reason),
             Error::VersionMismatch => "version mismatch",
             Error::WrongBatchResponseId(err) => {
                 return write!(f, "wrong batch response id, {}", err)
@@ -47,4 +246,199 @@ impl<E: fmt::Display> fmt::Display for Error<E> {
     }
 }
 
-impl<E: fmt::Display + fmt::Debug> error::Error for Error<E> {}
+impl<E> Error<E> {
+    /// A short name for the kind of error, used when the inner error can't be displayed.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Error::Auth(_) => "auth",
+            Error::BatchDuplicateResponseId(_) => "batch_duplicate_response_id",
+            Error::Cancelled => "cancelled",
+            Error::Connection(_) => "connection",
+            Error::Context { error, .. } => error.kind(),
+            Error::Decode(_) => "decode",
+            Error::EmptyBatch => "empty_batch",
+            Error::Encode(_) => "encode",
+            Error::Http { .. } => "http",
+            Error::Json(_) => "json",
+            Error::NonceMismatch => "nonce_mismatch",
+            Error::NotRecorded(_) => "not_recorded",
+            Error::Overflow { .. } => "overflow",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::ResponseTooLarge { .. } => "response_too_large",
+            Error::Resubscribed => "resubscribed",
+            Error::Rpc(_) => "rpc",
+            Error::Timeout => "timeout",
+            Error::TooDeep { .. } => "too_deep",
+            Error::TooManyElements { .. } => "too_many_elements",
+            Error::Unavailable { .. } => "unavailable",
+            Error::Unsupported(_) => "unsupported",
+            Error::VersionMismatch => "version_mismatch",
+            Error::WrongBatchResponseId(_) => "wrong_batch_response_id",
+            Error::WrongBatchResponseSize => "wrong_batch_response_size",
+        }
+    }
+
+    /// Annotate the error with the call it occurred in, unless it already is.
+    pub(crate) fn with_context(self, context: impl FnOnce() -> CallContext) -> Self {
+        match self {
+            Error::Context { .. } => self,
+            error => Error::Context {
+                context: context(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Returns the call the error occurred in, if known.
+    pub fn context(&self) -> Option<&CallContext> {
+        match self {
+            Error::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the error without the call context.
+    pub fn inner(&self) -> &Error<E> {
+        match self {
+            Error::Context { error, .. } => error.inner(),
+            error => error,
+        }
+    }
+
+    /// Converts into the error without the call context.
+    pub fn into_inner(self) -> Error<E> {
+        match self {
+            Error::Context { error, .. } => error.into_inner(),
+            error => error,
+        }
+    }
+
+    /// Returns the delay the server requested before trying again, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.inner() {
+            Error::RateLimited { retry_after, .. } | Error::Unavailable { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the failure is likely to go away when the call is retried.
+    ///
+    /// Connection errors, rate limiting, unavailability, timeouts and gateway errors are transient.
+    /// Errors in the response itself, such as malformed JSON, are permanent.
+    pub fn is_transient(&self) -> bool {
+        match self.inner() {
+            Error::Connection(_)
+            | Error::RateLimited { .. }
+            | Error::Timeout
+            | Error::Unavailable { .. } => true,
+            Error::Http { status, .. } => matches!(
+                *status,
+                StatusCode::REQUEST_TIMEOUT
+                    | StatusCode::INTERNAL_SERVER_ERROR
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Classifies failures as transient, and worth retrying, or permanent.
+pub trait Classify<E> {
+    /// Returns `true` if the call failing with `err` should be retried.
+    fn is_transient_error(&self, err: &Error<E>) -> bool {
+        err.is_transient()
+    }
+
+    /// Returns `true` if the call returning the error object `err` should be retried.
+    fn is_transient_rpc_error(&self, err: &RpcError) -> bool {
+        let _ = err;
+        false
+    }
+}
+
+/// The default [`Classify`] implementation.
+///
+/// Uses [`Error::is_transient`] and treats the configured ranges of JSON-RPC error codes as
+/// transient, by default none are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Classifier {
+    transient_codes: Vec<RangeInclusive<i32>>,
+}
+
+impl Classifier {
+    /// Treat error objects with a code in `codes` as transient, e.g. `-32099..=-32000`.
+    pub fn transient_codes(mut self, codes: RangeInclusive<i32>) -> Self {
+        self.transient_codes.push(codes);
+        self
+    }
+}
+
+impl<E> Classify<E> for Classifier {
+    fn is_transient_rpc_error(&self, err: &RpcError) -> bool {
+        self.transient_codes
+            .iter()
+            .any(|codes| codes.contains(&err.code))
+    }
+}
+
+impl<E: fmt::Display + fmt::Debug> error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // The inner errors are displayed along with the error, skip to their causes
+        match self {
+            Error::Auth(err) | Error::Decode(err) | Error::Encode(err) => err.source(),
+            Error::Context { error, .. } => error.source(),
+            Error::Json(err) => err.source(),
+            Error::Rpc(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    /// The messages of `err` and its causes, as printed by error reporters.
+    fn chain(err: &dyn error::Error) -> Vec<String> {
+        let mut messages = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(err) = source {
+            messages.push(err.to_string());
+            source = err.source();
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn inner_errors_are_reported_once() {
+        let err: Error<io::Error> = Error::Connection(io::Error::other("refused"));
+        assert_eq!(chain(&err), ["refused"]);
+
+        // The transport failure is displayed along with the call
+        let client = http::Client::new("http://127.0.0.1:1".to_owned(), None, None);
+        let request = client.build_request().method("ping").finish().unwrap();
+        let err = client.send(request).await.unwrap_err();
+        let messages = chain(&err);
+        assert!(messages[0].starts_with("service error, "), "{:?}", messages);
+        assert!(messages[0].contains("(method ping"), "{:?}", messages);
+
+        // Boxed service errors, as returned by tower middleware, are supported too
+        let err: Error<http::ConnectionError<BoxError>> =
+            Error::Connection(http::ConnectionError::Service("overloaded".into()));
+        assert_eq!(chain(&err), ["service error, overloaded"]);
+
+        let json = serde_json::from_str::<Value>("{").unwrap_err();
+        let err: Error<io::Error> = Error::Json(json);
+        assert_eq!(chain(&err).len(), 1);
+
+        let err: Error<io::Error> = Error::Auth("expired".into());
+        assert_eq!(chain(&err), ["authorization error, expired"]);
+    }
+}
@@ -0,0 +1,162 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::duplex::Client;
+use crate::server::Router;
+
+/// Both ends of a JSON-RPC connection, making calls to the remote peer and serving its calls.
+///
+/// Protocols such as LSP and DAP have each side call the other over the same connection. The
+/// calls of the remote peer are served like those of a connection to a [`tcp::Server`], within
+/// the limits of the router and measured by its metrics. Handlers get the connection with
+/// [`Connection::current`], to notify the remote peer.
+///
+/// [`tcp::Server`]: crate::server::tcp::Server
+/// [`Connection::current`]: crate::server::connection::Connection::current
+#[derive(Clone, Debug)]
+pub struct Peer {
+    client: Client,
+}
+
+impl Peer {
+    /// Creates a new peer over `io`, serving the methods of `router`.
+    ///
+    /// This must be called within a Tokio runtime.
+    pub fn new<T>(io: T, router: Router) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Peer {
+            client: Client::new_serving(io, router),
+        }
+    }
+
+    /// The client making calls to the remote peer, and receiving its notifications.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+
+    use super::*;
+    use crate::{
+        clients::RequestFactory,
+        objects::RpcError,
+        server::{connection::Connection, ConcurrencyLimit},
+    };
+
+    type Remote = (
+        Lines<BufReader<tokio::io::ReadHalf<DuplexStream>>>,
+        tokio::io::WriteHalf<DuplexStream>,
+    );
+
+    fn remote(io: DuplexStream) -> Remote {
+        let (read, write) = tokio::io::split(io);
+        (BufReader::new(read).lines(), write)
+    }
+
+    /// Send `message` from the remote peer, and read the next message it receives.
+    async fn exchange((lines, write): &mut Remote, message: Value) -> Value {
+        let message = format!("{}\n", message);
+        write.write_all(message.as_bytes()).await.unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new().register("add", |(a, b): (i64, i64)| async move {
+            Ok::<_, RpcError>(a + b)
+        })
+    }
+
+    #[tokio::test]
+    async fn serves_calls_and_batches() {
+        let (io, remote_io) = tokio::io::duplex(4096);
+        let _peer = Peer::new(io, router());
+        let mut remote = remote(remote_io);
+
+        let call = json!({ "jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 1 });
+        let response = exchange(&mut remote, call).await;
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "result": 3, "id": 1 }));
+
+        let batch = json!([
+            { "jsonrpc": "2.0", "method": "add", "params": [2, 2], "id": 2 },
+            { "jsonrpc": "2.0", "method": "missing", "id": 3 },
+        ]);
+        let response = exchange(&mut remote, batch).await;
+        assert_eq!(response[0]["result"], 4);
+        assert_eq!(response[1]["error"]["code"], -32601);
+    }
+
+    #[tokio::test]
+    async fn calls_the_remote_peer() {
+        let (io, remote_io) = tokio::io::duplex(4096);
+        let peer = Peer::new(io, router());
+        let (mut lines, mut write) = remote(remote_io);
+
+        let client = peer.client().clone();
+        let request = client.build_request().method("ping").finish().unwrap();
+        let calling = tokio::spawn(async move { client.send(request).await });
+        let line = lines.next_line().await.unwrap().unwrap();
+        let call: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(call["method"], "ping");
+        let response = json!({ "jsonrpc": "2.0", "result": "pong", "id": call["id"] });
+        let response = format!("{}\n", response);
+        write.write_all(response.as_bytes()).await.unwrap();
+
+        let response = calling.await.unwrap().unwrap();
+        assert_eq!(response.result, Some(json!("pong")));
+    }
+
+    #[tokio::test]
+    async fn applies_the_limits_of_the_router() {
+        let (io, remote_io) = tokio::io::duplex(4096);
+        let router = router()
+            .max_params_depth(1)
+            .concurrency_limit(ConcurrencyLimit::new().per_connection(0).reject(true));
+        let _peer = Peer::new(io, router);
+        let mut remote = remote(remote_io);
+
+        let call = json!({ "jsonrpc": "2.0", "method": "add", "params": [[1], 2], "id": 1 });
+        let response = exchange(&mut remote, call).await;
+        assert_eq!(response["error"]["code"], -32602);
+
+        let call = json!({ "jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 2 });
+        let response = exchange(&mut remote, call).await;
+        assert_eq!(response["error"]["message"], "Server overloaded");
+    }
+
+    #[tokio::test]
+    async fn handlers_notify_over_the_connection() {
+        let (io, remote_io) = tokio::io::duplex(4096);
+        let router = Router::new().register("hello", |()| async move {
+            let connection = Connection::current().unwrap();
+            connection.notify("greeting", json!(["hi"])).unwrap();
+            Ok::<_, RpcError>(true)
+        });
+        let _peer = Peer::new(io, router);
+        let mut remote = remote(remote_io);
+
+        let call = json!({ "jsonrpc": "2.0", "method": "hello", "id": 1 });
+        let notification = exchange(&mut remote, call).await;
+        assert_eq!(notification["method"], "greeting");
+        let response = remote.0.next_line().await.unwrap().unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], true);
+    }
+
+    #[tokio::test]
+    async fn clients_refuse_calls() {
+        let (io, remote_io) = tokio::io::duplex(4096);
+        let _client = Client::new(io);
+        let mut remote = remote(remote_io);
+
+        let call = json!({ "jsonrpc": "2.0", "method": "add", "params": [1, 2], "id": 7 });
+        let response = exchange(&mut remote, call).await;
+        assert_eq!(response["error"]["code"], -32601);
+        assert_eq!(response["id"], 7);
+    }
+}
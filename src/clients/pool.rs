@@ -0,0 +1,159 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use futures_core::Future;
+use hyper::{
+    rt::{Read, ReadBufCursor, Write},
+    Uri,
+};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use tower_service::Service;
+
+use super::events::{Event, Events};
+
+/// The connections made by a [`Tracked`] connector, shared by the clones of its client.
+#[derive(Debug, Default)]
+pub(crate) struct Pool {
+    open: AtomicUsize,
+    events: Mutex<Option<Events>>,
+}
+
+impl Pool {
+    /// Emit an [`Event::Connected`] to `events` for each connection made from now on.
+    pub(crate) fn set_events(&self, events: Events) {
+        *self.events.lock().unwrap() = Some(events);
+    }
+
+    /// The number of connections still open.
+    pub(crate) fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+}
+
+/// A connector counting the connections it made which are still open, for
+/// [`Stats::connections`], and emitting an [`Event::Connected`] for each to the events of its
+/// client.
+///
+/// The HTTP clients created by [`Client::new`] and the other constructors of the crate track
+/// their connections using it.
+///
+/// [`Stats::connections`]: super::http::Stats::connections
+/// [`Client::new`]: super::http::Client::new
+#[derive(Clone, Debug)]
+pub struct Tracked<C> {
+    inner: C,
+    pool: Arc<Pool>,
+}
+
+impl<C> Tracked<C> {
+    pub(crate) fn new(inner: C) -> Self {
+        Tracked {
+            inner,
+            pool: Arc::default(),
+        }
+    }
+
+    /// The connections made by the connector and its clones.
+    pub(crate) fn pool(&self) -> Arc<Pool> {
+        self.pool.clone()
+    }
+}
+
+impl<C> Service<Uri> for Tracked<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+{
+    type Response = TrackedIo<C::Response>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let host = uri
+            .host()
+            .unwrap_or("")
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_owned();
+        let connecting = self.inner.call(uri);
+        let pool = self.pool.clone();
+        Box::pin(async move {
+            let io = connecting.await?;
+            pool.open.fetch_add(1, Ordering::Relaxed);
+            let events = pool.events.lock().unwrap().clone();
+            if let Some(events) = events {
+                events.emit(|| Event::Connected { host });
+            }
+            Ok(TrackedIo { io, pool })
+        })
+    }
+}
+
+/// A connection made by a [`Tracked`] connector, counted as open until dropped.
+#[derive(Debug)]
+pub struct TrackedIo<T> {
+    io: T,
+    pool: Arc<Pool>,
+}
+
+impl<T> Drop for TrackedIo<T> {
+    fn drop(&mut self) {
+        self.pool.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T: Connection> Connection for TrackedIo<T> {
+    fn connected(&self) -> Connected {
+        self.io.connected()
+    }
+}
+
+impl<T: Read + Unpin> Read for TrackedIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_read(cx, buf)
+    }
+}
+
+impl<T: Write + Unpin> Write for TrackedIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
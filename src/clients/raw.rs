@@ -0,0 +1,190 @@
+use std::{borrow::Cow, collections::BTreeMap, collections::HashSet};
+
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::{value::RawValue, Value};
+
+use super::Error;
+use crate::objects::{Request, ResponseRef};
+
+/// How a client treats the ids of pre-encoded requests.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RawIds {
+    /// Forward the ids untouched, without checking the response ids.
+    #[default]
+    Forward,
+    /// Forward the ids untouched, checking that the response ids match.
+    Check,
+    /// Replace the ids with the client's own, checking that the response ids match.
+    ///
+    /// The members of stamped requests are reordered, but their values are left untouched.
+    Stamp,
+}
+
+/// The members of a pre-encoded request describing the call.
+#[derive(Deserialize)]
+struct Head<'a> {
+    #[serde(borrow)]
+    method: Cow<'a, str>,
+    // Absent and null ids are both treated as notifications
+    #[serde(default)]
+    id: Option<Value>,
+}
+
+/// A pre-encoded request or batch, ready to send.
+#[derive(Debug)]
+pub(crate) struct Payload {
+    /// The body sent.
+    pub(crate) body: Bytes,
+    /// Describes the call in errors, events and traces.
+    pub(crate) request: Request,
+    /// The ids expected in response, if checked.
+    pub(crate) expected: Option<Expected>,
+}
+
+/// The response ids expected for a pre-encoded request or batch.
+#[derive(Debug)]
+pub(crate) struct Expected {
+    ids: Vec<Value>,
+    batch: bool,
+}
+
+impl Payload {
+    /// Prepare a single pre-encoded request, drawing stamped ids from `nonce`.
+    pub(crate) fn single<E>(
+        body: Bytes,
+        ids: RawIds,
+        nonce: impl FnOnce() -> Value,
+    ) -> Result<Self, Error<E>> {
+        let head: Head = serde_json::from_slice(&body).map_err(Error::Json)?;
+        let (body, id) = match (ids, head.id) {
+            (RawIds::Stamp, Some(_)) => {
+                let id = nonce();
+                (stamp(&body, &id)?, Some(id))
+            }
+            (_, id) => (body.clone(), id),
+        };
+
+        let expected = match ids {
+            RawIds::Forward => None,
+            _ => Some(Expected {
+                ids: id.iter().cloned().collect(),
+                batch: false,
+            }),
+        };
+        Ok(Payload {
+            request: describe(head.method.into_owned(), id.unwrap_or_default()),
+            body,
+            expected,
+        })
+    }
+
+    /// Prepare a pre-encoded batch, drawing stamped ids from `nonce`.
+    pub(crate) fn batch<E>(
+        body: Bytes,
+        ids: RawIds,
+        mut nonce: impl FnMut() -> Value,
+    ) -> Result<Self, Error<E>> {
+        let heads: Vec<Head> = serde_json::from_slice(&body).map_err(Error::Json)?;
+        if heads.is_empty() {
+            return Err(Error::EmptyBatch);
+        }
+
+        let (body, ids_sent) = match ids {
+            RawIds::Stamp => {
+                let mut members: Vec<BTreeMap<String, Box<RawValue>>> =
+                    serde_json::from_slice(&body).map_err(Error::Json)?;
+                let mut ids_sent = Vec::new();
+                for (head, members) in heads.iter().zip(&mut members) {
+                    if head.id.is_some() {
+                        let id = nonce();
+                        members.insert("id".to_owned(), to_raw(&id));
+                        ids_sent.push(id);
+                    }
+                }
+                let body = serde_json::to_vec(&members).map_err(Error::Json)?;
+                (Bytes::from(body), ids_sent)
+            }
+            _ => (
+                body.clone(),
+                heads.iter().filter_map(|head| head.id.clone()).collect(),
+            ),
+        };
+
+        let expected = match ids {
+            RawIds::Forward => None,
+            _ => Some(Expected {
+                ids: ids_sent.clone(),
+                batch: true,
+            }),
+        };
+        Ok(Payload {
+            request: describe("batch".to_owned(), Value::Array(ids_sent)),
+            body,
+            expected,
+        })
+    }
+}
+
+impl Expected {
+    /// Check the ids of a response body against those sent.
+    ///
+    /// Error objects with a null id are accepted, since servers send them when the request
+    /// couldn't be parsed.
+    pub(crate) fn check<E>(&self, body: &[u8]) -> Result<(), Error<E>> {
+        let responses = match (self.batch, serde_json::from_slice::<Vec<ResponseRef>>(body)) {
+            (true, Ok(responses)) => responses,
+            // A single error object answers a batch which couldn't be parsed
+            _ => vec![ResponseRef::from_slice(body).map_err(Error::Json)?],
+        };
+        if responses.len() > self.ids.len().max(1) {
+            return Err(Error::WrongBatchResponseSize);
+        }
+
+        let expected: HashSet<String> = self.ids.iter().map(Value::to_string).collect();
+        let mut seen = HashSet::new();
+        for response in responses {
+            let id = response.id.get();
+            if response.is_error() && id == "null" {
+                continue;
+            }
+            let id: Value = serde_json::from_str(id).map_err(Error::Json)?;
+            let key = id.to_string();
+            if !expected.contains(&key) {
+                return Err(match self.batch {
+                    true => Error::WrongBatchResponseId(id),
+                    false => Error::NonceMismatch,
+                });
+            }
+            if !seen.insert(key) {
+                return Err(Error::BatchDuplicateResponseId(id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A placeholder request describing a pre-encoded call.
+fn describe(method: String, id: Value) -> Request {
+    Request {
+        method,
+        params: Value::Null,
+        id,
+        jsonrpc: "2.0".to_owned(),
+    }
+}
+
+/// Replace the id of a single pre-encoded request.
+fn stamp<E>(body: &[u8], id: &Value) -> Result<Bytes, Error<E>> {
+    let mut members: BTreeMap<String, Box<RawValue>> =
+        serde_json::from_slice(body).map_err(Error::Json)?;
+    members.insert("id".to_owned(), to_raw(id));
+    Ok(Bytes::from(
+        serde_json::to_vec(&members).map_err(Error::Json)?,
+    ))
+}
+
+fn to_raw(id: &Value) -> Box<RawValue> {
+    serde_json::value::to_raw_value(id).unwrap() // This is safe
+}
@@ -0,0 +1,382 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use hyper::body::Body as _;
+use serde::de::Error as _;
+use serde_json::Value;
+
+use super::{
+    http::{Body, ConnectionError, HttpError},
+    Error,
+};
+use crate::objects::RpcError;
+
+/// Where the scanner is within the response object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Before the opening brace.
+    Start,
+    /// Before a member name, or the closing brace of an empty object.
+    Key { first: bool },
+    /// Within a member name.
+    InKey { escaped: bool },
+    /// After a member name.
+    Colon,
+    /// Before a member value.
+    Value,
+    /// Within a member value, `depth` brackets deep.
+    InValue {
+        depth: usize,
+        string: bool,
+        escaped: bool,
+        scalar: bool,
+    },
+    /// After a member, before a comma or the closing brace.
+    Comma,
+    /// After the closing brace.
+    Done,
+}
+
+/// The raw JSON bytes of the result of a call, streamed as they are received.
+///
+/// The response object is parsed incrementally and only its other members are buffered. If the
+/// response carries an error object the stream ends with [`Error::Rpc`], a `null` result is held
+/// back until it is known that there is none. Since the id of the response may follow its
+/// result, a response to another request ends the stream with [`Error::NonceMismatch`].
+pub struct ResultStream<E> {
+    body: Body,
+    buffer: BytesMut,
+    state: State,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    // Whether the value being scanned is the result, which is passed through
+    streaming: bool,
+    has_result: bool,
+    held: Option<Bytes>,
+    error: Option<RpcError>,
+    // The id of the request, and whether the response carried it
+    id: Value,
+    id_matched: bool,
+    finished: bool,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<E> fmt::Debug for ResultStream<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResultStream")
+            .field("state", &self.state)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+impl<E> ResultStream<E> {
+    pub(crate) fn new(body: Body, id: Value) -> Self {
+        ResultStream {
+            body,
+            buffer: BytesMut::new(),
+            state: State::Start,
+            key: Vec::new(),
+            value: Vec::new(),
+            streaming: false,
+            has_result: false,
+            held: None,
+            error: None,
+            id,
+            id_matched: false,
+            finished: false,
+            _error: PhantomData,
+        }
+    }
+
+    /// Scan the buffered bytes, returning the next chunk of the result, if any.
+    fn scan(&mut self) -> Result<Option<Bytes>, serde_json::Error> {
+        let mut i = 0;
+        while i < self.buffer.len() {
+            let byte = self.buffer[i];
+            if let State::InValue {
+                depth,
+                string,
+                escaped,
+                scalar,
+            } = &mut self.state
+            {
+                let ended = if *scalar && !*string && is_delimiter(byte) {
+                    // The delimiter belongs to the response object
+                    true
+                } else {
+                    if *string {
+                        *string = *escaped || byte != b'"';
+                        *escaped = !*escaped && byte == b'\\';
+                    } else {
+                        match byte {
+                            b'"' => *string = true,
+                            b'{' | b'[' => *depth += 1,
+                            b'}' | b']' => *depth = depth.saturating_sub(1),
+                            _ => {}
+                        }
+                    }
+                    if !self.streaming {
+                        self.value.push(byte);
+                    }
+                    i += 1;
+                    !*scalar && !*string && *depth == 0
+                };
+                if ended {
+                    self.state = State::Comma;
+                    if self.streaming {
+                        self.streaming = false;
+                        // A scalar may end at the start of a chunk
+                        if i == 0 {
+                            continue;
+                        }
+                        return Ok(Some(self.buffer.split_to(i).freeze()));
+                    }
+                    self.finish_value()?;
+                }
+                continue;
+            }
+
+            self.state = match self.state {
+                State::InKey { escaped: true } => {
+                    self.key.push(byte);
+                    State::InKey { escaped: false }
+                }
+                State::InKey { .. } if byte == b'"' => State::Colon,
+                State::InKey { .. } => {
+                    self.key.push(byte);
+                    State::InKey {
+                        escaped: byte == b'\\',
+                    }
+                }
+                state if byte.is_ascii_whitespace() => state,
+                State::Start if byte == b'{' => State::Key { first: true },
+                State::Key { .. } if byte == b'"' => {
+                    self.key.clear();
+                    State::InKey { escaped: false }
+                }
+                State::Key { first: true } if byte == b'}' => State::Done,
+                State::Colon if byte == b':' => State::Value,
+                State::Value => {
+                    if self.key == b"result" {
+                        self.has_result = true;
+                        // Pass the result through, starting at the front of the buffer
+                        if byte != b'n' {
+                            self.streaming = true;
+                            self.buffer.advance(i);
+                            i = 0;
+                        }
+                    }
+                    self.state = State::InValue {
+                        depth: 0,
+                        string: false,
+                        escaped: false,
+                        scalar: !matches!(byte, b'{' | b'[' | b'"'),
+                    };
+                    continue;
+                }
+                State::Comma if byte == b',' => State::Key { first: false },
+                State::Comma if byte == b'}' => State::Done,
+                _ => {
+                    return Err(serde_json::Error::custom(format!(
+                        "unexpected byte {:?} in response",
+                        byte as char
+                    )))
+                }
+            };
+            i += 1;
+        }
+
+        if self.streaming && i > 0 {
+            return Ok(Some(self.buffer.split_to(i).freeze()));
+        }
+        self.buffer.advance(i);
+        Ok(None)
+    }
+
+    /// Handle a buffered member value.
+    fn finish_value(&mut self) -> Result<(), serde_json::Error> {
+        let value = std::mem::take(&mut self.value);
+        match self.key.as_slice() {
+            b"result" => self.held = Some(Bytes::from(value)),
+            b"error" if value != b"null" => self.error = Some(serde_json::from_slice(&value)?),
+            b"id" => self.id_matched = serde_json::from_slice::<Value>(&value)? == self.id,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The final item of the stream, once the response object is complete.
+    fn finish(&mut self) -> Option<Result<Bytes, HttpError<E>>> {
+        self.finished = true;
+        if let Some(error) = self.error.take() {
+            return Some(Err(Error::Rpc(error)));
+        }
+        if !self.has_result {
+            return Some(Err(Error::Json(serde_json::Error::custom(
+                "response has no result",
+            ))));
+        }
+        if !self.id_matched {
+            return Some(Err(Error::NonceMismatch));
+        }
+        self.held.take().map(Ok)
+    }
+}
+
+/// Whether `byte` ends a number or literal.
+fn is_delimiter(byte: u8) -> bool {
+    matches!(byte, b',' | b'}' | b']') || byte.is_ascii_whitespace()
+}
+
+impl<E> Stream for ResultStream<E> {
+    type Item = Result<Bytes, HttpError<E>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.finished {
+                return Poll::Ready(None);
+            }
+            match this.scan() {
+                Ok(Some(chunk)) => return Poll::Ready(Some(Ok(chunk))),
+                Ok(None) if this.state == State::Done => return Poll::Ready(this.finish()),
+                Ok(None) => {}
+                Err(err) => {
+                    this.finished = true;
+                    return Poll::Ready(Some(Err(Error::Json(err))));
+                }
+            }
+
+            let err = match Pin::new(&mut this.body).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    if let Some(chunk) = frame.data_ref() {
+                        this.buffer.extend_from_slice(chunk);
+                    }
+                    continue;
+                }
+                Poll::Ready(Some(Err(err))) => Error::Connection(ConnectionError::Body(err)),
+                Poll::Ready(None) => {
+                    Error::Json(serde_json::Error::custom("response ended unexpectedly"))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            this.finished = true;
+            return Poll::Ready(Some(Err(err)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures_util::{stream, StreamExt};
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+
+    use super::*;
+
+    /// Stream the result of the response sent in `chunks`, as a reply to the request with id 1.
+    async fn stream(chunks: &[&'static str]) -> Vec<Result<Bytes, HttpError<io::Error>>> {
+        let frames: Vec<_> = chunks
+            .iter()
+            .map(|chunk| Ok::<_, io::Error>(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+            .collect();
+        let body = Body::new(StreamBody::new(stream::iter(frames)));
+        ResultStream::new(body, Value::from(1)).collect().await
+    }
+
+    /// The result streamed, failing on any error.
+    async fn result(chunks: &[&'static str]) -> String {
+        let mut result = Vec::new();
+        for chunk in stream(chunks).await {
+            let chunk = chunk.unwrap();
+            assert!(!chunk.is_empty());
+            result.extend_from_slice(&chunk);
+        }
+        String::from_utf8(result).unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_results_split_across_chunks() {
+        let result = result(&[
+            r#"{"jsonrpc":"2.0","res"#,
+            r#"ult":{"a":"}\"","b":[1,"#,
+            r#"2]},"id":1}"#,
+        ])
+        .await;
+        assert_eq!(result, r#"{"a":"}\"","b":[1,2]}"#);
+    }
+
+    #[tokio::test]
+    async fn streams_escaped_strings() {
+        let result = result(&[r#"{"id":1,"result":"a\\"#, r#"\"b\"c","jsonrpc":"2.0"}"#]).await;
+        assert_eq!(result, r#""a\\\"b\"c""#);
+    }
+
+    #[tokio::test]
+    async fn streams_scalars_split_across_chunks() {
+        assert_eq!(result(&[r#"{"result":12"#, r#"34,"id":1}"#]).await, "1234");
+        assert_eq!(
+            result(&[r#"{"id":1,"result":12"#, r#"}"#, r#""#]).await,
+            "12"
+        );
+        assert_eq!(result(&[r#"{"id":1,"result":tr"#, r#"ue }"#]).await, "true");
+    }
+
+    #[tokio::test]
+    async fn holds_back_null_results() {
+        let result = result(&[r#"{"jsonrpc":"2.0","result":nu"#, r#"ll,"id":1}"#]).await;
+        assert_eq!(result, "null");
+
+        let error = r#"{"result":null,"error":{"code":-32000,"message":"failed"},"id":1}"#;
+        let items = stream(&[error]).await;
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0], Err(Error::Rpc(err)) if err.code == -32000));
+    }
+
+    #[tokio::test]
+    async fn ends_with_errors() {
+        let error = r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"missing"},"id":1}"#;
+        let items = stream(&[error]).await;
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0], Err(Error::Rpc(err)) if err.code == -32601));
+
+        // The result is already streamed when the error follows it
+        let items =
+            stream(&[r#"{"result":[1],"error":{"code":-32000,"message":"late"},"id":1}"#]).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), "[1]");
+        assert!(matches!(&items[1], Err(Error::Rpc(err)) if err.code == -32000));
+    }
+
+    #[tokio::test]
+    async fn fails_on_other_ids() {
+        let items = stream(&[r#"{"jsonrpc":"2.0","result":[1],"id":2}"#]).await;
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[1], Err(Error::NonceMismatch)));
+
+        let items = stream(&[r#"{"jsonrpc":"2.0","result":[1]}"#]).await;
+        assert!(matches!(items[1], Err(Error::NonceMismatch)));
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_bodies() {
+        let items = stream(&[r#"{"jsonrpc":"2.0","result":[1,"#]).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), "[1,");
+        let err = items[1].as_ref().unwrap_err();
+        assert!(err.to_string().contains("ended unexpectedly"), "{}", err);
+
+        let items = stream(&[r#"{"id":1}"#]).await;
+        let err = items[0].as_ref().unwrap_err();
+        assert!(err.to_string().contains("no result"), "{}", err);
+    }
+}
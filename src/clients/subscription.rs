@@ -0,0 +1,493 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{
+    duplex::{Client, DuplexError},
+    Error,
+};
+use crate::objects::Notification;
+
+/// What a subscription does with notifications arriving while its buffer is full.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the oldest buffered notification to make room.
+    DropOldest,
+    /// Drop the notification arriving.
+    DropNewest,
+    /// End the stream with [`Error::Overflow`].
+    #[default]
+    Error,
+}
+
+/// The buffering of the notifications of a subscription, until they are consumed.
+///
+/// Notifications dropped on overflow are counted by [`Subscription::lagged`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriptionBuffer {
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Default for SubscriptionBuffer {
+    fn default() -> Self {
+        SubscriptionBuffer {
+            capacity: 1024,
+            overflow: Overflow::Error,
+        }
+    }
+}
+
+impl SubscriptionBuffer {
+    /// Creates a buffer of 1024 notifications, ending the stream when it overflows.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of notifications buffered.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets what happens to notifications arriving while the buffer is full.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// The items of a subscription waiting to be consumed.
+#[derive(Debug)]
+pub(crate) struct Queue<I = Value> {
+    buffer: SubscriptionBuffer,
+    items: VecDeque<Result<I, DuplexError>>,
+    // The number of notifications buffered, errors don't count towards the capacity
+    notifications: usize,
+    lagged: u64,
+    // Whether the sender is dropped or the buffer overflowed, no more items are queued
+    closed: bool,
+    receiver_dropped: bool,
+    waker: Option<Waker>,
+}
+
+/// Queues the items of a subscription, ending it once dropped.
+#[derive(Debug)]
+pub(crate) struct Sender<I = Value>(Arc<Mutex<Queue<I>>>);
+
+/// The receiver of a subscription has been dropped.
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+/// Creates the queue of a subscription's items.
+pub(crate) fn channel<I>(buffer: SubscriptionBuffer) -> (Sender<I>, Arc<Mutex<Queue<I>>>) {
+    let queue = Arc::new(Mutex::new(Queue {
+        buffer,
+        items: VecDeque::new(),
+        notifications: 0,
+        lagged: 0,
+        closed: false,
+        receiver_dropped: false,
+        waker: None,
+    }));
+    (Sender(queue.clone()), queue)
+}
+
+impl<I> Sender<I> {
+    /// Queue an item, applying the overflow policy to notifications.
+    ///
+    /// Fails only once the receiver is dropped, items queued after an overflow are discarded.
+    pub(crate) fn send(&self, item: Result<I, DuplexError>) -> Result<(), Closed> {
+        let mut queue = self.0.lock().unwrap();
+        if queue.receiver_dropped {
+            return Err(Closed);
+        }
+        if queue.closed {
+            return Ok(());
+        }
+
+        if item.is_ok() {
+            if queue.notifications >= queue.buffer.capacity {
+                queue.lagged += 1;
+                match queue.buffer.overflow {
+                    Overflow::DropOldest => {
+                        let oldest = queue.items.iter().position(Result::is_ok);
+                        if let Some(oldest) = oldest {
+                            queue.items.remove(oldest);
+                            queue.notifications -= 1;
+                        }
+                    }
+                    Overflow::DropNewest => return Ok(()),
+                    Overflow::Error => {
+                        let capacity = queue.buffer.capacity;
+                        queue.items.push_back(Err(Error::Overflow { capacity }));
+                        queue.closed = true;
+                        queue.wake();
+                        return Ok(());
+                    }
+                }
+                // A zero capacity buffer drops every notification
+                if queue.notifications >= queue.buffer.capacity {
+                    return Ok(());
+                }
+            }
+            queue.notifications += 1;
+        }
+        queue.items.push_back(item);
+        queue.wake();
+        Ok(())
+    }
+}
+
+impl<I> Sender<I> {
+    /// Returns `true` once the receiver is dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.lock().unwrap().receiver_dropped
+    }
+}
+
+impl<I> Drop for Sender<I> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().unwrap();
+        queue.closed = true;
+        queue.wake();
+    }
+}
+
+impl<I> Queue<I> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Take the next item, or register to be woken once there is one.
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<I, DuplexError>>> {
+        let item = match self.items.pop_front() {
+            Some(item) => item,
+            None if self.closed => return Poll::Ready(None),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+        if item.is_ok() {
+            self.notifications -= 1;
+        }
+        Poll::Ready(Some(item))
+    }
+}
+
+/// The notifications sent by the server for a subscription, see [`Client::subscribe`].
+///
+/// Each notification is deserialized into `T` as it arrives, items which fail to deserialize are
+/// yielded as [`Error::Json`] without ending the stream. The stream ends with an error if the
+/// connection closes. Clients which reconnect yield [`Error::Resubscribed`] once the subscription
+/// is replayed, since notifications may have been missed.
+///
+/// Notifications are buffered until consumed, see [`SubscriptionBuffer`]. Dropping the
+/// subscription cancels it on the server, on a background task, once none of its forks remain.
+pub struct Subscription<T = Value> {
+    client: Client,
+    // The key of the subscription on the client, its id changes when it is replayed
+    subscription: u64,
+    // Taken once the subscription is cancelled
+    unsubscribe_method: Option<String>,
+    queue: Arc<Mutex<Queue>>,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("subscription", &self.subscription)
+            .field("unsubscribe_method", &self.unsubscribe_method)
+            .field("queued", &self.queued())
+            .field("lagged", &self.lagged())
+            .finish()
+    }
+}
+
+impl<T> Subscription<T> {
+    pub(crate) fn new(
+        client: Client,
+        subscription: u64,
+        unsubscribe_method: String,
+        queue: Arc<Mutex<Queue>>,
+    ) -> Self {
+        Subscription {
+            client,
+            subscription,
+            unsubscribe_method: Some(unsubscribe_method),
+            queue,
+            _item: PhantomData,
+        }
+    }
+
+    /// The subscription id assigned by the server, `None` while it is being replayed or once it
+    /// has ended.
+    pub fn id(&self) -> Option<Value> {
+        self.client.subscription_id(self.subscription)
+    }
+
+    /// The number of notifications dropped because the buffer was full.
+    pub fn lagged(&self) -> u64 {
+        self.queue.lock().unwrap().lagged
+    }
+
+    /// The number of notifications buffered, waiting to be consumed.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().notifications
+    }
+
+    /// Returns a new stream of the subscription's notifications, for another consumer.
+    ///
+    /// The fork receives the notifications arriving from now on, buffered independently of this
+    /// stream with the same capacity and overflow policy. The subscription stays active on the
+    /// server until it and all its forks are dropped or unsubscribed.
+    pub fn fork(&self) -> Self {
+        let buffer = self.queue.lock().unwrap().buffer;
+        Subscription {
+            client: self.client.clone(),
+            subscription: self.subscription,
+            unsubscribe_method: self.unsubscribe_method.clone(),
+            queue: self.client.fork(self.subscription, buffer),
+            _item: PhantomData,
+        }
+    }
+
+    /// End this stream, and cancel the subscription on the server if none of its forks remain,
+    /// waiting for it to confirm.
+    pub async fn unsubscribe(mut self) -> Result<(), DuplexError> {
+        let method = self.unsubscribe_method.take().unwrap(); // This is safe
+        if !self.release() {
+            return Ok(());
+        }
+        self.client.unsubscribe(self.subscription, &method).await
+    }
+
+    /// Stop receiving notifications, returning `true` if none of the forks remain.
+    fn release(&self) -> bool {
+        self.queue.lock().unwrap().receiver_dropped = true;
+        self.client.release(self.subscription)
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let method = match self.unsubscribe_method.take() {
+            Some(method) => method,
+            None => return,
+        };
+        if !self.release() {
+            return;
+        }
+        // Outside a runtime or once the connection has closed there is nothing to cancel
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) if !self.client.is_closed() => runtime,
+            _ => return,
+        };
+        let client = self.client.clone();
+        let subscription = self.subscription;
+        runtime.spawn(async move {
+            let _ = client.unsubscribe(subscription, &method).await;
+        });
+    }
+}
+
+impl<T: DeserializeOwned> Stream for Subscription<T> {
+    type Item = Result<T, DuplexError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.queue.lock().unwrap().poll_next(cx);
+        item.map(|item| {
+            item.map(|item| item.and_then(|item| serde_json::from_value(item).map_err(Error::Json)))
+        })
+    }
+}
+
+/// The notifications which aren't for a subscription and have no handler, see
+/// [`Client::notifications`].
+///
+/// Notifications are buffered until consumed like those of a subscription. The stream ends once
+/// the connection closes, or another stream is requested.
+pub struct Notifications {
+    queue: Arc<Mutex<Queue<Notification>>>,
+}
+
+impl fmt::Debug for Notifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifications")
+            .field("queued", &self.queued())
+            .field("lagged", &self.lagged())
+            .finish()
+    }
+}
+
+impl Notifications {
+    pub(crate) fn new(queue: Arc<Mutex<Queue<Notification>>>) -> Self {
+        Notifications { queue }
+    }
+
+    /// The number of notifications dropped because the buffer was full.
+    pub fn lagged(&self) -> u64 {
+        self.queue.lock().unwrap().lagged
+    }
+
+    /// The number of notifications buffered, waiting to be consumed.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().notifications
+    }
+}
+
+impl Drop for Notifications {
+    fn drop(&mut self) {
+        self.queue.lock().unwrap().receiver_dropped = true;
+    }
+}
+
+impl Stream for Notifications {
+    type Item = Result<Notification, DuplexError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.queue.lock().unwrap().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{task::noop_waker, StreamExt};
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+
+    use super::*;
+
+    /// Take the items queued in `queue`, until it is empty.
+    fn drain(queue: &Mutex<Queue<u32>>) -> Vec<Result<u32, DuplexError>> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut items = Vec::new();
+        while let Poll::Ready(Some(item)) = queue.lock().unwrap().poll_next(&mut cx) {
+            items.push(item);
+        }
+        items
+    }
+
+    /// Send 3 notifications through a buffer of 2 applying `overflow`.
+    fn overflow(overflow: Overflow) -> (Sender<u32>, Arc<Mutex<Queue<u32>>>) {
+        let buffer = SubscriptionBuffer::new().capacity(2).overflow(overflow);
+        let (sender, queue) = channel(buffer);
+        for item in 1..=3 {
+            sender.send(Ok(item)).unwrap();
+        }
+        assert_eq!(queue.lock().unwrap().lagged, 1);
+        (sender, queue)
+    }
+
+    #[test]
+    fn drops_the_oldest_on_overflow() {
+        let (_sender, queue) = overflow(Overflow::DropOldest);
+        let items = drain(&queue);
+        assert_eq!(
+            items.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            [2, 3]
+        );
+    }
+
+    #[test]
+    fn drops_the_newest_on_overflow() {
+        let (_sender, queue) = overflow(Overflow::DropNewest);
+        let items = drain(&queue);
+        assert_eq!(
+            items.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn ends_with_an_error_on_overflow() {
+        let (sender, queue) = overflow(Overflow::Error);
+        // Items after the overflow are discarded
+        sender.send(Ok(4)).unwrap();
+        let mut items = drain(&queue).into_iter();
+        assert_eq!(items.next().unwrap().unwrap(), 1);
+        assert_eq!(items.next().unwrap().unwrap(), 2);
+        let err = items.next().unwrap().unwrap_err();
+        assert!(matches!(err, Error::Overflow { capacity: 2 }));
+        assert!(items.next().is_none());
+        assert!(queue.lock().unwrap().closed);
+    }
+
+    /// Read the next request sent by the client.
+    async fn request<R: AsyncBufReadExt + Unpin>(requests: &mut Lines<R>) -> Value {
+        let line = requests.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// Send `message` to the client.
+    async fn send<W: AsyncWrite + Unpin>(write: &mut W, message: Value) {
+        let message = format!("{}\n", message);
+        write.write_all(message.as_bytes()).await.unwrap();
+    }
+
+    fn notification(item: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "subscription",
+            "params": { "subscription": "0x1", "result": item },
+        })
+    }
+
+    #[tokio::test]
+    async fn unsubscribes_once_every_fork_is_dropped() {
+        let (io, server) = tokio::io::duplex(4096);
+        let client = Client::new(io);
+        let (read, mut write) = tokio::io::split(server);
+        let mut requests = BufReader::new(read).lines();
+
+        let subscribing = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .subscribe::<u32>("subscribe", json!([]), "unsubscribe")
+                    .await
+            }
+        });
+        let subscribe = request(&mut requests).await;
+        assert_eq!(subscribe["method"], "subscribe");
+        let id = subscribe["id"].clone();
+        send(
+            &mut write,
+            json!({ "jsonrpc": "2.0", "result": "0x1", "id": id }),
+        )
+        .await;
+        let mut subscription = subscribing.await.unwrap().unwrap();
+        assert_eq!(subscription.id(), Some(json!("0x1")));
+
+        // Each fork receives the notifications
+        let mut fork = subscription.fork();
+        send(&mut write, notification(1)).await;
+        assert_eq!(subscription.next().await.unwrap().unwrap(), 1);
+        assert_eq!(fork.next().await.unwrap().unwrap(), 1);
+
+        // The subscription stays active while a fork remains
+        drop(subscription);
+        send(&mut write, notification(2)).await;
+        assert_eq!(fork.next().await.unwrap().unwrap(), 2);
+
+        drop(fork);
+        let unsubscribe = request(&mut requests).await;
+        assert_eq!(unsubscribe["method"], "unsubscribe");
+        assert_eq!(unsubscribe["params"], json!(["0x1"]));
+    }
+}
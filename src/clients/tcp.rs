@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use hyper_util::client::legacy::connect::HttpConnector;
+
+/// Socket options for the TCP connections made by a client.
+///
+/// Unlike the operating system default, Nagle's algorithm is disabled, since it delays the small
+/// writes typical of JSON-RPC calls. Other options are left to the operating system unless set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            nodelay: true,
+            keepalive: None,
+            keepalive_interval: None,
+            keepalive_retries: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    /// Creates the default options, with `TCP_NODELAY` set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `TCP_NODELAY`, disabling Nagle's algorithm.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sends keepalive probes once a connection has been idle for `idle`.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the time between unanswered keepalive probes.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets the number of unanswered keepalive probes before the connection is dropped.
+    pub fn keepalive_retries(mut self, retries: u32) -> Self {
+        self.keepalive_retries = Some(retries);
+        self
+    }
+
+    /// Sets the size of the socket send buffer, `SO_SNDBUF`.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size of the socket receive buffer, `SO_RCVBUF`.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Build an HTTP connector using these options.
+    pub(crate) fn connector(&self) -> HttpConnector {
+        let mut http = HttpConnector::new();
+        self.apply(&mut http);
+        http
+    }
+
+    /// Apply the options to the connections made by `http`.
+    pub(crate) fn apply<R>(&self, http: &mut HttpConnector<R>) {
+        http.set_nodelay(self.nodelay);
+        http.set_keepalive(self.keepalive);
+        http.set_keepalive_interval(self.keepalive_interval);
+        http.set_keepalive_retries(self.keepalive_retries);
+        http.set_send_buffer_size(self.send_buffer_size);
+        http.set_recv_buffer_size(self.recv_buffer_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Uri;
+    use tokio::net::TcpListener;
+    use tower_util::ServiceExt;
+
+    use super::*;
+
+    /// Connect to a local listener using `options`, returning whether `TCP_NODELAY` is set.
+    async fn nodelay(options: TcpOptions) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let stream = options.connector().oneshot(uri).await.unwrap();
+        stream.inner().nodelay().unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_the_options() {
+        assert!(nodelay(TcpOptions::new()).await);
+        let options = TcpOptions::new()
+            .nodelay(false)
+            .keepalive(Duration::from_secs(30))
+            .keepalive_interval(Duration::from_secs(5))
+            .keepalive_retries(3)
+            .send_buffer_size(64 * 1024)
+            .recv_buffer_size(64 * 1024);
+        assert!(!nodelay(options).await);
+    }
+}
@@ -0,0 +1,828 @@
+use std::{
+    collections::HashSet,
+    error, fmt, io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_core::Future;
+use hyper::{header::HeaderValue, Uri};
+use hyper_util::{
+    client::legacy::connect::{Connected, Connection, HttpConnector},
+    rt::TokioIo,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tower_service::Service;
+use zeroize::Zeroizing;
+
+use super::{
+    dns::Resolver,
+    events::{Event, Events},
+    proxy::Proxy,
+    tcp::TcpOptions,
+};
+use crate::auth::BoxError;
+
+/// Error building a TLS connector.
+#[derive(Debug)]
+pub enum TlsError {
+    /// The native TLS backend rejected the configuration.
+    #[cfg(feature = "tls-native")]
+    NativeTls(native_tls::Error),
+    /// The rustls backend rejected the configuration.
+    #[cfg(feature = "tls-rustls")]
+    Rustls(rustls::Error),
+    /// A certificate or key could not be parsed.
+    #[cfg(feature = "tls-rustls")]
+    Pem(rustls_pki_types::pem::Error),
+    /// The option is not supported by the selected backend.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(err) => write!(f, "native tls error, {}", err),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(err) => write!(f, "rustls error, {}", err),
+            #[cfg(feature = "tls-rustls")]
+            Self::Pem(err) => write!(f, "pem error, {}", err),
+            Self::Unsupported(option) => write!(f, "unsupported by tls backend, {}", option),
+        }
+    }
+}
+
+impl error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(err) => Some(err),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(err) => Some(err),
+            #[cfg(feature = "tls-rustls")]
+            Self::Pem(err) => Some(err),
+            Self::Unsupported(_) => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Certificate {
+    Pem(Vec<u8>),
+    Der(Vec<u8>),
+}
+
+#[derive(Clone)]
+enum Identity {
+    #[cfg_attr(not(feature = "tls-native"), allow(dead_code))]
+    Pkcs12 {
+        der: Zeroizing<Vec<u8>>,
+        password: Zeroizing<String>,
+    },
+    Pem {
+        cert: Vec<u8>,
+        key: Zeroizing<Vec<u8>>,
+    },
+}
+
+type VerifyFn = dyn Fn(&[u8], &str) -> bool + Send + Sync;
+
+/// A custom server certificate check, see [`TlsConfig::verify_certificate`].
+#[derive(Clone)]
+struct VerifyHook(Arc<VerifyFn>);
+
+impl fmt::Debug for VerifyHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("VerifyHook(..)")
+    }
+}
+
+/// Application protocols offered during the TLS handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alpn {
+    /// Only offer HTTP/1.1.
+    Http1,
+    /// Offer HTTP/2, falling back to HTTP/1.1.
+    PreferHttp2,
+}
+
+impl Alpn {
+    fn protocols(self) -> &'static [&'static str] {
+        match self {
+            Alpn::Http1 => &["http/1.1"],
+            Alpn::PreferHttp2 => &["h2", "http/1.1"],
+        }
+    }
+}
+
+/// Configuration for HTTPS connections.
+///
+/// Connections use native-tls, or rustls when only the `tls-rustls` feature is enabled.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    identity: Option<Identity>,
+    root_certificates: Vec<Certificate>,
+    insecure_hosts: HashSet<String>,
+    server_name: Option<String>,
+    verify: Option<VerifyHook>,
+    alpn: Option<Alpn>,
+    proxy: Option<Proxy>,
+    resolver: Option<Resolver>,
+    tcp: TcpOptions,
+    events: Option<Events>,
+    rustls: bool,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("identity", &self.identity.is_some())
+            .field("root_certificates", &self.root_certificates.len())
+            .field("insecure_hosts", &self.insecure_hosts)
+            .field("server_name", &self.server_name)
+            .field("verify", &self.verify)
+            .field("alpn", &self.alpn)
+            .field("proxy", &self.proxy)
+            .field("resolver", &self.resolver)
+            .field("tcp", &self.tcp)
+            .field("events", &self.events)
+            .field("rustls", &self.rustls)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Creates the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use rustls, even if native-tls is available.
+    #[cfg(feature = "tls-rustls")]
+    pub fn use_rustls(mut self) -> Self {
+        self.rustls = true;
+        self
+    }
+
+    /// Authenticate the client using a DER-formatted PKCS #12 archive.
+    ///
+    /// This is not supported by rustls.
+    pub fn identity_pkcs12<D, P>(mut self, der: D, password: P) -> Self
+    where
+        D: Into<Vec<u8>>,
+        P: Into<String>,
+    {
+        self.identity = Some(Identity::Pkcs12 {
+            der: Zeroizing::new(der.into()),
+            password: Zeroizing::new(password.into()),
+        });
+        self
+    }
+
+    /// Authenticate the client using a PEM-formatted certificate chain and PKCS #8 private key.
+    pub fn identity_pem<C, K>(mut self, cert: C, key: K) -> Self
+    where
+        C: Into<Vec<u8>>,
+        K: Into<Vec<u8>>,
+    {
+        self.identity = Some(Identity::Pem {
+            cert: cert.into(),
+            key: Zeroizing::new(key.into()),
+        });
+        self
+    }
+
+    /// Trust the PEM-formatted certificate `pem` in addition to the system roots.
+    pub fn add_root_certificate_pem<C: Into<Vec<u8>>>(mut self, pem: C) -> Self {
+        self.root_certificates.push(Certificate::Pem(pem.into()));
+        self
+    }
+
+    /// Trust the DER-formatted certificate `der` in addition to the system roots.
+    pub fn add_root_certificate_der<C: Into<Vec<u8>>>(mut self, der: C) -> Self {
+        self.root_certificates.push(Certificate::Der(der.into()));
+        self
+    }
+
+    /// Disables certificate and hostname verification for connections to `host`.
+    ///
+    /// # Warning
+    ///
+    /// Any certificate is accepted for this host, leaving the connection open to
+    /// man-in-the-middle attacks. This should only be used against lab setups with self-signed
+    /// certificates, prefer [`add_root_certificate_pem`] wherever possible.
+    ///
+    /// [`add_root_certificate_pem`]: TlsConfig::add_root_certificate_pem
+    pub fn danger_accept_invalid_certs_for_host<H: Into<String>>(mut self, host: H) -> Self {
+        self.insecure_hosts.insert(host.into());
+        self
+    }
+
+    /// Use `name` for SNI and certificate verification instead of the URL host.
+    ///
+    /// This is useful when connecting via an IP address or through a TLS-terminating tunnel.
+    pub fn server_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Checks server certificates with `verify` once the default verification succeeds, such as
+    /// to pin them.
+    ///
+    /// The callback receives the DER-encoded end-entity certificate and the expected server name,
+    /// and the connection is aborted if it returns `false`. The chain and hostname are still
+    /// verified, only hosts passed to [`danger_accept_invalid_certs_for_host`] skip both the
+    /// verification and the callback.
+    ///
+    /// [`danger_accept_invalid_certs_for_host`]: TlsConfig::danger_accept_invalid_certs_for_host
+    pub fn verify_certificate<F>(mut self, verify: F) -> Self
+    where
+        F: Fn(&[u8], &str) -> bool + Send + Sync + 'static,
+    {
+        self.verify = Some(VerifyHook(Arc::new(verify)));
+        self
+    }
+
+    /// Negotiate the HTTP version using ALPN.
+    ///
+    /// By default no protocols are offered and connections use HTTP/1.1.
+    pub fn alpn(mut self, alpn: Alpn) -> Self {
+        self.alpn = Some(alpn);
+        self
+    }
+
+    /// Tunnel connections through an HTTP proxy.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Resolve host names using `resolver` instead of the system resolver.
+    pub fn resolver(mut self, resolver: Resolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Sets the socket options of the TCP connections made.
+    pub fn tcp(mut self, tcp: TcpOptions) -> Self {
+        self.tcp = tcp;
+        self
+    }
+
+    /// Emits an event to `events` for each connection established.
+    ///
+    /// Clients built with [`Client::new_tls_with_config`] emit their connections to their own
+    /// events, this is for using the connector on its own.
+    ///
+    /// [`Client::new_tls_with_config`]: super::http::Client::new_tls_with_config
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// The `Proxy-Authorization` value to send with requests to `url`, if it is a plain HTTP URL
+    /// forwarded by the proxy.
+    pub(crate) fn proxy_authorization(&self, url: &str) -> Option<HeaderValue> {
+        let uri: Uri = url.parse().ok()?;
+        if uri.scheme_str() != Some("http") {
+            return None;
+        }
+        let proxy = self.proxy.as_ref()?;
+        proxy.intercept(false, uri.host()?.trim_matches(|c| c == '[' || c == ']'))?;
+        proxy.authorization().cloned()
+    }
+
+    /// Build a connector for HTTP and HTTPS URLs using this configuration.
+    pub fn connector(&self) -> Result<Connector, TlsError> {
+        let tls = self.backend(false)?;
+        let insecure_tls = if self.insecure_hosts.is_empty() {
+            None
+        } else {
+            Some(self.backend(true)?)
+        };
+
+        let resolver = self.resolver.clone().unwrap_or_default();
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+        self.tcp.apply(&mut http);
+        Ok(Connector {
+            http,
+            tls,
+            insecure_tls,
+            insecure_hosts: Arc::new(self.insecure_hosts.clone()),
+            server_name: self.server_name.clone(),
+            proxy: self.proxy.clone().map(Arc::new),
+            events: self.events.clone(),
+        })
+    }
+
+    fn backend(&self, insecure: bool) -> Result<Backend, TlsError> {
+        #[cfg(feature = "tls-rustls")]
+        {
+            if self.rustls || cfg!(not(feature = "tls-native")) {
+                return self.rustls(insecure).map(Backend::Rustls);
+            }
+        }
+        #[cfg(feature = "tls-native")]
+        {
+            let verify = if insecure { None } else { self.verify.clone() };
+            self.native_tls(insecure)
+                .map(|tls| Backend::Native(tls, verify))
+        }
+        #[cfg(not(feature = "tls-native"))]
+        {
+            let _ = insecure;
+            unreachable!("rustls is always selected without native-tls")
+        }
+    }
+
+    #[cfg(feature = "tls-native")]
+    fn native_tls(&self, insecure: bool) -> Result<tokio_native_tls::TlsConnector, TlsError> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for certificate in &self.root_certificates {
+            let certificate = match certificate {
+                Certificate::Pem(pem) => native_tls::Certificate::from_pem(pem),
+                Certificate::Der(der) => native_tls::Certificate::from_der(der),
+            };
+            builder.add_root_certificate(certificate.map_err(TlsError::NativeTls)?);
+        }
+        if insecure {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        if let Some(identity) = &self.identity {
+            let identity = match identity {
+                Identity::Pkcs12 { der, password } => {
+                    native_tls::Identity::from_pkcs12(der, password)
+                }
+                Identity::Pem { cert, key } => native_tls::Identity::from_pkcs8(cert, key),
+            };
+            builder.identity(identity.map_err(TlsError::NativeTls)?);
+        }
+        if let Some(alpn) = self.alpn {
+            builder.request_alpns(alpn.protocols());
+        }
+        Ok(builder.build().map_err(TlsError::NativeTls)?.into())
+    }
+
+    #[cfg(feature = "tls-rustls")]
+    fn rustls(&self, insecure: bool) -> Result<tokio_rustls::TlsConnector, TlsError> {
+        use rustls::{client::WebPkiServerVerifier, ClientConfig, RootCertStore};
+        use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        for certificate in &self.root_certificates {
+            let certificates = match certificate {
+                Certificate::Pem(pem) => CertificateDer::pem_slice_iter(pem)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(TlsError::Pem)?,
+                Certificate::Der(der) => vec![CertificateDer::from(der.clone())],
+            };
+            for certificate in certificates {
+                roots.add(certificate).map_err(TlsError::Rustls)?;
+            }
+        }
+
+        let roots = Arc::new(roots);
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_root_certificates(roots.clone());
+        let mut config = match &self.identity {
+            Some(Identity::Pem { cert, key }) => {
+                let chain = CertificateDer::pem_slice_iter(cert)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(TlsError::Pem)?;
+                let key = PrivateKeyDer::from_pem_slice(key).map_err(TlsError::Pem)?;
+                builder
+                    .with_client_auth_cert(chain, key)
+                    .map_err(TlsError::Rustls)?
+            }
+            Some(Identity::Pkcs12 { .. }) => {
+                return Err(TlsError::Unsupported("pkcs12 identity"));
+            }
+            None => builder.with_no_client_auth(),
+        };
+        if insecure {
+            let verifier = rustls_verify::Verifier {
+                provider,
+                inner: None,
+                verify: None,
+            };
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+        } else if let Some(verify) = &self.verify {
+            let inner = WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+                .build()
+                .map_err(|err| TlsError::Rustls(rustls::Error::General(err.to_string())))?;
+            let verifier = rustls_verify::Verifier {
+                provider,
+                inner: Some(inner),
+                verify: Some(verify.clone()),
+            };
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+        }
+        if let Some(alpn) = self.alpn {
+            config.alpn_protocols = alpn
+                .protocols()
+                .iter()
+                .map(|protocol| protocol.as_bytes().to_vec())
+                .collect();
+        }
+        Ok(Arc::new(config).into())
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+mod rustls_verify {
+    use std::sync::Arc;
+
+    use rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            WebPkiServerVerifier,
+        },
+        crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        CertificateError, DigitallySignedStruct, Error, SignatureScheme,
+    };
+    use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+
+    use super::VerifyHook;
+
+    /// Checks server certificates using `inner` then `verify`, or accepts any certificate if
+    /// there is no `inner` verifier.
+    ///
+    /// Handshake signatures are always checked.
+    #[derive(Debug)]
+    pub(super) struct Verifier {
+        pub(super) provider: Arc<CryptoProvider>,
+        pub(super) inner: Option<Arc<WebPkiServerVerifier>>,
+        pub(super) verify: Option<VerifyHook>,
+    }
+
+    impl ServerCertVerifier for Verifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            if let Some(inner) = &self.inner {
+                inner.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                )?;
+            }
+            match &self.verify {
+                Some(verify) if !(verify.0)(end_entity, &server_name.to_str()) => Err(
+                    Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure),
+                ),
+                _ => Ok(ServerCertVerified::assertion()),
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.provider
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+}
+
+/// The TLS implementation used by a [`Connector`].
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "tls-native")]
+    Native(tokio_native_tls::TlsConnector, Option<VerifyHook>),
+    #[cfg(feature = "tls-rustls")]
+    Rustls(tokio_rustls::TlsConnector),
+}
+
+impl Backend {
+    async fn connect(self, host: String, tcp: TcpStream) -> Result<MaybeTlsStream, BoxError> {
+        match self {
+            #[cfg(feature = "tls-native")]
+            Backend::Native(tls, verify) => {
+                let stream = tls.connect(&host, tcp).await?;
+                if let Some(verify) = verify {
+                    let certificate = stream.get_ref().peer_certificate()?;
+                    let der = certificate.map(|cert| cert.to_der()).transpose()?;
+                    if !der.is_some_and(|der| (verify.0)(&der, &host)) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "server certificate rejected",
+                        )
+                        .into());
+                    }
+                }
+                Ok(MaybeTlsStream::NativeTls(stream))
+            }
+            #[cfg(feature = "tls-rustls")]
+            Backend::Rustls(tls) => {
+                use std::convert::TryFrom;
+
+                let server_name = rustls_pki_types::ServerName::try_from(host)?;
+                Ok(MaybeTlsStream::Rustls(tls.connect(server_name, tcp).await?))
+            }
+        }
+    }
+}
+
+/// A connector for HTTP and HTTPS URLs, built from a [`TlsConfig`].
+#[derive(Clone)]
+pub struct Connector {
+    http: HttpConnector<Resolver>,
+    tls: Backend,
+    insecure_tls: Option<Backend>,
+    insecure_hosts: Arc<HashSet<String>>,
+    server_name: Option<String>,
+    proxy: Option<Arc<Proxy>>,
+    events: Option<Events>,
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("http", &self.http)
+            .field("insecure_hosts", &self.insecure_hosts)
+            .field("server_name", &self.server_name)
+            .field("proxy", &self.proxy)
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+type Connecting = Pin<Box<dyn Future<Output = Result<TokioIo<MaybeTlsStream>, BoxError>> + Send>>;
+
+impl Service<Uri> for Connector {
+    type Response = TokioIo<MaybeTlsStream>;
+    type Error = BoxError;
+    type Future = Connecting;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_https = uri.scheme_str() == Some("https");
+        let host = uri
+            .host()
+            .unwrap_or("")
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_owned();
+        let tls = match &self.insecure_tls {
+            Some(insecure_tls) if self.insecure_hosts.contains(&host) => insecure_tls.clone(),
+            _ => self.tls.clone(),
+        };
+        let port = uri.port_u16().unwrap_or(if is_https { 443 } else { 80 });
+        let proxy = self
+            .proxy
+            .clone()
+            .and_then(|proxy| Some((proxy.intercept(is_https, &host)?.clone(), proxy)));
+        let server_name = self.server_name.clone().unwrap_or_else(|| host.clone());
+        let events = self.events.clone();
+
+        let connecting = match &proxy {
+            Some((proxy_uri, _)) => self.http.call(proxy_uri.clone()),
+            None => self.http.call(uri),
+        };
+        Box::pin(async move {
+            let mut tcp = connecting.await?.into_inner();
+            let stream = match proxy {
+                // Plain HTTP requests are forwarded by the proxy
+                Some(_) if !is_https => MaybeTlsStream::Proxied(tcp),
+                Some((_, proxy)) => {
+                    tcp = proxy.tunnel(tcp, &host, port).await?;
+                    tls.connect(server_name, tcp).await?
+                }
+                None if is_https => tls.connect(server_name, tcp).await?,
+                None => MaybeTlsStream::Http(tcp),
+            };
+            if let Some(events) = events {
+                events.emit(|| Event::Connected { host });
+            }
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+/// A connection made by a [`Connector`], which may be encrypted.
+#[allow(clippy::large_enum_variant)]
+pub enum MaybeTlsStream {
+    /// A plain TCP connection.
+    Http(TcpStream),
+    /// A plain TCP connection to a proxy, which forwards the requests sent with their absolute
+    /// URL.
+    Proxied(TcpStream),
+    /// A connection encrypted using native-tls.
+    #[cfg(feature = "tls-native")]
+    NativeTls(tokio_native_tls::TlsStream<TcpStream>),
+    /// A connection encrypted using rustls.
+    #[cfg(feature = "tls-rustls")]
+    Rustls(tokio_rustls::client::TlsStream<TcpStream>),
+}
+
+impl fmt::Debug for MaybeTlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(_) => f.pad("Http(..)"),
+            Self::Proxied(_) => f.pad("Proxied(..)"),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(_) => f.pad("NativeTls(..)"),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(_) => f.pad("Rustls(..)"),
+        }
+    }
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            Self::Http(tcp) => tcp.connected(),
+            Self::Proxied(tcp) => tcp.connected().proxy(true),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(tls) => {
+                let tls = tls.get_ref();
+                let connected = tls.get_ref().get_ref().connected();
+                match tls.negotiated_alpn() {
+                    Ok(Some(protocol)) if protocol == b"h2" => connected.negotiated_h2(),
+                    _ => connected,
+                }
+            }
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(tls) => {
+                let (tcp, session) = tls.get_ref();
+                match session.alpn_protocol() {
+                    Some(b"h2") => tcp.connected().negotiated_h2(),
+                    _ => tcp.connected(),
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Http(tcp) | Self::Proxied(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(tls) => Pin::new(tls).poll_read(cx, buf),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(tls) => Pin::new(tls).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Http(tcp) | Self::Proxied(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(tls) => Pin::new(tls).poll_write(cx, buf),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(tls) => Pin::new(tls).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Http(tcp) | Self::Proxied(tcp) => Pin::new(tcp).poll_flush(cx),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(tls) => Pin::new(tls).poll_flush(cx),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(tls) => Pin::new(tls).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Http(tcp) | Self::Proxied(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            #[cfg(feature = "tls-native")]
+            Self::NativeTls(tls) => Pin::new(tls).poll_shutdown(cx),
+            #[cfg(feature = "tls-rustls")]
+            Self::Rustls(tls) => Pin::new(tls).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tls-rustls", feature = "server"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use rustls_pki_types::{pem::PemObject, CertificateDer};
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    use super::*;
+    use crate::server::tls::{
+        tests::{CERT, KEY},
+        ServerTlsConfig,
+    };
+
+    /// Accept TLS connections for `localhost` on a local port, returning its URI.
+    async fn serve() -> Uri {
+        let acceptor = ServerTlsConfig::new(CERT, KEY).acceptor(&[]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let _ = stream.write_all(b"hello").await;
+                }
+            }
+        });
+        format!("https://localhost:{}", port).parse().unwrap()
+    }
+
+    async fn connect(config: TlsConfig, uri: Uri) -> Result<(), BoxError> {
+        config.use_rustls().connector()?.call(uri).await.map(drop)
+    }
+
+    #[tokio::test]
+    async fn checks_verified_certificates() {
+        let uri = serve().await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seeing = seen.clone();
+        let config = TlsConfig::new()
+            .add_root_certificate_pem(CERT)
+            .verify_certificate(move |der, name| {
+                seeing.lock().unwrap().push((der.to_vec(), name.to_owned()));
+                true
+            });
+        connect(config, uri.clone()).await.unwrap();
+        let der = CertificateDer::from_pem_slice(CERT.as_bytes()).unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [(der.to_vec(), "localhost".to_owned())]
+        );
+
+        let config = TlsConfig::new()
+            .add_root_certificate_pem(CERT)
+            .verify_certificate(|_, _| false);
+        assert!(connect(config, uri).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn verifies_certificates_before_the_hook() {
+        let uri = serve().await;
+        // The certificate is not trusted, whatever the hook says
+        let config = TlsConfig::new().verify_certificate(|_, _| true);
+        assert!(connect(config, uri.clone()).await.is_err());
+
+        let config = TlsConfig::new()
+            .verify_certificate(|_, _| false)
+            .danger_accept_invalid_certs_for_host("localhost");
+        connect(config, uri).await.unwrap();
+    }
+}
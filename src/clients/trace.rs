@@ -0,0 +1,102 @@
+/// The W3C trace context headers propagated with a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceHeaders {
+    /// The `traceparent` header value.
+    pub traceparent: String,
+    /// The `tracestate` header value, omitted if empty.
+    pub tracestate: String,
+}
+
+impl TraceHeaders {
+    /// Creates the headers for the span `span_id` in the trace `trace_id`.
+    pub fn new(trace_id: u128, span_id: u64, sampled: bool) -> Self {
+        TraceHeaders {
+            traceparent: format!(
+                "00-{:032x}-{:016x}-{:02x}",
+                trace_id, span_id, sampled as u8
+            ),
+            tracestate: String::new(),
+        }
+    }
+
+    /// Sets the vendor-specific `tracestate` header value.
+    pub fn tracestate<S: Into<String>>(mut self, tracestate: S) -> Self {
+        self.tracestate = tracestate.into();
+        self
+    }
+}
+
+/// A source of the active trace context, queried when a call is made.
+pub trait TraceContext: Send + Sync + 'static {
+    /// Returns the headers for the active trace, if any.
+    fn current(&self) -> Option<TraceHeaders>;
+}
+
+/// Propagates the current OpenTelemetry context.
+#[cfg(feature = "opentelemetry")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpenTelemetry;
+
+#[cfg(feature = "opentelemetry")]
+impl TraceContext for OpenTelemetry {
+    fn current(&self) -> Option<TraceHeaders> {
+        use opentelemetry::trace::TraceContextExt;
+
+        let context = opentelemetry::Context::current();
+        let span_context = context.span().span_context().clone();
+        if !span_context.is_valid() {
+            return None;
+        }
+        let headers = TraceHeaders {
+            traceparent: format!(
+                "00-{}-{}-{:02x}",
+                span_context.trace_id(),
+                span_context.span_id(),
+                span_context.trace_flags().to_u8()
+            ),
+            tracestate: span_context.trace_state().header(),
+        };
+        Some(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_traceparent() {
+        let headers = TraceHeaders::new(0xabc, 0x12, true);
+        assert_eq!(
+            headers.traceparent,
+            "00-00000000000000000000000000000abc-0000000000000012-01"
+        );
+        let headers = TraceHeaders::new(u128::MAX, u64::MAX, false);
+        assert_eq!(
+            headers.traceparent,
+            "00-ffffffffffffffffffffffffffffffff-ffffffffffffffff-00"
+        );
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[test]
+    fn propagates_the_opentelemetry_context() {
+        use opentelemetry::trace::{
+            SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+        };
+
+        assert_eq!(OpenTelemetry.current(), None);
+
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(0xabc_u128.to_be_bytes()),
+            SpanId::from_bytes(0x12_u64.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::from_key_value([("vendor", "value")]).unwrap(),
+        );
+        let context = opentelemetry::Context::current().with_remote_span_context(span_context);
+        let _active = context.attach();
+        let headers = TraceHeaders::new(0xabc, 0x12, true).tracestate("vendor=value");
+        assert_eq!(OpenTelemetry.current(), Some(headers));
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures_core::Future;
+use futures_util::future::{select, Either};
+use tokio::time::Instant;
+
+/// Waits until a deadline, see [`Clock::sleep_until`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// The source of time of the time-based layers, such as [`Retry`] and [`Timeout`].
+///
+/// [`TokioClock`] is used by default, which follows [`tokio::time::pause`] in tests. Use a
+/// [`ManualClock`] to move time by hand.
+///
+/// [`Retry`]: crate::layers::Retry
+/// [`Timeout`]: crate::layers::Timeout
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Waits until `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The time of the Tokio runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// A clock which only moves when advanced, see [`ManualClock::advance`].
+///
+/// Clones share their time.
+#[derive(Clone)]
+pub struct ManualClock(Arc<Mutex<Manual>>);
+
+struct Manual {
+    now: Instant,
+    // The tasks sleeping until time moves, by sleep
+    sleepers: HashMap<u64, Waker>,
+    next_sleep: u64,
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let manual = self.0.lock().unwrap();
+        f.debug_struct("ManualClock")
+            .field("now", &manual.now)
+            .field("sleepers", &manual.sleepers.len())
+            .finish()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the current instant.
+    pub fn new() -> Self {
+        ManualClock(Arc::new(Mutex::new(Manual {
+            now: Instant::now(),
+            sleepers: HashMap::new(),
+            next_sleep: 0,
+        })))
+    }
+
+    /// Move time forward by `duration`, waking the tasks sleeping until then.
+    pub fn advance(&self, duration: Duration) {
+        let sleepers = {
+            let mut manual = self.0.lock().unwrap();
+            manual.now += duration;
+            std::mem::take(&mut manual.sleepers)
+        };
+        // Those sleeping longer go back to sleep
+        for sleeper in sleepers.into_values() {
+            sleeper.wake();
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let id = {
+            let mut manual = self.0.lock().unwrap();
+            manual.next_sleep += 1;
+            manual.next_sleep
+        };
+        Box::pin(ManualSleep {
+            clock: self.clone(),
+            deadline,
+            id,
+        })
+    }
+}
+
+struct ManualSleep {
+    clock: ManualClock,
+    deadline: Instant,
+    id: u64,
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut manual = self.clock.0.lock().unwrap();
+        if manual.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        // Polled again, e.g. in a `select!`, the sleep replaces its waker
+        match manual.sleepers.get(&self.id) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => {
+                manual.sleepers.insert(self.id, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for ManualSleep {
+    fn drop(&mut self) {
+        if let Ok(mut manual) = self.clock.0.lock() {
+            manual.sleepers.remove(&self.id);
+        }
+    }
+}
+
+/// A clock shared by the clones of a layer, [`TokioClock`] by default.
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn new<C: Clock>(clock: C) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub(crate) fn sleep_until(&self, deadline: Instant) -> Sleep {
+        self.0.sleep_until(deadline)
+    }
+
+    pub(crate) fn sleep(&self, duration: Duration) -> Sleep {
+        // A sleep too long for the clock never ends
+        match self.now().checked_add(duration) {
+            Some(deadline) => self.sleep_until(deadline),
+            None => Box::pin(futures_util::future::pending()),
+        }
+    }
+
+    /// Run `future` for up to `duration`, returning `None` if it didn't complete.
+    pub(crate) async fn timeout<F>(&self, duration: Duration, future: F) -> Option<F::Output>
+    where
+        F: Future,
+    {
+        let future = Box::pin(future);
+        match select(future, self.sleep(duration)).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(TokioClock)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn manual_sleep_waits_for_advance() {
+        let clock = ManualClock::new();
+        let mut sleep = clock.sleep_until(clock.now() + Duration::from_secs(2));
+        assert!((&mut sleep).now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!((&mut sleep).now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        assert!(sleep.now_or_never().is_some());
+    }
+
+    #[test]
+    fn manual_sleep_keeps_one_waker() {
+        let clock = ManualClock::new();
+        let mut sleep = clock.sleep_until(clock.now() + Duration::from_secs(1));
+        for _ in 0..3 {
+            assert!((&mut sleep).now_or_never().is_none());
+        }
+        assert_eq!(clock.0.lock().unwrap().sleepers.len(), 1);
+        drop(sleep);
+        assert!(clock.0.lock().unwrap().sleepers.is_empty());
+    }
+
+    #[test]
+    fn sleep_past_the_end_of_time_never_ends() {
+        let manual = ManualClock::new();
+        let clock = SharedClock::new(manual.clone());
+        let mut sleep = clock.sleep(Duration::MAX);
+        manual.advance(Duration::from_secs(1));
+        assert!((&mut sleep).now_or_never().is_none());
+    }
+
+    #[test]
+    fn timeout_expires_on_advance() {
+        let manual = ManualClock::new();
+        let clock = SharedClock::new(manual.clone());
+        let pending = futures_util::future::pending::<()>();
+        let mut timeout = Box::pin(clock.timeout(Duration::from_secs(1), pending));
+        assert!((&mut timeout).now_or_never().is_none());
+        manual.advance(Duration::from_secs(1));
+        assert_eq!(timeout.now_or_never(), Some(None));
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+use crate::{
+    clients::RequestFactory,
+    clock::{Clock, SharedClock},
+    objects::{Request, Response},
+};
+
+/// The health of an endpoint, as observed by the last probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Health {
+    /// No probe has completed yet.
+    Unknown,
+    /// The last probe returned a result, even a `null` one.
+    Healthy,
+    /// The last probe failed, timed out or returned an error object.
+    Unhealthy,
+}
+
+/// Periodically probes an endpoint and publishes its [`Health`].
+///
+/// A check probes the single endpoint behind its client. To track several endpoints, such as the
+/// candidates for failover, run a check for each and combine their receivers.
+#[derive(Debug)]
+pub struct HealthCheck<C> {
+    client: C,
+    method: String,
+    params: serde_json::Value,
+    interval: Duration,
+    timeout: Duration,
+    clock: SharedClock,
+    sender: watch::Sender<Health>,
+}
+
+impl<C> HealthCheck<C> {
+    /// Creates a health check calling `method` on the endpoint behind `client`.
+    ///
+    /// Probes are sent every 10 seconds and time out after 5 seconds by default.
+    pub fn new<S: Into<String>>(client: C, method: S) -> Self {
+        let (sender, _) = watch::channel(Health::Unknown);
+        HealthCheck {
+            client,
+            method: method.into(),
+            params: serde_json::Value::Null,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+            clock: SharedClock::default(),
+            sender,
+        }
+    }
+
+    /// Sets the parameters sent with the probe.
+    pub fn params<V: Into<serde_json::Value>>(mut self, params: V) -> Self {
+        self.params = params.into();
+        self
+    }
+
+    /// Sets the time between probes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the time after which a probe is considered failed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the clock timing the probes, [`TokioClock`](crate::clock::TokioClock) by default.
+    pub fn clock<K: Clock>(mut self, clock: K) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Subscribe to changes in the endpoint health.
+    ///
+    /// Receivers are only notified when the health changes, not after each probe.
+    pub fn subscribe(&self) -> watch::Receiver<Health> {
+        self.sender.subscribe()
+    }
+}
+
+impl<C> HealthCheck<C>
+where
+    C: Service<Request, Response = Response> + RequestFactory + Clone,
+{
+    /// Probe the endpoint once and publish the outcome.
+    pub async fn probe(&self) -> Health {
+        let request = self
+            .client
+            .build_request()
+            .method(self.method.clone())
+            .params(self.params.clone())
+            .finish()
+            .unwrap(); // This is safe
+        let probe = self.client.clone().oneshot(request);
+        let health = match self.clock.timeout(self.timeout, probe).await {
+            Some(Ok(response)) if response.error.is_none() => Health::Healthy,
+            _ => Health::Unhealthy,
+        };
+        self.sender.send_if_modified(|current| {
+            let modified = *current != health;
+            *current = health;
+            modified
+        });
+        health
+    }
+
+    /// Probe the endpoint forever, this should be spawned onto a runtime.
+    pub async fn run(self) {
+        let mut next = self.clock.now();
+        loop {
+            self.clock.sleep_until(next).await;
+            self.probe().await;
+            next += self.interval;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future,
+        task::{Context, Poll},
+    };
+
+    use futures_core::future::BoxFuture;
+
+    use super::*;
+    use crate::{
+        clients::mock::{MockClient, MockError},
+        clock::ManualClock,
+        objects::{RequestBuilder, RpcError},
+    };
+
+    /// A client whose calls never complete.
+    #[derive(Clone)]
+    struct Hanging;
+
+    impl Service<Request> for Hanging {
+        type Response = Response;
+        type Error = MockError;
+        type Future = BoxFuture<'static, Result<Response, MockError>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), MockError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request) -> Self::Future {
+            Box::pin(future::pending())
+        }
+    }
+
+    impl RequestFactory for Hanging {
+        fn build_request(&self) -> RequestBuilder {
+            Request::build().id(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_the_health_of_each_probe() {
+        let client = MockClient::new();
+        client.respond_sequence(
+            "net_version",
+            vec![
+                Ok("1".into()),
+                Err(RpcError::internal_error()),
+                Ok("1".into()),
+            ],
+        );
+        let clock = ManualClock::new();
+        let check = HealthCheck::new(client.clone(), "net_version")
+            .interval(Duration::from_secs(10))
+            .clock(clock.clone());
+        let mut health = check.subscribe();
+        assert_eq!(*health.borrow(), Health::Unknown);
+        tokio::spawn(check.run());
+
+        // The first probe is sent right away, the next ones once the interval elapsed
+        health.changed().await.unwrap();
+        assert_eq!(*health.borrow_and_update(), Health::Healthy);
+        clock.advance(Duration::from_secs(10));
+        health.changed().await.unwrap();
+        assert_eq!(*health.borrow_and_update(), Health::Unhealthy);
+        clock.advance(Duration::from_secs(10));
+        health.changed().await.unwrap();
+        assert_eq!(*health.borrow_and_update(), Health::Healthy);
+        client.assert_called_times("net_version", 3);
+    }
+
+    #[tokio::test]
+    async fn probes_time_out() {
+        let clock = ManualClock::new();
+        let check = HealthCheck::new(Hanging, "net_version")
+            .timeout(Duration::from_secs(5))
+            .clock(clock.clone());
+        let mut health = check.subscribe();
+        tokio::spawn(check.run());
+
+        tokio::task::yield_now().await;
+        assert_eq!(*health.borrow(), Health::Unknown);
+        clock.advance(Duration::from_secs(5));
+        health.changed().await.unwrap();
+        assert_eq!(*health.borrow(), Health::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn null_results_are_healthy() {
+        let client = MockClient::new();
+        client.respond("ping", ());
+        let check = HealthCheck::new(client, "ping");
+        assert_eq!(check.probe().await, Health::Healthy);
+    }
+
+    #[tokio::test]
+    async fn notifies_changes_only() {
+        let client = MockClient::new();
+        client.respond("net_version", "1");
+        let check = HealthCheck::new(client, "net_version");
+        let mut health = check.subscribe();
+
+        check.probe().await;
+        assert!(health.has_changed().unwrap());
+        assert_eq!(*health.borrow_and_update(), Health::Healthy);
+        check.probe().await;
+        assert!(!health.has_changed().unwrap());
+    }
+}
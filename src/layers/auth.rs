@@ -0,0 +1,86 @@
+use std::task::{Context, Poll};
+
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION},
+    Request as HttpRequest,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::auth::{basic_auth, bearer_auth};
+
+/// Sets the `Authorization` header on HTTP requests which don't already carry one.
+///
+/// This wraps the HTTP service passed to [`Client::from_service`].
+///
+/// [`Client::from_service`]: crate::clients::http::Client::from_service
+#[derive(Clone, Debug)]
+pub struct Auth<S> {
+    inner: S,
+    value: HeaderValue,
+}
+
+impl<S> Auth<S> {
+    /// Wraps a HTTP service, authorizing requests using `value`.
+    pub fn new(inner: S, value: HeaderValue) -> Self {
+        Auth { inner, value }
+    }
+
+    /// Returns a reference to the wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, B> Service<HttpRequest<B>> for Auth<S>
+where
+    S: Service<HttpRequest<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: HttpRequest<B>) -> Self::Future {
+        request
+            .headers_mut()
+            .entry(AUTHORIZATION)
+            .or_insert_with(|| self.value.clone());
+        self.inner.call(request)
+    }
+}
+
+/// A [`Layer`] producing [`Auth`] services.
+#[derive(Clone, Debug)]
+pub struct AuthLayer {
+    value: HeaderValue,
+}
+
+impl AuthLayer {
+    /// Authorize using HTTP basic authentication.
+    pub fn basic(user: &str, password: Option<&str>) -> Self {
+        AuthLayer {
+            value: basic_auth(user, password.unwrap_or_default()),
+        }
+    }
+
+    /// Authorize using a bearer token.
+    ///
+    /// Fails if the token contains characters which are invalid in a header.
+    pub fn bearer(token: &str) -> Result<Self, hyper::header::InvalidHeaderValue> {
+        Ok(AuthLayer {
+            value: bearer_auth(token)?,
+        })
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth::new(inner, self.value.clone())
+    }
+}
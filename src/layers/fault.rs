@@ -0,0 +1,336 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http_body_util::BodyExt;
+use hyper::{
+    body::{Body as HttpBody, Bytes},
+    header::CONTENT_LENGTH,
+    Request as HttpRequest, Response as HttpResponse,
+};
+use serde_json::Value;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::FutResponse;
+use crate::{
+    auth::BoxError,
+    clients::http::Body,
+    clock::{Clock, SharedClock},
+};
+
+/// The id replacing those of responses given the wrong id.
+const WRONG_ID: &str = "injected-wrong-id";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Settings {
+    latency: f64,
+    delay: Duration,
+    connection_errors: f64,
+    truncated_bodies: f64,
+    wrong_ids: f64,
+    corrupt_json: f64,
+}
+
+/// Injects failures into the HTTP exchanges of a client at random, for testing how an application
+/// handles them.
+///
+/// Each fault is drawn independently for each request, with its configured probability: a delay
+/// before the request is sent, a connection error in place of the response, a response body cut
+/// short, responses with the wrong id, and a response body which isn't valid JSON. This wraps the
+/// HTTP service passed to [`Client::from_service`].
+///
+/// [`Client::from_service`]: crate::clients::http::Client::from_service
+#[derive(Clone, Debug)]
+pub struct Fault<S> {
+    inner: S,
+    settings: Arc<Settings>,
+    rng: Arc<Rng>,
+    clock: SharedClock,
+}
+
+impl<S> Fault<S> {
+    /// Returns a reference to the wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, B> Service<HttpRequest<Body>> for Fault<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = HttpResponse<Body>;
+    type Error = BoxError;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: HttpRequest<Body>) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let settings = self.settings.clone();
+        let rng = self.rng.clone();
+        let clock = self.clock.clone();
+        Box::pin(async move {
+            if rng.chance(settings.latency) {
+                clock.sleep(settings.delay).await;
+            }
+            if rng.chance(settings.connection_errors) {
+                let err = io::Error::new(io::ErrorKind::ConnectionReset, "injected fault");
+                return Err(err.into());
+            }
+
+            let response = inner.call(request).await.map_err(Into::into)?;
+            let (mut parts, body) = response.into_parts();
+            let mut body = body.collect().await.map_err(Into::into)?.to_bytes();
+            if rng.chance(settings.wrong_ids) {
+                body = wrong_ids(body);
+            }
+            if rng.chance(settings.corrupt_json) && !body.is_empty() {
+                // Control characters are invalid anywhere in JSON
+                let mut corrupt = body.to_vec();
+                corrupt[rng.below(body.len())] = 0;
+                body = corrupt.into();
+            }
+            if rng.chance(settings.truncated_bodies) && !body.is_empty() {
+                body = body.slice(..rng.below(body.len()));
+            }
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(HttpResponse::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+/// Replace the id of the response, or of each response in a batch, unless it isn't JSON.
+fn wrong_ids(body: Bytes) -> Bytes {
+    let mut json: Value = match serde_json::from_slice(&body) {
+        Ok(json) => json,
+        Err(_) => return body,
+    };
+    let responses = match &mut json {
+        Value::Array(batch) => batch.iter_mut().collect(),
+        response => vec![response],
+    };
+    for response in responses {
+        if let Some(id) = response.get_mut("id") {
+            *id = WRONG_ID.into();
+        }
+    }
+    serde_json::to_vec(&json).unwrap().into() // This is safe
+}
+
+/// A xorshift generator, enough to draw faults.
+#[derive(Debug)]
+struct Rng(AtomicU64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero
+        Rng(AtomicU64::new(seed | 1))
+    }
+
+    fn next(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let previous = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap(); // This is safe
+        step(previous)
+    }
+
+    /// Returns `true` with `probability`.
+    fn chance(&self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// A number below `n`, which must not be 0.
+    fn below(&self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// A [`Layer`] producing [`Fault`] services.
+///
+/// Probabilities are between 0, never, and 1, always, the setters panic otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct FaultLayer {
+    settings: Settings,
+    seed: Option<u64>,
+    clock: SharedClock,
+}
+
+impl FaultLayer {
+    /// Creates a layer injecting no faults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay requests by `delay` with `probability`.
+    pub fn latency(mut self, probability: f64, delay: Duration) -> Self {
+        self.settings.latency = checked(probability);
+        self.settings.delay = delay;
+        self
+    }
+
+    /// Fail requests with a connection error with `probability`, without sending them.
+    pub fn connection_errors(mut self, probability: f64) -> Self {
+        self.settings.connection_errors = checked(probability);
+        self
+    }
+
+    /// Cut response bodies short with `probability`.
+    pub fn truncated_bodies(mut self, probability: f64) -> Self {
+        self.settings.truncated_bodies = checked(probability);
+        self
+    }
+
+    /// Replace the ids of responses with `probability`.
+    pub fn wrong_ids(mut self, probability: f64) -> Self {
+        self.settings.wrong_ids = checked(probability);
+        self
+    }
+
+    /// Corrupt response bodies so that they aren't valid JSON with `probability`.
+    pub fn corrupt_json(mut self, probability: f64) -> Self {
+        self.settings.corrupt_json = checked(probability);
+        self
+    }
+
+    /// Draw the faults from `seed`, so that runs inject the same faults, rather than from the
+    /// time.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the clock measuring the injected latency, [`TokioClock`] by default.
+    ///
+    /// [`TokioClock`]: crate::clock::TokioClock
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl<S> Layer<S> for FaultLayer {
+    type Service = Fault<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let seed = self.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            now.map_or(0, |now| now.as_nanos() as u64)
+        });
+        Fault {
+            inner,
+            settings: Arc::new(self.settings.clone()),
+            rng: Arc::new(Rng::new(seed)),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+/// Panics unless `probability` is between 0 and 1.
+fn checked(probability: f64) -> f64 {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "fault probability must be between 0 and 1"
+    );
+    probability
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures_util::FutureExt;
+    use http_body_util::Full;
+    use serde_json::json;
+    use tower_util::{service_fn, ServiceExt};
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const BODY: &str = r#"{"jsonrpc":"2.0","result":"pong","id":1}"#;
+
+    fn request() -> HttpRequest<Body> {
+        HttpRequest::new(Body::from(Bytes::new()))
+    }
+
+    async fn respond(layer: FaultLayer) -> Result<Bytes, BoxError> {
+        let server = service_fn(|_: HttpRequest<Body>| async {
+            Ok::<_, Infallible>(HttpResponse::new(Full::new(Bytes::from(BODY))))
+        });
+        let response = layer.layer(server).oneshot(request()).await?;
+        Ok(response.into_body().collect().await?.to_bytes())
+    }
+
+    #[tokio::test]
+    async fn latency_follows_the_clock() {
+        let clock = ManualClock::new();
+        let layer = FaultLayer::new()
+            .latency(1.0, Duration::from_secs(2))
+            .clock(clock.clone());
+        let mut response = Box::pin(respond(layer));
+        assert!((&mut response).now_or_never().is_none());
+        clock.advance(Duration::from_secs(2));
+        let body = response.now_or_never().unwrap().unwrap();
+        assert_eq!(body, BODY);
+    }
+
+    #[tokio::test]
+    async fn injects_each_fault() {
+        assert!(respond(FaultLayer::new().connection_errors(1.0))
+            .await
+            .is_err());
+
+        let body = respond(FaultLayer::new().wrong_ids(1.0)).await.unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["id"], json!(WRONG_ID));
+
+        let body = respond(FaultLayer::new().corrupt_json(1.0)).await.unwrap();
+        assert!(serde_json::from_slice::<Value>(&body).is_err());
+
+        let body = respond(FaultLayer::new().truncated_bodies(1.0))
+            .await
+            .unwrap();
+        assert!(body.len() < BODY.len());
+
+        let body = respond(FaultLayer::new()).await.unwrap();
+        assert_eq!(body, BODY);
+    }
+
+    #[test]
+    fn seed_repeats_the_faults() {
+        let draw = |seed| {
+            let rng = Rng::new(seed);
+            (0..64).map(|_| rng.chance(0.5)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+    }
+
+    #[test]
+    #[should_panic(expected = "between 0 and 1")]
+    fn rejects_invalid_probabilities() {
+        let _ = FaultLayer::new().wrong_ids(1.5);
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::FutResponse;
+use crate::{
+    clients::{Error, RequestFactory},
+    objects::{Request, RequestBuilder, Response, RpcError},
+};
+
+/// A recorded call and its response.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    method: String,
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    #[serde(skip)]
+    replayed: bool,
+}
+
+/// Whether calls go to the server and are recorded, or are replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// The calls of a fixture file, shared by the clones of a layer and its services.
+struct Store {
+    path: PathBuf,
+    mode: Mode,
+    entries: Mutex<Entries>,
+}
+
+struct Entries {
+    entries: Vec<Entry>,
+    // Whether calls were recorded since the file was written
+    unsaved: bool,
+}
+
+impl fmt::Debug for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Store")
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .field("entries", &self.entries.lock().unwrap().entries.len())
+            .finish()
+    }
+}
+
+impl Store {
+    /// Record the response to `request`, written to the file by [`Store::save`].
+    fn record(&self, request: &Request, response: &Response) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.entries.push(Entry {
+            method: request.method.clone(),
+            params: request.params.clone(),
+            result: response.result.clone(),
+            error: response.error.clone(),
+            replayed: false,
+        });
+        entries.unsaved = true;
+    }
+
+    /// The contents of the fixture file, if calls were recorded since it was written.
+    fn unsaved(&self) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.unsaved {
+            return None;
+        }
+        entries.unsaved = false;
+        Some(serde_json::to_vec_pretty(&entries.entries).unwrap()) // This is safe
+    }
+
+    /// Write the calls recorded to the fixture file, off the runtime.
+    async fn save(self: Arc<Self>) -> io::Result<()> {
+        let json = match self.unsaved() {
+            Some(json) => json,
+            None => return Ok(()),
+        };
+        let store = self.clone();
+        let written = tokio::task::spawn_blocking(move || fs::write(&store.path, json))
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+        if written.is_err() {
+            self.entries.lock().unwrap().unsaved = true;
+        }
+        written
+    }
+
+    /// The recorded response to `request`, if any.
+    ///
+    /// Calls recorded with the same method and parameters are replayed in order, the last is
+    /// repeated once they all were.
+    fn replay(&self, request: &Request) -> Option<Response> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut entries.entries;
+        let matching: Vec<_> = (0..entries.len())
+            .filter(|&i| entries[i].method == request.method && entries[i].params == request.params)
+            .collect();
+        let i = *matching
+            .iter()
+            .find(|&&i| !entries[i].replayed)
+            .or_else(|| matching.last())?;
+        let entry = &mut entries[i];
+        entry.replayed = true;
+        // A `null` result reads back as none
+        let result = match entry.error {
+            Some(_) => None,
+            None => Some(entry.result.clone().unwrap_or(Value::Null)),
+        };
+        Some(Response {
+            result,
+            error: entry.error.clone(),
+            id: request.id.clone(),
+            jsonrpc: Some("2.0".to_owned()),
+        })
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        // The last chance to write what wasn't, errors are only seen by `finish`
+        if let Some(json) = self.unsaved() {
+            let _ = fs::write(&self.path, json);
+        }
+    }
+}
+
+/// Records the calls made and their responses to a fixture file, or replays them from it, so
+/// that tests against a real node can run without one.
+///
+/// Recorded calls are matched by method and parameters, responses with an error object included.
+/// Failed calls aren't recorded. Replayed calls which weren't recorded fail with
+/// [`Error::NotRecorded`], without reaching the wrapped client.
+///
+/// The fixture file is written by [`Fixture::finish`], or once the layer and its services are
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct Fixture<S> {
+    inner: S,
+    store: Arc<Store>,
+}
+
+impl<S> Fixture<S> {
+    /// Returns a reference to the wrapped client.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Write the calls recorded so far to the fixture file, see [`FixtureLayer::finish`].
+    pub async fn finish(&self) -> io::Result<()> {
+        self.store.clone().save().await
+    }
+}
+
+impl<S, E> Service<Request> for Fixture<S>
+where
+    S: Service<Request, Response = Response, Error = Error<E>>,
+    S::Future: Send + 'static,
+    E: 'static,
+{
+    type Response = Response;
+    type Error = Error<E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.store.mode {
+            Mode::Record => self.inner.poll_ready(cx),
+            Mode::Replay => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let store = self.store.clone();
+        if store.mode == Mode::Replay {
+            let response = store.replay(&request);
+            return Box::pin(async move {
+                response.ok_or_else(|| Error::NotRecorded(Box::new(request)))
+            });
+        }
+        let fut = self.inner.call(request.clone());
+        Box::pin(async move {
+            let response = fut.await?;
+            store.record(&request, &response);
+            Ok(response)
+        })
+    }
+}
+
+impl<S: RequestFactory> RequestFactory for Fixture<S> {
+    fn build_request(&self) -> RequestBuilder {
+        self.inner.build_request()
+    }
+}
+
+/// A [`Layer`] producing [`Fixture`] services.
+#[derive(Clone, Debug)]
+pub struct FixtureLayer {
+    store: Arc<Store>,
+}
+
+impl FixtureLayer {
+    /// Creates a layer recording calls to the fixture file at `path`, replacing its contents.
+    pub fn record<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        fs::write(&path, "[]")?;
+        Ok(Self::with(path, Mode::Record, Vec::new()))
+    }
+
+    /// Creates a layer replaying the calls recorded in the fixture file at `path`.
+    pub fn replay<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let entries = serde_json::from_slice(&fs::read(&path)?)?;
+        Ok(Self::with(path, Mode::Replay, entries))
+    }
+
+    /// Creates a layer replaying the fixture file at `path` if it exists, and otherwise recording
+    /// it.
+    ///
+    /// Delete the file to record it again.
+    pub fn auto<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match path.as_ref().exists() {
+            true => Self::replay(path),
+            false => Self::record(path),
+        }
+    }
+
+    /// Returns `true` if calls are replayed rather than recorded.
+    pub fn is_replaying(&self) -> bool {
+        self.store.mode == Mode::Replay
+    }
+
+    /// Write the calls recorded so far to the fixture file.
+    ///
+    /// This is otherwise done once the layer and its services are dropped, when errors writing the
+    /// file are ignored.
+    pub async fn finish(&self) -> io::Result<()> {
+        self.store.clone().save().await
+    }
+
+    fn with(path: PathBuf, mode: Mode, entries: Vec<Entry>) -> Self {
+        FixtureLayer {
+            store: Arc::new(Store {
+                path,
+                mode,
+                entries: Mutex::new(Entries {
+                    entries,
+                    unsaved: false,
+                }),
+            }),
+        }
+    }
+}
+
+impl<S> Layer<S> for FixtureLayer {
+    type Service = Fixture<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Fixture {
+            inner,
+            store: self.store.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower_util::ServiceExt;
+
+    use super::*;
+    use crate::clients::mock::MockClient;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fixture-{}-{}.json", name, std::process::id()))
+    }
+
+    fn call(method: &str, params: Value) -> Request {
+        let request = Request::build().method(method).params(params).id(0);
+        request.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_what_was_recorded() {
+        let path = path("replay");
+        let mock = MockClient::new();
+        mock.respond_sequence("count", vec![Ok(json!(1)), Ok(json!(2))])
+            .respond("empty", Value::Null);
+        let layer = FixtureLayer::record(&path).unwrap();
+        let fixture = layer.layer(mock.clone());
+        for request in [
+            call("count", json!([])),
+            call("count", json!([])),
+            call("empty", json!([])),
+        ] {
+            fixture.clone().oneshot(request).await.unwrap();
+        }
+        // Nothing is written until finished
+        assert_eq!(fs::read(&path).unwrap(), b"[]");
+        layer.finish().await.unwrap();
+        drop((layer, fixture));
+
+        let layer = FixtureLayer::auto(&path).unwrap();
+        assert!(layer.is_replaying());
+        let fixture = layer.layer(MockClient::new());
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            let response = fixture.clone().oneshot(call("count", json!([]))).await;
+            results.push(response.unwrap().result.unwrap());
+        }
+        // The last is repeated
+        assert_eq!(results, [json!(1), json!(2), json!(2)]);
+        let response = fixture.oneshot(call("empty", json!([]))).await.unwrap();
+        assert_eq!(response.result, Some(Value::Null));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unrecorded_calls_fail() {
+        let path = path("missing");
+        fs::write(&path, "[]").unwrap();
+        let fixture = FixtureLayer::replay(&path)
+            .unwrap()
+            .layer(MockClient::new());
+        let err = fixture.oneshot(call("ping", json!([1]))).await.unwrap_err();
+        match err {
+            Error::NotRecorded(request) => {
+                assert_eq!(request.method, "ping");
+                assert_eq!(request.params, json!([1]));
+            }
+            err => panic!("unexpected error {}", err),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn written_once_dropped() {
+        let path = path("drop");
+        let mock = MockClient::new();
+        mock.respond("ping", "pong");
+        let fixture = FixtureLayer::record(&path).unwrap().layer(mock);
+        fixture
+            .clone()
+            .oneshot(call("ping", json!([])))
+            .await
+            .unwrap();
+        drop(fixture);
+        let entries: Vec<Entry> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].result, Some(json!("pong")));
+        fs::remove_file(&path).unwrap();
+    }
+}
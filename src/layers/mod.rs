@@ -0,0 +1,32 @@
+pub mod auth;
+pub mod fault;
+pub mod fixture;
+pub mod rate_limit;
+pub mod retry;
+pub mod timeout;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "tracing")]
+pub mod wire_log;
+
+use std::pin::Pin;
+
+use futures_core::Future;
+
+pub use self::{
+    auth::{Auth, AuthLayer},
+    fault::{Fault, FaultLayer},
+    fixture::{Fixture, FixtureLayer},
+    rate_limit::{RateLimit, RateLimitLayer},
+    retry::{Retry, RetryLayer},
+    timeout::{Timeout, TimeoutLayer},
+};
+#[cfg(feature = "tracing")]
+pub use self::{
+    trace::{Trace, TraceLayer},
+    wire_log::{WireLog, WireLogLayer},
+};
+pub use tower_layer::Layer;
+
+/// The future of the response of a layer's service.
+type FutResponse<R, E> = Pin<Box<dyn Future<Output = Result<R, E>> + 'static + Send>>;
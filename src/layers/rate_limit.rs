@@ -0,0 +1,297 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::FutResponse;
+use crate::{
+    clients::{Error, RequestFactory},
+    clock::{Clock, SharedClock, Sleep},
+    objects::{Request, RequestBuilder, Response},
+};
+
+/// The cool-down applied after being rate limited without a `Retry-After` header.
+pub(crate) const DEFAULT_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// The longest cool-down applied by default, whatever the delay requested by the server.
+pub(crate) const DEFAULT_MAX_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// A cool-down shared between clones, holding back calls until it expires.
+pub(crate) struct Cooldown {
+    until: Arc<Mutex<Option<Instant>>>,
+    max: Duration,
+    sleep: Option<Sleep>,
+    clock: SharedClock,
+}
+
+impl Cooldown {
+    pub(crate) fn new() -> Self {
+        Cooldown {
+            until: Arc::new(Mutex::new(None)),
+            max: DEFAULT_MAX_COOLDOWN,
+            sleep: None,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Cap the cool-down applied by [`extend`] at `max`.
+    ///
+    /// [`extend`]: Cooldown::extend
+    pub(crate) fn set_max(&mut self, max: Duration) {
+        self.max = max;
+    }
+
+    /// Measure the cool-down with `clock`.
+    pub(crate) fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+        self.sleep = None;
+    }
+
+    /// Returns the instant the cool-down expires, if it is active.
+    pub(crate) fn until(&self) -> Option<Instant> {
+        self.until
+            .lock()
+            .unwrap()
+            .filter(|until| *until > self.clock.now())
+    }
+
+    /// Extend the cool-down to last at least `duration` from now, up to the maximum.
+    pub(crate) fn extend(&self, duration: Duration) {
+        // The delay is chosen by the server, it mustn't overflow the clock
+        let until = match self.clock.now().checked_add(duration.min(self.max)) {
+            Some(until) => until,
+            None => return,
+        };
+        let mut current = self.until.lock().unwrap();
+        *current = (*current).max(Some(until));
+    }
+
+    /// Wait out the cool-down.
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+            match self.until() {
+                Some(until) => self.sleep = Some(self.clock.sleep_until(until)),
+                None => return Poll::Ready(()),
+            }
+        }
+    }
+}
+
+impl Clone for Cooldown {
+    fn clone(&self) -> Self {
+        Cooldown {
+            until: self.until.clone(),
+            max: self.max,
+            sleep: None,
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Cooldown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cooldown")
+            .field("until", &self.until())
+            .finish()
+    }
+}
+
+/// Holds back calls after one fails with [`Error::RateLimited`].
+///
+/// The cool-down lasts for the delay requested by the server, or one second if none was given,
+/// up to a minute by default. It is shared with clones.
+#[derive(Clone, Debug)]
+pub struct RateLimit<S> {
+    inner: S,
+    cooldown: Cooldown,
+}
+
+impl<S> RateLimit<S> {
+    /// Wraps a client.
+    pub fn new(inner: S) -> Self {
+        RateLimit {
+            inner,
+            cooldown: Cooldown::new(),
+        }
+    }
+
+    /// Sets the clock measuring the cool-down, [`TokioClock`] by default.
+    ///
+    /// [`TokioClock`]: crate::clock::TokioClock
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.cooldown.set_clock(SharedClock::new(clock));
+        self
+    }
+
+    /// Sets the longest cool-down, whatever the delay requested by the server.
+    pub fn max_cooldown(mut self, max: Duration) -> Self {
+        self.cooldown.set_max(max);
+        self
+    }
+
+    /// Returns the instant until which calls are held back.
+    pub fn cooldown(&self) -> Option<Instant> {
+        self.cooldown.until()
+    }
+
+    /// Returns a reference to the wrapped client.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, E> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response, Error = Error<E>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Error<E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.cooldown.poll_ready(cx).is_pending() {
+            return Poll::Pending;
+        }
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let fut = self.inner.call(request);
+        let cooldown = self.cooldown.clone();
+        Box::pin(async move {
+            let result = fut.await;
+            if let Err(err) = &result {
+                if let Error::RateLimited { retry_after, .. } = err.inner() {
+                    cooldown.extend(retry_after.unwrap_or(DEFAULT_COOLDOWN));
+                }
+            }
+            result
+        })
+    }
+}
+
+impl<S: RequestFactory> RequestFactory for RateLimit<S> {
+    fn build_request(&self) -> RequestBuilder {
+        self.inner.build_request()
+    }
+}
+
+/// A [`Layer`] producing [`RateLimit`] services.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitLayer {
+    clock: SharedClock,
+    max_cooldown: Option<Duration>,
+}
+
+impl RateLimitLayer {
+    /// Creates a layer holding back calls after being rate limited.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the clock measuring the cool-down, see [`RateLimit::clock`].
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Sets the longest cool-down, see [`RateLimit::max_cooldown`].
+    pub fn max_cooldown(mut self, max: Duration) -> Self {
+        self.max_cooldown = Some(max);
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let mut service = RateLimit::new(inner);
+        service.cooldown.set_clock(self.clock.clone());
+        if let Some(max) = self.max_cooldown {
+            service.cooldown.set_max(max);
+        }
+        service
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use tower_util::ServiceExt;
+
+    use super::*;
+    use crate::{clients::mock::MockClient, clock::ManualClock};
+
+    #[tokio::test]
+    async fn holds_back_calls_during_the_cooldown() {
+        let mock = MockClient::new();
+        mock.fail("ping", || Error::RateLimited {
+            retry_after: Some(Duration::from_secs(3)),
+            error: None,
+        });
+        let clock = ManualClock::new();
+        let mut limited = RateLimitLayer::new()
+            .clock(clock.clone())
+            .layer(mock.clone());
+        let request = mock.build_request().method("ping").finish().unwrap();
+        let result = limited.ready_and().await.unwrap().call(request).await;
+        assert!(result.is_err());
+
+        assert_eq!(
+            limited.cooldown(),
+            Some(clock.now() + Duration::from_secs(3))
+        );
+        assert!(limited.ready_and().now_or_never().is_none());
+        clock.advance(Duration::from_secs(3));
+        assert!(limited.cooldown().is_none());
+        assert!(limited.ready_and().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn defaults_to_one_second() {
+        let mock = MockClient::new();
+        mock.fail("ping", || Error::RateLimited {
+            retry_after: None,
+            error: None,
+        });
+        let clock = ManualClock::new();
+        let mut limited = RateLimit::new(mock.clone()).clock(clock.clone());
+        let request = mock.build_request().method("ping").finish().unwrap();
+        let _ = limited.ready_and().await.unwrap().call(request).await;
+        assert_eq!(limited.cooldown(), Some(clock.now() + DEFAULT_COOLDOWN));
+    }
+
+    #[tokio::test]
+    async fn caps_the_cooldown() {
+        let mock = MockClient::new();
+        mock.fail("ping", || Error::RateLimited {
+            retry_after: Some(Duration::from_secs(u64::MAX)),
+            error: None,
+        });
+        let clock = ManualClock::new();
+        let mut limited = RateLimitLayer::new()
+            .clock(clock.clone())
+            .max_cooldown(Duration::from_secs(5))
+            .layer(mock.clone());
+        let request = mock.build_request().method("ping").finish().unwrap();
+        let _ = limited.ready_and().await.unwrap().call(request).await;
+        assert_eq!(
+            limited.cooldown(),
+            Some(clock.now() + Duration::from_secs(5))
+        );
+    }
+}
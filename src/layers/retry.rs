@@ -0,0 +1,331 @@
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+use tower_util::ServiceExt;
+
+use super::FutResponse;
+use crate::{
+    clients::{
+        events::{Event, Events},
+        Classifier, Classify, Error, RequestFactory,
+    },
+    clock::{Clock, SharedClock},
+    objects::{Request, RequestBuilder, Response},
+};
+
+#[derive(Clone, Debug)]
+struct Settings {
+    attempts: usize,
+    backoff: Duration,
+    max_delay: Duration,
+    events: Option<Events>,
+    clock: SharedClock,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            attempts: 2,
+            backoff: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            events: None,
+            clock: SharedClock::default(),
+        }
+    }
+}
+
+/// Retries failed calls with exponential backoff.
+///
+/// When the server asks the client to back off, using the `Retry-After` header, the requested
+/// delay is used in place of the backoff schedule, bounded by the maximum delay.
+///
+/// Which failures are retried is decided by the [`Classify`] implementation, [`Classifier`] by
+/// default.
+#[derive(Clone, Debug)]
+pub struct Retry<S, C = Classifier> {
+    inner: S,
+    classifier: C,
+    settings: Settings,
+}
+
+impl<S> Retry<S> {
+    /// Wraps a client, by default retrying twice starting with a 100 millisecond delay.
+    pub fn new(inner: S) -> Self {
+        Retry {
+            inner,
+            classifier: Classifier::default(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+impl<S, C> Retry<S, C> {
+    /// Sets the classifier deciding which failures are retried.
+    pub fn classifier<D>(self, classifier: D) -> Retry<S, D> {
+        Retry {
+            inner: self.inner,
+            classifier,
+            settings: self.settings,
+        }
+    }
+
+    /// Sets the maximum number of retries.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.settings.attempts = attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry, doubling with each subsequent retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.settings.backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound on the delay between retries.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.settings.max_delay = max_delay;
+        self
+    }
+
+    /// Emits an event to `events` before each retry.
+    pub fn events(mut self, events: Events) -> Self {
+        self.settings.events = Some(events);
+        self
+    }
+
+    /// Sets the clock waiting out the delays between retries, [`TokioClock`] by default.
+    ///
+    /// [`TokioClock`]: crate::clock::TokioClock
+    pub fn clock<K: Clock>(mut self, clock: K) -> Self {
+        self.settings.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Returns a reference to the wrapped client.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+/// Whether the outcome of a call is worth retrying.
+fn should_retry<C, E>(classifier: &C, result: &Result<Response, Error<E>>) -> bool
+where
+    C: Classify<E>,
+{
+    match result {
+        Ok(response) => response
+            .error
+            .as_ref()
+            .is_some_and(|err| classifier.is_transient_rpc_error(err)),
+        Err(err) => classifier.is_transient_error(err),
+    }
+}
+
+impl<S, C, E> Service<Request> for Retry<S, C>
+where
+    S: Service<Request, Response = Response, Error = Error<E>> + Clone + Send + 'static,
+    S::Future: Send,
+    C: Classify<E> + Clone + Send + 'static,
+    E: Send + 'static,
+{
+    type Response = Response;
+    type Error = Error<E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let classifier = self.classifier.clone();
+        let Settings {
+            attempts,
+            mut backoff,
+            max_delay,
+            events,
+            clock,
+        } = self.settings.clone();
+
+        let fut = async move {
+            let mut result = inner.call(request.clone()).await;
+            for attempt in 1..=attempts {
+                if !should_retry(&classifier, &result) {
+                    return result;
+                }
+                let retry_after = result.as_ref().err().and_then(Error::retry_after);
+                let delay = retry_after.unwrap_or(backoff).min(max_delay);
+                if let Some(events) = &events {
+                    events.emit(|| Event::RetryScheduled {
+                        id: request.id.clone(),
+                        method: request.method.clone(),
+                        attempt,
+                        delay,
+                    });
+                }
+                clock.sleep(delay).await;
+                backoff = (backoff * 2).min(max_delay);
+
+                result = match inner.ready_and().await {
+                    Ok(inner) => inner.call(request.clone()).await,
+                    Err(err) => Err(err),
+                };
+            }
+            result
+        };
+
+        Box::pin(fut)
+    }
+}
+
+impl<S: RequestFactory, C> RequestFactory for Retry<S, C> {
+    fn build_request(&self) -> RequestBuilder {
+        self.inner.build_request()
+    }
+}
+
+/// A [`Layer`] producing [`Retry`] services.
+#[derive(Clone, Debug)]
+pub struct RetryLayer<C = Classifier> {
+    classifier: C,
+    settings: Settings,
+}
+
+impl RetryLayer {
+    /// Creates a layer with the same defaults as [`Retry::new`].
+    pub fn new() -> Self {
+        RetryLayer {
+            classifier: Classifier::default(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+impl Default for RetryLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> RetryLayer<C> {
+    /// Sets the classifier deciding which failures are retried.
+    pub fn classifier<D>(self, classifier: D) -> RetryLayer<D> {
+        RetryLayer {
+            classifier,
+            settings: self.settings,
+        }
+    }
+
+    /// Sets the maximum number of retries.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.settings.attempts = attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry, doubling with each subsequent retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.settings.backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound on the delay between retries.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.settings.max_delay = max_delay;
+        self
+    }
+
+    /// Emits an event to `events` before each retry.
+    pub fn events(mut self, events: Events) -> Self {
+        self.settings.events = Some(events);
+        self
+    }
+
+    /// Sets the clock waiting out the delays between retries, see [`Retry::clock`].
+    pub fn clock<K: Clock>(mut self, clock: K) -> Self {
+        self.settings.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl<S, C: Clone> Layer<S> for RetryLayer<C> {
+    type Service = Retry<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Retry {
+            inner,
+            classifier: self.classifier.clone(),
+            settings: self.settings.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+    use crate::{clients::mock::MockClient, clock::ManualClock};
+
+    /// Let the spawned tasks run until they wait.
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_after_the_backoff() {
+        let mock = MockClient::new();
+        mock.fail("ping", || Error::Timeout);
+        let clock = ManualClock::new();
+        let retry = RetryLayer::new()
+            .attempts(2)
+            .backoff(Duration::from_secs(1))
+            .clock(clock.clone())
+            .layer(mock.clone());
+        let request = mock.build_request().method("ping").finish().unwrap();
+        let mut call = tokio::spawn(retry.oneshot(request));
+
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 1);
+        clock.advance(Duration::from_millis(999));
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 1);
+        clock.advance(Duration::from_millis(1));
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 2);
+        // The backoff doubles
+        clock.advance(Duration::from_secs(2));
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 3);
+        let result = (&mut call).now_or_never().unwrap().unwrap();
+        assert!(matches!(result.unwrap_err().inner(), Error::Timeout));
+    }
+
+    #[tokio::test]
+    async fn server_delay_replaces_the_backoff() {
+        let mock = MockClient::new();
+        mock.fail("ping", || Error::RateLimited {
+            retry_after: Some(Duration::from_secs(5)),
+            error: None,
+        });
+        let clock = ManualClock::new();
+        let retry = Retry::new(mock.clone()).attempts(1).clock(clock.clone());
+        let request = mock.build_request().method("ping").finish().unwrap();
+        let _call = tokio::spawn(retry.oneshot(request));
+
+        settle().await;
+        clock.advance(Duration::from_secs(4));
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 1);
+        clock.advance(Duration::from_secs(1));
+        settle().await;
+        assert_eq!(mock.call_count("ping"), 2);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::FutResponse;
+use crate::{
+    clients::{Error, RequestFactory},
+    clock::{Clock, SharedClock},
+    objects::{Request, RequestBuilder, Response},
+};
+
+/// Fails calls with [`Error::Timeout`] if they take longer than the timeout.
+#[derive(Clone, Debug)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Duration,
+    clock: SharedClock,
+}
+
+impl<S> Timeout<S> {
+    /// Wraps a client, applying `timeout` to each call.
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        Timeout {
+            inner,
+            timeout,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Sets the clock measuring the timeout, [`TokioClock`] by default.
+    ///
+    /// [`TokioClock`]: crate::clock::TokioClock
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Returns a reference to the wrapped client.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, E> Service<Request> for Timeout<S>
+where
+    S: Service<Request, Response = Response, Error = Error<E>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Error<E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let fut = self.inner.call(request);
+        let clock = self.clock.clone();
+        let timeout = self.timeout;
+        Box::pin(async move {
+            clock
+                .timeout(timeout, fut)
+                .await
+                .unwrap_or(Err(Error::Timeout))
+        })
+    }
+}
+
+impl<S: RequestFactory> RequestFactory for Timeout<S> {
+    fn build_request(&self) -> RequestBuilder {
+        self.inner.build_request()
+    }
+}
+
+/// A [`Layer`] producing [`Timeout`] services.
+#[derive(Clone, Debug)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+    clock: SharedClock,
+}
+
+impl TimeoutLayer {
+    /// Creates a layer applying `timeout` to each call.
+    pub fn new(timeout: Duration) -> Self {
+        TimeoutLayer {
+            timeout,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Sets the clock measuring the timeout, see [`Timeout::clock`].
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            timeout: self.timeout,
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future, io};
+
+    use futures_util::FutureExt;
+    use tower_util::{service_fn, ServiceExt};
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[tokio::test]
+    async fn times_out_when_the_clock_moves() {
+        let never =
+            service_fn(|_: Request| future::pending::<Result<Response, Error<io::Error>>>());
+        let clock = ManualClock::new();
+        let timeout = TimeoutLayer::new(Duration::from_secs(1))
+            .clock(clock.clone())
+            .layer(never);
+        let request = Request::build().method("ping").id(0).finish().unwrap();
+        let mut call = Box::pin(timeout.oneshot(request));
+
+        assert!((&mut call).now_or_never().is_none());
+        clock.advance(Duration::from_secs(1));
+        let err = call.now_or_never().unwrap().unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+    }
+}
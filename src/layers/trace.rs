@@ -0,0 +1,218 @@
+use std::{
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::Instrument;
+
+use super::FutResponse;
+use crate::{
+    clients::{Error, RequestFactory},
+    objects::{Request, RequestBuilder, Response},
+};
+
+/// Runs each call in a `json_rpc_call` span, ending with an event recording its latency and
+/// outcome.
+///
+/// The HTTP client traces its calls already, this brings the same spans to other clients.
+#[derive(Clone, Debug)]
+pub struct Trace<S> {
+    inner: S,
+}
+
+impl<S> Trace<S> {
+    /// Wraps a client, tracing each call.
+    pub fn new(inner: S) -> Self {
+        Trace { inner }
+    }
+
+    /// Returns a reference to the wrapped client.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, E> Service<Request> for Trace<S>
+where
+    S: Service<Request, Response = Response, Error = Error<E>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Error<E>;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let span = tracing::debug_span!(
+            "json_rpc_call",
+            method = %request.method,
+            id = %request.id,
+        );
+        let fut = span.in_scope(|| self.inner.call(request));
+        let fut = async move {
+            let start = Instant::now();
+            let result = fut.await;
+            let latency = start.elapsed();
+            match &result {
+                Ok(Response {
+                    error: Some(err), ..
+                }) => tracing::debug!(?latency, outcome = "rpc_error", code = err.code),
+                Ok(_) => tracing::debug!(?latency, outcome = "ok"),
+                Err(err) => tracing::debug!(?latency, outcome = "error", error = err.kind()),
+            }
+            result
+        };
+        Box::pin(fut.instrument(span))
+    }
+}
+
+impl<S: RequestFactory> RequestFactory for Trace<S> {
+    fn build_request(&self) -> RequestBuilder {
+        self.inner.build_request()
+    }
+}
+
+/// A [`Layer`] producing [`Trace`] services.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceLayer;
+
+impl TraceLayer {
+    /// Creates a layer tracing each call.
+    pub fn new() -> Self {
+        TraceLayer
+    }
+}
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace::new(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fmt, io,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::json;
+    use tower_util::{service_fn, ServiceExt};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+    use crate::{clients::mock::MockClient, objects::RpcError};
+
+    type Fields = HashMap<String, String>;
+
+    /// Records the fields of the spans opened and the events logged.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+    impl Recorder {
+        fn records(&self) -> Vec<Fields> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+            let mut fields = Fields::new();
+            fields.insert("span".to_owned(), attributes.metadata().name().to_owned());
+            attributes.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn traces_calls() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let mock = MockClient::new();
+        mock.respond("ping", true)
+            .respond_error("fail", RpcError::invalid_params());
+        let client = TraceLayer::new().layer(mock);
+
+        for method in ["ping", "fail"] {
+            let request = client.build_request().method(method).params(json!([]));
+            client
+                .clone()
+                .oneshot(request.finish().unwrap())
+                .await
+                .unwrap();
+        }
+
+        let records = recorder.records();
+        assert_eq!(records[0]["span"], "json_rpc_call");
+        assert_eq!(records[0]["method"], "ping");
+        assert_eq!(records[0]["id"], "0");
+        assert_eq!(records[1]["outcome"], "ok");
+        assert!(records[1].contains_key("latency"));
+        assert_eq!(records[2]["method"], "fail");
+        assert_eq!(records[2]["id"], "1");
+        assert_eq!(records[3]["outcome"], "rpc_error");
+        assert_eq!(
+            records[3]["code"],
+            RpcError::invalid_params().code.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn traces_failed_calls() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let failing =
+            service_fn(|_: Request| async { Err::<Response, _>(Error::<io::Error>::Timeout) });
+        let request = Request::build().method("ping").id(7).finish().unwrap();
+        let err = Trace::new(failing).oneshot(request).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+
+        let records = recorder.records();
+        assert_eq!(records[0]["id"], "7");
+        assert_eq!(records[1]["outcome"], "error");
+        assert_eq!(records[1]["error"], "timeout");
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use hyper::{
+    body::{Body as HttpBody, Bytes, Frame, SizeHint},
+    header::{
+        HeaderMap, HeaderName, AUTHORIZATION, CONTENT_ENCODING, COOKIE, PROXY_AUTHORIZATION,
+        SET_COOKIE,
+    },
+    Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Uri,
+};
+use serde_json::Value;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::FutResponse;
+use crate::{
+    auth::BoxError,
+    clients::{compression, http::Body},
+};
+
+/// The replacement for redacted values.
+const REDACTED: &str = "<redacted>";
+
+#[derive(Clone, Debug)]
+struct Settings {
+    max_body_size: usize,
+    redact_params: Vec<String>,
+    redact_headers: HashSet<HeaderName>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_body_size: 4096,
+            redact_params: Vec::new(),
+            redact_headers: [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl Settings {
+    /// Redact the configured headers, as well as those marked as sensitive.
+    fn headers(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if value.is_sensitive() || self.redact_headers.contains(name) {
+                    REDACTED.to_owned()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    /// Redact the configured params of a request, or of each request in a batch.
+    fn redact(&self, body: &[u8]) -> Option<Vec<u8>> {
+        let mut json: Value = serde_json::from_slice(body).ok()?;
+        let requests = match &mut json {
+            Value::Array(batch) => batch.iter_mut().collect(),
+            request => vec![request],
+        };
+        for request in requests {
+            let params = match request.get_mut("params") {
+                Some(params) => params,
+                None => continue,
+            };
+            for path in &self.redact_params {
+                if let Some(value) = params.pointer_mut(path) {
+                    *value = Value::String(REDACTED.to_owned());
+                }
+            }
+        }
+        Some(serde_json::to_vec(&json).unwrap()) // This is safe
+    }
+
+    /// Display up to the maximum size of `body`, the start of a body `size` bytes long, if known.
+    fn truncate(&self, body: &[u8], size: Option<usize>) -> String {
+        let start = &body[..body.len().min(self.max_body_size)];
+        let start = String::from_utf8_lossy(start);
+        match size {
+            Some(size) if size <= self.max_body_size => start.into_owned(),
+            Some(size) => format!("{}... ({} bytes)", start, size),
+            None if body.len() <= self.max_body_size => start.into_owned(),
+            None => format!("{}...", start),
+        }
+    }
+}
+
+/// What a logged body belongs to.
+enum Head {
+    Request {
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+    },
+    Response {
+        status: StatusCode,
+        headers: HeaderMap,
+    },
+}
+
+/// The start of a body, logged along with its head once it ends.
+struct Log {
+    settings: Arc<Settings>,
+    head: Head,
+    captured: Vec<u8>,
+    size: usize,
+}
+
+impl Log {
+    fn new(settings: Arc<Settings>, head: Head) -> Self {
+        Log {
+            settings,
+            head,
+            captured: Vec::new(),
+            size: 0,
+        }
+    }
+
+    fn capture(&mut self, data: &[u8]) {
+        let room = self
+            .settings
+            .max_body_size
+            .saturating_sub(self.captured.len());
+        self.captured
+            .extend_from_slice(&data[..room.min(data.len())]);
+        self.size += data.len();
+    }
+
+    /// Describe the body seen, decoded, and redacted if it is a request.
+    fn body(&self, headers: &HeaderMap, request: bool) -> String {
+        // Only whole bodies can be decoded and redacted
+        let complete = self.captured.len() == self.size;
+        let redact = request && !self.settings.redact_params.is_empty();
+        let mut body = Bytes::from(self.captured.clone());
+        let mut size = Some(self.size);
+
+        if headers.contains_key(CONTENT_ENCODING) {
+            let limit = Some(self.settings.max_body_size);
+            body = match complete.then(|| compression::decompress(headers, body, limit)) {
+                Some(Ok(decoded)) => decoded,
+                _ => return format!("<{} bytes, encoded>", self.size),
+            };
+            // Decoding stops past the maximum size
+            size = None;
+        }
+        if redact {
+            body = match self.settings.redact(&body) {
+                Some(redacted) if complete => redacted.into(),
+                _ => return REDACTED.to_owned(),
+            };
+            size = Some(body.len());
+        }
+        self.settings.truncate(&body, size)
+    }
+
+    fn emit(self) {
+        match &self.head {
+            Head::Request {
+                method,
+                uri,
+                headers,
+            } => tracing::debug!(
+                target: "async_json_rpc::wire",
+                method = %method,
+                uri = %uri,
+                headers = ?self.settings.headers(headers),
+                body = %self.body(headers, true),
+                "request",
+            ),
+            Head::Response { status, headers } => tracing::debug!(
+                target: "async_json_rpc::wire",
+                status = %status,
+                headers = ?self.settings.headers(headers),
+                body = %self.body(headers, false),
+                "response",
+            ),
+        }
+    }
+}
+
+/// Passes a body through, logging its start once it ends, fails or is dropped.
+struct Tee<B> {
+    body: Pin<Box<B>>,
+    log: Option<Log>,
+}
+
+impl<B> Tee<B> {
+    fn new(body: B, log: Log) -> Self {
+        Tee {
+            body: Box::pin(body),
+            log: Some(log),
+        }
+    }
+}
+
+impl<B: HttpBody<Data = Bytes>> HttpBody for Tee<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let frame = ready!(self.body.as_mut().poll_frame(cx));
+        match (&frame, &mut self.log) {
+            (Some(Ok(frame)), Some(log)) => {
+                if let Some(data) = frame.data_ref() {
+                    log.capture(data);
+                }
+            }
+            (_, log) => {
+                if let Some(log) = log.take() {
+                    log.emit();
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl<B> Drop for Tee<B> {
+    fn drop(&mut self) {
+        if let Some(log) = self.log.take() {
+            log.emit();
+        }
+    }
+}
+
+/// Logs the HTTP requests and responses exchanged with the server as `tracing` debug events.
+///
+/// Bodies are logged up to a maximum size and the `Authorization`, `Proxy-Authorization`,
+/// `Cookie` and `Set-Cookie` headers are redacted, as are headers marked as sensitive. This wraps
+/// the HTTP service passed to [`Client::from_service`].
+///
+/// Bodies keep streaming, each is logged once it was sent or read, or dropped. Encoded bodies are
+/// logged decoded if they fit in the maximum size, and requests too large to be redacted are
+/// logged as redacted.
+///
+/// [`Client::from_service`]: crate::clients::http::Client::from_service
+#[derive(Clone, Debug)]
+pub struct WireLog<S> {
+    inner: S,
+    settings: Arc<Settings>,
+}
+
+impl<S> WireLog<S> {
+    /// Wraps a HTTP service, logging with the default settings.
+    pub fn new(inner: S) -> Self {
+        WireLog {
+            inner,
+            settings: Arc::new(Settings::default()),
+        }
+    }
+
+    /// Returns a reference to the wrapped service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, B> Service<HttpRequest<Body>> for WireLog<S>
+where
+    S: Service<HttpRequest<Body>, Response = HttpResponse<B>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = HttpResponse<Body>;
+    type Error = BoxError;
+    type Future = FutResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: HttpRequest<Body>) -> Self::Future {
+        // Take the service which was driven to readiness
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let settings = self.settings.clone();
+        let (parts, body) = request.into_parts();
+        let head = Head::Request {
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            headers: parts.headers.clone(),
+        };
+        let body = Body::new(Tee::new(body, Log::new(settings.clone(), head)));
+        let response = inner.call(HttpRequest::from_parts(parts, body));
+        Box::pin(async move {
+            let response = response.await.map_err(Into::into)?;
+            let (parts, body) = response.into_parts();
+            let head = Head::Response {
+                status: parts.status,
+                headers: parts.headers.clone(),
+            };
+            let body = Body::new(Tee::new(body, Log::new(settings, head)));
+            Ok(HttpResponse::from_parts(parts, body))
+        })
+    }
+}
+
+/// A [`Layer`] producing [`WireLog`] services.
+#[derive(Clone, Debug, Default)]
+pub struct WireLogLayer {
+    settings: Settings,
+}
+
+impl WireLogLayer {
+    /// Creates a layer with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of body bytes logged, 4 KiB by default.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.settings.max_body_size = max_body_size;
+        self
+    }
+
+    /// Redacts the request param at the JSON pointer `path`, e.g. `/0` or `/password`.
+    pub fn redact_param<P: Into<String>>(mut self, path: P) -> Self {
+        self.settings.redact_params.push(path.into());
+        self
+    }
+
+    /// Redacts the header `name` in requests and responses.
+    pub fn redact_header(mut self, name: HeaderName) -> Self {
+        self.settings.redact_headers.insert(name);
+        self
+    }
+}
+
+impl<S> Layer<S> for WireLogLayer {
+    type Service = WireLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WireLog {
+            inner,
+            settings: Arc::new(self.settings.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use futures_util::{stream, FutureExt};
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use tokio::sync::mpsc;
+    use tower_util::{service_fn, ServiceExt};
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+
+    type Fields = HashMap<String, String>;
+
+    /// Records the fields of the events logged.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<Fields> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_owned(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut Visitor(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    async fn echo(request: HttpRequest<Body>) -> Result<HttpResponse<Body>, Infallible> {
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        Ok(HttpResponse::new(Body::from(body)))
+    }
+
+    fn request(body: &'static str) -> HttpRequest<Body> {
+        HttpRequest::new(Body::from(Bytes::from(body)))
+    }
+
+    #[tokio::test]
+    async fn bodies_keep_streaming() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let (sender, receiver) = mpsc::unbounded_channel::<Bytes>();
+        let frames = stream::unfold(receiver, |mut receiver| async move {
+            let data = receiver.recv().await?;
+            Some((Ok::<_, Infallible>(Frame::data(data)), receiver))
+        });
+        let body = Arc::new(Mutex::new(Some(Body::new(StreamBody::new(frames)))));
+        let server = service_fn(move |request: HttpRequest<Body>| {
+            let body = body.lock().unwrap().take().unwrap();
+            async move {
+                let _ = request.into_body().collect().await;
+                Ok::<_, Infallible>(HttpResponse::new(body))
+            }
+        });
+
+        // The response is returned before its body is sent
+        let call = WireLogLayer::new().layer(server).oneshot(request("{}"));
+        let response = call.now_or_never().unwrap().unwrap();
+        assert_eq!(recorder.events().len(), 1);
+        sender.send(Bytes::from_static(b"[1,")).unwrap();
+        sender.send(Bytes::from_static(b"2]")).unwrap();
+        drop(sender);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "[1,2]");
+
+        let events = recorder.events();
+        assert_eq!(events[0]["message"], "request");
+        assert_eq!(events[0]["body"], "{}");
+        assert_eq!(events[1]["message"], "response");
+        assert_eq!(events[1]["body"], "[1,2]");
+    }
+
+    #[tokio::test]
+    async fn logs_the_start_of_large_bodies() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let log = WireLogLayer::new().max_body_size(4).layer(service_fn(echo));
+        let response = log.oneshot(request("0123456789")).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "0123456789");
+        for event in recorder.events() {
+            assert_eq!(event["body"], "0123... (10 bytes)");
+        }
+    }
+
+    #[tokio::test]
+    async fn redacts_params() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let log = WireLogLayer::new()
+            .redact_param("/password")
+            .layer(service_fn(echo));
+        let call = r#"{"method":"login","params":{"user":"a","password":"b"}}"#;
+        let _ = log.clone().oneshot(request(call)).await.unwrap();
+        let logged: Value = serde_json::from_str(&recorder.events()[0]["body"]).unwrap();
+        assert_eq!(logged["params"]["password"], REDACTED);
+        assert_eq!(logged["params"]["user"], "a");
+
+        // Requests which can't be read whole aren't logged
+        let log = WireLogLayer::new()
+            .max_body_size(8)
+            .redact_param("/password")
+            .layer(service_fn(echo));
+        let _ = log.oneshot(request(call)).await.unwrap();
+        assert_eq!(recorder.events()[2]["body"], REDACTED);
+    }
+
+    #[tokio::test]
+    async fn marks_encoded_bodies() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let server = service_fn(|_: HttpRequest<Body>| async {
+            let mut response = HttpResponse::new(Full::new(Bytes::from_static(b"\x1f\x8b")));
+            let encoding = hyper::header::HeaderValue::from_static("unknown");
+            response.headers_mut().insert(CONTENT_ENCODING, encoding);
+            Ok::<_, Infallible>(response)
+        });
+        let response = WireLogLayer::new()
+            .layer(server)
+            .oneshot(request(""))
+            .await
+            .unwrap();
+        let _ = response.into_body().collect().await.unwrap();
+        assert_eq!(recorder.events()[1]["body"], "<2 bytes, encoded>");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn decodes_gzip_bodies() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let server = service_fn(|_: HttpRequest<Body>| async {
+            let body = compression::gzip(br#"{"result":1}"#);
+            let mut response = HttpResponse::new(Full::new(body));
+            let encoding = hyper::header::HeaderValue::from_static("gzip");
+            response.headers_mut().insert(CONTENT_ENCODING, encoding);
+            Ok::<_, Infallible>(response)
+        });
+        let response = WireLogLayer::new()
+            .layer(server)
+            .oneshot(request(""))
+            .await
+            .unwrap();
+        let _ = response.into_body().collect().await.unwrap();
+        assert_eq!(recorder.events()[1]["body"], r#"{"result":1}"#);
+    }
+}
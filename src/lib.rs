@@ -1,3 +1,22 @@
+pub mod auth;
 pub mod clients;
+pub mod clock;
+pub mod health;
+pub mod layers;
 pub mod objects;
 pub mod prelude;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(feature = "macros")]
+pub use async_json_rpc_macros::rpc;
+
+/// Used by the code generated by the macros.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+    pub use serde_json;
+    pub use tower_util::ServiceExt;
+}
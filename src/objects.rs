@@ -1,17 +1,107 @@
-use serde::{Deserialize, Serialize};
-pub use serde_json::Error as JsonError;
+use std::{borrow::Cow, error, fmt};
+
+use serde::{Deserialize, Deserializer, Serialize};
+pub use serde_json::{value::RawValue, Error as JsonError};
+
+/// Write `value` as single-line JSON, or pretty-printed JSON with the alternate flag `{:#}`.
+fn fmt_json<T: Serialize>(value: &T, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let json = if f.alternate() {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    f.write_str(&json.map_err(|_| fmt::Error)?)
+}
 
 /// A JSON-RPC error object.
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct RpcError {
     /// The integer identifier of the error.
     pub code: i32,
     /// A string describing the error.
     pub message: String,
     /// Additional data specific to the error
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<serde_json::Value>,
 }
 
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_json(self, f)
+    }
+}
+
+impl error::Error for RpcError {}
+
+impl RpcError {
+    /// The code of the error for a message which isn't valid JSON.
+    pub const PARSE_ERROR: i32 = -32700;
+    /// The code of the error for a message which isn't a valid request object.
+    pub const INVALID_REQUEST: i32 = -32600;
+    /// The code of the error for a call to a method which isn't served.
+    pub const METHOD_NOT_FOUND: i32 = -32601;
+    /// The code of the error for a call with invalid parameters.
+    pub const INVALID_PARAMS: i32 = -32602;
+    /// The code of the error for a call which failed within the server.
+    pub const INTERNAL_ERROR: i32 = -32603;
+    /// The code of errors carrying no code of their own, in the range reserved for
+    /// implementation-defined server errors.
+    pub const SERVER_ERROR: i32 = -32000;
+    /// The code of the error for a call refused by a rate limit, as used by many public
+    /// endpoints.
+    pub const RATE_LIMITED: i32 = -32029;
+
+    /// Creates an error object without data.
+    pub fn new<S: Into<String>>(code: i32, message: S) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// The "Parse error" error object.
+    pub fn parse_error() -> Self {
+        Self::new(Self::PARSE_ERROR, "Parse error")
+    }
+
+    /// The "Invalid Request" error object.
+    pub fn invalid_request() -> Self {
+        Self::new(Self::INVALID_REQUEST, "Invalid Request")
+    }
+
+    /// The "Method not found" error object.
+    pub fn method_not_found() -> Self {
+        Self::new(Self::METHOD_NOT_FOUND, "Method not found")
+    }
+
+    /// The "Invalid params" error object.
+    pub fn invalid_params() -> Self {
+        Self::new(Self::INVALID_PARAMS, "Invalid params")
+    }
+
+    /// The "Internal error" error object.
+    pub fn internal_error() -> Self {
+        Self::new(Self::INTERNAL_ERROR, "Internal error")
+    }
+
+    /// A "Server error" error object with `message`.
+    pub fn server_error<S: Into<String>>(message: S) -> Self {
+        Self::new(Self::SERVER_ERROR, message)
+    }
+
+    /// The "Rate limited" error object.
+    pub fn rate_limited() -> Self {
+        Self::new(Self::RATE_LIMITED, "Rate limited")
+    }
+
+    /// Attach `data` to the error object.
+    pub fn with_data<V: Into<serde_json::Value>>(mut self, data: V) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+}
+
 /// Represents the JSON-RPC request object.
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Request {
@@ -21,6 +111,12 @@ pub struct Request {
     pub jsonrpc: String,
 }
 
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_json(self, f)
+    }
+}
+
 impl Request {
     pub fn build() -> RequestBuilder {
         RequestBuilder::default()
@@ -38,6 +134,14 @@ pub struct RequestBuilder {
 #[derive(Debug)]
 pub struct IncompleteRequest;
 
+impl fmt::Display for IncompleteRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("incomplete request, the id and method are required")
+    }
+}
+
+impl error::Error for IncompleteRequest {}
+
 impl RequestBuilder {
     pub fn method<S: Into<String>>(mut self, method: S) -> Self {
         self.method = Some(method.into());
@@ -87,15 +191,45 @@ impl RequestBuilder {
     }
 }
 
+/// A JSON-RPC notification, a request without an id which expects no response.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Notification {
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_json(self, f)
+    }
+}
+
 /// Represents the JSON-RPC response object.
-#[derive(Debug, Clone, PartialEq, Deserialize)]
-pub struct Response {
-    pub result: Option<serde_json::Value>,
+///
+/// The result is parsed into a [`Value`] by default, see [`RawResponse`] to defer parsing.
+///
+/// [`Value`]: serde_json::Value
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Response<R = serde_json::Value> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<R>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<RpcError>,
     pub id: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub jsonrpc: Option<String>,
 }
 
+/// A JSON-RPC response object keeping the unparsed result, which is only parsed once extracted.
+pub type RawResponse = Response<Box<RawValue>>;
+
+impl<R: Serialize> fmt::Display for Response<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_json(self, f)
+    }
+}
+
 impl Response {
     /// Extract the result.
     pub fn result<T: serde::de::DeserializeOwned>(&self) -> Option<Result<T, JsonError>> {
@@ -106,7 +240,23 @@ impl Response {
     pub fn into_result<T: serde::de::DeserializeOwned>(self) -> Option<Result<T, JsonError>> {
         self.result.map(serde_json::from_value)
     }
+}
+
+impl RawResponse {
+    /// Parse the result.
+    pub fn result<'a, T: Deserialize<'a>>(&'a self) -> Option<Result<T, JsonError>> {
+        self.result
+            .as_ref()
+            .map(|raw| serde_json::from_str(raw.get()))
+    }
 
+    /// Parse the result, consuming the response.
+    pub fn into_result<T: serde::de::DeserializeOwned>(self) -> Option<Result<T, JsonError>> {
+        self.result.map(|raw| serde_json::from_str(raw.get()))
+    }
+}
+
+impl<R> Response<R> {
     /// Returns the [`RpcError`].
     pub fn error(self) -> Option<RpcError> {
         self.error
@@ -122,3 +272,180 @@ impl Response {
         self.error.is_some()
     }
 }
+
+/// A JSON-RPC error object borrowing from the response body.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RpcErrorRef<'a> {
+    /// The integer identifier of the error.
+    pub code: i32,
+    /// A string describing the error, borrowed unless it contains escapes.
+    #[serde(borrow)]
+    pub message: Cow<'a, str>,
+    /// Additional data specific to the error.
+    #[serde(borrow)]
+    pub data: Option<&'a RawValue>,
+}
+
+impl RpcErrorRef<'_> {
+    /// Copies the error object out of the response body.
+    pub fn into_owned(self) -> Result<RpcError, JsonError> {
+        Ok(RpcError {
+            code: self.code,
+            message: self.message.into_owned(),
+            data: self
+                .data
+                .map(|data| serde_json::from_str(data.get()))
+                .transpose()?,
+        })
+    }
+}
+
+/// A JSON-RPC response object borrowing from the response body.
+///
+/// Unlike [`Response`] this allocates no more than escaped strings require, the result is parsed
+/// only once it is extracted.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResponseRef<'a> {
+    #[serde(borrow)]
+    pub result: Option<&'a RawValue>,
+    #[serde(borrow)]
+    pub error: Option<RpcErrorRef<'a>>,
+    #[serde(borrow)]
+    pub id: &'a RawValue,
+    #[serde(borrow, default, deserialize_with = "borrow_optional_str")]
+    pub jsonrpc: Option<Cow<'a, str>>,
+}
+
+/// Deserialize an optional string, borrowing it unless it contains escapes.
+///
+/// Serde only borrows strings held directly in a `Cow` field, not within an `Option`.
+fn borrow_optional_str<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+    let borrowed = Option::<Borrowed>::deserialize(deserializer)?;
+    Ok(borrowed.map(|borrowed| borrowed.0))
+}
+
+impl<'a> ResponseRef<'a> {
+    /// Parse a JSON response body.
+    pub fn from_slice(body: &'a [u8]) -> Result<Self, JsonError> {
+        serde_json::from_slice(body)
+    }
+
+    /// Extract the result, which may borrow from the body.
+    pub fn result<T: Deserialize<'a>>(&self) -> Option<Result<T, JsonError>> {
+        self.result.map(|raw| serde_json::from_str(raw.get()))
+    }
+
+    /// Returns `true` if the result field is [`Some`] value.
+    pub fn is_result(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Returns `true` if the error field is [`Some`] value.
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn displays_json() {
+        let request = Request::build()
+            .method("add")
+            .params(json!([1, 2]))
+            .id(1)
+            .finish()
+            .unwrap();
+        assert_eq!(
+            request.to_string(),
+            r#"{"method":"add","params":[1,2],"id":1,"jsonrpc":"2.0"}"#
+        );
+        assert_eq!(
+            format!("{:#}", request),
+            "{\n  \"method\": \"add\",\n  \"params\": [\n    1,\n    2\n  ],\n  \"id\": 1,\n  \
+             \"jsonrpc\": \"2.0\"\n}"
+        );
+
+        let err = RpcError::invalid_params().with_data("missing b");
+        let response = Response {
+            result: None,
+            error: Some(err.clone()),
+            id: json!(1),
+            jsonrpc: Some("2.0".to_owned()),
+        };
+        assert_eq!(
+            err.to_string(),
+            r#"{"code":-32602,"message":"Invalid params","data":"missing b"}"#
+        );
+        assert_eq!(
+            response.to_string(),
+            r#"{"error":{"code":-32602,"message":"Invalid params","data":"missing b"},"id":1,"jsonrpc":"2.0"}"#
+        );
+        // Displayed responses can be parsed back
+        let parsed: Response = serde_json::from_str(&format!("{:#}", response)).unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn parses_raw_results_on_demand() {
+        let body = r#"{"jsonrpc":"2.0","result":{"b": 2, "a": [1]},"id":1}"#;
+        let response: RawResponse = serde_json::from_str(body).unwrap();
+        // The result is kept as sent
+        assert_eq!(
+            response.result.as_ref().unwrap().get(),
+            r#"{"b": 2, "a": [1]}"#
+        );
+
+        let result: Value = response.result().unwrap().unwrap();
+        assert_eq!(result, json!({ "a": [1], "b": 2 }));
+        let result: &RawValue = response.result().unwrap().unwrap();
+        assert_eq!(result.get(), r#"{"b": 2, "a": [1]}"#);
+        assert!(response.result::<Vec<u8>>().unwrap().is_err());
+
+        let result: HashMap<String, Value> = response.into_result().unwrap().unwrap();
+        assert_eq!(result["b"], 2);
+
+        let body = r#"{"jsonrpc":"2.0","error":{"code":1,"message":"no"},"id":1}"#;
+        let response: RawResponse = serde_json::from_str(body).unwrap();
+        assert!(response.result::<Value>().is_none());
+        assert!(response.into_result::<Value>().is_none());
+    }
+
+    #[test]
+    fn borrows_from_the_body() {
+        let body = br#"{"jsonrpc":"2.0","result":["plain","esc\"aped"],"id":"a"}"#;
+        let response = ResponseRef::from_slice(body).unwrap();
+        assert!(matches!(response.jsonrpc, Some(Cow::Borrowed("2.0"))));
+        assert_eq!(response.id.get(), r#""a""#);
+        // Escaped strings can't be borrowed
+        let result: (&str, String) = response.result().unwrap().unwrap();
+        assert_eq!(result, ("plain", "esc\"aped".to_owned()));
+        assert!(response.result::<(&str, &str)>().unwrap().is_err());
+
+        let body = br#"{"error":{"code":-1,"message":"un\u0069code","data":[1]},"id":null}"#;
+        let response = ResponseRef::from_slice(body).unwrap();
+        assert!(response.is_error() && !response.is_result());
+        let err = response.error.unwrap();
+        assert!(matches!(err.message, Cow::Owned(_)));
+        let err = err.into_owned().unwrap();
+        assert_eq!(err, RpcError::new(-1, "unicode").with_data(json!([1])));
+
+        let body = br#"{"error":{"code":-1,"message":"plain"},"id":1}"#;
+        let response = ResponseRef::from_slice(body).unwrap();
+        let err = response.error.unwrap();
+        assert!(matches!(err.message, Cow::Borrowed("plain")));
+        assert_eq!(err.into_owned().unwrap(), RpcError::new(-1, "plain"));
+    }
+}
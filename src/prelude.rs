@@ -1,3 +1,5 @@
+#[cfg(feature = "server")]
+pub use crate::server::IntoRpcError;
 pub use crate::{
     clients::{Error, RequestFactory},
     objects::RpcError,
@@ -0,0 +1,185 @@
+use std::{fmt, future::Future, sync::Arc};
+
+use futures_core::future::BoxFuture;
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    HeaderMap,
+};
+use zeroize::Zeroizing;
+
+/// Validates credentials, see [`Authentication::validator`].
+type Validator = Arc<dyn Fn(Credentials) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// The credentials of an HTTP request, from its `Authorization` header.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// HTTP basic authentication.
+    Basic {
+        /// The user name.
+        user: String,
+        /// The password.
+        password: Zeroizing<String>,
+    },
+    /// A bearer token.
+    Bearer(Zeroizing<String>),
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { user, .. } => f
+                .debug_struct("Basic")
+                .field("user", user)
+                .finish_non_exhaustive(),
+            Credentials::Bearer(_) => f.write_str("Bearer(..)"),
+        }
+    }
+}
+
+impl Credentials {
+    /// Parse the value of an `Authorization` header.
+    fn parse(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let (scheme, value) = value.split_once(' ')?;
+        let value = value.trim();
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = Zeroizing::new(base64::decode(value).ok()?);
+            let decoded = std::str::from_utf8(&decoded).ok()?;
+            let (user, password) = decoded.split_once(':')?;
+            Some(Credentials::Basic {
+                user: user.to_owned(),
+                password: Zeroizing::new(password.to_owned()),
+            })
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Credentials::Bearer(Zeroizing::new(value.to_owned())))
+        } else {
+            None
+        }
+    }
+}
+
+/// Authentication of the HTTP requests made to a server, using basic or bearer credentials.
+///
+/// Requests without valid credentials are rejected with `401 Unauthorized` before any call is
+/// handled, as are WebSocket upgrades. Servers without HTTP, such as over TCP, are not covered.
+#[derive(Clone)]
+pub struct Authentication {
+    validator: Validator,
+    basic: bool,
+    bearer: bool,
+    realm: String,
+}
+
+impl fmt::Debug for Authentication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authentication")
+            .field("basic", &self.basic)
+            .field("bearer", &self.bearer)
+            .field("realm", &self.realm)
+            .finish()
+    }
+}
+
+impl Authentication {
+    fn new(validator: Validator, basic: bool, bearer: bool) -> Self {
+        Authentication {
+            validator,
+            basic,
+            bearer,
+            realm: "jsonrpc".to_owned(),
+        }
+    }
+
+    /// Accept HTTP basic authentication by any of `users`, pairs of user names and passwords.
+    pub fn basic<I, U, P>(users: I) -> Self
+    where
+        I: IntoIterator<Item = (U, P)>,
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let users: Vec<_> = users
+            .into_iter()
+            .map(|(user, password)| (user.into(), Zeroizing::new(password.into())))
+            .collect();
+        let validator = move |credentials| {
+            let valid = match credentials {
+                Credentials::Basic { user, password } => users.iter().any(|(u, p)| {
+                    // Both are compared, so that timing doesn't tell which differs
+                    let user_eq = constant_time_eq(u.as_bytes(), user.as_bytes());
+                    constant_time_eq(p.as_bytes(), password.as_bytes()) & user_eq
+                }),
+                Credentials::Bearer(_) => false,
+            };
+            Box::pin(async move { valid }) as BoxFuture<'static, _>
+        };
+        Self::new(Arc::new(validator), true, false)
+    }
+
+    /// Accept any of the bearer `tokens`.
+    pub fn bearer<I, T>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let tokens: Vec<_> = tokens
+            .into_iter()
+            .map(|token| Zeroizing::new(token.into()))
+            .collect();
+        let validator = move |credentials| {
+            let valid = match credentials {
+                Credentials::Bearer(token) => tokens
+                    .iter()
+                    .any(|t| constant_time_eq(t.as_bytes(), token.as_bytes())),
+                Credentials::Basic { .. } => false,
+            };
+            Box::pin(async move { valid }) as BoxFuture<'static, _>
+        };
+        Self::new(Arc::new(validator), false, true)
+    }
+
+    /// Accept the credentials for which `validator` resolves to `true`, such as tokens looked up
+    /// in a database.
+    pub fn validator<F, Fut>(validator: F) -> Self
+    where
+        F: Fn(Credentials) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        let validator =
+            move |credentials| Box::pin(validator(credentials)) as BoxFuture<'static, _>;
+        Self::new(Arc::new(validator), true, true)
+    }
+
+    /// Sets the realm named in the `WWW-Authenticate` header of rejections, `jsonrpc` by default.
+    pub fn realm<S: Into<String>>(mut self, realm: S) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Returns `true` if the request with `headers` carries valid credentials.
+    pub(crate) async fn authenticate(&self, headers: &HeaderMap) -> bool {
+        match headers.get(AUTHORIZATION).and_then(Credentials::parse) {
+            Some(credentials) => (self.validator)(credentials).await,
+            None => false,
+        }
+    }
+
+    /// Add the challenges of a rejection to `headers`.
+    pub(crate) fn challenge(&self, headers: &mut HeaderMap) {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let schemes = [("Basic", self.basic), ("Bearer", self.bearer)];
+        for (scheme, _) in schemes.iter().filter(|(_, accepted)| *accepted) {
+            let challenge = format!("{} realm=\"{}\"", scheme, realm);
+            if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+                headers.append(WWW_AUTHENTICATE, challenge);
+            }
+        }
+    }
+}
+
+/// Compare `a` and `b` in a time independent of their contents.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::Value;
+
+use super::connection::{self, Connection};
+
+/// Sends notifications to the connections subscribed to a topic, such as for
+/// `eth_subscribe`-style APIs.
+///
+/// Handlers subscribe the connection of their call, see [`Connection::current`], and any task
+/// holding a clone of the broadcaster may publish. Connections which have closed are
+/// unsubscribed as they are found, when publishing to or subscribing to their topics.
+#[derive(Clone, Default)]
+pub struct Broadcaster {
+    // The subscribers of each topic, by connection id
+    topics: Arc<Mutex<HashMap<String, HashMap<u64, Connection>>>>,
+}
+
+impl fmt::Debug for Broadcaster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let topics = self.topics.lock().unwrap();
+        f.debug_struct("Broadcaster")
+            .field("topics", &topics.keys())
+            .finish()
+    }
+}
+
+impl Broadcaster {
+    /// Creates a broadcaster without topics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe `connection` to `topic`, returning `false` if it already was.
+    pub fn subscribe<T: Into<String>>(&self, topic: T, connection: &Connection) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+        let subscribers = topics.entry(topic.into()).or_default();
+        subscribers.retain(|_, connection| !connection.is_closed());
+        subscribers
+            .insert(connection.id(), connection.clone())
+            .is_none()
+    }
+
+    /// Unsubscribe `connection` from `topic`, returning `false` if it wasn't subscribed.
+    pub fn unsubscribe(&self, topic: &str, connection: &Connection) -> bool {
+        let mut topics = self.topics.lock().unwrap();
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return false,
+        };
+        let removed = subscribers.remove(&connection.id()).is_some();
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        removed
+    }
+
+    /// Unsubscribe `connection` from every topic, along with the connections which have closed.
+    pub fn unsubscribe_all(&self, connection: &Connection) {
+        let mut topics = self.topics.lock().unwrap();
+        topics.retain(|_, subscribers| {
+            subscribers.remove(&connection.id());
+            subscribers.retain(|_, connection| !connection.is_closed());
+            !subscribers.is_empty()
+        });
+    }
+
+    /// The number of open connections subscribed to `topic`.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        let topics = self.topics.lock().unwrap();
+        topics.get(topic).map_or(0, |subscribers| {
+            subscribers
+                .values()
+                .filter(|connection| !connection.is_closed())
+                .count()
+        })
+    }
+
+    /// The topics with subscribers.
+    pub fn topics(&self) -> Vec<String> {
+        let topics = self.topics.lock().unwrap();
+        topics.keys().cloned().collect()
+    }
+
+    /// Send a notification of `method` with `params` to the subscribers of `topic`, returning the
+    /// number of connections it was sent to.
+    pub fn publish(&self, topic: &str, method: &str, params: Value) -> io::Result<usize> {
+        // Encoded once for all subscribers
+        let notification = connection::notification(method, &params)?;
+        let mut topics = self.topics.lock().unwrap();
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return Ok(0),
+        };
+        subscribers.retain(|_, connection| connection.send(notification.clone()).is_ok());
+        let sent = subscribers.len();
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use serde_json::json;
+
+    use super::*;
+    use crate::server::{connection::Outgoing, Router, Shutdown};
+
+    fn connection() -> (Connection, connection::Queue) {
+        let router = Router::new().into_served();
+        Connection::new(None, Shutdown::new(), router.connection_slots())
+    }
+
+    /// The notification queued in `queue`, if any.
+    fn received(queue: &mut connection::Queue) -> Option<Value> {
+        match queue.recv().now_or_never() {
+            Some(Some(Outgoing::Message(message))) => {
+                Some(serde_json::from_slice(&message).unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn publishes_to_subscribers() {
+        let broadcaster = Broadcaster::new();
+        let (first, mut first_queue) = connection();
+        let (second, mut second_queue) = connection();
+        assert!(broadcaster.subscribe("blocks", &first));
+        assert!(!broadcaster.subscribe("blocks", &first));
+        assert!(broadcaster.subscribe("blocks", &second));
+        assert!(broadcaster.subscribe("logs", &second));
+        assert_eq!(broadcaster.subscribers("blocks"), 2);
+
+        let sent = broadcaster.publish("blocks", "block", json!([1])).unwrap();
+        assert_eq!(sent, 2);
+        let notification = json!({ "jsonrpc": "2.0", "method": "block", "params": [1] });
+        assert_eq!(received(&mut first_queue), Some(notification.clone()));
+        assert_eq!(received(&mut second_queue), Some(notification));
+
+        assert_eq!(broadcaster.publish("logs", "log", json!([])).unwrap(), 1);
+        assert_eq!(received(&mut first_queue), None);
+        assert!(received(&mut second_queue).is_some());
+        assert_eq!(broadcaster.publish("txs", "tx", json!([])).unwrap(), 0);
+
+        assert!(broadcaster.unsubscribe("blocks", &first));
+        assert!(!broadcaster.unsubscribe("blocks", &first));
+        broadcaster.unsubscribe_all(&second);
+        assert!(broadcaster.topics().is_empty());
+    }
+
+    #[test]
+    fn prunes_closed_connections() {
+        let broadcaster = Broadcaster::new();
+        let (open, mut queue) = connection();
+        let (closed, closed_queue) = connection();
+        broadcaster.subscribe("blocks", &open);
+        broadcaster.subscribe("blocks", &closed);
+        broadcaster.subscribe("logs", &closed);
+        drop(closed_queue);
+        assert_eq!(broadcaster.subscribers("blocks"), 1);
+
+        // Publishing skips and forgets the closed connection
+        assert_eq!(
+            broadcaster.publish("blocks", "block", json!([])).unwrap(),
+            1
+        );
+        assert!(received(&mut queue).is_some());
+        assert_eq!(broadcaster.topics.lock().unwrap()["blocks"].len(), 1);
+
+        // As does unsubscribing another connection from every topic
+        broadcaster.unsubscribe_all(&open);
+        assert!(broadcaster.topics().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::objects::RpcError;
+
+/// Limits on the calls handled at once by a server, see [`Router::concurrency_limit`].
+///
+/// Calls beyond a limit wait for a call to complete by default, they may instead fail at once
+/// with a "Server overloaded" error. Notifications count towards the limits, and are dropped
+/// when rejected.
+///
+/// [`Router::concurrency_limit`]: super::Router::concurrency_limit
+#[derive(Clone, Debug, Default)]
+pub struct ConcurrencyLimit {
+    global: Option<usize>,
+    per_connection: Option<usize>,
+    reject: bool,
+}
+
+impl ConcurrencyLimit {
+    /// Creates limits which don't limit anything, until set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the most calls handled at once across all connections.
+    pub fn global(mut self, limit: usize) -> Self {
+        self.global = Some(limit);
+        self
+    }
+
+    /// Sets the most calls handled at once for each connection.
+    ///
+    /// Each TCP connection carrying plain HTTP requests counts as a connection, as does each
+    /// WebSocket.
+    pub fn per_connection(mut self, limit: usize) -> Self {
+        self.per_connection = Some(limit);
+        self
+    }
+
+    /// Sets whether calls beyond a limit fail at once, instead of waiting their turn.
+    pub fn reject(mut self, reject: bool) -> Self {
+        self.reject = reject;
+        self
+    }
+}
+
+/// The limits of a router, with the slots shared by all connections.
+#[derive(Clone, Debug)]
+pub(crate) struct Limiter {
+    limit: ConcurrencyLimit,
+    global: Option<Arc<Semaphore>>,
+}
+
+/// The slots of the calls of a connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Slots(Option<Arc<Semaphore>>);
+
+/// The slots taken by a call, freed once dropped.
+pub(crate) struct Permits {
+    _connection: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: ConcurrencyLimit) -> Self {
+        let global = limit.global.map(|limit| Arc::new(Semaphore::new(limit)));
+        Limiter { limit, global }
+    }
+
+    /// The slots of a new connection.
+    pub(crate) fn slots(&self) -> Slots {
+        Slots(
+            self.limit
+                .per_connection
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+        )
+    }
+
+    /// Take a slot of `connection` and a global slot, failing if either is taken when rejecting.
+    pub(crate) async fn acquire(&self, connection: &Slots) -> Result<Permits, RpcError> {
+        // The connection's slot comes first, so that a busy connection holds no global slot
+        let connection = self.take(connection.0.as_ref()).await?;
+        let global = self.take(self.global.as_ref()).await?;
+        Ok(Permits {
+            _connection: connection,
+            _global: global,
+        })
+    }
+
+    async fn take(
+        &self,
+        semaphore: Option<&Arc<Semaphore>>,
+    ) -> Result<Option<OwnedSemaphorePermit>, RpcError> {
+        let semaphore = match semaphore {
+            Some(semaphore) => semaphore.clone(),
+            None => return Ok(None),
+        };
+        let permit = match self.limit.reject {
+            true => semaphore
+                .try_acquire_owned()
+                .map_err(|_| RpcError::server_error("Server overloaded"))?,
+            false => semaphore.acquire_owned().await.unwrap(), // This is safe
+        };
+        Ok(Some(permit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    fn rejected(acquiring: Result<Permits, RpcError>) -> bool {
+        match acquiring {
+            Ok(_) => false,
+            Err(err) => err.message == "Server overloaded",
+        }
+    }
+
+    #[test]
+    fn limits_each_connection() {
+        let limiter = Limiter::new(ConcurrencyLimit::new().per_connection(1).reject(true));
+        let (first, second) = (limiter.slots(), limiter.slots());
+        let permits = limiter.acquire(&first).now_or_never().unwrap().unwrap();
+        assert!(rejected(limiter.acquire(&first).now_or_never().unwrap()));
+        // Other connections have their own slots
+        assert!(limiter.acquire(&second).now_or_never().unwrap().is_ok());
+
+        drop(permits);
+        assert!(limiter.acquire(&first).now_or_never().unwrap().is_ok());
+    }
+
+    #[test]
+    fn limits_all_connections() {
+        let limiter = Limiter::new(ConcurrencyLimit::new().global(1).reject(true));
+        let (first, second) = (limiter.slots(), limiter.slots());
+        let permits = limiter.acquire(&first).now_or_never().unwrap().unwrap();
+        assert!(rejected(limiter.acquire(&second).now_or_never().unwrap()));
+
+        drop(permits);
+        assert!(limiter.acquire(&second).now_or_never().unwrap().is_ok());
+    }
+
+    #[test]
+    fn waits_for_a_slot() {
+        let limiter = Limiter::new(ConcurrencyLimit::new().global(1).per_connection(1));
+        let slots = limiter.slots();
+        let permits = limiter.acquire(&slots).now_or_never().unwrap().unwrap();
+        let mut waiting = Box::pin(limiter.acquire(&slots));
+        assert!((&mut waiting).now_or_never().is_none());
+
+        drop(permits);
+        assert!(waiting.now_or_never().unwrap().is_ok());
+    }
+}
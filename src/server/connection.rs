@@ -0,0 +1,269 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::Bytes;
+use futures_util::future::{select, Either};
+use hyper::http::Extensions;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use super::{concurrency::Slots, Shutdown};
+
+/// The id of the next connection accepted.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The messages queued for a connection before the client is deemed too slow and the connection
+/// is closed.
+const QUEUE_SIZE: usize = 1024;
+
+tokio::task_local! {
+    /// The connection of the call being handled.
+    static CURRENT: Connection;
+}
+
+/// A message queued for the task writing to a connection.
+#[derive(Clone, Debug)]
+pub(crate) enum Outgoing {
+    /// A JSON-RPC message.
+    Message(Bytes),
+    /// A WebSocket control frame, its opcode and payload.
+    Control(u8, Bytes),
+    /// Close the connection once the messages queued before are written.
+    Close,
+    /// The queue overflowed, close the connection without writing the messages queued.
+    Overflow,
+}
+
+/// The messages queued for the task writing to a connection.
+pub(crate) struct Queue {
+    messages: mpsc::Receiver<Outgoing>,
+    overflow: CancellationToken,
+}
+
+impl Queue {
+    /// The next message to write, [`Outgoing::Overflow`] once the queue has overflowed.
+    pub(crate) async fn recv(&mut self) -> Option<Outgoing> {
+        if self.overflow.is_cancelled() {
+            return Some(Outgoing::Overflow);
+        }
+        match select(
+            Box::pin(self.messages.recv()),
+            Box::pin(self.overflow.cancelled()),
+        )
+        .await
+        {
+            Either::Left((message, _)) => message,
+            Either::Right(_) => Some(Outgoing::Overflow),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutgoingNotification<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    params: &'a Value,
+}
+
+/// Encode a notification of `method` with `params`.
+pub(crate) fn notification(method: &str, params: &Value) -> io::Result<Outgoing> {
+    let notification = OutgoingNotification {
+        jsonrpc: "2.0",
+        method,
+        params,
+    };
+    let message = serde_json::to_vec(&notification)?;
+    Ok(Outgoing::Message(Bytes::from(message)))
+}
+
+/// A handle to a connection to a server which carries messages both ways, such as a WebSocket or
+/// a TCP connection.
+///
+/// Handlers get the connection of the call they handle with [`Connection::current`], and may
+/// keep it to push notifications to the client later. State kept for the lifetime of the
+/// connection, such as a session, is stored by type, see [`Connection::insert`].
+#[derive(Clone)]
+pub struct Connection {
+    id: u64,
+    peer_addr: Option<SocketAddr>,
+    outgoing: mpsc::Sender<Outgoing>,
+    overflow: CancellationToken,
+    state: Arc<Mutex<Extensions>>,
+    shutdown: Shutdown,
+    // The calls in flight
+    tasks: TaskTracker,
+    slots: Slots,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("id", &self.id)
+            .field("peer_addr", &self.peer_addr)
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl Connection {
+    /// Creates a connection, along with the queue of the messages to write to it.
+    pub(crate) fn new(
+        peer_addr: Option<SocketAddr>,
+        shutdown: Shutdown,
+        slots: Slots,
+    ) -> (Self, Queue) {
+        let (outgoing, messages) = mpsc::channel(QUEUE_SIZE);
+        let overflow = CancellationToken::new();
+        let connection = Connection {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            outgoing,
+            overflow: overflow.clone(),
+            state: Arc::default(),
+            shutdown,
+            tasks: TaskTracker::new(),
+            slots,
+        };
+        (connection, Queue { messages, overflow })
+    }
+
+    /// The connection of the call being handled, `None` outside a handler or if the call was
+    /// made over plain HTTP.
+    pub fn current() -> Option<Connection> {
+        CURRENT.try_with(Connection::clone).ok()
+    }
+
+    /// Run `future` on its own task with `self` as the current connection.
+    pub(crate) fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let future = CURRENT.scope(self.clone(), future);
+        self.shutdown.spawn(self.tasks.track_future(future));
+    }
+
+    /// The slots of the calls of the connection, see [`ConcurrencyLimit::per_connection`].
+    ///
+    /// [`ConcurrencyLimit::per_connection`]: super::ConcurrencyLimit::per_connection
+    pub(crate) fn slots(&self) -> &Slots {
+        &self.slots
+    }
+
+    /// Spawn `future` on its own task, with the current connection, if any, as its own.
+    pub(crate) fn spawn_current<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::in_current_span(future);
+        match Connection::current() {
+            Some(connection) => tokio::spawn(CURRENT.scope(connection, future)),
+            None => tokio::spawn(future),
+        }
+    }
+
+    /// Wait for the calls in flight to be answered, then send the shutdown notification, if any,
+    /// and `close`.
+    pub(crate) async fn drain(&self, close: Outgoing) {
+        self.finish().await;
+        if let Some((method, params)) = self.shutdown.farewell() {
+            let _ = self.notify(method, params.clone());
+        }
+        let _ = self.send(close);
+    }
+
+    /// Wait for the calls in flight to be answered, then send `close`, so that handles kept by
+    /// handlers see the connection as closed.
+    pub(crate) async fn close(&self, close: Outgoing) {
+        self.finish().await;
+        let _ = self.send(close);
+    }
+
+    async fn finish(&self) {
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+
+    /// Identifies the connection among those accepted by the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the client, if connected over TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Store `value` as the connection's state of type `T`, returning the previous value.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.state.lock().unwrap().insert(value)
+    }
+
+    /// Returns a copy of the connection's state of type `T`, if any.
+    ///
+    /// To share mutable state between the calls of a connection, store it behind a lock.
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.state.lock().unwrap().get().cloned()
+    }
+
+    /// Remove the connection's state of type `T`, returning it.
+    pub fn remove<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.state.lock().unwrap().remove()
+    }
+
+    /// Returns `true` once the connection has closed.
+    pub fn is_closed(&self) -> bool {
+        self.outgoing.is_closed() || self.overflow.is_cancelled()
+    }
+
+    /// Run `future` to completion, unless the queue overflows first, `None` if so.
+    pub(crate) async fn unless_overflowed<F: Future>(&self, future: F) -> Option<F::Output> {
+        match select(Box::pin(future), Box::pin(self.overflow.cancelled())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Send a notification of `method` with `params` to the client.
+    pub fn notify(&self, method: &str, params: Value) -> io::Result<()> {
+        self.send(notification(method, &params)?)
+    }
+
+    /// Queue `message` for the writer, failing once the connection has closed.
+    ///
+    /// A client too slow to keep up with the messages sent to it has its connection closed
+    /// rather than have them pile up.
+    pub(crate) fn send(&self, message: Outgoing) -> io::Result<()> {
+        if self.overflow.is_cancelled() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "connection closed",
+            ));
+        }
+        match self.outgoing.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflow.cancel();
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "connection queue full",
+                ))
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "connection closed",
+            )),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use hyper::{
+    header::{
+        HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+        AUTHORIZATION, CONTENT_TYPE, VARY,
+    },
+    HeaderMap,
+};
+
+/// Cross-origin resource sharing, letting web pages of other origins call a server from the
+/// browser.
+///
+/// Preflight requests are answered by the server. WebSocket upgrades from origins which aren't
+/// allowed are rejected with `403 Forbidden`, browsers don't apply CORS to WebSockets.
+#[derive(Clone, Debug)]
+pub struct Cors {
+    // Any origin is allowed if `None`
+    origins: Option<Vec<HeaderValue>>,
+    headers: Vec<HeaderName>,
+    max_age: Option<Duration>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: None,
+            headers: vec![CONTENT_TYPE, AUTHORIZATION],
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+impl Cors {
+    /// Creates the default policy, allowing any origin to send the `Content-Type` and
+    /// `Authorization` headers, without credentials.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the origins allowed, such as `https://app.example.com`, in place of any origin.
+    ///
+    /// Origins which aren't valid header values are ignored.
+    pub fn origins<I, S>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let origins = origins
+            .into_iter()
+            .filter_map(|origin| HeaderValue::from_str(origin.as_ref()).ok())
+            .collect();
+        self.origins = Some(origins);
+        self
+    }
+
+    /// Sets the request headers allowed.
+    pub fn headers<I: IntoIterator<Item = HeaderName>>(mut self, headers: I) -> Self {
+        self.headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Sets how long browsers may cache the answer to a preflight request.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets whether browsers may send credentials, such as cookies, with requests.
+    ///
+    /// Credentials require the origins allowed to be set with [`origins`], any origin could
+    /// otherwise act on behalf of the users. Without them, every cross-origin request is refused.
+    ///
+    /// [`origins`]: Cors::origins
+    pub fn credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Returns `true` if requests from `origin` are allowed.
+    pub(crate) fn allows(&self, origin: &HeaderValue) -> bool {
+        match &self.origins {
+            Some(origins) => origins.contains(origin),
+            None => !self.credentials,
+        }
+    }
+
+    /// Add the headers allowing a request from `origin`, if it is allowed, to its response.
+    pub(crate) fn apply(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        if !self.allows(origin) {
+            return;
+        }
+        // Only listed origins are allowed with credentials
+        let allowed = match self.origins.is_none() {
+            true => HeaderValue::from_static("*"),
+            false => origin.clone(),
+        };
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Add the headers answering a preflight request from `origin` to its response.
+    pub(crate) fn preflight(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        self.apply(origin, headers);
+        if !self.allows(origin) {
+            return;
+        }
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("POST"),
+        );
+        if !self.headers.is_empty() {
+            let allowed: Vec<_> = self.headers.iter().map(HeaderName::as_str).collect();
+            let allowed = allowed.join(", ");
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                allowed.parse().unwrap(), // This is safe
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: HeaderValue = HeaderValue::from_static("https://app.example");
+
+    fn preflight(cors: &Cors, origin: HeaderValue) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        cors.preflight(&origin, &mut headers);
+        headers
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let cors = Cors::new().max_age(Duration::from_secs(600));
+        let headers = preflight(&cors, ORIGIN);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "POST");
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, authorization"
+        );
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(headers[VARY], "origin");
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[test]
+    fn refuses_other_origins() {
+        let cors = Cors::new().origins(["https://app.example"]);
+        assert_eq!(
+            preflight(&cors, ORIGIN)[ACCESS_CONTROL_ALLOW_ORIGIN],
+            ORIGIN
+        );
+
+        let headers = preflight(&cors, HeaderValue::from_static("https://evil.example"));
+        assert_eq!(headers[VARY], "origin");
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_METHODS));
+    }
+
+    #[test]
+    fn allows_credentials_from_listed_origins() {
+        let cors = Cors::new()
+            .origins(["https://app.example"])
+            .credentials(true);
+        let mut headers = HeaderMap::new();
+        cors.apply(&ORIGIN, &mut headers);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], ORIGIN);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let evil = HeaderValue::from_static("https://evil.example");
+        assert!(!cors.allows(&evil));
+    }
+
+    #[test]
+    fn refuses_credentials_from_any_origin() {
+        let cors = Cors::new().credentials(true);
+        assert!(!cors.allows(&ORIGIN));
+        let mut headers = HeaderMap::new();
+        cors.apply(&ORIGIN, &mut headers);
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        assert!(!preflight(&cors, ORIGIN).contains_key(ACCESS_CONTROL_ALLOW_METHODS));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{convert::Infallible, error, fmt, io};
+
+use crate::objects::RpcError;
+
+/// Conversion of the errors returned by handlers into the error objects of their responses.
+///
+/// This is implemented for anything convertible into an [`RpcError`], and for common error types,
+/// which fail with the "Server error" code `-32000`. Their messages may reveal internal details,
+/// so a generic message is sent and theirs is logged with `tracing`, when enabled. Use
+/// [`rpc_error!`] to give each variant of an error enum its own code and message.
+///
+/// [`rpc_error!`]: crate::rpc_error
+pub trait IntoRpcError {
+    /// Convert into an error object.
+    fn into_rpc_error(self) -> RpcError;
+}
+
+impl<E: Into<RpcError>> IntoRpcError for E {
+    fn into_rpc_error(self) -> RpcError {
+        self.into()
+    }
+}
+
+/// The error object answering a call which failed with `err`, logging `err` rather than sending
+/// it.
+pub(crate) fn hidden(err: &dyn fmt::Display) -> RpcError {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = %err, "call failed");
+    #[cfg(not(feature = "tracing"))]
+    let _ = err;
+    RpcError::server_error("Server error")
+}
+
+impl From<io::Error> for RpcError {
+    fn from(err: io::Error) -> Self {
+        hidden(&err)
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> Self {
+        hidden(&err)
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        RpcError::server_error(message)
+    }
+}
+
+impl From<&str> for RpcError {
+    fn from(message: &str) -> Self {
+        RpcError::server_error(message)
+    }
+}
+
+impl From<Box<dyn error::Error + Send + Sync>> for RpcError {
+    fn from(err: Box<dyn error::Error + Send + Sync>) -> Self {
+        match err.downcast::<RpcError>() {
+            Ok(err) => *err,
+            Err(err) => hidden(&err),
+        }
+    }
+}
+
+impl From<Infallible> for RpcError {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+/// Implement [`IntoRpcError`] for an error enum, giving each variant a code.
+///
+/// The message of the error object is the [`Display`] of the error. An optional `data` closure
+/// attaches data to every error object.
+///
+/// ```
+/// use std::fmt;
+///
+/// use async_json_rpc::{objects::RpcError, rpc_error};
+/// use serde_json::json;
+///
+/// #[derive(Debug)]
+/// enum AccountError {
+///     NotFound(u64),
+///     Locked { until: u64 },
+///     Corrupted,
+/// }
+///
+/// impl fmt::Display for AccountError {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         match self {
+///             AccountError::NotFound(id) => write!(f, "account {} not found", id),
+///             AccountError::Locked { until } => write!(f, "account locked until {}", until),
+///             AccountError::Corrupted => f.write_str("account corrupted"),
+///         }
+///     }
+/// }
+///
+/// rpc_error! {
+///     AccountError {
+///         AccountError::NotFound(_) => -32001,
+///         AccountError::Locked { .. } => -32002,
+///         _ => -32000,
+///     }
+///     data = |err: &AccountError| json!({ "kind": format!("{:?}", err) })
+/// }
+///
+/// let err = RpcError::from(AccountError::NotFound(7));
+/// assert_eq!(err.code, -32001);
+/// assert_eq!(err.message, "account 7 not found");
+/// assert_eq!(RpcError::from(AccountError::Corrupted).code, -32000);
+/// ```
+///
+/// [`Display`]: std::fmt::Display
+#[macro_export]
+macro_rules! rpc_error {
+    ($ty:ty { $($pat:pat => $code:expr),+ $(,)? } $(data = $data:expr)?) => {
+        impl ::core::convert::From<$ty> for $crate::objects::RpcError {
+            #[allow(unreachable_patterns)]
+            fn from(err: $ty) -> Self {
+                let code = match &err {
+                    $($pat => $code,)+
+                };
+                let data: ::core::option::Option<$crate::__private::serde_json::Value> =
+                    ::core::option::Option::None;
+                $(let data = ::core::option::Option::Some(($data)(&err));)?
+                $crate::objects::RpcError {
+                    code,
+                    message: ::std::string::ToString::to_string(&err),
+                    data,
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_internal_errors() {
+        let server_error = RpcError::server_error("Server error");
+        let err = io::Error::new(io::ErrorKind::NotFound, "/etc/secrets missing");
+        assert_eq!(err.into_rpc_error(), server_error);
+        let err = serde_json::from_str::<u8>("-1").unwrap_err();
+        assert_eq!(err.into_rpc_error(), server_error);
+        let err: Box<dyn error::Error + Send + Sync> = "database unreachable".into();
+        assert_eq!(err.into_rpc_error(), server_error);
+    }
+
+    #[test]
+    fn converts_error_objects() {
+        let invalid = RpcError::invalid_params().with_data("missing b");
+        assert_eq!(invalid.clone().into_rpc_error(), invalid);
+        // Error objects are found within boxed errors
+        let err: Box<dyn error::Error + Send + Sync> = Box::new(invalid.clone());
+        assert_eq!(err.into_rpc_error(), invalid);
+
+        assert_eq!(
+            "no such block".into_rpc_error(),
+            RpcError::server_error("no such block")
+        );
+        let message = "no such block".to_owned();
+        assert_eq!(
+            message.into_rpc_error(),
+            RpcError::server_error("no such block")
+        );
+    }
+}
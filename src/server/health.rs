@@ -0,0 +1,230 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use futures_core::{future::BoxFuture, Future};
+use futures_util::future;
+use serde_json::{json, Map, Value};
+
+use crate::objects::RpcError;
+
+/// The longest a check may take by default before it fails.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A check of a service, failing with why.
+type CheckFn = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+/// The checks behind the `system.health` and `system.ready` methods, see [`Router::health`].
+///
+/// Health checks tell whether the service is alive, such as whether its workers run, readiness
+/// checks whether it may serve calls, such as whether its database is reachable. The service is
+/// ready when every check passes, health checks included. Checks run concurrently on each probe.
+///
+/// Both methods return a report of the checks, such as
+/// `{"status": "fail", "checks": {"database": {"status": "fail", "error": "refused"}}}`, as
+/// their result when passing and as the data of their error otherwise.
+///
+/// [`Router::health`]: super::Router::health
+#[derive(Clone)]
+pub struct Health {
+    checks: Vec<Check>,
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+struct Check {
+    name: String,
+    // Whether the check is of readiness only
+    readiness: bool,
+    check: CheckFn,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            checks: Vec::new(),
+            timeout: CHECK_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.checks.iter().map(|check| &check.name).collect();
+        f.debug_struct("Health")
+            .field("checks", &names)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Health {
+    /// Creates checks which always pass.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the health check `name`, which the service must pass to be healthy and ready.
+    pub fn check<N, F, Fut, E>(self, name: N, check: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        self.add(name.into(), false, check)
+    }
+
+    /// Add the readiness check `name`, which the service must pass to be ready.
+    pub fn ready_check<N, F, Fut, E>(self, name: N, check: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        self.add(name.into(), true, check)
+    }
+
+    /// Sets the longest a check may take before it fails, 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn add<F, Fut, E>(mut self, name: String, readiness: bool, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let check = move || {
+            let checking = check();
+            Box::pin(async move { checking.await.map_err(|err| err.to_string()) })
+                as BoxFuture<'static, _>
+        };
+        self.checks.push(Check {
+            name,
+            readiness,
+            check: Arc::new(check),
+        });
+        self
+    }
+
+    /// Run the health checks, and the readiness checks if `ready`, returning whether they all
+    /// passed and their report.
+    pub(crate) fn probe(&self, ready: bool) -> BoxFuture<'static, (bool, Value)> {
+        let timeout = self.timeout;
+        let checks = self
+            .checks
+            .iter()
+            .filter(|check| ready || !check.readiness)
+            .map(|check| {
+                let name = check.name.clone();
+                let checking = (check.check)();
+                async move {
+                    let outcome = match tokio::time::timeout(timeout, checking).await {
+                        Ok(outcome) => outcome,
+                        Err(_) => Err("timed out".to_owned()),
+                    };
+                    (name, outcome)
+                }
+            });
+        let checks = future::join_all(checks);
+        Box::pin(async move {
+            let mut passed = true;
+            let mut report = Map::new();
+            for (name, outcome) in checks.await {
+                let status = match outcome {
+                    Ok(()) => json!({ "status": "ok" }),
+                    Err(err) => {
+                        passed = false;
+                        json!({ "status": "fail", "error": err })
+                    }
+                };
+                report.insert(name, status);
+            }
+            let status = if passed { "ok" } else { "fail" };
+            (passed, json!({ "status": status, "checks": report }))
+        })
+    }
+
+    /// Run the checks as the method `system.health`, or `system.ready` if `ready`.
+    pub(crate) fn call(&self, ready: bool) -> BoxFuture<'static, Result<Value, RpcError>> {
+        let probing = self.probe(ready);
+        Box::pin(async move {
+            match probing.await {
+                (true, report) => Ok(report),
+                (false, report) if ready => {
+                    Err(RpcError::server_error("Not ready").with_data(report))
+                }
+                (false, report) => Err(RpcError::server_error("Unhealthy").with_data(report)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future;
+
+    use super::*;
+    use crate::{
+        objects::{Request, Response},
+        server::Router,
+    };
+
+    async fn call(health: Health, method: &str) -> Response {
+        let router = Router::new().health(health).into_served();
+        let request = Request::build().method(method).id(1).finish().unwrap();
+        router.serve(request).await
+    }
+
+    #[tokio::test]
+    async fn reports_the_checks() {
+        let health = Health::new()
+            .check("workers", || async { Ok::<_, String>(()) })
+            .check("queue", || async { Err("full") });
+
+        let response = call(health, "system.health").await;
+        let err = response.error.unwrap();
+        assert_eq!(err.message, "Unhealthy");
+        assert_eq!(
+            err.data.unwrap(),
+            json!({
+                "status": "fail",
+                "checks": {
+                    "workers": { "status": "ok" },
+                    "queue": { "status": "fail", "error": "full" },
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn readiness_checks_only_affect_readiness() {
+        let health = Health::new()
+            .check("workers", || async { Ok::<_, String>(()) })
+            .ready_check("database", || async { Err("refused") });
+
+        let response = call(health.clone(), "system.health").await;
+        let report = response.result.unwrap();
+        assert_eq!(report["status"], "ok");
+        assert!(report["checks"].get("database").is_none());
+
+        let response = call(health, "system.ready").await;
+        let err = response.error.unwrap();
+        assert_eq!(err.message, "Not ready");
+        let report = err.data.unwrap();
+        assert_eq!(report["checks"]["workers"]["status"], "ok");
+        assert_eq!(report["checks"]["database"]["error"], "refused");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn checks_time_out() {
+        let health = Health::new()
+            .check("stuck", future::pending::<Result<(), String>>)
+            .timeout(Duration::from_secs(1));
+        let (passed, report) = health.probe(false).await;
+        assert!(!passed);
+        assert_eq!(report["checks"]["stuck"]["error"], "timed out");
+    }
+}
@@ -0,0 +1,628 @@
+use std::{convert::Infallible, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_core::future::BoxFuture;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{
+        ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN, RETRY_AFTER,
+    },
+    service::service_fn,
+    Method, StatusCode,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, ToSocketAddrs},
+};
+
+#[cfg(feature = "tls-rustls")]
+use super::tls::{Acceptor, ServerTlsConfig, TlsError};
+use super::{
+    auth::Authentication, concurrency::Slots, cors::Cors, rate_limit::Refusals, trace::RemoteTrace,
+    ws, Origin, Router, Shutdown,
+};
+
+type HttpResponse = hyper::Response<Full<Bytes>>;
+
+/// A JSON-RPC server over HTTP, answering the requests POSTed to any path.
+///
+/// Bodies made only of notifications are answered with `204 No Content`.
+///
+/// Clients may also upgrade a connection to a WebSocket, carrying calls and notifications both
+/// ways. Handlers get the WebSocket of the call they handle with [`Connection::current`], to push
+/// notifications to the client.
+///
+/// [`Connection::current`]: super::connection::Connection::current
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    shared: Shared,
+    #[cfg(feature = "tls-rustls")]
+    tls: Option<Acceptor>,
+}
+
+/// The configuration of a server, shared by its requests.
+#[derive(Debug)]
+struct Shared {
+    router: Arc<Router>,
+    shutdown: Shutdown,
+    authentication: Option<Authentication>,
+    cors: Option<Cors>,
+}
+
+impl Server {
+    /// Creates a server listening on `addr`, serving `router`.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, router: Router) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr).await?,
+            shared: Shared {
+                router: router.into_served(),
+                shutdown: Shutdown::new(),
+                authentication: None,
+                cors: None,
+            },
+            #[cfg(feature = "tls-rustls")]
+            tls: None,
+        })
+    }
+
+    /// Shut the server down gracefully once `shutdown` is triggered.
+    ///
+    /// Connections stop accepting requests, HTTP/2 connections are sent a GOAWAY frame.
+    pub fn graceful_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shared.shutdown = shutdown;
+        self
+    }
+
+    /// Reject the requests without valid credentials, see [`Authentication`].
+    pub fn authentication(mut self, authentication: Authentication) -> Self {
+        self.shared.authentication = Some(authentication);
+        self
+    }
+
+    /// Allow web pages of other origins to call the server, see [`Cors`].
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.shared.cors = Some(cors);
+        self
+    }
+
+    /// Serve HTTPS, and WebSockets over TLS, using `config`.
+    ///
+    /// Clients may choose HTTP/2 or HTTP/1.1 during the handshake.
+    #[cfg(feature = "tls-rustls")]
+    pub fn tls(mut self, config: &ServerTlsConfig) -> Result<Self, TlsError> {
+        self.tls = Some(config.acceptor(&["h2", "http/1.1"])?);
+        Ok(self)
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections and serve them, each on its own task, until shut down.
+    ///
+    /// Connections are served over HTTP/1.1 or HTTP/2, as the client chooses. Errors accepting a
+    /// connection, such as running out of file descriptors, are retried after a pause.
+    pub async fn serve(self) {
+        let shared = Arc::new(self.shared);
+        let shutdown = &shared.shutdown;
+        while let Some(accepted) = shutdown.unless_triggered(self.listener.accept()).await {
+            let (stream, peer_addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+
+            let shared = shared.clone();
+            #[cfg(feature = "tls-rustls")]
+            if let Some(tls) = self.tls.clone() {
+                shutdown.spawn(async move {
+                    if let Ok(stream) = tls.accept(stream).await {
+                        serve_connection(stream, shared, peer_addr).await;
+                    }
+                });
+                continue;
+            }
+            shutdown.spawn(serve_connection(stream, shared, peer_addr));
+        }
+        shutdown.drain().await;
+    }
+}
+
+/// Serve the requests made over a connection until it closes, or the server is shut down.
+async fn serve_connection<T>(io: T, shared: Arc<Shared>, peer_addr: SocketAddr)
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let shutdown = shared.shutdown.clone();
+    let _connected = shared.router.connected();
+    // Plain HTTP requests of the connection share its slots
+    let slots = Arc::new(shared.router.connection_slots());
+    let service =
+        service_fn(move |request| respond(shared.clone(), request, peer_addr, slots.clone()));
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    let mut connection = Box::pin(connection);
+    if shutdown
+        .unless_triggered(connection.as_mut())
+        .await
+        .is_none()
+    {
+        // Requests in flight are still answered
+        connection.as_mut().graceful_shutdown();
+        let _ = connection.await;
+    }
+}
+
+/// Respond to an HTTP request.
+async fn respond(
+    shared: Arc<Shared>,
+    request: hyper::Request<Incoming>,
+    peer_addr: SocketAddr,
+    slots: Arc<Slots>,
+) -> Result<HttpResponse, Infallible> {
+    let (cors, origin) = match (&shared.cors, request.headers().get(ORIGIN)) {
+        (Some(cors), Some(origin)) => (cors, origin.clone()),
+        _ => return Ok(route(&shared, request, peer_addr, &slots).await),
+    };
+    if request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        let mut response = status(StatusCode::NO_CONTENT);
+        cors.preflight(&origin, response.headers_mut());
+        return Ok(response);
+    }
+    let mut response = match ws::is_upgrade(&request) && !cors.allows(&origin) {
+        true => status(StatusCode::FORBIDDEN),
+        false => route(&shared, request, peer_addr, &slots).await,
+    };
+    cors.apply(&origin, response.headers_mut());
+    Ok(response)
+}
+
+/// Respond to an HTTP request, once allowed by the CORS policy.
+async fn route(
+    shared: &Arc<Shared>,
+    request: hyper::Request<Incoming>,
+    peer_addr: SocketAddr,
+    slots: &Slots,
+) -> HttpResponse {
+    // Probes are answered without credentials, which orchestrators don't have
+    if request.method() == Method::GET && !ws::is_upgrade(&request) {
+        let ready = match request.uri().path() {
+            "/health" => Some(false),
+            "/ready" => Some(true),
+            _ => None,
+        };
+        if let Some(probing) = ready.and_then(|ready| shared.router.probe(ready)) {
+            return probe(probing).await;
+        }
+    }
+    if let Some(authentication) = &shared.authentication {
+        if !authentication.authenticate(request.headers()).await {
+            let mut response = status(StatusCode::UNAUTHORIZED);
+            authentication.challenge(response.headers_mut());
+            return response;
+        }
+    }
+    if ws::is_upgrade(&request) {
+        let router = shared.router.clone();
+        let shutdown = shared.shutdown.clone();
+        return ws::upgrade(request, router, peer_addr, shutdown);
+    }
+    if request.method() != Method::POST {
+        let mut response = status(StatusCode::METHOD_NOT_ALLOWED);
+        response
+            .headers_mut()
+            .insert(ALLOW, "POST".parse().unwrap()); // This is safe
+        return response;
+    }
+    let trace = RemoteTrace::from_headers(request.headers());
+
+    // Bodies are never buffered beyond the limit
+    let limit = shared.router.message_limit();
+    let len = request.headers().get(CONTENT_LENGTH);
+    let len = len.and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if len.is_some_and(|len| len > limit as u64) {
+        return status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let body = match Limited::new(request.into_body(), limit).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) if err.is::<LengthLimitError>() => return status(StatusCode::PAYLOAD_TOO_LARGE),
+        Err(_) => return status(StatusCode::BAD_REQUEST),
+    };
+
+    // Notifications are answered without a body
+    let refusals = Refusals::default();
+    let origin = Origin {
+        slots,
+        peer_addr: Some(peer_addr),
+        trace: trace.as_ref(),
+        refusals: Some(&refusals),
+    };
+    let batch = body.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[');
+    let answer = super::handle(&shared.router, &body, origin).await;
+    let mut response = match answer {
+        Some(answer) => {
+            let mut response = hyper::Response::new(Full::new(Bytes::from(answer)));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "application/json".parse().unwrap()); // This is safe
+            response
+        }
+        None => status(StatusCode::NO_CONTENT),
+    };
+    // A single call refused is refused with its request, the calls of a batch each in their own
+    // response
+    if let Some(retry_after) = refusals.retry_after() {
+        if !batch {
+            *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        }
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, retry_after.into());
+    }
+    response
+}
+
+/// The report of the health or readiness checks, with status 503 if one failed.
+async fn probe(probing: BoxFuture<'static, (bool, Value)>) -> HttpResponse {
+    let (passed, report) = probing.await;
+    let mut response = hyper::Response::new(Full::new(Bytes::from(report.to_string())));
+    if !passed {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap()); // This is safe
+    response
+}
+
+/// An empty response with `status`.
+fn status(status: StatusCode) -> HttpResponse {
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{
+        objects::RpcError,
+        server::{auth::Credentials, Health, RateLimit},
+    };
+
+    /// The handshake key of the example of RFC 6455, section 1.3.
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    /// Serve `router` on a local port, with the CORS `cors` policy if any.
+    async fn serve(router: Router, cors: Option<Cors>) -> SocketAddr {
+        let mut server = Server::bind("127.0.0.1:0", router).await.unwrap();
+        if let Some(cors) = cors {
+            server = server.cors(cors);
+        }
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+        addr
+    }
+
+    /// Send a WebSocket upgrade request with `headers` to `addr`, returning the head of the
+    /// response.
+    async fn upgrade(addr: SocketAddr, headers: &[&str]) -> String {
+        let mut request = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+                           Upgrade: websocket\r\n"
+            .to_owned();
+        for header in headers {
+            request += header;
+            request += "\r\n";
+        }
+        request += "\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        // The connection stays open after the head, whether upgraded or not
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            assert_eq!(stream.read(&mut byte).await.unwrap(), 1);
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap().to_ascii_lowercase()
+    }
+
+    /// Post `body` to `addr`, returning the head and body of the response.
+    async fn post(addr: SocketAddr, body: &str) -> (String, String) {
+        post_with(addr, &[], body).await
+    }
+
+    /// Post `body` with the extra `headers` to `addr`, returning the head and body of the
+    /// response.
+    async fn post_with(addr: SocketAddr, headers: &[&str], body: &str) -> (String, String) {
+        let mut request = "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                           Content-Type: application/json\r\n"
+            .to_owned();
+        for header in headers {
+            request += header;
+            request += "\r\n";
+        }
+        request += &format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.to_ascii_lowercase(), body.to_owned())
+    }
+
+    fn limited() -> Router {
+        let limit = RateLimit::new(1, Duration::from_secs(60));
+        Router::new()
+            .register("limited", |_: Value| async { Ok::<_, RpcError>(true) })
+            .rate_limit("limited", limit)
+    }
+
+    #[tokio::test]
+    async fn refuses_rate_limited_calls() {
+        let addr = serve(limited(), None).await;
+        let call = r#"{"jsonrpc":"2.0","method":"limited","id":1}"#;
+        let (head, _) = post(addr, call).await;
+        assert!(head.starts_with("http/1.1 200 "), "{}", head);
+
+        let (head, body) = post(addr, call).await;
+        assert!(head.starts_with("http/1.1 429 "), "{}", head);
+        assert!(head.contains("\r\nretry-after: 60"), "{}", head);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["error"]["code"], RpcError::rate_limited().code);
+        assert_eq!(response["error"]["data"]["retry_after"], 60);
+    }
+
+    #[tokio::test]
+    async fn refuses_rate_limited_notifications() {
+        let addr = serve(limited(), None).await;
+        let notification = r#"{"jsonrpc":"2.0","method":"limited"}"#;
+        let (head, _) = post(addr, notification).await;
+        assert!(head.starts_with("http/1.1 204 "), "{}", head);
+
+        let (head, body) = post(addr, notification).await;
+        assert!(head.starts_with("http/1.1 429 "), "{}", head);
+        assert!(head.contains("\r\nretry-after: 60"), "{}", head);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn answers_batches_with_refused_calls() {
+        let addr = serve(limited(), None).await;
+        let batch = r#"[{"jsonrpc":"2.0","method":"limited","id":1},
+                        {"jsonrpc":"2.0","method":"limited","id":2}]"#;
+        let (head, body) = post(addr, batch).await;
+        // Each call is answered in the batch
+        assert!(head.starts_with("http/1.1 200 "), "{}", head);
+        assert!(head.contains("\r\nretry-after: 60"), "{}", head);
+        let responses: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(responses[0]["result"], true);
+        assert_eq!(responses[1]["error"]["code"], RpcError::rate_limited().code);
+    }
+
+    /// Get `path` from `addr`, returning the head and body of the response.
+    async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.to_ascii_lowercase(), body.to_owned())
+    }
+
+    /// Serve a `ping` method, accepting the basic credentials `user:secret` or the bearer token
+    /// `token`.
+    async fn authenticated() -> SocketAddr {
+        authenticated_router(Router::new()).await
+    }
+
+    /// Serve `router` and a `ping` method, authenticated like [`authenticated`].
+    async fn authenticated_router(router: Router) -> SocketAddr {
+        let router = router.register("ping", |_: Value| async { Ok::<_, RpcError>(true) });
+        let authentication = Authentication::validator(|credentials| async move {
+            match credentials {
+                Credentials::Basic { user, password } => user == "user" && *password == "secret",
+                Credentials::Bearer(token) => *token == "token",
+            }
+        })
+        .realm("tests");
+        let server = Server::bind("127.0.0.1:0", router).await.unwrap();
+        let server = server.authentication(authentication);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+        addr
+    }
+
+    #[tokio::test]
+    async fn challenges_requests_without_valid_credentials() {
+        let addr = authenticated().await;
+        let call = r#"{"jsonrpc":"2.0","method":"ping","id":1}"#;
+        for headers in [
+            &[][..],
+            &["Authorization: Basic dXNlcjp3cm9uZw=="],
+            &["Authorization: Bearer other"],
+            &["Authorization: Digest username=\"user\""],
+        ] {
+            let (head, body) = post_with(addr, headers, call).await;
+            assert!(head.starts_with("http/1.1 401 "), "{}", head);
+            assert!(
+                head.contains("\r\nwww-authenticate: basic realm=\"tests\"\r\n"),
+                "{}",
+                head
+            );
+            assert!(
+                head.contains("\r\nwww-authenticate: bearer realm=\"tests\"\r\n"),
+                "{}",
+                head
+            );
+            assert!(body.is_empty());
+        }
+
+        let head = upgrade(
+            addr,
+            &[
+                "Sec-WebSocket-Version: 13",
+                &format!("Sec-WebSocket-Key: {}", KEY),
+            ],
+        )
+        .await;
+        assert!(head.starts_with("http/1.1 401 "), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_credentials() {
+        let addr = authenticated().await;
+        let call = r#"{"jsonrpc":"2.0","method":"ping","id":1}"#;
+        for headers in [
+            &["Authorization: Basic dXNlcjpzZWNyZXQ="][..],
+            &["Authorization: bearer token"],
+        ] {
+            let (head, body) = post_with(addr, headers, call).await;
+            assert!(head.starts_with("http/1.1 200 "), "{}", head);
+            assert!(!head.contains("www-authenticate"), "{}", head);
+            let response: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(response["result"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_probes_without_credentials() {
+        let health = Health::new()
+            .check("workers", || async { Ok::<_, String>(()) })
+            .ready_check("database", || async { Err("refused") });
+        let addr = authenticated_router(Router::new().health(health)).await;
+
+        let (head, body) = get(addr, "/health").await;
+        assert!(head.starts_with("http/1.1 200 "), "{}", head);
+        assert!(
+            head.contains("\r\ncontent-type: application/json"),
+            "{}",
+            head
+        );
+        let report: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["checks"]["workers"]["status"], "ok");
+
+        let (head, body) = get(addr, "/ready").await;
+        assert!(head.starts_with("http/1.1 503 "), "{}", head);
+        let report: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["status"], "fail");
+        assert_eq!(report["checks"]["database"]["error"], "refused");
+
+        // Calls still require credentials
+        let call = r#"{"jsonrpc":"2.0","method":"system.health","id":1}"#;
+        let (head, _) = post(addr, call).await;
+        assert!(head.starts_with("http/1.1 401 "), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn only_answers_probes_when_served() {
+        let addr = serve(Router::new(), None).await;
+        let (head, _) = get(addr, "/health").await;
+        assert!(head.starts_with("http/1.1 405 "), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn upgrades_to_websockets() {
+        let addr = serve(Router::new(), None).await;
+        let head = upgrade(
+            addr,
+            &[
+                "Sec-WebSocket-Version: 13",
+                &format!("Sec-WebSocket-Key: {}", KEY),
+            ],
+        )
+        .await;
+        assert!(head.starts_with("http/1.1 101 "), "{}", head);
+        assert!(head.contains("sec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo=\r\n"));
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_handshakes() {
+        let addr = serve(Router::new(), None).await;
+        let key = format!("Sec-WebSocket-Key: {}", KEY);
+        for headers in [
+            &["Sec-WebSocket-Version: 13"][..],
+            &["Sec-WebSocket-Version: 8", &key],
+            &[&key],
+        ] {
+            let head = upgrade(addr, headers).await;
+            assert!(head.starts_with("http/1.1 400 "), "{}", head);
+            assert!(head.contains("sec-websocket-version: 13\r\n"), "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_preflight_requests() {
+        let cors = Cors::new()
+            .origins(["https://allowed.example"])
+            .credentials(true);
+        let addr = serve(Router::new(), Some(cors)).await;
+        let request = "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                       Origin: https://allowed.example\r\n\
+                       Access-Control-Request-Method: POST\r\n\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut head = String::new();
+        stream.read_to_string(&mut head).await.unwrap();
+        let head = head.to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 204 "), "{}", head);
+        assert!(
+            head.contains("\r\naccess-control-allow-origin: https://allowed.example\r\n"),
+            "{}",
+            head
+        );
+        assert!(
+            head.contains("\r\naccess-control-allow-credentials: true\r\n"),
+            "{}",
+            head
+        );
+        assert!(
+            head.contains("\r\naccess-control-allow-methods: post\r\n"),
+            "{}",
+            head
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_upgrades_from_other_origins() {
+        let cors = Cors::new().origins(["https://allowed.example"]);
+        let addr = serve(Router::new(), Some(cors)).await;
+        let handshake = [
+            "Sec-WebSocket-Version: 13",
+            &format!("Sec-WebSocket-Key: {}", KEY),
+        ];
+
+        let other = [&handshake[..], &["Origin: https://other.example"]].concat();
+        let head = upgrade(addr, &other).await;
+        assert!(head.starts_with("http/1.1 403 "), "{}", head);
+
+        let allowed = [&handshake[..], &["Origin: https://allowed.example"]].concat();
+        let head = upgrade(addr, &allowed).await;
+        assert!(head.starts_with("http/1.1 101 "), "{}", head);
+    }
+}
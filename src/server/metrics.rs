@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::clients::latency::{Latency, LatencyWindow};
+
+/// The number of latency samples kept per method by default.
+const LATENCY_WINDOW: usize = 1024;
+
+// The names of the metrics reported to the recorder of the `metrics` crate
+#[cfg(feature = "metrics")]
+const CALLS: &str = "json_rpc_calls_total";
+#[cfg(feature = "metrics")]
+const ERRORS: &str = "json_rpc_call_errors_total";
+#[cfg(feature = "metrics")]
+const DURATION: &str = "json_rpc_call_duration_seconds";
+#[cfg(feature = "metrics")]
+const CONNECTIONS: &str = "json_rpc_connections";
+
+/// The calls of a method served, see [`Metrics::calls`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallStats {
+    /// The number of calls, notifications included.
+    pub calls: u64,
+    /// The number of calls which failed, by error code.
+    pub errors: HashMap<i32, u64>,
+}
+
+/// Measures the calls served by a router and the connections to its servers, see
+/// [`Router::metrics`].
+///
+/// Clones share their measurements, keep one to read them. Calls of the methods which aren't
+/// registered are counted together under the empty method name, so that clients can't grow the
+/// measurements without bound.
+///
+/// With the `metrics` feature, the calls and connections of every router are also reported to
+/// the recorder installed for the [`metrics`](::metrics) crate, whether measured here or not:
+/// the `json_rpc_calls_total` and `json_rpc_call_errors_total` counters, labelled by `method`
+/// and error `code`, the `json_rpc_call_duration_seconds` histogram of the handlers, labelled by
+/// `method`, and the `json_rpc_connections` gauge.
+///
+/// [`Router::metrics`]: super::Router::metrics
+#[derive(Clone, Debug)]
+pub struct Metrics(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    connections: AtomicUsize,
+    calls: Mutex<HashMap<String, CallStats>>,
+    latency: LatencyWindow,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::with_latency_window(LATENCY_WINDOW)
+    }
+}
+
+impl Metrics {
+    /// Creates measurements keeping the latency of the last 1024 calls to each method.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates measurements keeping the latency of the last `samples` calls to each method.
+    pub fn with_latency_window(samples: usize) -> Self {
+        Metrics(Arc::new(Inner {
+            connections: AtomicUsize::new(0),
+            calls: Mutex::default(),
+            latency: LatencyWindow::new(samples),
+        }))
+    }
+
+    /// The number of open connections, HTTP connections and WebSockets included.
+    pub fn connections(&self) -> usize {
+        self.0.connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the calls served, by method.
+    pub fn calls(&self) -> HashMap<String, CallStats> {
+        self.0.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the latency percentiles of the handlers of recent calls to `method`.
+    pub fn latency(&self, method: &str) -> Option<Latency> {
+        self.0.latency.get(method)
+    }
+
+    /// Returns the latency percentiles of the handlers of recent calls, by method.
+    pub fn latencies(&self) -> HashMap<String, Latency> {
+        self.0.latency.all()
+    }
+
+    /// Count a call of `method` failing with `error`, if any, which was handled in `latency`.
+    pub(crate) fn record(&self, method: &str, error: Option<i32>, latency: Option<Duration>) {
+        {
+            let mut calls = self.0.calls.lock().unwrap();
+            let stats = match calls.get_mut(method) {
+                Some(stats) => stats,
+                None => calls.entry(method.to_owned()).or_default(),
+            };
+            stats.calls += 1;
+            if let Some(code) = error {
+                *stats.errors.entry(code).or_default() += 1;
+            }
+        }
+        if let Some(latency) = latency {
+            self.0.latency.record(method, latency);
+        }
+    }
+}
+
+/// Report a call of `method` failing with `error`, if any, which was handled in `latency`, to
+/// the recorder of the `metrics` crate.
+#[cfg(feature = "metrics")]
+pub(crate) fn report(method: &str, error: Option<i32>, latency: Option<Duration>) {
+    let method = method.to_owned();
+    ::metrics::counter!(CALLS, "method" => method.clone()).increment(1);
+    if let Some(code) = error {
+        let code = code.to_string();
+        ::metrics::counter!(ERRORS, "method" => method.clone(), "code" => code).increment(1);
+    }
+    if let Some(latency) = latency {
+        ::metrics::histogram!(DURATION, "method" => method).record(latency);
+    }
+}
+
+/// Counts a connection as open until dropped, in `metrics` if any.
+pub(crate) struct Connected(Option<Metrics>);
+
+impl Connected {
+    pub(crate) fn new(metrics: Option<Metrics>) -> Self {
+        if let Some(metrics) = &metrics {
+            metrics.0.connections.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "metrics")]
+        ::metrics::gauge!(CONNECTIONS).increment(1.0);
+        Connected(metrics)
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.0 {
+            metrics.0.connections.fetch_sub(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "metrics")]
+        ::metrics::gauge!(CONNECTIONS).decrement(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        objects::{Response, RpcError},
+        server::Router,
+    };
+
+    fn response(error: Option<RpcError>) -> Response {
+        Response {
+            result: error.is_none().then_some(Value::Null),
+            error,
+            id: Value::from(1),
+            jsonrpc: Some("2.0".to_owned()),
+        }
+    }
+
+    fn router(metrics: Metrics) -> Router {
+        Router::new()
+            .register("known", |_: Value| async { Ok::<_, RpcError>(()) })
+            .metrics(metrics)
+    }
+
+    #[test]
+    fn counts_calls() {
+        let metrics = Metrics::new();
+        let router = router(metrics.clone());
+        let latency = Some(Duration::from_millis(5));
+        router.record("known", &response(None), latency);
+        router.record(
+            "known",
+            &response(Some(RpcError::invalid_params())),
+            latency,
+        );
+        router.record("other", &response(Some(RpcError::method_not_found())), None);
+
+        let calls = metrics.calls();
+        assert_eq!(calls["known"].calls, 2);
+        assert_eq!(calls["known"].errors[&RpcError::invalid_params().code], 1);
+        // Unregistered methods are counted together
+        assert_eq!(calls[""].calls, 1);
+        assert!(metrics.latency("known").is_some());
+        assert!(metrics.latency("").is_none());
+    }
+
+    #[test]
+    fn counts_connections() {
+        let metrics = Metrics::new();
+        let router = router(metrics.clone());
+        let first = router.connected();
+        let second = router.connected();
+        assert_eq!(metrics.connections(), 2);
+        drop((first, second));
+        assert_eq!(metrics.connections(), 0);
+    }
+
+    #[cfg(feature = "metrics")]
+    mod recorder {
+        use std::sync::{Arc, Mutex};
+
+        use ::metrics::{
+            Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+            Recorder, SharedString, Unit,
+        };
+
+        use super::*;
+
+        /// Sums the values reported, by metric name and labels.
+        #[derive(Clone, Default)]
+        struct Sums(Arc<Mutex<HashMap<String, f64>>>);
+
+        struct Handle {
+            key: String,
+            sums: Sums,
+        }
+
+        impl Handle {
+            fn add(&self, value: f64) {
+                *self
+                    .sums
+                    .0
+                    .lock()
+                    .unwrap()
+                    .entry(self.key.clone())
+                    .or_default() += value;
+            }
+        }
+
+        impl CounterFn for Handle {
+            fn increment(&self, value: u64) {
+                self.add(value as f64)
+            }
+
+            fn absolute(&self, _: u64) {}
+        }
+
+        impl GaugeFn for Handle {
+            fn increment(&self, value: f64) {
+                self.add(value)
+            }
+
+            fn decrement(&self, value: f64) {
+                self.add(-value)
+            }
+
+            fn set(&self, _: f64) {}
+        }
+
+        impl HistogramFn for Handle {
+            fn record(&self, _: f64) {
+                self.add(1.0)
+            }
+        }
+
+        impl Sums {
+            fn handle(&self, key: &Key) -> Arc<Handle> {
+                let mut name = key.name().to_owned();
+                for label in key.labels() {
+                    name += &format!(",{}={}", label.key(), label.value());
+                }
+                let sums = self.clone();
+                Arc::new(Handle { key: name, sums })
+            }
+
+            fn get(&self, key: &str) -> f64 {
+                self.0.lock().unwrap().get(key).copied().unwrap_or_default()
+            }
+        }
+
+        impl Recorder for Sums {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                Counter::from_arc(self.handle(key))
+            }
+
+            fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::from_arc(self.handle(key))
+            }
+
+            fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::from_arc(self.handle(key))
+            }
+        }
+
+        #[test]
+        fn reports_to_the_recorder() {
+            let sums = Sums::default();
+            // Reported whether measured by the router or not
+            let router =
+                Router::new().register("known", |_: Value| async { Ok::<_, RpcError>(()) });
+            ::metrics::with_local_recorder(&sums, || {
+                let latency = Some(Duration::from_millis(5));
+                router.record("known", &response(None), latency);
+                let err = RpcError::invalid_params();
+                router.record("known", &response(Some(err)), latency);
+                router.record("other", &response(Some(RpcError::method_not_found())), None);
+                let _connected = router.connected();
+                assert_eq!(sums.get("json_rpc_connections"), 1.0);
+            });
+
+            assert_eq!(sums.get("json_rpc_calls_total,method=known"), 2.0);
+            assert_eq!(sums.get("json_rpc_calls_total,method="), 1.0);
+            let errors = "json_rpc_call_errors_total,method=known,code=-32602";
+            assert_eq!(sums.get(errors), 1.0);
+            assert_eq!(sums.get("json_rpc_call_duration_seconds,method=known"), 2.0);
+            assert_eq!(sums.get("json_rpc_connections"), 0.0);
+        }
+    }
+}
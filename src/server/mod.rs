@@ -0,0 +1,472 @@
+pub mod auth;
+pub mod broadcast;
+pub mod concurrency;
+pub mod connection;
+pub mod cors;
+pub mod error;
+pub mod health;
+pub mod http;
+pub mod metrics;
+pub mod openrpc;
+pub mod rate_limit;
+pub mod router;
+mod schema;
+pub mod shutdown;
+pub mod stdio;
+pub mod tcp;
+#[cfg(feature = "tls-rustls")]
+pub mod tls;
+pub(crate) mod trace;
+#[cfg(unix)]
+pub mod unix;
+pub(crate) mod ws;
+
+pub use self::{
+    broadcast::Broadcaster, concurrency::ConcurrencyLimit, error::IntoRpcError, health::Health,
+    metrics::Metrics, openrpc::MethodDoc, rate_limit::RateLimit, router::Router,
+    shutdown::Shutdown,
+};
+
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+use bytes::Bytes;
+use futures_util::{
+    future,
+    stream::{self, StreamExt},
+};
+use serde_json::Value;
+
+use self::{
+    concurrency::Slots,
+    connection::{Connection, Outgoing},
+    rate_limit::Refusals,
+    trace::RemoteTrace,
+};
+use crate::objects::{Request, Response, RpcError};
+
+/// Where the calls of a message come from.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Origin<'a> {
+    /// The slots of the connection, see [`ConcurrencyLimit::per_connection`].
+    pub(crate) slots: &'a Slots,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    pub(crate) peer_addr: Option<SocketAddr>,
+    /// The trace of the HTTP request carrying the message.
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "opentelemetry")),
+        allow(dead_code)
+    )]
+    pub(crate) trace: Option<&'a RemoteTrace>,
+    /// Notes the calls refused by a rate limit, if they are answered over HTTP.
+    pub(crate) refusals: Option<&'a Refusals>,
+}
+
+/// A call received by a server, a request or, without an id, a notification.
+#[derive(Debug)]
+struct Call {
+    id: Option<Value>,
+    method: String,
+    params: Value,
+}
+
+impl Call {
+    /// Parse a request object, failing with the id to answer if it is invalid.
+    fn parse(message: Value) -> Result<Self, Value> {
+        let mut message = match message {
+            Value::Object(message) => message,
+            _ => return Err(Value::Null),
+        };
+        let id = match message.remove("id") {
+            None => None,
+            Some(id @ (Value::Null | Value::Number(_) | Value::String(_))) => Some(id),
+            Some(_) => return Err(Value::Null),
+        };
+
+        if message.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(id.unwrap_or_default());
+        }
+        let method = match message.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err(id.unwrap_or_default()),
+        };
+        // A null params member is taken as omitted, many clients send one
+        let params = match message.remove("params") {
+            None | Some(Value::Null) => Value::Null,
+            Some(params @ (Value::Array(_) | Value::Object(_))) => params,
+            Some(_) => return Err(id.unwrap_or_default()),
+        };
+        Ok(Call { id, method, params })
+    }
+}
+
+/// Handle the body of a message sent to a server, a request or a batch of requests, returning
+/// the body of the response.
+///
+/// There is no response to notifications, nor to batches of notifications.
+pub(crate) async fn handle(router: &Router, body: &[u8], origin: Origin<'_>) -> Option<Vec<u8>> {
+    let message = match serde_json::from_slice(body) {
+        Ok(message) => message,
+        Err(_) => return Some(encode(&respond_error(Value::Null, RpcError::parse_error()))),
+    };
+    match message {
+        Value::Array(calls) if calls.is_empty() => Some(encode(&respond_error(
+            Value::Null,
+            RpcError::invalid_request(),
+        ))),
+        Value::Array(calls) if calls.len() > router.batch_len_limit() => {
+            let err = format!("batches are limited to {} calls", router.batch_len_limit());
+            let err = RpcError::invalid_request().with_data(err);
+            Some(encode(&respond_error(Value::Null, err)))
+        }
+        Value::Array(calls) => {
+            let responses: Vec<_> = stream::iter(calls)
+                .map(|call| respond(router, call, origin))
+                .buffered(router.batch_limit())
+                .filter_map(future::ready)
+                .collect()
+                .await;
+            match responses.is_empty() {
+                true => None,
+                false => Some(encode(&responses)),
+            }
+        }
+        call => respond(router, call, origin)
+            .await
+            .map(|response| encode(&response)),
+    }
+}
+
+/// Handle a message received over `connection` on its own task, sending the response, if any.
+pub(crate) fn dispatch(message: Vec<u8>, connection: &Connection, router: &Arc<Router>) {
+    let router = router.clone();
+    let responding = connection.clone();
+    connection.spawn(async move {
+        let origin = Origin {
+            slots: responding.slots(),
+            peer_addr: responding.peer_addr(),
+            trace: None,
+            refusals: None,
+        };
+        if let Some(response) = handle(&router, &message, origin).await {
+            let _ = responding.send(Outgoing::Message(Bytes::from(response)));
+        }
+    });
+}
+
+/// Respond to a request object, unless it is a notification.
+async fn respond(router: &Router, message: Value, origin: Origin<'_>) -> Option<Response> {
+    let call = match Call::parse(message) {
+        Ok(call) => call,
+        Err(id) => return Some(respond_error(id, RpcError::invalid_request())),
+    };
+    let id = call.id.clone();
+
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "json_rpc_serve",
+        method = %call.method,
+        id = %id.as_ref().unwrap_or(&serde_json::Value::Null),
+        peer_addr = tracing::field::Empty,
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+    );
+    #[cfg(feature = "tracing")]
+    {
+        if let Some(peer_addr) = origin.peer_addr {
+            span.record("peer_addr", tracing::field::display(peer_addr));
+        }
+        if let Some(trace) = origin.trace {
+            span.record("trace_id", format_args!("{:032x}", trace.trace_id));
+            span.record("parent_id", format_args!("{:016x}", trace.parent_id));
+        }
+    }
+
+    let serving = serve(router, call, origin);
+    // Handlers continue the trace of the request, such as when making calls themselves
+    #[cfg(feature = "opentelemetry")]
+    let serving = {
+        use opentelemetry::trace::FutureExt;
+        let context = origin.trace.map(RemoteTrace::context);
+        serving.with_context(context.unwrap_or_else(opentelemetry::Context::current))
+    };
+    #[cfg(feature = "tracing")]
+    let serving = tracing::Instrument::instrument(trace_outcome(serving), span);
+
+    let response = serving.await;
+    // Layers can't change the id of the response
+    Some(Response {
+        id: id?,
+        ..response
+    })
+}
+
+/// Serve a call, within its rate limit, taking its slots.
+async fn serve(router: &Router, call: Call, origin: Origin<'_>) -> Response {
+    let notification = call.id.is_none();
+    let id = call.id.unwrap_or_default();
+    if depth(&call.params) > router.depth_limit() {
+        let err = format!("params are limited to {} levels", router.depth_limit());
+        let response = respond_error(id, RpcError::invalid_params().with_data(err));
+        router.record(&call.method, &response, None);
+        return response;
+    }
+    router.check_missing(&call.method, &call.params, notification);
+    let peer = origin.peer_addr.map(|peer_addr| peer_addr.ip());
+    if let Err(retry_after) = router.check_rate(&call.method, peer) {
+        if let Some(refusals) = origin.refusals {
+            refusals.add(retry_after);
+        }
+        let data = serde_json::json!({ "retry_after": rate_limit::seconds(retry_after) });
+        let response = respond_error(id, RpcError::rate_limited().with_data(data));
+        router.record(&call.method, &response, None);
+        return response;
+    }
+    let _permits = match router.acquire(origin.slots).await {
+        Ok(permits) => permits,
+        Err(err) => {
+            let response = respond_error(id, err);
+            router.record(&call.method, &response, None);
+            return response;
+        }
+    };
+    let method = call.method.clone();
+    let request = Request {
+        method: call.method,
+        params: call.params,
+        id,
+        jsonrpc: "2.0".to_owned(),
+    };
+    let start = Instant::now();
+    let response = router.serve(request).await;
+    router.record(&method, &response, Some(start.elapsed()));
+    response
+}
+
+/// Emit an event recording the latency and outcome of a call.
+#[cfg(feature = "tracing")]
+async fn trace_outcome<F: std::future::Future<Output = Response>>(fut: F) -> Response {
+    let start = Instant::now();
+    let response = fut.await;
+    let latency = start.elapsed();
+    match &response.error {
+        Some(error) => tracing::debug!(?latency, outcome = "rpc_error", code = error.code),
+        None => tracing::debug!(?latency, outcome = "ok"),
+    }
+    response
+}
+
+/// The response to a message larger than `router` accepts.
+pub(crate) fn too_big(router: &Router) -> Outgoing {
+    let err = format!("messages are limited to {} bytes", router.message_limit());
+    let response = respond_error(Value::Null, RpcError::invalid_request().with_data(err));
+    Outgoing::Message(Bytes::from(encode(&response)))
+}
+
+/// The nesting depth of `value`, scalars are at depth 0.
+fn depth(value: &Value) -> usize {
+    // Parsing limits the depth of values, so this can't overflow the stack
+    let children = match value {
+        Value::Array(values) => values.iter().map(depth).max(),
+        Value::Object(values) => values.values().map(depth).max(),
+        _ => return 0,
+    };
+    children.unwrap_or(0) + 1
+}
+
+fn respond_error(id: Value, error: RpcError) -> Response {
+    Response {
+        result: None,
+        error: Some(error),
+        id,
+        jsonrpc: Some("2.0".to_owned()),
+    }
+}
+
+fn encode<T: serde::Serialize>(response: &T) -> Vec<u8> {
+    serde_json::to_vec(response).unwrap() // This is safe
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A router serving the methods of the examples of the JSON-RPC 2.0 specification.
+    fn router() -> Router {
+        Router::new()
+            .register("subtract", |(a, b): (i64, i64)| async move {
+                Ok::<_, RpcError>(a - b)
+            })
+            .register("sum", |values: Vec<i64>| async move {
+                Ok::<_, RpcError>(values.iter().sum::<i64>())
+            })
+            .register("update", |_: Value| async { Ok::<_, RpcError>(()) })
+            .register("notify_hello", |_: Value| async { Ok::<_, RpcError>(()) })
+            .register("get_data", |_: Value| async {
+                Ok::<_, RpcError>(json!(["hello", 5]))
+            })
+    }
+
+    /// Handle `body`, returning the response, if any.
+    async fn handle_json(router: &Router, body: &str) -> Option<Value> {
+        let slots = router.connection_slots();
+        let origin = Origin {
+            slots: &slots,
+            peer_addr: None,
+            trace: None,
+            refusals: None,
+        };
+        let response = handle(router, body.as_bytes(), origin).await?;
+        Some(serde_json::from_slice(&response).unwrap())
+    }
+
+    fn error(code: i64, id: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "error": { "code": code }, "id": id })
+    }
+
+    /// Drop the messages and data of errors, which aren't specified.
+    fn codes(mut response: Value) -> Value {
+        match &mut response {
+            Value::Array(responses) => responses.iter_mut().for_each(strip),
+            response => strip(response),
+        }
+        response
+    }
+
+    fn strip(response: &mut Value) {
+        if let Some(error) = response.get_mut("error").and_then(Value::as_object_mut) {
+            error.retain(|key, _| key == "code");
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_requests() {
+        let body = r#"{"jsonrpc": "2.0", "method": "subtract", "params": [42, 23], "id": 1}"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "result": 19, "id": 1 }));
+
+        let body = r#"{"jsonrpc": "2.0", "method": "subtract", "params": [23, 42], "id": "a"}"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "result": -19, "id": "a" })
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_notifications() {
+        let body = r#"{"jsonrpc": "2.0", "method": "update", "params": [1, 2, 3, 4, 5]}"#;
+        assert_eq!(handle_json(&router(), body).await, None);
+        // Even if they fail
+        let body = r#"{"jsonrpc": "2.0", "method": "foobar"}"#;
+        assert_eq!(handle_json(&router(), body).await, None);
+        let body = r#"{"jsonrpc": "2.0", "method": "subtract", "params": ["a"]}"#;
+        assert_eq!(handle_json(&router(), body).await, None);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_methods() {
+        let body = r#"{"jsonrpc": "2.0", "method": "foobar", "id": "1"}"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(codes(response), error(-32601, json!("1")));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_json() {
+        let body = r#"{"jsonrpc": "2.0", "method": "foobar, "params": "bar", "baz]"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(codes(response), error(-32700, Value::Null));
+
+        let body = r#"[
+            {"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"},
+            {"jsonrpc": "2.0", "method"
+        ]"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(codes(response), error(-32700, Value::Null));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() {
+        let invalid = [
+            r#"{"jsonrpc": "2.0", "method": 1, "params": "bar"}"#,
+            r#"{"jsonrpc": "2.0", "method": "subtract", "params": "bar", "id": null}"#,
+            r#"{"method": "subtract", "params": [42, 23], "id": null}"#,
+            r#"{"jsonrpc": "2.0", "method": "subtract", "id": {"a": 1}}"#,
+            r#""subtract""#,
+        ];
+        for body in invalid {
+            let response = handle_json(&router(), body).await.unwrap();
+            assert_eq!(codes(response), error(-32600, Value::Null), "{}", body);
+        }
+
+        // The id is kept when it can be read
+        let body = r#"{"jsonrpc": "1.0", "method": "subtract", "params": [42, 23], "id": 7}"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(codes(response), error(-32600, json!(7)));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_params() {
+        let body = r#"{"jsonrpc": "2.0", "method": "subtract", "params": ["a", 1], "id": 2}"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        assert_eq!(codes(response), error(-32602, json!(2)));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_batches() {
+        let response = handle_json(&router(), "[]").await.unwrap();
+        assert_eq!(codes(response), error(-32600, Value::Null));
+
+        let response = handle_json(&router(), "[1]").await.unwrap();
+        assert_eq!(codes(response), json!([error(-32600, Value::Null)]));
+
+        let response = handle_json(&router(), "[1, 2, 3]").await.unwrap();
+        let invalid = error(-32600, Value::Null);
+        assert_eq!(codes(response), json!([invalid, invalid, invalid]));
+    }
+
+    #[tokio::test]
+    async fn answers_batches() {
+        let body = r#"[
+            {"jsonrpc": "2.0", "method": "sum", "params": [1,2,4], "id": "1"},
+            {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]},
+            {"jsonrpc": "2.0", "method": "subtract", "params": [42,23], "id": "2"},
+            {"foo": "boo"},
+            {"jsonrpc": "2.0", "method": "foo.get", "params": {"name": "myself"}, "id": "5"},
+            {"jsonrpc": "2.0", "method": "get_data", "id": "9"}
+        ]"#;
+        let response = handle_json(&router(), body).await.unwrap();
+        // The responses are in the order of the requests
+        let expected = json!([
+            { "jsonrpc": "2.0", "result": 7, "id": "1" },
+            { "jsonrpc": "2.0", "result": 19, "id": "2" },
+            error(-32600, Value::Null),
+            error(-32601, json!("5")),
+            { "jsonrpc": "2.0", "result": ["hello", 5], "id": "9" },
+        ]);
+        assert_eq!(codes(response), expected);
+    }
+
+    #[tokio::test]
+    async fn ignores_batches_of_notifications() {
+        let body = r#"[
+            {"jsonrpc": "2.0", "method": "notify_sum", "params": [1,2,4]},
+            {"jsonrpc": "2.0", "method": "notify_hello", "params": [7]}
+        ]"#;
+        assert_eq!(handle_json(&router(), body).await, None);
+    }
+
+    #[tokio::test]
+    async fn limits_batches_and_params() {
+        let router = router().max_batch_len(2).max_params_depth(2);
+        let call = r#"{"jsonrpc": "2.0", "method": "get_data", "id": 1}"#;
+        let body = format!("[{0}, {0}, {0}]", call);
+        let response = handle_json(&router, &body).await.unwrap();
+        assert_eq!(codes(response), error(-32600, Value::Null));
+
+        let body = r#"{"jsonrpc": "2.0", "method": "sum", "params": [[[1]]], "id": 1}"#;
+        let response = handle_json(&router, body).await.unwrap();
+        assert_eq!(codes(response), error(-32602, json!(1)));
+    }
+}
@@ -0,0 +1,292 @@
+#[cfg(feature = "schemars")]
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde_json::{json, Map, Value};
+
+/// The version of the OpenRPC specification the documents follow.
+const OPENRPC_VERSION: &str = "1.2.6";
+
+/// The description of a method in the OpenRPC document of a router, see [`Router::describe`].
+///
+/// Schemas are JSON Schema documents as values, such as those derived by `schemars` and
+/// converted with [`serde_json::to_value`]. With the `schemars` feature, they are derived when
+/// registering a method with `Router::register_with_schemas`.
+///
+/// [`Router::describe`]: super::Router::describe
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MethodDoc {
+    summary: Option<String>,
+    description: Option<String>,
+    params: Vec<ContentDescriptor>,
+    result: Option<ContentDescriptor>,
+    deprecated: bool,
+}
+
+/// A named value with its schema.
+#[derive(Clone, Debug, PartialEq)]
+struct ContentDescriptor {
+    name: String,
+    schema: Value,
+    required: bool,
+}
+
+impl MethodDoc {
+    /// Creates a description without parameters nor result.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the short summary of what the method does.
+    pub fn summary<S: Into<String>>(mut self, summary: S) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Sets the longer description of the method.
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Add a required parameter, after those added before.
+    pub fn param<S: Into<String>>(mut self, name: S, schema: Value) -> Self {
+        self.params.push(ContentDescriptor {
+            name: name.into(),
+            schema,
+            required: true,
+        });
+        self
+    }
+
+    /// Add an optional parameter, after those added before.
+    pub fn optional_param<S: Into<String>>(mut self, name: S, schema: Value) -> Self {
+        self.params.push(ContentDescriptor {
+            name: name.into(),
+            schema,
+            required: false,
+        });
+        self
+    }
+
+    /// Sets the result of the method.
+    pub fn result<S: Into<String>>(mut self, name: S, schema: Value) -> Self {
+        self.result = Some(ContentDescriptor {
+            name: name.into(),
+            schema,
+            required: true,
+        });
+        self
+    }
+
+    /// Mark the method as deprecated.
+    pub fn deprecated(mut self) -> Self {
+        self.deprecated = true;
+        self
+    }
+
+    /// Take the parameters and result from `derived`, unless described.
+    pub(crate) fn or_derived(mut self, derived: &MethodDoc) -> Self {
+        if self.params.is_empty() {
+            self.params = derived.params.clone();
+        }
+        if self.result.is_none() {
+            self.result = derived.result.clone();
+        }
+        self
+    }
+
+    /// The method object named `name` of a document.
+    pub(crate) fn to_method(&self, name: &str) -> Value {
+        let mut method = Map::new();
+        method.insert("name".to_owned(), name.into());
+        if let Some(summary) = &self.summary {
+            method.insert("summary".to_owned(), summary.as_str().into());
+        }
+        if let Some(description) = &self.description {
+            method.insert("description".to_owned(), description.as_str().into());
+        }
+        let params = self.params.iter().map(ContentDescriptor::to_value);
+        method.insert("params".to_owned(), params.collect());
+        // The result is required, anything may be returned unless described
+        let result = match &self.result {
+            Some(result) => result.to_value(),
+            None => json!({ "name": "result", "schema": {} }),
+        };
+        method.insert("result".to_owned(), result);
+        if self.deprecated {
+            method.insert("deprecated".to_owned(), true.into());
+        }
+        Value::Object(method)
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl MethodDoc {
+    /// Describe the parameters of type `P` and result of type `T` of a method.
+    ///
+    /// The fields of a struct are parameters by name, the elements of a tuple by index. Other
+    /// types, such as [`Value`], aren't described.
+    pub(crate) fn derive<P: JsonSchema, T: JsonSchema>() -> Self {
+        let mut doc = MethodDoc::new().result("result", schema::<T>());
+        let params = schema::<P>();
+        if let Some(Value::Object(properties)) = params.get("properties") {
+            let required = params.get("required").and_then(Value::as_array);
+            for (name, schema) in properties {
+                let name = name.as_str();
+                doc = match required.is_some_and(|required| required.contains(&name.into())) {
+                    true => doc.param(name, schema.clone()),
+                    false => doc.optional_param(name, schema.clone()),
+                };
+            }
+        } else if let Some(Value::Array(items)) = params.get("items") {
+            for (index, schema) in items.iter().enumerate() {
+                doc = doc.param(index.to_string(), schema.clone());
+            }
+        }
+        doc
+    }
+}
+
+/// The schema of `T`, with the schemas it refers to inlined so that it stands alone.
+#[cfg(feature = "schemars")]
+fn schema<T: JsonSchema>() -> Value {
+    let schema = SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>();
+    let mut schema = serde_json::to_value(schema).unwrap(); // This is safe
+    if let Value::Object(schema) = &mut schema {
+        schema.remove("$schema");
+    }
+    schema
+}
+
+impl ContentDescriptor {
+    fn to_value(&self) -> Value {
+        json!({
+            "name": self.name,
+            "schema": self.schema,
+            "required": self.required,
+        })
+    }
+}
+
+/// An OpenRPC document titled `title`, for the API at `version`, with `methods`.
+pub(crate) fn document(title: &str, version: &str, methods: Vec<Value>) -> Value {
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": {
+            "title": title,
+            "version": version,
+        },
+        "methods": methods,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{Router, RpcError};
+
+    #[test]
+    fn documents_methods() {
+        let doc = MethodDoc::new()
+            .summary("Add two numbers")
+            .param("a", json!({ "type": "integer" }))
+            .optional_param("b", json!({ "type": "integer" }))
+            .deprecated();
+        let router = Router::new()
+            .register("add", |_: Value| async { Ok::<_, RpcError>(()) })
+            .describe("add", doc);
+        let document = router.openrpc("Calculator", "1.0");
+        assert_eq!(
+            document["info"],
+            json!({ "title": "Calculator", "version": "1.0" })
+        );
+        let expected = json!({
+            "name": "add",
+            "summary": "Add two numbers",
+            "params": [
+                { "name": "a", "schema": { "type": "integer" }, "required": true },
+                { "name": "b", "schema": { "type": "integer" }, "required": false },
+            ],
+            "result": { "name": "result", "schema": {} },
+            "deprecated": true,
+        });
+        assert_eq!(document["methods"], json!([expected]));
+    }
+
+    #[cfg(feature = "schemars")]
+    mod derived {
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+
+        use super::*;
+
+        #[derive(Deserialize, JsonSchema)]
+        #[allow(dead_code)]
+        struct Transfer {
+            to: String,
+            amount: Amount,
+            memo: Option<String>,
+        }
+
+        #[derive(Deserialize, Serialize, JsonSchema)]
+        struct Amount {
+            value: u64,
+        }
+
+        fn method(router: &Router) -> Value {
+            router.openrpc("Bank", "1.0")["methods"][0].clone()
+        }
+
+        #[test]
+        fn documents_params_by_name() {
+            let router = Router::new()
+                .register_with_schemas("transfer", |_: Transfer| async { Ok::<_, RpcError>(true) });
+            let method = method(&router);
+            let params = method["params"].as_array().unwrap();
+            let names: Vec<_> = params.iter().map(|param| &param["name"]).collect();
+            assert_eq!(names, ["amount", "memo", "to"]);
+            let required: Vec<_> = params.iter().map(|param| &param["required"]).collect();
+            assert_eq!(required, [true, false, true]);
+            // Referred schemas are inlined
+            let amount = &params[0]["schema"];
+            assert_eq!(amount["properties"]["value"]["type"], "integer");
+            assert_eq!(method["result"]["schema"]["type"], "boolean");
+        }
+
+        #[test]
+        fn documents_params_by_index() {
+            let router = Router::new()
+                .register_with_schemas("add", |(a, b): (u64, u64)| async move {
+                    Ok::<_, RpcError>(Amount { value: a + b })
+                });
+            let method = method(&router);
+            assert_eq!(method["params"][0]["name"], "0");
+            assert_eq!(method["params"][1]["name"], "1");
+            assert_eq!(method["params"][1]["schema"]["type"], "integer");
+            assert_eq!(method["result"]["schema"]["title"], "Amount");
+        }
+
+        #[test]
+        fn prefers_descriptions() {
+            let doc = MethodDoc::new()
+                .summary("Add")
+                .param("pair", json!({ "type": "array" }));
+            let router = Router::new()
+                .register_with_schemas("add", |(a, b): (u64, u64)| async move {
+                    Ok::<_, RpcError>(a + b)
+                })
+                .describe("add", doc);
+            let method = method(&router);
+            assert_eq!(method["summary"], "Add");
+            assert_eq!(method["params"].as_array().unwrap().len(), 1);
+            assert_eq!(method["result"]["schema"]["type"], "integer");
+
+            // Registering the method again drops the derived schemas
+            let router = router.register("add", |_: Value| async { Ok::<_, RpcError>(()) });
+            let described = router.openrpc("Bank", "1.0")["methods"][0].clone();
+            assert_eq!(described["result"]["schema"], json!({}));
+        }
+    }
+}
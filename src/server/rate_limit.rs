@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The most peers tracked by a limit before the buckets which have refilled are dropped.
+const MAX_PEERS: usize = 4096;
+
+/// A limit on the rate of the calls of a method, see [`Router::rate_limit`].
+///
+/// Calls are allowed in bursts of up to `calls`, refilled evenly over `per`. Calls beyond the
+/// limit fail with the "Rate limited" error, its data tells in how many seconds to retry, as
+/// does the `Retry-After` header over HTTP.
+///
+/// [`Router::rate_limit`]: super::Router::rate_limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    calls: u32,
+    per: Duration,
+    per_peer: bool,
+}
+
+impl RateLimit {
+    /// Creates a limit of `calls` calls per `per`, shared by all clients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `calls` is 0 or `per` is zero.
+    pub fn new(calls: u32, per: Duration) -> Self {
+        assert!(calls > 0, "rate limit must allow at least 1 call");
+        assert!(!per.is_zero(), "rate limit period must not be zero");
+        RateLimit {
+            calls,
+            per,
+            per_peer: false,
+        }
+    }
+
+    /// Sets whether each client IP address gets its own allowance, rather than sharing it.
+    ///
+    /// Clients without an address, such as over stdio, share an allowance.
+    pub fn per_peer(mut self, per_peer: bool) -> Self {
+        self.per_peer = per_peer;
+        self
+    }
+}
+
+/// The calls allowed right now, refilled over time.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Enforces a rate limit, with the buckets of its clients.
+#[derive(Clone, Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Arc<Mutex<HashMap<Option<IpAddr>, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Take a call from the allowance of `peer`, failing with how long until one is allowed.
+    pub(crate) fn check(&self, peer: Option<IpAddr>) -> Result<(), Duration> {
+        let key = peer.filter(|_| self.limit.per_peer);
+        let capacity = f64::from(self.limit.calls);
+        let rate = capacity / self.limit.per.as_secs_f64();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_PEERS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * rate < capacity
+            });
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+/// Notes the calls of a message refused by a rate limit, to answer over HTTP.
+#[derive(Debug, Default)]
+pub(crate) struct Refusals {
+    calls: AtomicUsize,
+    // The longest wait for a call to be allowed, in whole seconds
+    retry_after: AtomicU64,
+}
+
+impl Refusals {
+    pub(crate) fn add(&self, retry_after: Duration) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.retry_after
+            .fetch_max(seconds(retry_after), Ordering::Relaxed);
+    }
+
+    /// The seconds to wait until the calls refused may be retried, if any were refused.
+    pub(crate) fn retry_after(&self) -> Option<u64> {
+        match self.calls.load(Ordering::Relaxed) {
+            0 => None,
+            _ => Some(self.retry_after.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The whole seconds until a call refused for `retry_after` may be retried.
+pub(crate) fn seconds(retry_after: Duration) -> u64 {
+    let millis = retry_after.as_millis().min(u128::from(u64::MAX)) as u64;
+    millis.div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+    const OTHER: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED));
+
+    #[test]
+    fn allows_bursts() {
+        let limiter = RateLimiter::new(RateLimit::new(2, Duration::from_secs(60)));
+        assert!(limiter.check(PEER).is_ok());
+        // Clients share the allowance
+        assert!(limiter.check(OTHER).is_ok());
+        let retry_after = limiter.check(PEER).unwrap_err();
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn limits_each_peer() {
+        let limit = RateLimit::new(1, Duration::from_secs(60)).per_peer(true);
+        let limiter = RateLimiter::new(limit);
+        assert!(limiter.check(PEER).is_ok());
+        assert!(limiter.check(PEER).is_err());
+        assert!(limiter.check(OTHER).is_ok());
+        assert!(limiter.check(None).is_ok());
+        assert!(limiter.check(None).is_err());
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::new(RateLimit::new(1, Duration::from_millis(20)));
+        assert!(limiter.check(None).is_ok());
+        let retry_after = limiter.check(None).unwrap_err();
+        std::thread::sleep(retry_after);
+        assert!(limiter.check(None).is_ok());
+    }
+
+    #[test]
+    fn retries_after_the_longest_wait() {
+        let refusals = Refusals::default();
+        assert_eq!(refusals.retry_after(), None);
+        refusals.add(Duration::from_millis(1500));
+        refusals.add(Duration::from_millis(200));
+        // Rounded up to whole seconds
+        assert_eq!(refusals.retry_after(), Some(2));
+    }
+}
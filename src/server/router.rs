@@ -0,0 +1,895 @@
+use std::{
+    any::{self, Any, TypeId},
+    collections::{BTreeSet, HashMap},
+    convert::Infallible,
+    fmt,
+    net::IpAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::{future::BoxFuture, Future};
+use futures_util::FutureExt;
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::{
+    concurrency::{ConcurrencyLimit, Limiter, Permits, Slots},
+    connection::Connection,
+    error,
+    health::Health,
+    metrics::{Connected, Metrics},
+    openrpc::{self, MethodDoc},
+    rate_limit::{RateLimit, RateLimiter},
+    respond_error, schema, IntoRpcError,
+};
+use crate::{
+    clients::Error,
+    objects::{Request, Response, RpcError},
+};
+
+/// The calls received by a server, as handled by the layers of a [`Router`], see
+/// [`Router::layer`].
+pub type BoxService = tower_util::BoxService<Request, Response, Error<Infallible>>;
+
+/// Handles the calls of a method, see [`Router::register`].
+type MethodHandler =
+    Arc<dyn Fn(Value) -> BoxFuture<'static, Result<Value, RpcError>> + Send + Sync>;
+
+/// Handles the calls of the methods which aren't registered, see [`Router::fallback`].
+type FallbackHandler =
+    Arc<dyn Fn(String, Value) -> BoxFuture<'static, Result<Value, RpcError>> + Send + Sync>;
+
+/// Observes the calls of the methods which aren't served, see [`Router::on_method_missing`].
+type MissingHook = Arc<dyn Fn(&str, &Value) + Send + Sync>;
+
+/// Wraps the service handling calls, see [`Router::layer`].
+type LayerFn = Arc<dyn Fn(BoxService) -> BoxService + Send + Sync>;
+
+/// The number of calls of a batch handled at once by default.
+const BATCH_PARALLELISM: usize = 16;
+
+/// The method returning the OpenRPC document of a server, see [`Router::discover`].
+const DISCOVER: &str = "rpc.discover";
+
+/// The method probing whether a server is healthy, see [`Router::health`].
+const HEALTH: &str = "system.health";
+
+/// The method probing whether a server is ready, see [`Router::health`].
+const READY: &str = "system.ready";
+
+/// The largest message accepted by default, in bytes.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The most calls in a batch accepted by default.
+const MAX_BATCH_LEN: usize = 1024;
+
+/// The deepest nesting of parameters accepted by default.
+const MAX_PARAMS_DEPTH: usize = 64;
+
+/// Dispatches the calls received by a server to the handlers of their methods.
+///
+/// Calls of a method which isn't registered go to the fallback, if any, and otherwise fail with
+/// the "Method not found" error.
+#[derive(Clone)]
+pub struct Router {
+    methods: HashMap<String, MethodHandler>,
+    docs: HashMap<String, MethodDoc>,
+    // The params and result of the methods, as derived from their types
+    derived: HashMap<String, MethodDoc>,
+    schemas: HashMap<String, Value>,
+    timeouts: HashMap<String, Duration>,
+    rate_limits: HashMap<String, RateLimiter>,
+    detach_timed_out: bool,
+    // The title and version of the document returned by `rpc.discover`, if served
+    discover: Option<(String, String)>,
+    // The checks of `system.health` and `system.ready`, if served
+    health: Option<Health>,
+    fallback: Option<FallbackHandler>,
+    on_method_missing: Option<MissingHook>,
+    on_notification_missing: Option<MissingHook>,
+    // The state passed to handlers, by type
+    states: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    batch_parallelism: usize,
+    max_message_size: usize,
+    max_batch_len: usize,
+    max_params_depth: usize,
+    concurrency: Option<Limiter>,
+    panic_messages: bool,
+    metrics: Option<Metrics>,
+    layers: Vec<LayerFn>,
+    // The layers wrapping the router, built once served, see `Router::into_served`
+    pipeline: Option<Arc<Mutex<BoxService>>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            methods: HashMap::new(),
+            docs: HashMap::new(),
+            derived: HashMap::new(),
+            schemas: HashMap::new(),
+            timeouts: HashMap::new(),
+            rate_limits: HashMap::new(),
+            detach_timed_out: false,
+            discover: None,
+            health: None,
+            fallback: None,
+            on_method_missing: None,
+            on_notification_missing: None,
+            states: HashMap::new(),
+            batch_parallelism: BATCH_PARALLELISM,
+            max_message_size: MAX_MESSAGE_SIZE,
+            max_batch_len: MAX_BATCH_LEN,
+            max_params_depth: MAX_PARAMS_DEPTH,
+            concurrency: None,
+            panic_messages: false,
+            metrics: None,
+            layers: Vec::new(),
+            pipeline: None,
+        }
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("methods", &self.methods.keys())
+            .field("fallback", &self.fallback.is_some())
+            .field("batch_parallelism", &self.batch_parallelism)
+            .field("max_message_size", &self.max_message_size)
+            .field("max_batch_len", &self.max_batch_len)
+            .field("max_params_depth", &self.max_params_depth)
+            .field("concurrency", &self.concurrency)
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl Router {
+    /// Creates a router serving no methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `method` using `handler`, called with the parameters of each call deserialized into
+    /// `P`.
+    ///
+    /// Positional parameters deserialize into a tuple, named parameters into a struct, which also
+    /// accepts its fields by position. Take a [`Value`] to handle the parameters as sent. Missing
+    /// parameters are deserialized from `null`, such as into `()` or an [`Option`]. Calls with
+    /// parameters which fail to deserialize fail with the "Invalid params" error, describing why.
+    ///
+    /// The result is serialized into the response, errors are converted into an error object, see
+    /// [`IntoRpcError`].
+    /// This replaces any handler of `method`.
+    pub fn register<M, P, F, Fut, T, E>(mut self, method: M, handler: F) -> Self
+    where
+        M: Into<String>,
+        P: DeserializeOwned,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: IntoRpcError,
+    {
+        let handler = move |params| {
+            let handling = serde_json::from_value(params).map(&handler);
+            Box::pin(async move {
+                match handling {
+                    Ok(handling) => into_result(handling.await),
+                    Err(err) => Err(RpcError::invalid_params().with_data(err.to_string())),
+                }
+            }) as BoxFuture<'static, _>
+        };
+        let method = method.into();
+        self.derived.remove(&method);
+        self.methods.insert(method, Arc::new(handler));
+        self
+    }
+
+    /// Serve `method` using `handler` like [`Router::register`], documenting its parameters and
+    /// result in the OpenRPC document with the schemas derived from their types.
+    ///
+    /// The fields of a struct are documented as parameters by name, the elements of a tuple by
+    /// index. Parameters and results described with [`Router::describe`] take precedence.
+    #[cfg(feature = "schemars")]
+    pub fn register_with_schemas<M, P, F, Fut, T, E>(self, method: M, handler: F) -> Self
+    where
+        M: Into<String>,
+        P: DeserializeOwned + JsonSchema,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize + JsonSchema,
+        E: IntoRpcError,
+    {
+        let method = method.into();
+        let mut router = self.register(method.clone(), handler);
+        router.derived.insert(method, MethodDoc::derive::<P, T>());
+        router
+    }
+
+    /// Add `state` for the handlers registered with [`Router::register_with_state`], replacing
+    /// any state of the same type.
+    ///
+    /// State is shared by the calls of all connections, such as a database pool or
+    /// configuration, see [`Connection::insert`] for the state of a connection.
+    ///
+    /// [`Connection::insert`]: super::connection::Connection::insert
+    pub fn state<S: Send + Sync + 'static>(mut self, state: S) -> Self {
+        self.states.insert(TypeId::of::<S>(), Arc::new(state));
+        self
+    }
+
+    /// Serve `method` using `handler`, called with the state of type `S` and the parameters of
+    /// each call, deserialized like those of [`Router::register`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no state of type `S` has been added with [`Router::state`].
+    pub fn register_with_state<M, S, P, F, Fut, T, E>(self, method: M, handler: F) -> Self
+    where
+        M: Into<String>,
+        S: Send + Sync + 'static,
+        P: DeserializeOwned,
+        F: Fn(Arc<S>, P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: IntoRpcError,
+    {
+        let state = match self.states.get(&TypeId::of::<S>()) {
+            Some(state) => state.clone().downcast::<S>().unwrap(), // This is safe
+            None => panic!("no state of type {} in router", any::type_name::<S>()),
+        };
+        self.register(method, move |params| handler(state.clone(), params))
+    }
+
+    /// Serve the methods which aren't registered using `handler`, called with the method and
+    /// parameters of each call.
+    ///
+    /// This is useful to proxy calls to another server, or to serve methods named dynamically.
+    pub fn fallback<F, Fut, T, E>(mut self, handler: F) -> Self
+    where
+        F: Fn(String, Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize,
+        E: IntoRpcError,
+    {
+        let handler = move |method, params| {
+            let handling = handler(method, params);
+            Box::pin(async move { into_result(handling.await) }) as BoxFuture<'static, _>
+        };
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Call `hook` with the method and parameters of each request to a method which isn't served,
+    /// before it fails with the "Method not found" error.
+    pub fn on_method_missing<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &Value) + Send + Sync + 'static,
+    {
+        self.on_method_missing = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the method and parameters of each notification to a method which isn't
+    /// served.
+    ///
+    /// Notifications are never answered, so such notifications are otherwise dropped silently.
+    pub fn on_notification_missing<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &Value) + Send + Sync + 'static,
+    {
+        self.on_notification_missing = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the number of calls of a batch which are handled at once, 16 by default.
+    ///
+    /// The calls of a batch are handled concurrently, the responses are in the order of the
+    /// calls. Set this to 1 to handle them one after another.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parallelism` is 0.
+    pub fn batch_parallelism(mut self, parallelism: usize) -> Self {
+        assert!(parallelism > 0, "batch parallelism must be at least 1");
+        self.batch_parallelism = parallelism;
+        self
+    }
+
+    /// The number of calls of a batch which are handled at once.
+    pub(crate) fn batch_limit(&self) -> usize {
+        self.batch_parallelism
+    }
+
+    /// Sets the size of the largest message accepted, in bytes, 16 MiB by default.
+    ///
+    /// Larger messages are not read: HTTP requests are answered with `413 Payload Too Large`,
+    /// connections are answered with an "Invalid request" error and closed. This applies to the
+    /// messages received by every server.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Sets the most calls accepted in a batch, 1024 by default.
+    ///
+    /// Longer batches are answered with a single "Invalid request" error, none of their calls are
+    /// handled.
+    pub fn max_batch_len(mut self, len: usize) -> Self {
+        self.max_batch_len = len;
+        self
+    }
+
+    /// Sets the deepest nesting of arrays and objects accepted in the parameters of a call, 64
+    /// by default.
+    ///
+    /// The parameters themselves are at depth 1. Calls with parameters nested deeper fail with
+    /// the "Invalid params" error, without reaching their handler.
+    pub fn max_params_depth(mut self, depth: usize) -> Self {
+        self.max_params_depth = depth;
+        self
+    }
+
+    /// Fail the calls of `method` which take longer than `timeout` with a "Server error",
+    /// replacing any timeout of `method`.
+    ///
+    /// Handlers are aborted once their call times out, unless detached, see
+    /// [`Router::detach_timed_out`]. Unlike a [`TimeoutLayer`], this applies to a single method.
+    ///
+    /// [`TimeoutLayer`]: crate::layers::TimeoutLayer
+    pub fn method_timeout<M: Into<String>>(mut self, method: M, timeout: Duration) -> Self {
+        self.timeouts.insert(method.into(), timeout);
+        self
+    }
+
+    /// Sets whether the handlers of calls which time out keep running to completion on their own
+    /// task, their result discarded, rather than being aborted, `false` by default.
+    ///
+    /// Detach handlers which must not be interrupted midway, such as when writing to a database.
+    pub fn detach_timed_out(mut self, detach: bool) -> Self {
+        self.detach_timed_out = detach;
+        self
+    }
+
+    /// Limit the rate of the calls of `method`, see [`RateLimit`], replacing any limit of
+    /// `method`.
+    pub fn rate_limit<M: Into<String>>(mut self, method: M, limit: RateLimit) -> Self {
+        self.rate_limits
+            .insert(method.into(), RateLimiter::new(limit));
+        self
+    }
+
+    /// Take a call of `method` by `peer` from its rate limit, failing with how long until one is
+    /// allowed.
+    pub(crate) fn check_rate(&self, method: &str, peer: Option<IpAddr>) -> Result<(), Duration> {
+        match self.rate_limits.get(method) {
+            Some(limiter) => limiter.check(peer),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets whether the message of a panicking handler is sent to the client as the data of the
+    /// "Internal error" failing the call, `false` by default.
+    ///
+    /// Panics of handlers fail their call, the server keeps running. Messages may reveal details
+    /// of the server, only send them to trusted clients.
+    pub fn panic_messages(mut self, expose: bool) -> Self {
+        self.panic_messages = expose;
+        self
+    }
+
+    /// Measure the calls served and the connections to the servers of the router in `metrics`.
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Count a call of `method` answered with `response`, whose handler took `latency`.
+    pub(crate) fn record(&self, method: &str, response: &Response, latency: Option<Duration>) {
+        let method = match self.serves(method) {
+            true => method,
+            false => "",
+        };
+        let error = response.error.as_ref().map(|error| error.code);
+        #[cfg(feature = "metrics")]
+        super::metrics::report(method, error, latency);
+        if let Some(metrics) = &self.metrics {
+            metrics.record(method, error, latency);
+        }
+    }
+
+    /// Count a connection as open until the guard is dropped.
+    pub(crate) fn connected(&self) -> Connected {
+        Connected::new(self.metrics.clone())
+    }
+
+    /// Limit the calls handled at once, see [`ConcurrencyLimit`].
+    ///
+    /// This applies to the calls received by every server, the slots of the global limit are
+    /// shared by the servers of the router.
+    pub fn concurrency_limit(mut self, limit: ConcurrencyLimit) -> Self {
+        self.concurrency = Some(Limiter::new(limit));
+        self
+    }
+
+    /// The slots of the calls of a new connection.
+    pub(crate) fn connection_slots(&self) -> Slots {
+        self.concurrency
+            .as_ref()
+            .map(Limiter::slots)
+            .unwrap_or_default()
+    }
+
+    /// Take the slots needed to handle a call received over the connection with `slots`.
+    pub(crate) async fn acquire(&self, slots: &Slots) -> Result<Option<Permits>, RpcError> {
+        match &self.concurrency {
+            Some(limiter) => limiter.acquire(slots).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The size of the largest message accepted, in bytes.
+    pub(crate) fn message_limit(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// The most calls accepted in a batch.
+    pub(crate) fn batch_len_limit(&self) -> usize {
+        self.max_batch_len
+    }
+
+    /// The deepest nesting of parameters accepted.
+    pub(crate) fn depth_limit(&self) -> usize {
+        self.max_params_depth
+    }
+
+    /// Wrap the handling of calls in `layer`, such as a [`TimeoutLayer`].
+    ///
+    /// Layers see each call as a [`Request`], the id of a notification is `null` and its response
+    /// is discarded. Errors returned by a layer fail the call, the error object of an
+    /// [`Error::Rpc`] is kept and other errors are described by a "Server error". Layers added
+    /// later wrap those added before. This applies to the calls received by every server.
+    ///
+    /// [`TimeoutLayer`]: crate::layers::TimeoutLayer
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxService> + Send + Sync + 'static,
+        L::Service:
+            Service<Request, Response = Response, Error = Error<Infallible>> + Send + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers.push(Arc::new(move |service| {
+            BoxService::new(layer.layer(service))
+        }));
+        self
+    }
+
+    /// The names of the registered methods.
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        self.methods.keys().map(String::as_str)
+    }
+
+    /// Describe `method` in the OpenRPC document of the router, see [`Router::openrpc`].
+    ///
+    /// Methods served by the fallback are documented once described. This replaces any
+    /// description of `method`.
+    pub fn describe<M: Into<String>>(mut self, method: M, doc: MethodDoc) -> Self {
+        self.docs.insert(method.into(), doc);
+        self
+    }
+
+    /// Validate the parameters of the calls of `method` against the JSON Schema `schema` before
+    /// they reach its handler, such as one derived by `schemars` and converted with
+    /// [`serde_json::to_value`].
+    ///
+    /// Parameters are validated as sent, an array if positional, an object if named, and `null`
+    /// if omitted. Calls with invalid parameters fail with the "Invalid params" error, with the
+    /// JSON pointer to the first value failing as the `path` of its data, and why as its
+    /// `message`. Most keywords are supported, not `pattern` nor `format`. This replaces any
+    /// schema of `method`.
+    pub fn params_schema<M: Into<String>>(mut self, method: M, schema: Value) -> Self {
+        self.schemas.insert(method.into(), schema);
+        self
+    }
+
+    /// Serve the OpenRPC document of the API titled `title`, at `version`, from the
+    /// `rpc.discover` method, see [`Router::openrpc`].
+    ///
+    /// The method isn't served unless enabled, so that deployments may keep their API private.
+    /// A handler registered for `rpc.discover` takes precedence.
+    pub fn discover<T: Into<String>, V: Into<String>>(mut self, title: T, version: V) -> Self {
+        self.discover = Some((title.into(), version.into()));
+        self
+    }
+
+    /// Serve the `system.health` and `system.ready` methods, running `health`, see [`Health`].
+    ///
+    /// The HTTP server also answers `GET /health` and `GET /ready` with the report, with status
+    /// 503 if a check failed. These are answered without authentication, for orchestrators to
+    /// probe. Handlers registered for the methods take precedence.
+    pub fn health(mut self, health: Health) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Run the checks of the health, or readiness if `ready`, if served.
+    pub(crate) fn probe(&self, ready: bool) -> Option<BoxFuture<'static, (bool, Value)>> {
+        self.health.as_ref().map(|health| health.probe(ready))
+    }
+
+    /// Generate the OpenRPC document of the API titled `title`, at `version`.
+    ///
+    /// The registered and described methods are listed by name. Methods which aren't described
+    /// take any parameters and return anything.
+    pub fn openrpc(&self, title: &str, version: &str) -> Value {
+        let names: BTreeSet<_> = self.methods.keys().chain(self.docs.keys()).collect();
+        let methods = names
+            .into_iter()
+            .map(|name| {
+                let doc = self.docs.get(name).cloned().unwrap_or_default();
+                match self.derived.get(name) {
+                    Some(derived) => doc.or_derived(derived).to_method(name),
+                    None => doc.to_method(name),
+                }
+            })
+            .collect();
+        openrpc::document(title, version, methods)
+    }
+
+    /// Call the handler of `method`, catching its panics, within its timeout, if any.
+    pub(crate) fn call(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> BoxFuture<'static, Result<Value, RpcError>> {
+        let expose = self.panic_messages;
+        let handling = panic::catch_unwind(AssertUnwindSafe(|| self.route(method, params)));
+        let handling = async move {
+            let result = match handling {
+                Ok(handling) => AssertUnwindSafe(handling).catch_unwind().await,
+                Err(panic) => Err(panic),
+            };
+            result.unwrap_or_else(|panic| Err(panicked(panic, expose)))
+        };
+        let timeout = match self.timeouts.get(method) {
+            Some(timeout) => *timeout,
+            None => return Box::pin(handling),
+        };
+        let timed_out = || Err(RpcError::server_error("timed out"));
+        if !self.detach_timed_out {
+            return Box::pin(async move {
+                tokio::time::timeout(timeout, handling)
+                    .await
+                    .unwrap_or_else(|_| timed_out())
+            });
+        }
+        let task = Connection::spawn_current(handling);
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, task).await {
+                Ok(Ok(result)) => result,
+                // The runtime is shutting down
+                Ok(Err(_)) => Err(RpcError::internal_error()),
+                Err(_) => timed_out(),
+            }
+        })
+    }
+
+    /// Call the handler of `method`.
+    fn route(&self, method: &str, params: Value) -> BoxFuture<'static, Result<Value, RpcError>> {
+        if let Some(schema) = self.schemas.get(method) {
+            if let Err(err) = schema::validate(schema, &params) {
+                let err = RpcError::invalid_params().with_data(err);
+                return Box::pin(async move { Err(err) });
+            }
+        }
+        if let Some(handler) = self.methods.get(method) {
+            return handler(params);
+        }
+        if let (Some((title, version)), DISCOVER) = (&self.discover, method) {
+            let document = self.openrpc(title, version);
+            return Box::pin(async move { Ok(document) });
+        }
+        if let (Some(health), HEALTH | READY) = (&self.health, method) {
+            return health.call(method == READY);
+        }
+        if let Some(fallback) = &self.fallback {
+            return fallback(method.to_owned(), params);
+        }
+        let err = RpcError::method_not_found();
+        Box::pin(async move { Err(err) })
+    }
+
+    /// Returns `true` if `method` is registered, or built in.
+    fn serves(&self, method: &str) -> bool {
+        self.methods.contains_key(method)
+            || (self.discover.is_some() && method == DISCOVER)
+            || (self.health.is_some() && (method == HEALTH || method == READY))
+    }
+
+    /// Call the hook observing calls of `method`, if it isn't served.
+    pub(crate) fn check_missing(&self, method: &str, params: &Value, notification: bool) {
+        if self.serves(method) || self.fallback.is_some() {
+            return;
+        }
+        let hook = match notification {
+            true => &self.on_notification_missing,
+            false => &self.on_method_missing,
+        };
+        if let Some(hook) = hook {
+            hook(method, params);
+        }
+    }
+
+    /// Build the layers wrapping the router, for a server to share between its connections.
+    pub(crate) fn into_served(mut self) -> Arc<Router> {
+        if !self.layers.is_empty() {
+            let layers = std::mem::take(&mut self.layers);
+            let service = BoxService::new(Routes(Arc::new(self.clone())));
+            let service = layers.iter().fold(service, |service, layer| layer(service));
+            self.layers = layers;
+            self.pipeline = Some(Arc::new(Mutex::new(service)));
+        }
+        Arc::new(self)
+    }
+
+    /// Handle `request` through the layers, if any.
+    pub(crate) async fn serve(&self, request: Request) -> Response {
+        let pipeline = match &self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return respond(self.call(&request.method, request.params), request.id).await,
+        };
+
+        let id = request.id.clone();
+        let mut service = pipeline.lock().await;
+        let response = match futures_util::future::poll_fn(|cx| service.poll_ready(cx)).await {
+            Ok(()) => service.call(request),
+            Err(err) => Box::pin(async move { Err(err) }),
+        };
+        drop(service);
+        match response.await {
+            Ok(response) => response,
+            Err(err) => {
+                let err = match err.into_inner() {
+                    Error::Rpc(err) => err,
+                    err => error::hidden(&err),
+                };
+                respond_error(id, err)
+            }
+        }
+    }
+}
+
+/// The router as a service, wrapped by its layers.
+struct Routes(Arc<Router>);
+
+impl Service<Request> for Routes {
+    type Response = Response;
+    type Error = Error<Infallible>;
+    type Future = BoxFuture<'static, Result<Response, Error<Infallible>>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let handling = self.0.call(&request.method, request.params);
+        let id = request.id;
+        Box::pin(async move { Ok(respond(handling, id).await) })
+    }
+}
+
+/// Build the response to a call from the outcome of its handler.
+async fn respond(handling: BoxFuture<'static, Result<Value, RpcError>>, id: Value) -> Response {
+    match handling.await {
+        Ok(result) => Response {
+            result: Some(result),
+            error: None,
+            id,
+            jsonrpc: Some("2.0".to_owned()),
+        },
+        Err(err) => respond_error(id, err),
+    }
+}
+
+/// The error of a call whose handler panicked with `panic`, describing it if `expose`.
+fn panicked(panic: Box<dyn Any + Send>, expose: bool) -> RpcError {
+    let err = RpcError::internal_error();
+    if !expose {
+        return err;
+    }
+    match panic.downcast::<String>() {
+        Ok(message) => err.with_data(*message),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => err.with_data(*message),
+            None => err,
+        },
+    }
+}
+
+/// Convert the outcome of a handler into the result or error object of its response.
+fn into_result<T: Serialize, E: IntoRpcError>(result: Result<T, E>) -> Result<Value, RpcError> {
+    let result = result.map_err(IntoRpcError::into_rpc_error)?;
+    serde_json::to_value(result)
+        .map_err(|err| RpcError::internal_error().with_data(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use serde_json::json;
+    use tower_layer::Identity;
+
+    use super::*;
+
+    fn call(method: &str) -> Request {
+        let request = Request::build().method(method).id(1).params(json!([]));
+        request.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn clones_keep_their_methods() {
+        let base = Router::new()
+            .register("base", |_: Value| async { Ok::<_, RpcError>("base") })
+            .layer(Identity::new());
+        let first = base
+            .clone()
+            .register("first", |_: Value| async { Ok::<_, RpcError>(1) })
+            .into_served();
+        let second = base
+            .register("second", |_: Value| async { Ok::<_, RpcError>(2) })
+            .into_served();
+
+        assert_eq!(first.serve(call("first")).await.result, Some(json!(1)));
+        assert_eq!(second.serve(call("second")).await.result, Some(json!(2)));
+        assert_eq!(second.serve(call("base")).await.result, Some(json!("base")));
+        let missing = first.serve(call("second")).await.error.unwrap();
+        assert_eq!(missing, RpcError::method_not_found());
+    }
+
+    #[tokio::test]
+    async fn hides_internal_errors() {
+        let router = Router::new()
+            .register("read", |_: Value| async {
+                Err::<(), _>(io::Error::other("/etc/secret: permission denied"))
+            })
+            .into_served();
+        let err = router.serve(call("read")).await.error.unwrap();
+        assert_eq!(err, RpcError::server_error("Server error"));
+
+        let failing = tower_layer::layer_fn(|_: BoxService| {
+            tower_util::service_fn(|_: Request| async {
+                Err::<Response, _>(Error::Auth("database password rejected".into()))
+            })
+        });
+        let router = Router::new().layer(failing).into_served();
+        let err = router.serve(call("read")).await.error.unwrap();
+        assert_eq!(err, RpcError::server_error("Server error"));
+    }
+
+    #[tokio::test]
+    async fn deserializes_params() {
+        let router = Router::new()
+            .register("pair", |(a, b): (u8, String)| async move {
+                Ok::<_, RpcError>(format!("{}{}", a, b))
+            })
+            .register("none", |_: ()| async { Ok::<_, RpcError>("none") });
+        assert_eq!(router.call("pair", json!([1, "a"])).await, Ok(json!("1a")));
+        assert_eq!(router.call("none", Value::Null).await, Ok(json!("none")));
+        let err = router.call("pair", json!([1])).await.unwrap_err();
+        assert_eq!(err.code, RpcError::invalid_params().code);
+        assert!(err.data.is_some());
+    }
+
+    #[tokio::test]
+    async fn validates_params() {
+        let schema = json!({
+            "type": "array",
+            "prefixItems": [{ "type": "string", "minLength": 1 }, { "type": "integer" }],
+            "minItems": 2,
+        });
+        let router = Router::new()
+            .register("pair", |(a, b): (String, i64)| async move {
+                Ok::<_, RpcError>(format!("{}{}", a, b))
+            })
+            .params_schema("pair", schema)
+            .into_served();
+
+        let request = Request::build()
+            .method("pair")
+            .id(1)
+            .params(json!(["a", 1]));
+        let response = router.serve(request.finish().unwrap()).await;
+        assert_eq!(response.result, Some(json!("a1")));
+
+        let request = Request::build().method("pair").id(2).params(json!(["", 1]));
+        let err = router.serve(request.finish().unwrap()).await.error.unwrap();
+        assert_eq!(err.code, RpcError::invalid_params().code);
+        let data = err.data.unwrap();
+        assert_eq!(data["path"], "/0");
+        assert_eq!(data["message"], "expected at least 1 characters");
+
+        let request = Request::build().method("pair").id(3).params(json!(["a"]));
+        let err = router.serve(request.finish().unwrap()).await.error.unwrap();
+        assert_eq!(err.data.unwrap()["path"], "");
+    }
+
+    #[tokio::test]
+    async fn shares_state() {
+        let router = Router::new()
+            .state(String::from("state"))
+            .register_with_state("get", |state: Arc<String>, _: Value| async move {
+                Ok::<_, RpcError>(state.to_string())
+            });
+        assert_eq!(router.call("get", Value::Null).await, Ok(json!("state")));
+    }
+
+    #[test]
+    #[should_panic(expected = "no state of type")]
+    fn requires_state() {
+        let _ = Router::new().register_with_state("get", |_: Arc<String>, _: Value| async {
+            Ok::<_, RpcError>(())
+        });
+    }
+
+    #[tokio::test]
+    async fn falls_back() {
+        let router = Router::new()
+            .register("known", |_: Value| async { Ok::<_, RpcError>("known") })
+            .fallback(|method, params| async move { Ok::<_, RpcError>(json!([method, params])) });
+        assert_eq!(router.call("known", json!([])).await, Ok(json!("known")));
+        let result = router.call("other", json!([1])).await;
+        assert_eq!(result, Ok(json!(["other", [1]])));
+    }
+
+    #[tokio::test]
+    async fn reports_missing_methods() {
+        let missing = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let methods = missing.clone();
+        let notifications = missing.clone();
+        let router = Router::new()
+            .register("known", |_: Value| async { Ok::<_, RpcError>(()) })
+            .on_method_missing(move |method, _| methods.lock().unwrap().push(method.to_owned()))
+            .on_notification_missing(move |method, _| {
+                notifications.lock().unwrap().push(format!("{}!", method))
+            });
+        router.check_missing("known", &Value::Null, false);
+        router.check_missing("request", &Value::Null, false);
+        router.check_missing("notification", &Value::Null, true);
+        assert_eq!(*missing.lock().unwrap(), ["request", "notification!"]);
+    }
+
+    #[tokio::test]
+    async fn catches_panics() {
+        let router = Router::new().register("panic", |_: Value| async {
+            panic!("oops");
+            #[allow(unreachable_code)]
+            Ok::<_, RpcError>(())
+        });
+        let err = router.call("panic", Value::Null).await.unwrap_err();
+        assert_eq!(err.code, RpcError::internal_error().code);
+        let err = router.panic_messages(true).call("panic", Value::Null).await;
+        assert!(err.unwrap_err().to_string().contains("oops"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_methods() {
+        let router = Router::new()
+            .register("slow", |_: Value| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok::<_, RpcError>(())
+            })
+            .method_timeout("slow", Duration::from_secs(1));
+        let err = router.call("slow", Value::Null).await.unwrap_err();
+        assert_eq!(err, RpcError::server_error("timed out"));
+    }
+}
@@ -0,0 +1,559 @@
+use serde_json::{json, Map, Value};
+
+/// The deepest nesting of schemas followed, bounding cycles of references.
+const MAX_DEPTH: usize = 128;
+
+/// Why a value doesn't match a schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SchemaError {
+    /// The JSON pointer to the value failing, empty for the root.
+    pub(crate) path: String,
+    pub(crate) message: String,
+}
+
+/// Validate `value` against the JSON Schema `schema`.
+///
+/// Supported are boolean schemas, `$ref` to a JSON pointer within the schema, `type`, `enum`,
+/// `const`, the numeric bounds, `minLength` and `maxLength`, `items`, `prefixItems`, `minItems`
+/// and `maxItems`, `properties`, `required`, `additionalProperties`, `allOf`, `anyOf`, `oneOf`
+/// and `not`. Other keywords are ignored.
+pub(crate) fn validate(schema: &Value, value: &Value) -> Result<(), SchemaError> {
+    let validator = Validator { root: schema };
+    validator.check(schema, value, &mut String::new(), 0)
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Validator<'a> {
+    fn check(
+        &self,
+        schema: &'a Value,
+        value: &Value,
+        path: &mut String,
+        depth: usize,
+    ) -> Result<(), SchemaError> {
+        if depth > MAX_DEPTH {
+            return Err(fail(path, "schema nested too deeply"));
+        }
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => return Err(fail(path, "no value is allowed")),
+            Value::Object(schema) => schema,
+            _ => return Ok(()),
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.root.pointer(pointer));
+            match target {
+                Some(target) => self.check(target, value, path, depth + 1)?,
+                None => return Err(fail(path, format!("unresolved reference {}", reference))),
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let matches = match types {
+                Value::String(name) => has_type(value, name),
+                Value::Array(names) => names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|name| has_type(value, name)),
+                _ => true,
+            };
+            if !matches {
+                let message = format!("expected {}, found {}", types, type_name(value));
+                return Err(fail(path, message));
+            }
+        }
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(value) {
+                return Err(fail(
+                    path,
+                    format!("expected one of {}", Value::from(values.clone())),
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                return Err(fail(path, format!("expected {}", expected)));
+            }
+        }
+        match value {
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    check_bounds(schema, number, path)?;
+                }
+            }
+            Value::String(string) => {
+                let len = string.chars().count() as u64;
+                check_len(schema, "minLength", "maxLength", len, "characters", path)?;
+            }
+            Value::Array(items) => self.check_items(schema, items, path, depth)?,
+            Value::Object(object) => self.check_properties(schema, object, path, depth)?,
+            _ => {}
+        }
+        self.check_combinations(schema, value, path, depth)
+    }
+
+    fn check_items(
+        &self,
+        schema: &'a Map<String, Value>,
+        items: &[Value],
+        path: &mut String,
+        depth: usize,
+    ) -> Result<(), SchemaError> {
+        check_len(
+            schema,
+            "minItems",
+            "maxItems",
+            items.len() as u64,
+            "items",
+            path,
+        )?;
+        // Schemas of the first items, `items` as an array before draft 2020-12
+        let (prefix, rest) = match (schema.get("prefixItems"), schema.get("items")) {
+            (Some(Value::Array(prefix)), rest) => (&prefix[..], rest),
+            (None, Some(Value::Array(prefix))) => (&prefix[..], schema.get("additionalItems")),
+            (_, rest) => (&[][..], rest),
+        };
+        for (i, item) in items.iter().enumerate() {
+            let item_schema = match prefix.get(i) {
+                Some(item_schema) => item_schema,
+                None => match rest {
+                    Some(rest) => rest,
+                    None => break,
+                },
+            };
+            let len = path.len();
+            path.push_str(&format!("/{}", i));
+            self.check(item_schema, item, path, depth + 1)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+
+    fn check_properties(
+        &self,
+        schema: &'a Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &mut String,
+        depth: usize,
+    ) -> Result<(), SchemaError> {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            let missing = required
+                .iter()
+                .filter_map(Value::as_str)
+                .find(|name| !object.contains_key(*name));
+            if let Some(name) = missing {
+                return Err(fail(path, format!("missing property {:?}", name)));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        for (name, property) in object {
+            let property_schema = match properties.and_then(|properties| properties.get(name)) {
+                Some(property_schema) => property_schema,
+                None => match additional {
+                    Some(additional) => additional,
+                    None => continue,
+                },
+            };
+            let len = path.len();
+            // Escaped as in JSON pointers
+            path.push('/');
+            path.push_str(&name.replace('~', "~0").replace('/', "~1"));
+            if property_schema == &Value::Bool(false) {
+                return Err(fail(path, "unexpected property"));
+            }
+            self.check(property_schema, property, path, depth + 1)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+
+    fn check_combinations(
+        &self,
+        schema: &'a Map<String, Value>,
+        value: &Value,
+        path: &mut String,
+        depth: usize,
+    ) -> Result<(), SchemaError> {
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for schema in schemas {
+                self.check(schema, value, path, depth + 1)?;
+            }
+        }
+        // Matching any of the schemas, the error of the first is reported
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            let mut first = None;
+            for schema in schemas {
+                match self.check(schema, value, &mut path.clone(), depth + 1) {
+                    Ok(()) => {
+                        first = None;
+                        break;
+                    }
+                    Err(err) => {
+                        first.get_or_insert(err);
+                    }
+                }
+            }
+            if let Some(err) = first {
+                return Err(err);
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            let matching = schemas
+                .iter()
+                .filter(|schema| {
+                    self.check(schema, value, &mut path.clone(), depth + 1)
+                        .is_ok()
+                })
+                .count();
+            if matching != 1 {
+                let message = format!("expected to match one schema, matched {}", matching);
+                return Err(fail(path, message));
+            }
+        }
+        if let Some(schema) = schema.get("not") {
+            if self
+                .check(schema, value, &mut path.clone(), depth + 1)
+                .is_ok()
+            {
+                return Err(fail(path, "matched a schema it must not"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_bounds(schema: &Map<String, Value>, number: f64, path: &str) -> Result<(), SchemaError> {
+    let bound = |keyword| schema.get(keyword).and_then(Value::as_f64);
+    if let Some(minimum) = bound("minimum") {
+        if number < minimum {
+            return Err(fail(path, format!("expected at least {}", minimum)));
+        }
+    }
+    if let Some(maximum) = bound("maximum") {
+        if number > maximum {
+            return Err(fail(path, format!("expected at most {}", maximum)));
+        }
+    }
+    if let Some(minimum) = bound("exclusiveMinimum") {
+        if number <= minimum {
+            return Err(fail(path, format!("expected more than {}", minimum)));
+        }
+    }
+    if let Some(maximum) = bound("exclusiveMaximum") {
+        if number >= maximum {
+            return Err(fail(path, format!("expected less than {}", maximum)));
+        }
+    }
+    Ok(())
+}
+
+fn check_len(
+    schema: &Map<String, Value>,
+    min: &str,
+    max: &str,
+    len: u64,
+    unit: &str,
+    path: &str,
+) -> Result<(), SchemaError> {
+    if let Some(min) = schema.get(min).and_then(Value::as_u64) {
+        if len < min {
+            return Err(fail(path, format!("expected at least {} {}", min, unit)));
+        }
+    }
+    if let Some(max) = schema.get(max).and_then(Value::as_u64) {
+        if len > max {
+            return Err(fail(path, format!("expected at most {} {}", max, unit)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if `value` is of the JSON Schema type `name`.
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        // Numbers with a zero fractional part are integers
+        "integer" => match value {
+            Value::Number(number) => {
+                number.is_i64()
+                    || number.is_u64()
+                    || number.as_f64().is_some_and(|n| n.fract() == 0.0)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+impl From<SchemaError> for Value {
+    fn from(err: SchemaError) -> Self {
+        json!({ "path": err.path, "message": err.message })
+    }
+}
+
+fn fail<M: Into<String>>(path: &str, message: M) -> SchemaError {
+    SchemaError {
+        path: path.to_owned(),
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The path and message of the error validating `value` against `schema`.
+    fn error(schema: Value, value: Value) -> (String, String) {
+        let err = validate(&schema, &value).unwrap_err();
+        (err.path, err.message)
+    }
+
+    #[test]
+    fn boolean_schemas() {
+        assert!(validate(&json!(true), &json!(1)).is_ok());
+        assert_eq!(error(json!(false), json!(1)).1, "no value is allowed");
+    }
+
+    #[test]
+    fn types() {
+        let schema = json!({ "type": "integer" });
+        assert!(validate(&schema, &json!(2)).is_ok());
+        assert!(validate(&schema, &json!(2.0)).is_ok());
+        assert_eq!(
+            error(schema, json!(2.5)),
+            (
+                String::new(),
+                r#"expected "integer", found number"#.to_owned()
+            )
+        );
+
+        let schema = json!({ "type": ["string", "null"] });
+        assert!(validate(&schema, &json!("a")).is_ok());
+        assert!(validate(&schema, &Value::Null).is_ok());
+        assert!(validate(&schema, &json!({})).is_err());
+    }
+
+    #[test]
+    fn enum_and_const() {
+        let schema = json!({ "enum": ["latest", "pending"] });
+        assert!(validate(&schema, &json!("latest")).is_ok());
+        assert_eq!(
+            error(schema, json!("earliest")).1,
+            r#"expected one of ["latest","pending"]"#
+        );
+
+        let schema = json!({ "const": 1 });
+        assert!(validate(&schema, &json!(1)).is_ok());
+        assert_eq!(error(schema, json!(2)).1, "expected 1");
+    }
+
+    #[test]
+    fn numeric_bounds() {
+        let schema = json!({ "minimum": 1, "maximum": 3 });
+        assert!(validate(&schema, &json!(1)).is_ok());
+        assert!(validate(&schema, &json!(3)).is_ok());
+        assert_eq!(error(schema.clone(), json!(0)).1, "expected at least 1");
+        assert_eq!(error(schema, json!(4)).1, "expected at most 3");
+
+        let schema = json!({ "exclusiveMinimum": 1, "exclusiveMaximum": 3 });
+        assert!(validate(&schema, &json!(2)).is_ok());
+        assert_eq!(error(schema.clone(), json!(1)).1, "expected more than 1");
+        assert_eq!(error(schema, json!(3)).1, "expected less than 3");
+    }
+
+    #[test]
+    fn string_lengths() {
+        let schema = json!({ "minLength": 2, "maxLength": 3 });
+        // Lengths are in characters, not bytes
+        assert!(validate(&schema, &json!("éé")).is_ok());
+        assert_eq!(
+            error(schema.clone(), json!("a")).1,
+            "expected at least 2 characters"
+        );
+        assert_eq!(
+            error(schema, json!("abcd")).1,
+            "expected at most 3 characters"
+        );
+    }
+
+    #[test]
+    fn items() {
+        let schema = json!({ "items": { "type": "string" }, "minItems": 1, "maxItems": 2 });
+        assert!(validate(&schema, &json!(["a", "b"])).is_ok());
+        assert_eq!(
+            error(schema.clone(), json!([])).1,
+            "expected at least 1 items"
+        );
+        assert_eq!(
+            error(schema.clone(), json!(["a", "b", "c"])).1,
+            "expected at most 2 items"
+        );
+        assert_eq!(error(schema, json!(["a", 1])).0, "/1");
+
+        let schema = json!({
+            "prefixItems": [{ "type": "string" }, { "type": "integer" }],
+            "items": false,
+        });
+        assert!(validate(&schema, &json!(["a", 1])).is_ok());
+        assert!(validate(&schema, &json!(["a"])).is_ok());
+        assert_eq!(error(schema.clone(), json!([1])).0, "/0");
+        assert_eq!(error(schema, json!(["a", 1, 2])).0, "/2");
+
+        // Positional items as before draft 2020-12
+        let schema = json!({ "items": [{ "type": "string" }], "additionalItems": false });
+        assert!(validate(&schema, &json!(["a"])).is_ok());
+        assert_eq!(error(schema, json!(["a", "b"])).0, "/1");
+    }
+
+    #[test]
+    fn properties() {
+        let schema = json!({
+            "properties": { "to": { "type": "string" }, "value": { "minimum": 0 } },
+            "required": ["to"],
+        });
+        assert!(validate(&schema, &json!({ "to": "a", "extra": 1 })).is_ok());
+        assert_eq!(
+            error(schema.clone(), json!({ "value": 1 })),
+            (String::new(), r#"missing property "to""#.to_owned())
+        );
+        assert_eq!(error(schema, json!({ "to": "a", "value": -1 })).0, "/value");
+
+        let schema = json!({ "additionalProperties": { "type": "integer" } });
+        assert_eq!(error(schema, json!({ "a": "b" })).0, "/a");
+        let schema = json!({ "properties": { "a": true }, "additionalProperties": false });
+        assert_eq!(
+            error(schema, json!({ "a": 1, "b": 2 })),
+            ("/b".to_owned(), "unexpected property".to_owned())
+        );
+    }
+
+    #[test]
+    fn escapes_paths() {
+        let schema = json!({ "additionalProperties": { "type": "integer" } });
+        assert_eq!(error(schema.clone(), json!({ "a/b": "c" })).0, "/a~1b");
+        assert_eq!(error(schema.clone(), json!({ "a~b": "c" })).0, "/a~0b");
+        // `~` is escaped first, so that `~1` isn't read back as `/`
+        assert_eq!(error(schema, json!({ "~1": "c" })).0, "/~01");
+    }
+
+    #[test]
+    fn nested_paths() {
+        let schema = json!({
+            "items": { "properties": { "tags": { "items": { "type": "string" } } } },
+        });
+        let value = json!([{ "tags": ["a"] }, { "tags": ["b", 2] }]);
+        assert_eq!(error(schema, value).0, "/1/tags/1");
+    }
+
+    #[test]
+    fn references() {
+        let schema = json!({
+            "$defs": { "address": { "type": "string", "minLength": 42 } },
+            "items": { "$ref": "#/$defs/address" },
+        });
+        assert!(validate(&schema, &json!(["a".repeat(42)])).is_ok());
+        assert_eq!(
+            error(schema, json!(["0x"])),
+            (
+                "/0".to_owned(),
+                "expected at least 42 characters".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn unresolved_references() {
+        let schema = json!({ "$ref": "#/$defs/missing" });
+        assert_eq!(
+            error(schema, json!(1)).1,
+            "unresolved reference #/$defs/missing"
+        );
+        // Only references within the schema are resolved
+        let schema = json!({ "$ref": "https://example.com/schema.json" });
+        assert!(validate(&schema, &json!(1)).is_err());
+    }
+
+    #[test]
+    fn cyclic_references() {
+        let schema = json!({ "$ref": "#" });
+        assert_eq!(error(schema, json!(1)).1, "schema nested too deeply");
+
+        // A recursive schema terminates with the value
+        let schema = json!({ "type": "array", "items": { "$ref": "#" } });
+        assert!(validate(&schema, &json!([[[]], []])).is_ok());
+        assert_eq!(error(schema, json!([[1]])).0, "/0/0");
+    }
+
+    #[test]
+    fn all_of() {
+        let schema = json!({ "allOf": [{ "type": "integer" }, { "minimum": 2 }] });
+        assert!(validate(&schema, &json!(2)).is_ok());
+        assert_eq!(
+            error(schema.clone(), json!("a")).1,
+            r#"expected "integer", found string"#
+        );
+        assert_eq!(error(schema, json!(1)).1, "expected at least 2");
+    }
+
+    #[test]
+    fn any_of() {
+        let schema = json!({ "anyOf": [{ "type": "string" }, { "minimum": 2 }] });
+        assert!(validate(&schema, &json!("a")).is_ok());
+        assert!(validate(&schema, &json!(3)).is_ok());
+        // The error of the first schema is reported
+        assert_eq!(
+            error(schema, json!(1)).1,
+            r#"expected "string", found number"#
+        );
+    }
+
+    #[test]
+    fn one_of() {
+        let schema = json!({ "oneOf": [{ "type": "integer" }, { "minimum": 2 }] });
+        assert!(validate(&schema, &json!(1)).is_ok());
+        assert!(validate(&schema, &json!(2.5)).is_ok());
+        assert_eq!(
+            error(schema.clone(), json!(3)).1,
+            "expected to match one schema, matched 2"
+        );
+        assert_eq!(
+            error(schema, json!(1.5)).1,
+            "expected to match one schema, matched 0"
+        );
+    }
+
+    #[test]
+    fn not() {
+        let schema = json!({ "not": { "type": "null" } });
+        assert!(validate(&schema, &json!(1)).is_ok());
+        assert_eq!(error(schema, Value::Null).1, "matched a schema it must not");
+    }
+
+    #[test]
+    fn ignores_unsupported_keywords() {
+        let schema = json!({ "type": "string", "pattern": "^0x", "format": "email" });
+        assert!(validate(&schema, &json!("a")).is_ok());
+    }
+}
@@ -0,0 +1,240 @@
+use std::{future::Future, time::Duration};
+
+use futures_util::future::{select, Either};
+use serde_json::Value;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// A handle to shut servers down gracefully.
+///
+/// Once triggered, the servers given the handle stop accepting connections and reading calls,
+/// then wait for the calls in flight to complete and their responses to be written, before
+/// closing WebSocket and TCP connections cleanly. Whatever is still running after the grace
+/// period is aborted. Clones of a handle share its state, one handle may shut several servers
+/// down.
+///
+/// ```ignore
+/// let shutdown = Shutdown::new().grace_period(Duration::from_secs(10));
+/// shutdown.trigger_on(tokio::signal::ctrl_c());
+/// server.graceful_shutdown(shutdown).serve().await;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    triggered: CancellationToken,
+    aborted: CancellationToken,
+    tasks: TaskTracker,
+    grace_period: Duration,
+    notification: Option<(String, Value)>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            triggered: CancellationToken::new(),
+            aborted: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            grace_period: Duration::from_secs(30),
+            notification: None,
+        }
+    }
+}
+
+impl Shutdown {
+    /// Creates a handle with a grace period of 30 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long the calls in flight are waited for once triggered.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Sets a notification of `method` with `params` sent over each WebSocket and TCP connection
+    /// when shutting down, once the calls in flight are answered.
+    ///
+    /// This tells clients to reconnect elsewhere rather than treat the disconnection as a
+    /// failure.
+    pub fn notification<M: Into<String>>(mut self, method: M, params: Value) -> Self {
+        self.notification = Some((method.into(), params));
+        self
+    }
+
+    /// Start shutting down.
+    pub fn trigger(&self) {
+        self.triggered.cancel();
+    }
+
+    /// Start shutting down once `signal` completes, such as [`tokio::signal::ctrl_c`].
+    ///
+    /// This must be called within a Tokio runtime.
+    ///
+    /// [`tokio::signal::ctrl_c`]: https://docs.rs/tokio/1/tokio/signal/fn.ctrl_c.html
+    pub fn trigger_on<F>(&self, signal: F)
+    where
+        F: Future + Send + 'static,
+    {
+        let triggered = self.triggered.clone();
+        tokio::spawn(async move {
+            signal.await;
+            triggered.cancel();
+        });
+    }
+
+    /// Returns `true` once shutting down.
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.is_cancelled()
+    }
+
+    /// Wait until shutting down.
+    ///
+    /// Long-running handlers, such as those streaming notifications, may wait on this to finish
+    /// early.
+    pub async fn triggered(&self) {
+        self.triggered.cancelled().await
+    }
+
+    /// The notification sent over each connection when shutting down.
+    pub(crate) fn farewell(&self) -> Option<(&str, &Value)> {
+        let (method, params) = self.notification.as_ref()?;
+        Some((method, params))
+    }
+
+    /// Run `future` on its own task, waited for when shutting down and aborted once the grace
+    /// period is over.
+    pub(crate) fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let aborted = self.aborted.clone();
+        self.tasks.spawn(async move {
+            select(Box::pin(future), Box::pin(aborted.cancelled())).await;
+        });
+    }
+
+    /// Wait for the tasks spawned to complete, aborting them after the grace period.
+    pub(crate) async fn drain(&self) {
+        self.tasks.close();
+        let waiting = self.tasks.wait();
+        if tokio::time::timeout(self.grace_period, waiting)
+            .await
+            .is_err()
+        {
+            self.aborted.cancel();
+            self.tasks.wait().await;
+        }
+    }
+
+    /// Run `future` until it completes or shutting down starts, returning `None` in the latter
+    /// case.
+    pub(crate) async fn unless_triggered<F: Future>(&self, future: F) -> Option<F::Output> {
+        match select(Box::pin(future), Box::pin(self.triggered.cancelled())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        sync::{oneshot, Notify},
+        time::{sleep, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        objects::RpcError,
+        server::{tcp, Router},
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn drains_the_tasks() {
+        let shutdown = Shutdown::new();
+        let done = Arc::new(AtomicBool::new(false));
+        shutdown.spawn({
+            let done = done.clone();
+            async move {
+                sleep(Duration::from_secs(1)).await;
+                done.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let start = Instant::now();
+        shutdown.trigger();
+        shutdown.drain().await;
+        assert!(done.load(Ordering::Relaxed));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_the_tasks_after_the_grace_period() {
+        let shutdown = Shutdown::new().grace_period(Duration::from_secs(10));
+        let (running, mut aborted) = oneshot::channel::<()>();
+        shutdown.spawn(async move {
+            future::pending::<()>().await;
+            drop(running);
+        });
+
+        let start = Instant::now();
+        shutdown.trigger();
+        shutdown.drain().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+        // The task was dropped with its sender
+        assert_eq!(
+            aborted.try_recv(),
+            Err(oneshot::error::TryRecvError::Closed)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn says_farewell_once_the_calls_are_answered() {
+        let shutdown = Shutdown::new().notification("shutdown", json!({ "reconnect": true }));
+        let handling = Arc::new(Notify::new());
+        let router = Router::new().register("slow", {
+            let handling = handling.clone();
+            move |_: Value| {
+                let handling = handling.clone();
+                async move {
+                    handling.notify_one();
+                    sleep(Duration::from_secs(1)).await;
+                    Ok::<_, RpcError>(true)
+                }
+            }
+        });
+        let (client, server) = tokio::io::duplex(4096);
+        let router = router.into_served();
+        let serving = tokio::spawn(tcp::serve(server, None, router, None, shutdown.clone()));
+        let (read, mut write) = tokio::io::split(client);
+        let call = json!({ "jsonrpc": "2.0", "method": "slow", "id": 1 });
+        write
+            .write_all(format!("{}\n", call).as_bytes())
+            .await
+            .unwrap();
+        handling.notified().await;
+
+        shutdown.trigger();
+        let mut lines = BufReader::new(read).lines();
+        let response = lines.next_line().await.unwrap().unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], true);
+        let farewell = lines.next_line().await.unwrap().unwrap();
+        let farewell: Value = serde_json::from_str(&farewell).unwrap();
+        assert_eq!(farewell["method"], "shutdown");
+        assert_eq!(farewell["params"], json!({ "reconnect": true }));
+        assert!(farewell.get("id").is_none());
+        // The connection is closed afterwards
+        assert!(lines.next_line().await.unwrap().is_none());
+        serving.await.unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use std::{io, sync::Arc};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use super::{
+    connection::{Connection, Outgoing, Queue},
+    tcp, Router, Shutdown,
+};
+
+/// The longest header line read, in bytes.
+const MAX_HEADER_SIZE: u64 = 8 * 1024;
+
+/// How messages are delimited on a stream.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// One message per line.
+    #[default]
+    Lines,
+    /// Each message is preceded by a `Content-Length` header, as in the Language Server Protocol.
+    ContentLength,
+}
+
+/// A JSON-RPC server reading calls from stdin and writing responses to stdout.
+///
+/// This lets a tool be run as the child process of an editor or another client, like a language
+/// server. Nothing else may be written to stdout while serving, log to stderr instead.
+#[derive(Debug)]
+pub struct Server {
+    router: Arc<Router>,
+    framing: Framing,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Creates a server serving `router`, one message per line.
+    pub fn new(router: Router) -> Self {
+        Server {
+            router: router.into_served(),
+            framing: Framing::default(),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Sets how messages are delimited.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Shut the server down gracefully once `shutdown` is triggered, no more calls are read.
+    pub fn graceful_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Serve the calls read from stdin until it closes or the server is shut down, once the
+    /// responses to the calls in flight are written.
+    pub async fn serve(self) -> io::Result<()> {
+        self.serve_io(tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Serve the calls read from `read` until it closes, writing the responses to `write`.
+    pub async fn serve_io<R, W>(self, read: R, write: W) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let _connected = self.router.connected();
+        let (connection, messages) =
+            Connection::new(None, self.shutdown.clone(), self.router.connection_slots());
+        let writer = match self.framing {
+            Framing::Lines => tokio::spawn(tcp::write_lines(write, messages)),
+            Framing::ContentLength => tokio::spawn(write_content(write, messages)),
+        };
+
+        let reading = async {
+            let reading = async {
+                match self.framing {
+                    Framing::Lines => tcp::read_lines(read, &connection, &self.router).await,
+                    Framing::ContentLength => read_content(read, &connection, &self.router).await,
+                }
+            };
+            // Stop reading once the client can't keep up with the responses
+            connection
+                .unless_overflowed(reading)
+                .await
+                .unwrap_or(Ok(()))
+        };
+        let read = self
+            .shutdown
+            .unless_triggered(reading)
+            .await
+            .unwrap_or(Ok(()));
+        // The calls in flight may be aborted once the grace period is over
+        match self.shutdown.is_triggered() {
+            true => self.shutdown.drain().await,
+            false => connection.close(Outgoing::Close).await,
+        }
+        drop(connection);
+        let written = writer
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+        read.and(written)
+    }
+}
+
+/// Read messages preceded by headers and dispatch them until the stream closes.
+async fn read_content<R>(read: R, connection: &Connection, router: &Arc<Router>) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut read = BufReader::new(read);
+    let mut header = String::new();
+    loop {
+        // The headers end with an empty line
+        let mut len = None;
+        loop {
+            header.clear();
+            let n = (&mut read)
+                .take(MAX_HEADER_SIZE)
+                .read_line(&mut header)
+                .await?;
+            if n == 0 {
+                return Ok(());
+            }
+            if !header.ends_with('\n') && n as u64 == MAX_HEADER_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "header too long",
+                ));
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    len = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let len = match len {
+            Some(len) if len <= router.message_limit() => len,
+            Some(_) => {
+                let _ = connection.send(super::too_big(router));
+                return Err(io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "message too big",
+                ));
+            }
+            None => {
+                let err = "missing or invalid Content-Length header";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        };
+
+        let mut message = vec![0; len];
+        read.read_exact(&mut message).await?;
+        super::dispatch(message, connection, router);
+    }
+}
+
+/// Write the messages queued for the client, each preceded by a `Content-Length` header.
+async fn write_content<W: AsyncWrite + Unpin>(mut write: W, mut messages: Queue) -> io::Result<()> {
+    while let Some(message) = messages.recv().await {
+        match message {
+            Outgoing::Message(message) => {
+                let header = format!("Content-Length: {}\r\n\r\n", message.len());
+                write.write_all(header.as_bytes()).await?;
+                write.write_all(&message).await?;
+                write.flush().await?;
+            }
+            Outgoing::Close | Outgoing::Overflow => break,
+            Outgoing::Control(..) => {}
+        }
+    }
+    write.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::objects::RpcError;
+
+    /// Serve `router` over a stream framed by `Content-Length` headers, returning the client's end.
+    fn serve(router: Router) -> (DuplexStream, tokio::task::JoinHandle<io::Result<()>>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (read, write) = tokio::io::split(server);
+        let server = Server::new(router).framing(Framing::ContentLength);
+        (client, tokio::spawn(server.serve_io(read, write)))
+    }
+
+    /// Read the message following the next headers from `read`.
+    async fn receive<R: AsyncRead + Unpin>(read: &mut BufReader<R>) -> Value {
+        let mut header = String::new();
+        read.read_line(&mut header).await.unwrap();
+        let len = header
+            .trim()
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        read.read_line(&mut header).await.unwrap();
+        let mut message = vec![0; len];
+        read.read_exact(&mut message).await.unwrap();
+        serde_json::from_slice(&message).unwrap()
+    }
+
+    fn echo() -> Router {
+        Router::new()
+            .register("echo", |params: Value| async { Ok::<_, RpcError>(params) })
+            .max_message_size(256)
+    }
+
+    #[tokio::test]
+    async fn frames_messages_with_content_length() {
+        let (client, server) = serve(echo());
+        let (read, mut write) = tokio::io::split(client);
+        let mut read = BufReader::new(read);
+
+        for id in 1..=2 {
+            let call = json!({ "jsonrpc": "2.0", "method": "echo", "params": [id], "id": id });
+            let call = call.to_string();
+            let message = format!(
+                "Content-Type: application/vscode-jsonrpc\r\ncontent-length: {}\r\n\r\n{}",
+                call.len(),
+                call
+            );
+            write.write_all(message.as_bytes()).await.unwrap();
+            let response = receive(&mut read).await;
+            assert_eq!(
+                response,
+                json!({ "jsonrpc": "2.0", "result": [id], "id": id })
+            );
+        }
+
+        write.shutdown().await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_messages_too_big() {
+        let (client, server) = serve(echo());
+        let (read, mut write) = tokio::io::split(client);
+        write
+            .write_all(b"Content-Length: 257\r\n\r\n")
+            .await
+            .unwrap();
+
+        let response = receive(&mut BufReader::new(read)).await;
+        assert_eq!(response["error"]["code"], RpcError::invalid_request().code);
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_headers() {
+        let (mut client, server) = serve(echo());
+        client
+            .write_all(b"Content-Type: json\r\n\r\n{}")
+            .await
+            .unwrap();
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let (mut client, server) = serve(echo());
+        let header = "X".repeat(MAX_HEADER_SIZE as usize);
+        client.write_all(header.as_bytes()).await.unwrap();
+        let err = server.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
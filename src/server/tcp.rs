@@ -0,0 +1,299 @@
+use std::{fmt, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, ToSocketAddrs},
+};
+
+#[cfg(feature = "tls-rustls")]
+use super::tls::{Acceptor, ServerTlsConfig, TlsError};
+use super::{
+    connection::{Connection, Outgoing, Queue},
+    Router, Shutdown,
+};
+
+/// Called with each connection as it is accepted, see [`Server::on_connect`].
+pub(crate) type ConnectHook = Arc<dyn Fn(&Connection) + Send + Sync>;
+
+/// A JSON-RPC server over TCP, exchanging newline-delimited JSON.
+///
+/// This is the framing used by Electrum servers and many daemons. The server may push
+/// notifications to a client using its [`Connection`], and keep state for the lifetime of each
+/// connection.
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    on_connect: Option<ConnectHook>,
+    shutdown: Shutdown,
+    #[cfg(feature = "tls-rustls")]
+    tls: Option<Acceptor>,
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("listener", &self.listener)
+            .field("router", &self.router)
+            .finish()
+    }
+}
+
+impl Server {
+    /// Creates a server listening on `addr`, serving `router`.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, router: Router) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr).await?,
+            router: router.into_served(),
+            on_connect: None,
+            shutdown: Shutdown::new(),
+            #[cfg(feature = "tls-rustls")]
+            tls: None,
+        })
+    }
+
+    /// Call `hook` with each connection as it is accepted, before any of its calls are handled.
+    ///
+    /// This is where the state of a connection is initialized, see [`Connection::insert`].
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Connection) + Send + Sync + 'static,
+    {
+        self.on_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Shut the server down gracefully once `shutdown` is triggered.
+    pub fn graceful_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Serve connections over TLS, using `config`.
+    #[cfg(feature = "tls-rustls")]
+    pub fn tls(mut self, config: &ServerTlsConfig) -> Result<Self, TlsError> {
+        self.tls = Some(config.acceptor(&[])?);
+        Ok(self)
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections and serve them, each on its own task, until shut down.
+    ///
+    /// Errors accepting a connection, such as running out of file descriptors, are retried after
+    /// a pause.
+    pub async fn serve(self) {
+        while let Some(accepted) = self.shutdown.unless_triggered(self.listener.accept()).await {
+            let (stream, peer_addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+            let router = self.router.clone();
+            let on_connect = self.on_connect.clone();
+            let shutdown = self.shutdown.clone();
+            #[cfg(feature = "tls-rustls")]
+            if let Some(tls) = self.tls.clone() {
+                self.shutdown.spawn(async move {
+                    if let Ok(stream) = tls.accept(stream).await {
+                        serve(stream, Some(peer_addr), router, on_connect, shutdown).await;
+                    }
+                });
+                continue;
+            }
+            self.shutdown
+                .spawn(serve(stream, Some(peer_addr), router, on_connect, shutdown));
+        }
+        self.shutdown.drain().await;
+    }
+}
+
+/// Serve the calls made over a connection carrying newline-delimited JSON, until it closes.
+///
+/// Calls are handled concurrently, each on its own task. Once shutting down, no more calls are
+/// read.
+pub(crate) async fn serve<T>(
+    io: T,
+    peer_addr: Option<SocketAddr>,
+    router: Arc<Router>,
+    on_connect: Option<ConnectHook>,
+    shutdown: Shutdown,
+) where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let _connected = router.connected();
+    let (read, write) = tokio::io::split(io);
+    let (connection, messages) =
+        Connection::new(peer_addr, shutdown.clone(), router.connection_slots());
+    if let Some(on_connect) = on_connect {
+        on_connect(&connection);
+    }
+    let writer = tokio::spawn(write_lines(write, messages));
+
+    // A client too slow to read its responses is disconnected
+    let reading = async {
+        let reading = read_lines(read, &connection, &router);
+        connection
+            .unless_overflowed(reading)
+            .await
+            .unwrap_or(Ok(()))
+    };
+    match shutdown.unless_triggered(reading).await {
+        // The client is told why a line too long to read closes the connection
+        Some(Ok(())) => connection.close(Outgoing::Close).await,
+        Some(Err(err)) if err.kind() == io::ErrorKind::OutOfMemory => {
+            connection.close(Outgoing::Close).await
+        }
+        // The connection failed
+        Some(Err(_)) => return writer.abort(),
+        // Close the connection even if handlers keep it to push notifications
+        None => connection.drain(Outgoing::Close).await,
+    }
+    // The messages queued are still written
+    drop(connection);
+    let _ = writer.await;
+}
+
+/// Read lines and dispatch them until the client closes the connection.
+pub(crate) async fn read_lines<R>(
+    read: R,
+    connection: &Connection,
+    router: &Arc<Router>,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut read = BufReader::new(read);
+    loop {
+        let mut line = Vec::new();
+        let limit = router.message_limit() as u64 + 1;
+        let n = (&mut read).take(limit).read_until(b'\n', &mut line).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        if line.last() != Some(&b'\n') && n as u64 == limit {
+            let _ = connection.send(super::too_big(router));
+            return Err(io::Error::new(io::ErrorKind::OutOfMemory, "line too long"));
+        }
+        if !line.trim_ascii().is_empty() {
+            super::dispatch(line, connection, router);
+        }
+    }
+}
+
+/// Write the messages queued for the client as lines, until the connection closes.
+pub(crate) async fn write_lines<W: AsyncWrite + Unpin>(
+    mut write: W,
+    mut messages: Queue,
+) -> io::Result<()> {
+    while let Some(message) = messages.recv().await {
+        match message {
+            Outgoing::Message(message) => {
+                write.write_all(&message).await?;
+                write.write_all(b"\n").await?;
+                write.flush().await?;
+            }
+            Outgoing::Close | Outgoing::Overflow => break,
+            // Lines carry no control messages
+            Outgoing::Control(..) => {}
+        }
+    }
+    write.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::{
+        io::Lines,
+        net::{
+            tcp::{OwnedReadHalf, OwnedWriteHalf},
+            TcpStream,
+        },
+    };
+
+    use super::*;
+    use crate::objects::RpcError;
+
+    /// The name of a client, stored by the connect hook.
+    #[derive(Clone)]
+    struct Name(String);
+
+    /// Serve a router answering `name` from the connection's state and `slow` after a pause,
+    /// returning a connection to it.
+    async fn connect(shutdown: Shutdown) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let router = Router::new()
+            .register("name", |_: Value| async {
+                let connection = Connection::current().unwrap();
+                Ok::<_, RpcError>(connection.get::<Name>().unwrap().0)
+            })
+            .register("slow", |_: Value| async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok::<_, RpcError>(true)
+            })
+            .max_message_size(256);
+        let server = Server::bind("127.0.0.1:0", router)
+            .await
+            .unwrap()
+            .on_connect(|connection| {
+                connection.insert(Name(format!("client {}", connection.id())));
+            })
+            .graceful_shutdown(shutdown);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        (BufReader::new(read).lines(), write)
+    }
+
+    /// Read the next message from `lines`.
+    async fn receive(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_lines() {
+        let (mut lines, mut write) = connect(Shutdown::new()).await;
+        let calls = "{\"jsonrpc\":\"2.0\",\"method\":\"name\",\"id\":1}\n\n\
+                     {\"jsonrpc\":\"2.0\",\"method\":\"name\",\"id\":2}\n";
+        write.write_all(calls.as_bytes()).await.unwrap();
+        let first = receive(&mut lines).await;
+        let second = receive(&mut lines).await;
+        assert_eq!(first["id"], 1);
+        assert_eq!(second["id"], 2);
+        let name = first["result"].as_str().unwrap();
+        assert!(name.starts_with("client "), "{}", name);
+        assert_eq!(second["result"], name);
+
+        // A line too long is answered with an error, then the connection is closed
+        write.write_all(&[b' '; 300]).await.unwrap();
+        let response = receive(&mut lines).await;
+        assert_eq!(response["error"]["code"], RpcError::invalid_request().code);
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn drains_calls_when_shutting_down() {
+        let shutdown = Shutdown::new().notification("bye", json!([]));
+        let (mut lines, mut write) = connect(shutdown.clone()).await;
+        let call = "{\"jsonrpc\":\"2.0\",\"method\":\"slow\",\"id\":1}\n";
+        write.write_all(call.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.trigger();
+
+        // The call in flight is answered before the farewell and the connection closing
+        let response = receive(&mut lines).await;
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "result": true, "id": 1 })
+        );
+        let farewell = receive(&mut lines).await;
+        assert_eq!(farewell["method"], "bye");
+        assert_eq!(lines.next_line().await.unwrap(), None);
+    }
+}
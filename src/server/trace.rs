@@ -0,0 +1,69 @@
+use hyper::{header::HeaderName, HeaderMap};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// The W3C trace context of a request, from its `traceparent` and `tracestate` headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    not(any(feature = "tracing", feature = "opentelemetry")),
+    allow(dead_code)
+)]
+pub(crate) struct RemoteTrace {
+    pub(crate) trace_id: u128,
+    pub(crate) parent_id: u64,
+    pub(crate) flags: u8,
+    pub(crate) tracestate: String,
+}
+
+impl RemoteTrace {
+    /// Parse the trace context of a request with `headers`, if any and valid.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?;
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+        // Later versions may append fields, version 0 may not
+        if version.len() != 2 || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return None;
+        }
+        if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        let trace = RemoteTrace {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            tracestate: headers
+                .get_all(TRACESTATE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        // All zero ids are invalid
+        match trace.trace_id != 0 && trace.parent_id != 0 {
+            true => Some(trace),
+            false => None,
+        }
+    }
+
+    /// The OpenTelemetry context with the remote span as the parent of new spans.
+    #[cfg(feature = "opentelemetry")]
+    pub(crate) fn context(&self) -> opentelemetry::Context {
+        use opentelemetry::trace::{
+            SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+        };
+
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(self.trace_id.to_be_bytes()),
+            SpanId::from_bytes(self.parent_id.to_be_bytes()),
+            TraceFlags::new(self.flags),
+            true,
+            self.tracestate.parse().unwrap_or(TraceState::NONE),
+        );
+        opentelemetry::Context::new().with_remote_span_context(span_context)
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fmt,
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::net::UnixListener;
+
+use super::{
+    connection::Connection,
+    tcp::{self, ConnectHook},
+    Router, Shutdown,
+};
+
+/// Options for the socket file of a [`Server`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnixOptions {
+    mode: Option<u32>,
+    replace: bool,
+}
+
+impl Default for UnixOptions {
+    fn default() -> Self {
+        UnixOptions {
+            mode: None,
+            replace: true,
+        }
+    }
+}
+
+impl UnixOptions {
+    /// Creates the default options, replacing a stale socket file and keeping the permissions
+    /// given by the umask.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the permissions of the socket file, such as `0o660` to allow only the owner and group
+    /// to connect.
+    ///
+    /// The permissions are set once the socket is bound, tighten the umask to close the window
+    /// in between.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets whether a socket file left at the path, such as by a server which crashed, is removed
+    /// before binding. Other kinds of files are never removed.
+    pub fn replace(mut self, replace: bool) -> Self {
+        self.replace = replace;
+        self
+    }
+}
+
+/// A JSON-RPC server on a Unix domain socket, exchanging newline-delimited JSON.
+///
+/// This is the usual local IPC surface of daemons, access is controlled by the permissions of
+/// the socket file. Connections are served like those of a [`tcp::Server`]. The socket file is
+/// removed once the server is dropped, unless it was replaced since.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    // The device and inode of the socket file, to leave it alone once replaced
+    file: (u64, u64),
+    router: Arc<Router>,
+    on_connect: Option<ConnectHook>,
+    shutdown: Shutdown,
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("path", &self.path)
+            .field("router", &self.router)
+            .finish()
+    }
+}
+
+impl Server {
+    /// Creates a server listening on the socket at `path`, serving `router`.
+    ///
+    /// This must be called within a Tokio runtime.
+    pub fn bind<P: AsRef<Path>>(path: P, router: Router) -> io::Result<Self> {
+        Self::bind_with(path, router, UnixOptions::default())
+    }
+
+    /// Creates a server listening on the socket at `path` created using `options`, serving
+    /// `router`.
+    ///
+    /// This must be called within a Tokio runtime.
+    pub fn bind_with<P: AsRef<Path>>(
+        path: P,
+        router: Router,
+        options: UnixOptions,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        if options.replace {
+            match fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+                _ => {}
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        let metadata = fs::symlink_metadata(path)?;
+        let server = Server {
+            listener,
+            path: path.to_owned(),
+            file: (metadata.dev(), metadata.ino()),
+            router: router.into_served(),
+            on_connect: None,
+            shutdown: Shutdown::new(),
+        };
+        if let Some(mode) = options.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        Ok(server)
+    }
+
+    /// Call `hook` with each connection as it is accepted, before any of its calls are handled.
+    ///
+    /// This is where the state of a connection is initialized, see [`Connection::insert`].
+    pub fn on_connect<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Connection) + Send + Sync + 'static,
+    {
+        self.on_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Shut the server down gracefully once `shutdown` is triggered.
+    pub fn graceful_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// The path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept connections and serve them, each on its own task, until shut down.
+    ///
+    /// Errors accepting a connection, such as running out of file descriptors, are retried after
+    /// a pause.
+    pub async fn serve(self) {
+        while let Some(accepted) = self.shutdown.unless_triggered(self.listener.accept()).await {
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            self.shutdown.spawn(tcp::serve(
+                stream,
+                None,
+                self.router.clone(),
+                self.on_connect.clone(),
+                self.shutdown.clone(),
+            ));
+        }
+        self.shutdown.drain().await;
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if (metadata.dev(), metadata.ino()) == self.file => {
+                let _ = fs::remove_file(&self.path);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, os::unix::net, process};
+
+    use super::*;
+
+    /// A path for the socket of the test `name`, free of any file.
+    fn socket_path(name: &str) -> PathBuf {
+        let name = format!("async-json-rpc-{}-{}.sock", process::id(), name);
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn sets_the_mode() {
+        let path = socket_path("mode");
+        let options = UnixOptions::new().mode(0o600);
+        let _server = Server::bind_with(&path, Router::new(), options).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_sockets() {
+        let path = socket_path("stale");
+        // Left behind by a server which crashed
+        drop(net::UnixListener::bind(&path).unwrap());
+
+        let options = UnixOptions::new().replace(false);
+        let err = Server::bind_with(&path, Router::new(), options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        let _server = Server::bind(&path, Router::new()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_other_files() {
+        let path = socket_path("other");
+        fs::write(&path, "data").unwrap();
+
+        assert!(Server::bind(&path, Router::new()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removes_the_socket_on_drop() {
+        let path = socket_path("drop");
+        let first = Server::bind(&path, Router::new()).unwrap();
+        let second = Server::bind(&path, Router::new()).unwrap();
+
+        // The socket of the first server was replaced by the second's
+        drop(first);
+        assert!(path.exists());
+        drop(second);
+        assert!(!path.exists());
+    }
+}
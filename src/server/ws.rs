@@ -0,0 +1,464 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{
+    body::Incoming,
+    header::{
+        HeaderMap, HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+        SEC_WEBSOCKET_VERSION, UPGRADE,
+    },
+    Method, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use super::{
+    connection::{Connection, Outgoing, Queue},
+    Router, Shutdown,
+};
+
+/// Appended to the key of a handshake before hashing it, see RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_TOO_BIG: u16 = 1009;
+const CLOSE_TRY_AGAIN_LATER: u16 = 1013;
+
+/// A frame received from the client.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Returns `true` if `request` asks to upgrade the connection to a WebSocket.
+pub(crate) fn is_upgrade<B>(request: &hyper::Request<B>) -> bool {
+    let headers = request.headers();
+    request.method() == Method::GET
+        && has_token(headers, &CONNECTION, "upgrade")
+        && has_token(headers, &UPGRADE, "websocket")
+}
+
+fn has_token(headers: &HeaderMap, name: &hyper::header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Complete the handshake of a WebSocket upgrade, serving the connection once upgraded.
+pub(crate) fn upgrade(
+    request: hyper::Request<Incoming>,
+    router: Arc<Router>,
+    peer_addr: SocketAddr,
+    shutdown: Shutdown,
+) -> hyper::Response<Full<Bytes>> {
+    let headers = request.headers();
+    let version = headers.get(SEC_WEBSOCKET_VERSION);
+    let key = match headers.get(SEC_WEBSOCKET_KEY) {
+        Some(key) if version.map(HeaderValue::as_bytes) == Some(b"13") => key,
+        _ => {
+            let mut response = hyper::Response::new(Full::default());
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+                .headers_mut()
+                .insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+            return response;
+        }
+    };
+    let accept = accept_key(key.as_bytes());
+
+    shutdown.clone().spawn(async move {
+        if let Ok(upgraded) = hyper::upgrade::on(request).await {
+            serve(TokioIo::new(upgraded), router, Some(peer_addr), shutdown).await;
+        }
+    });
+
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+    let headers = response.headers_mut();
+    headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+    headers.insert(SEC_WEBSOCKET_ACCEPT, accept.parse().unwrap()); // This is safe
+    response
+}
+
+/// Serve the calls made over a WebSocket until it closes.
+///
+/// Calls are handled concurrently, each on its own task.
+async fn serve<T>(io: T, router: Arc<Router>, peer_addr: Option<SocketAddr>, shutdown: Shutdown)
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let _connected = router.connected();
+    let (read, write) = tokio::io::split(io);
+    let (connection, messages) =
+        Connection::new(peer_addr, shutdown.clone(), router.connection_slots());
+    let writer = tokio::spawn(write_frames(write, messages));
+
+    // The writer tells a client too slow to read its messages why it is disconnected
+    let reading = async {
+        let reading = read_messages(read, &connection, &router);
+        connection
+            .unless_overflowed(reading)
+            .await
+            .unwrap_or(Ok(()))
+    };
+    let code = match shutdown.unless_triggered(reading).await {
+        Some(Ok(())) => None,
+        None => {
+            connection.drain(close(CLOSE_GOING_AWAY)).await;
+            None
+        }
+        Some(Err(err)) if err.kind() == io::ErrorKind::InvalidData => Some(CLOSE_PROTOCOL_ERROR),
+        Some(Err(err)) if err.kind() == io::ErrorKind::OutOfMemory => Some(CLOSE_TOO_BIG),
+        // The connection is gone, there is no one to tell
+        Some(Err(_)) => return writer.abort(),
+    };
+    if let Some(code) = code {
+        let _ = connection.send(close(code));
+    }
+    drop(connection);
+    let _ = writer.await;
+}
+
+/// Read messages and dispatch them until the client closes the connection.
+async fn read_messages<R>(read: R, connection: &Connection, router: &Arc<Router>) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut read = BufReader::new(read);
+    let mut message = Vec::new();
+    // Whether a fragmented message is being read
+    let mut fragmented = false;
+    loop {
+        let frame = read_frame(&mut read, router.message_limit()).await?;
+        match frame.opcode {
+            OP_CONTINUATION if fragmented => {}
+            OP_TEXT | OP_BINARY if !fragmented => message.clear(),
+            OP_CLOSE => {
+                // Echo the status code, if any, to complete the closing handshake
+                let code = frame.payload.get(..2).unwrap_or_default();
+                let _ = connection.send(Outgoing::Control(OP_CLOSE, Bytes::copy_from_slice(code)));
+                return Ok(());
+            }
+            OP_PING => {
+                let _ = connection.send(Outgoing::Control(OP_PONG, Bytes::from(frame.payload)));
+                continue;
+            }
+            OP_PONG => continue,
+            _ => return Err(invalid("unexpected frame")),
+        }
+
+        if message.len() + frame.payload.len() > router.message_limit() {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "message too big",
+            ));
+        }
+        message.extend_from_slice(&frame.payload);
+        fragmented = !frame.fin;
+        if !fragmented {
+            super::dispatch(std::mem::take(&mut message), connection, router);
+        }
+    }
+}
+
+/// Read a frame of at most `limit` bytes, unmasking its payload.
+async fn read_frame<R: AsyncRead + Unpin>(read: &mut R, limit: usize) -> io::Result<Frame> {
+    let mut header = [0; 2];
+    read.read_exact(&mut header).await?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0F;
+    if header[0] & 0x70 != 0 {
+        return Err(invalid("reserved bits set"));
+    }
+    // Clients must mask their frames
+    if header[1] & 0x80 == 0 {
+        return Err(invalid("unmasked frame"));
+    }
+    let len = match header[1] & 0x7F {
+        126 => u64::from(read.read_u16().await?),
+        127 => read.read_u64().await?,
+        len => u64::from(len),
+    };
+    if opcode & 0x8 != 0 && (!fin || len > 125) {
+        return Err(invalid("invalid control frame"));
+    }
+    if len > limit as u64 {
+        return Err(io::Error::new(io::ErrorKind::OutOfMemory, "frame too big"));
+    }
+
+    let mut mask = [0; 4];
+    read.read_exact(&mut mask).await?;
+    let mut payload = vec![0; len as usize];
+    read.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Write the messages queued for the client as frames, until the connection closes.
+async fn write_frames<W: AsyncWrite + Unpin>(mut write: W, mut messages: Queue) -> io::Result<()> {
+    while let Some(message) = messages.recv().await {
+        let (opcode, payload) = match message {
+            Outgoing::Message(payload) => (OP_TEXT, payload),
+            Outgoing::Control(opcode, payload) => (opcode, payload),
+            Outgoing::Close => break,
+            Outgoing::Overflow => (OP_CLOSE, close_payload(CLOSE_TRY_AGAIN_LATER)),
+        };
+        write
+            .write_all(&frame_header(opcode, payload.len()))
+            .await?;
+        write.write_all(&payload).await?;
+        write.flush().await?;
+        if opcode == OP_CLOSE {
+            break;
+        }
+    }
+    write.shutdown().await
+}
+
+/// Encode the header of an unmasked, final frame.
+fn frame_header(opcode: u8, len: usize) -> Vec<u8> {
+    let mut header = vec![0x80 | opcode];
+    match len {
+        0..=125 => header.push(len as u8),
+        126..=0xFFFF => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    header
+}
+
+/// A close frame with the status `code`.
+fn close(code: u16) -> Outgoing {
+    Outgoing::Control(OP_CLOSE, close_payload(code))
+}
+
+fn close_payload(code: u16) -> Bytes {
+    Bytes::copy_from_slice(&code.to_be_bytes())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The `Sec-WebSocket-Accept` value answering the handshake key `key`, see RFC 6455.
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(GUID.as_bytes());
+    base64::encode(sha1.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::{objects::Request, server::RpcError};
+
+    /// Encode a frame as a client would, masking its payload.
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = frame_header(opcode, payload.len());
+        if !fin {
+            frame[0] &= 0x7F;
+        }
+        frame[1] |= 0x80;
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    /// Read an unmasked frame as sent by the server, `None` once the connection is closed.
+    async fn server_frame(read: &mut DuplexStream) -> Option<(u8, Vec<u8>)> {
+        let mut header = [0; 2];
+        read.read_exact(&mut header).await.ok()?;
+        let len = match header[1] {
+            126 => usize::from(read.read_u16().await.unwrap()),
+            127 => read.read_u64().await.unwrap() as usize,
+            len => usize::from(len),
+        };
+        let mut payload = vec![0; len];
+        read.read_exact(&mut payload).await.unwrap();
+        Some((header[0] & 0x0F, payload))
+    }
+
+    /// Serve a WebSocket with an `echo` method, returning the client's end.
+    fn connect() -> DuplexStream {
+        let router = Router::new()
+            .register("echo", |params: Value| async { Ok::<_, RpcError>(params) })
+            .into_served();
+        let (client, server) = tokio::io::duplex(1 << 20);
+        tokio::spawn(serve(server, router, None, Shutdown::new()));
+        client
+    }
+
+    #[test]
+    fn accepts_the_handshake_key() {
+        // The example of RFC 6455, section 1.3
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn encodes_frame_lengths() {
+        assert_eq!(frame_header(OP_TEXT, 125), [0x81, 125]);
+        assert_eq!(frame_header(OP_TEXT, 126), [0x81, 126, 0, 126]);
+        assert_eq!(frame_header(OP_BINARY, 0xFFFF), [0x82, 126, 0xFF, 0xFF]);
+        assert_eq!(
+            frame_header(OP_CLOSE, 0x10000),
+            [0x88, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn unmasks_frames() {
+        // The masked example of RFC 6455, section 5.7
+        let frame = [
+            0x81, 0x85, 0x37, 0xFA, 0x21, 0x3D, 0x7F, 0x9F, 0x4D, 0x51, 0x58,
+        ];
+        let frame = read_frame(&mut &frame[..], 1024).await.unwrap();
+        assert!(frame.fin);
+        assert_eq!(frame.opcode, OP_TEXT);
+        assert_eq!(frame.payload, b"Hello");
+    }
+
+    #[tokio::test]
+    async fn reads_extended_lengths() {
+        for len in [126, 0xFFFF, 0x10000] {
+            let payload = vec![b'x'; len];
+            let frame = masked(true, OP_BINARY, &payload);
+            let read = read_frame(&mut &frame[..], 1 << 20).await.unwrap();
+            assert_eq!(read.payload, payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_frames() {
+        let unmasked = [0x81, 0x05, b'H', b'e', b'l', b'l', b'o'];
+        let err = read_frame(&mut &unmasked[..], 1024).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reserved = masked(true, OP_TEXT, b"Hello");
+        reserved[0] |= 0x40;
+        let err = read_frame(&mut &reserved[..], 1024).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let too_big = masked(true, OP_TEXT, &[0; 64]);
+        let err = read_frame(&mut &too_big[..], 63).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+    }
+
+    #[tokio::test]
+    async fn limits_control_frames() {
+        let fragmented = masked(false, OP_PING, b"ping");
+        let err = read_frame(&mut &fragmented[..], 1024).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let too_long = masked(true, OP_PING, &[0; 126]);
+        let err = read_frame(&mut &too_long[..], 1024).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn reassembles_fragmented_messages() {
+        let mut client = connect();
+        let mut frames = masked(false, OP_TEXT, br#"{"jsonrpc":"2.0","method":"echo","#);
+        // Control frames may come between the fragments of a message
+        frames.extend(masked(true, OP_PING, b"ping"));
+        frames.extend(masked(true, OP_CONTINUATION, br#""params":[1],"id":1}"#));
+        client.write_all(&frames).await.unwrap();
+
+        let pong = server_frame(&mut client).await.unwrap();
+        assert_eq!(pong, (OP_PONG, b"ping".to_vec()));
+        let (opcode, response) = server_frame(&mut client).await.unwrap();
+        assert_eq!(opcode, OP_TEXT);
+        let response: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["result"], serde_json::json!([1]));
+    }
+
+    #[tokio::test]
+    async fn echoes_the_close_frame() {
+        let mut client = connect();
+        let request = Request::build().method("echo").id(1).finish().unwrap();
+        let request = serde_json::to_vec(&request).unwrap();
+        client
+            .write_all(&masked(true, OP_TEXT, &request))
+            .await
+            .unwrap();
+        assert_eq!(server_frame(&mut client).await.unwrap().0, OP_TEXT);
+
+        client
+            .write_all(&masked(true, OP_CLOSE, &1000u16.to_be_bytes()))
+            .await
+            .unwrap();
+        let close = server_frame(&mut client).await.unwrap();
+        assert_eq!(close, (OP_CLOSE, vec![0x03, 0xE8]));
+        assert!(server_frame(&mut client).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn closes_on_protocol_errors() {
+        let mut client = connect();
+        client
+            .write_all(&masked(true, OP_CONTINUATION, b"{}"))
+            .await
+            .unwrap();
+        let close = server_frame(&mut client).await.unwrap();
+        assert_eq!(
+            close,
+            (OP_CLOSE, CLOSE_PROTOCOL_ERROR.to_be_bytes().to_vec())
+        );
+        assert!(server_frame(&mut client).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn closes_slow_clients() {
+        let router = Router::new().into_served();
+        let (connection, messages) =
+            Connection::new(None, Shutdown::new(), router.connection_slots());
+        let message = Outgoing::Message(Bytes::from_static(b"{}"));
+        while connection.send(message.clone()).is_ok() {}
+        assert!(connection.is_closed());
+
+        // The messages queued are dropped in favour of telling the client why it is closed
+        let mut written = Vec::new();
+        write_frames(&mut written, messages).await.unwrap();
+        let mut close = frame_header(OP_CLOSE, 2);
+        close.extend_from_slice(&CLOSE_TRY_AGAIN_LATER.to_be_bytes());
+        assert_eq!(written, close);
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    convert::Infallible,
+    fmt, io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::CONTENT_TYPE,
+    service::service_fn,
+    StatusCode,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{clients::compression, objects::RpcError};
+
+type HttpResponse = hyper::Response<Full<Bytes>>;
+
+/// A JSON-RPC server on an ephemeral local port, answering the calls it expects with canned
+/// replies, for testing clients.
+///
+/// Each call is matched against the pending expectations in the order they were added, the
+/// first matching answers it. Calls matching no expectation fail with the "Method not found"
+/// error object and are reported as unexpected. Dropping the server verifies that every
+/// expectation was met and no unexpected call was made, see [`MockServer::verify`].
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    received: Vec<Value>,
+    unexpected: Vec<Value>,
+}
+
+/// A call expected by a [`MockServer`], with its reply.
+///
+/// Expectations are answered with a `null` result unless set otherwise, once unless
+/// [`Expectation::times`] is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expectation {
+    method: String,
+    params: Option<Value>,
+    reply: Reply,
+    delay: Option<Duration>,
+    times: usize,
+    matched: usize,
+}
+
+/// The reply to an expected call.
+#[derive(Clone, Debug, PartialEq)]
+enum Reply {
+    Result(Value),
+    Error(RpcError),
+    /// A body sent as is in place of the response, such as malformed JSON.
+    Body(StatusCode, Bytes),
+}
+
+impl fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockServer")
+            .field("addr", &self.addr)
+            .field("expectations", &state.expectations)
+            .field("received", &state.received.len())
+            .finish()
+    }
+}
+
+impl MockServer {
+    /// Starts a server on an ephemeral port of the loopback interface.
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let task = tokio::spawn(serve(listener, state.clone()));
+        Ok(MockServer { addr, state, task })
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The URL of the server, to connect clients to.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Expect a call, after those expected before.
+    pub fn expect(&self, expectation: Expectation) -> &Self {
+        self.state.lock().unwrap().expectations.push(expectation);
+        self
+    }
+
+    /// Returns the calls received, in order, as sent.
+    pub fn received(&self) -> Vec<Value> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Panics if an expectation wasn't met, or a call which wasn't expected was received.
+    #[track_caller]
+    pub fn verify(&self) {
+        let state = self.state.lock().unwrap();
+        let mut failures = Vec::new();
+        for expectation in &state.expectations {
+            if expectation.matched < expectation.times {
+                failures.push(format!(
+                    "expected {} calls of {}{}, received {}",
+                    expectation.times,
+                    expectation.method,
+                    expectation
+                        .params
+                        .as_ref()
+                        .map_or_else(String::new, |params| format!(" with {}", params)),
+                    expectation.matched
+                ));
+            }
+        }
+        for call in &state.unexpected {
+            failures.push(format!("unexpected call {}", call));
+        }
+        if !failures.is_empty() {
+            panic!("mock server expectations failed: {}", failures.join("; "));
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        // Don't panic while panicking, which would abort the test run
+        if !thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+impl Expectation {
+    /// Expect a call of `method`, with any parameters.
+    pub fn call<M: Into<String>>(method: M) -> Self {
+        Expectation {
+            method: method.into(),
+            params: None,
+            reply: Reply::Result(Value::Null),
+            delay: None,
+            times: 1,
+            matched: 0,
+        }
+    }
+
+    /// Only match calls with `params`.
+    pub fn params<V: Into<Value>>(mut self, params: V) -> Self {
+        self.params = Some(params.into());
+        self
+    }
+
+    /// Reply with `result`.
+    pub fn result<V: Into<Value>>(mut self, result: V) -> Self {
+        self.reply = Reply::Result(result.into());
+        self
+    }
+
+    /// Reply with the error object `err`.
+    pub fn error(mut self, err: RpcError) -> Self {
+        self.reply = Reply::Error(err);
+        self
+    }
+
+    /// Reply with `body` and `status` in place of a response, such as malformed JSON or an error
+    /// page.
+    pub fn body<B: Into<Bytes>>(mut self, status: StatusCode, body: B) -> Self {
+        self.reply = Reply::Body(status, body.into());
+        self
+    }
+
+    /// Wait for `delay` before replying.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Expect `times` calls, answering each.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, method: &str, params: &Value) -> bool {
+        self.matched < self.times
+            && self.method == method
+            && self
+                .params
+                .as_ref()
+                .is_none_or(|expected| expected == params)
+    }
+}
+
+/// Accept connections and serve them until the server is dropped.
+async fn serve(listener: TcpListener, state: Arc<Mutex<State>>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let state = state.clone();
+        let service = service_fn(move |request| respond(state.clone(), request));
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let _ = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+/// Answer the calls of a request from the expectations.
+async fn respond(
+    state: Arc<Mutex<State>>,
+    request: hyper::Request<Incoming>,
+) -> Result<HttpResponse, Infallible> {
+    let (parts, body) = request.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => return Ok(status(StatusCode::BAD_REQUEST)),
+    };
+    // Accept requests compressed by `Client::with_request_compression`
+    let body = match compression::decompress(&parts.headers, body, None) {
+        Ok(body) => body,
+        Err(_) => return Ok(status(StatusCode::UNSUPPORTED_MEDIA_TYPE)),
+    };
+    let (calls, batch) = match serde_json::from_slice(&body) {
+        Ok(Value::Array(calls)) => (calls, true),
+        Ok(call) => (vec![call], false),
+        Err(_) => {
+            let err = RpcError::parse_error();
+            let body = json!({ "jsonrpc": "2.0", "error": err, "id": null });
+            return Ok(json_response(StatusCode::OK, body.to_string()));
+        }
+    };
+
+    let mut delay = None;
+    let mut raw = None;
+    let mut responses = Vec::new();
+    for call in calls {
+        let method = call.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = call.get("params").cloned().unwrap_or(Value::Null);
+        let reply = {
+            let mut state = state.lock().unwrap();
+            state.received.push(call.clone());
+            let expectation = state
+                .expectations
+                .iter_mut()
+                .find(|expectation| expectation.matches(method, &params));
+            let reply = expectation.map(|expectation| {
+                expectation.matched += 1;
+                (expectation.reply.clone(), expectation.delay)
+            });
+            if reply.is_none() {
+                state.unexpected.push(call.clone());
+            }
+            reply
+        };
+        let (reply, call_delay) =
+            reply.unwrap_or((Reply::Error(RpcError::method_not_found()), None));
+        delay = delay.max(call_delay);
+        // Notifications are answered without a response
+        let id = match call.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        match reply {
+            Reply::Result(result) => {
+                responses.push(json!({ "jsonrpc": "2.0", "result": result, "id": id }))
+            }
+            Reply::Error(err) => {
+                responses.push(json!({ "jsonrpc": "2.0", "error": err, "id": id }))
+            }
+            Reply::Body(status, body) => raw = Some((status, body)),
+        }
+    }
+
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+    // A raw body replaces the whole response, batched or not
+    if let Some((status, body)) = raw {
+        let mut response = hyper::Response::new(Full::new(body));
+        *response.status_mut() = status;
+        return Ok(response);
+    }
+    let body = match (batch, responses.pop()) {
+        (_, None) => return Ok(status(StatusCode::NO_CONTENT)),
+        (false, Some(response)) => response,
+        (true, Some(response)) => {
+            responses.push(response);
+            Value::Array(responses)
+        }
+    };
+    Ok(json_response(StatusCode::OK, body.to_string()))
+}
+
+fn json_response(status: StatusCode, body: String) -> HttpResponse {
+    let mut response = hyper::Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap()); // This is safe
+    response
+}
+
+/// An empty response with `status`.
+fn status(status: StatusCode) -> HttpResponse {
+    let mut response = hyper::Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+
+    /// Post `body` to `server`, returning the status and body of the response.
+    async fn post(server: &MockServer, body: &str) -> (u16, String) {
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_owned())
+    }
+
+    #[tokio::test]
+    async fn answers_expected_calls_in_order() {
+        let server = MockServer::start().await.unwrap();
+        server
+            .expect(Expectation::call("get").params(json!(["b"])).result("B"))
+            .expect(Expectation::call("get").result("any").times(2))
+            .expect(Expectation::call("fail").error(RpcError::invalid_params()));
+
+        let (status, body) = post(&server, r#"{"method":"get","params":["a"],"id":1}"#).await;
+        assert_eq!(status, 200);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "result": "any", "id": 1 })
+        );
+
+        let batch = r#"[{"method":"get","params":["b"],"id":2},
+                        {"method":"get","params":["c"],"id":3},
+                        {"method":"fail","id":4}]"#;
+        let (_, body) = post(&server, batch).await;
+        let responses: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(responses[0]["result"], "B");
+        assert_eq!(responses[1]["result"], "any");
+        assert_eq!(responses[2]["error"], json!(RpcError::invalid_params()));
+        assert_eq!(server.received().len(), 4);
+        server.verify();
+    }
+
+    #[tokio::test]
+    async fn answers_notifications_without_a_body() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("notify"));
+        let (status, body) = post(&server, r#"{"method":"notify"}"#).await;
+        assert_eq!(status, 204);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replies_with_scripted_bodies() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("broken").body(StatusCode::BAD_GATEWAY, "{\"jsonrpc"));
+        let (status, body) = post(&server, r#"{"method":"broken","id":1}"#).await;
+        assert_eq!(status, 502);
+        assert_eq!(body, "{\"jsonrpc");
+    }
+
+    #[tokio::test]
+    async fn delays_replies() {
+        let server = MockServer::start().await.unwrap();
+        let delay = Duration::from_millis(50);
+        server.expect(Expectation::call("slow").delay(delay));
+        let start = Instant::now();
+        let (_, body) = post(&server, r#"{"method":"slow","id":1}"#).await;
+        assert!(start.elapsed() >= delay);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["result"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn answers_malformed_requests_with_a_parse_error() {
+        let server = MockServer::start().await.unwrap();
+        let (status, body) = post(&server, "{").await;
+        assert_eq!(status, 200);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["error"], json!(RpcError::parse_error()));
+        assert!(server.received().is_empty());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "unexpected call {\"id\":1,\"method\":\"other\"}")]
+    async fn reports_unexpected_calls() {
+        let server = MockServer::start().await.unwrap();
+        let (_, body) = post(&server, r#"{"method":"other","id":1}"#).await;
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["error"], json!(RpcError::method_not_found()));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected 2 calls of get with [1], received 1")]
+    async fn reports_unmet_expectations_on_drop() {
+        let server = MockServer::start().await.unwrap();
+        server.expect(Expectation::call("get").params(json!([1])).times(2));
+        post(&server, r#"{"method":"get","params":[1],"id":1}"#).await;
+    }
+}
@@ -0,0 +1,110 @@
+use async_json_rpc::{
+    clients::{http::Client, Error, RequestFactory},
+    objects::RpcError,
+    rpc,
+    server::http::Server,
+    testing::{Expectation, MockServer},
+};
+use serde_json::{json, Value};
+
+#[rpc]
+pub trait Calculator {
+    async fn version(&self) -> Result<String, RpcError>;
+
+    async fn negate(&self, value: i64) -> Result<i64, RpcError>;
+
+    #[rpc(name = "calc_add")]
+    async fn add(&self, a: i64, b: i64, label: Option<String>) -> Result<String, RpcError>;
+}
+
+struct MyCalculator;
+
+impl Calculator for MyCalculator {
+    async fn version(&self) -> Result<String, RpcError> {
+        Ok("1.0".to_owned())
+    }
+
+    async fn negate(&self, value: i64) -> Result<i64, RpcError> {
+        value.checked_neg().ok_or_else(RpcError::invalid_params)
+    }
+
+    async fn add(&self, a: i64, b: i64, label: Option<String>) -> Result<String, RpcError> {
+        Ok(format!("{}{}", label.unwrap_or_default(), a + b))
+    }
+}
+
+/// Serve `MyCalculator` over HTTP, returning its URL.
+async fn serve() -> String {
+    let server = Server::bind("127.0.0.1:0", MyCalculator.into_router())
+        .await
+        .unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    tokio::spawn(server.serve());
+    url
+}
+
+#[tokio::test]
+async fn calls_methods_on_a_mock_server() {
+    let server = MockServer::start().await.unwrap();
+    server
+        .expect(Expectation::call("version").result("1.0"))
+        .expect(Expectation::call("negate").params(json!([2])).result(-2))
+        .expect(
+            Expectation::call("calc_add")
+                .params(json!([1, 2, "sum: "]))
+                .result("sum: 3"),
+        );
+    let client = CalculatorClient::new(Client::new(server.url(), None, None));
+
+    assert_eq!(client.version().await.unwrap(), "1.0");
+    assert_eq!(client.negate(2).await.unwrap(), -2);
+    let sum = client.add(1, 2, Some("sum: ".to_owned())).await.unwrap();
+    assert_eq!(sum, "sum: 3");
+    server.verify();
+}
+
+#[tokio::test]
+async fn reports_errors_from_a_mock_server() {
+    let server = MockServer::start().await.unwrap();
+    server
+        .expect(Expectation::call("negate").error(RpcError::invalid_params()))
+        .expect(Expectation::call("version").result(1));
+    let client = CalculatorClient::new(Client::new(server.url(), None, None));
+
+    let err = client.negate(i64::MIN).await.unwrap_err();
+    assert!(matches!(err, Error::Rpc(err) if err == RpcError::invalid_params()));
+    // The result doesn't deserialize
+    let err = client.version().await.unwrap_err();
+    assert!(matches!(err, Error::Json(_)));
+}
+
+#[tokio::test]
+async fn round_trips_through_the_router() {
+    let client = CalculatorClient::new(Client::new(serve().await, None, None));
+
+    assert_eq!(client.version().await.unwrap(), "1.0");
+    assert_eq!(client.negate(2).await.unwrap(), -2);
+    let err = client.negate(i64::MIN).await.unwrap_err();
+    assert!(matches!(err, Error::Rpc(err) if err == RpcError::invalid_params()));
+    assert_eq!(client.add(1, 2, None).await.unwrap(), "3");
+}
+
+#[tokio::test]
+async fn accepts_params_by_name() {
+    let client = Client::new(serve().await, None, None);
+    let request = client
+        .build_request()
+        .method("calc_add")
+        .params(json!({ "a": 1, "b": 2, "label": "sum: " }))
+        .finish()
+        .unwrap();
+    let response = client.send(request).await.unwrap();
+    assert_eq!(response.result, Some(Value::from("sum: 3")));
+
+    // Methods without params accept an empty array, or nothing
+    for params in [json!([]), Value::Null] {
+        let request = client.build_request().method("version").params(params);
+        let response = client.send(request.finish().unwrap()).await.unwrap();
+        assert_eq!(response.result, Some(Value::from("1.0")));
+    }
+}